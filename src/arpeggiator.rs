@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::ArpPattern;
+use crate::processor::NoteEvent;
+use crate::quantize::Grid;
+use crate::stress::Rng;
+use crate::Engine;
+
+// The arpeggiator: while a chord is held, one of its notes plays at a time, stepping through
+// them on the tempo grid instead of holding every key, for games that can't hold many notes
+// at once. The first note of a chord plays right away and starts the steps; each step lets
+// go of the last note before pressing the next. Letting go of everything stops it.
+
+// How often the arpeggiator looks for a step that's due
+const ARP_POLL: Duration = Duration::from_millis(1);
+
+pub struct Arpeggiator {
+    rng: Rng,
+    held: Vec<NoteEvent>, // Note-ons of the keys held down, in the order pressed
+    step: usize,
+    sounding: Option<NoteEvent>,
+    start_us: u64,           // Where the steps are counted from
+    next_us: Option<u64>,    // The next step; None while nothing is held
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Self { rng: Rng::new(1), held: Vec::new(), step: 0, sounding: None, start_us: 0, next_us: None }
+    }
+}
+
+impl Arpeggiator {
+    /// A key pressed at `now_us`; the first of a chord is due right away.
+    pub fn press(&mut self, event: NoteEvent, now_us: u64) {
+        self.held.retain(|held| held.channel != event.channel || held.note != event.note);
+        self.held.push(event);
+        if self.next_us.is_none() {
+            self.step = 0;
+            self.start_us = now_us;
+            self.next_us = Some(now_us);
+        }
+    }
+
+    /// A key let go. With nothing held any more, the note sounding stops: its note-off
+    /// comes back to be played.
+    pub fn release(&mut self, event: NoteEvent) -> Option<NoteEvent> {
+        self.held.retain(|held| held.channel != event.channel || held.note != event.note);
+        if !self.held.is_empty() {
+            return None;
+        }
+        self.next_us = None;
+        self.sounding.take().map(|on| NoteEvent::off(on.channel, on.note))
+    }
+
+    /// When the steps started, for lining up their grid.
+    pub fn start_us(&self) -> u64 {
+        self.start_us
+    }
+
+    /// When the next step is due, if anything is held.
+    pub fn next_due(&self) -> Option<u64> {
+        self.next_us
+    }
+
+    /// The notes to play for a step due by `now_us`, the last one's note-off first; the
+    /// step after is on the next line of `grid`.
+    pub fn tick(&mut self, now_us: u64, grid: Grid, pattern: ArpPattern) -> Vec<NoteEvent> {
+        let mut out = Vec::new();
+        if self.next_us.is_none_or(|next_us| now_us < next_us) || self.held.is_empty() {
+            return out;
+        }
+        if let Some(on) = self.sounding.take() {
+            out.push(NoteEvent::off(on.channel, on.note));
+        }
+        let mut notes = self.held.clone();
+        notes.sort_by_key(|event| event.note);
+        let n = notes.len();
+        let index = match pattern {
+            ArpPattern::Up => self.step % n,
+            ArpPattern::Down => n - 1 - self.step % n,
+            ArpPattern::UpDown => {
+                // Up and back down without playing the top and bottom twice
+                let cycle = (2 * n).saturating_sub(2).max(1);
+                let at = self.step % cycle;
+                if at < n { at } else { cycle - at }
+            }
+            ArpPattern::Random => self.rng.below(n as u64) as usize,
+        };
+        self.step += 1;
+        self.sounding = Some(notes[index]);
+        out.push(notes[index]);
+        self.next_us = Some(grid.next_after(now_us));
+        out
+    }
+
+    /// Forgets everything held, e.g. on panic or when turned off. Returns the note-off for
+    /// the note sounding, if any.
+    pub fn clear(&mut self) -> Option<NoteEvent> {
+        self.held.clear();
+        self.next_us = None;
+        self.sounding.take().map(|on| NoteEvent::off(on.channel, on.note))
+    }
+}
+
+/// Plays the arpeggiator's steps as they fall due. Only needed on the real clock; offline
+/// runs step from `Engine::advance_to`.
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || loop {
+        thread::sleep(ARP_POLL);
+        if let Err(e) = shared.flush_arpeggio()
+            && !e.is_dropped_note()
+        {
+            eprintln!("Arpeggiator: {}", e);
+        }
+    });
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedFd, OwnedObjectPath, OwnedValue, Value};
+
+use crate::startup::find_port;
+use crate::worker::MidiQueue;
+use crate::Engine;
+
+// Bluetooth LE MIDI keyboards, straight through BlueZ over D-Bus: no bridge to set up. A scan
+// lists the devices offering the BLE-MIDI service; connecting pairs first if the device
+// isn't paired yet ("just works" pairing, no PIN), then takes the MIDI characteristic's
+// notifications as a socket and queues every message in them like a MIDI port's.
+//
+// BLE-MIDI packets carry timestamps and running status across messages; `parse_packet`
+// undoes that. The timestamps are dropped: messages play as they arrive, like any port's.
+
+const BLUEZ: &str = "org.bluez";
+const ADAPTER_IFACE: &str = "org.bluez.Adapter1";
+const DEVICE_IFACE: &str = "org.bluez.Device1";
+const CHARACTERISTIC_IFACE: &str = "org.bluez.GattCharacteristic1";
+const MIDI_SERVICE_UUID: &str = "03b80e5a-ede8-4b33-a751-6ce34ec4c700";
+const MIDI_CHARACTERISTIC_UUID: &str = "7772e5db-3868-4112-a1a9-f2669d106bf3";
+
+// How long a scan looks for devices
+pub const SCAN_TIME: Duration = Duration::from_secs(5);
+// How long a freshly connected device gets to list its services
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+// How often the reading thread looks up from the socket to see if it should stop
+const POLL: Duration = Duration::from_millis(200);
+
+/// A BLE-MIDI device BlueZ knows about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BleDevice {
+    pub path: String, // BlueZ object path
+    pub name: String,
+    pub paired: bool,
+    pub connected: bool,
+}
+
+type Objects = HashMap<OwnedObjectPath, HashMap<String, HashMap<String, OwnedValue>>>;
+
+fn managed_objects(connection: &Connection) -> zbus::Result<Objects> {
+    let manager = Proxy::new(connection, BLUEZ, "/", "org.freedesktop.DBus.ObjectManager")?;
+    manager.call("GetManagedObjects", &())
+}
+
+fn string_of(properties: &HashMap<String, OwnedValue>, name: &str) -> Option<String> {
+    properties.get(name).and_then(|v| v.downcast_ref::<&str>().ok()).map(str::to_string)
+}
+
+fn bool_of(properties: &HashMap<String, OwnedValue>, name: &str) -> bool {
+    properties.get(name).and_then(|v| v.downcast_ref::<bool>().ok()).unwrap_or(false)
+}
+
+fn has_uuid(properties: &HashMap<String, OwnedValue>, name: &str, uuid: &str) -> bool {
+    let uuids = properties.get(name).and_then(|v| v.try_clone().ok()).and_then(|v| Vec::<String>::try_from(v).ok());
+    uuids.is_some_and(|uuids| uuids.iter().any(|u| u.eq_ignore_ascii_case(uuid)))
+}
+
+fn midi_devices(objects: &Objects) -> Vec<BleDevice> {
+    let mut devices: Vec<BleDevice> = objects
+        .iter()
+        .filter_map(|(path, interfaces)| {
+            let device = interfaces.get(DEVICE_IFACE)?;
+            has_uuid(device, "UUIDs", MIDI_SERVICE_UUID).then(|| BleDevice {
+                path: path.to_string(),
+                name: string_of(device, "Alias").or_else(|| string_of(device, "Name")).unwrap_or_else(|| path.to_string()),
+                paired: bool_of(device, "Paired"),
+                connected: bool_of(device, "Connected"),
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    devices
+}
+
+/// Looks for BLE-MIDI devices for `duration` on the first Bluetooth adapter, then lists
+/// every one BlueZ knows (paired ones that are off included).
+pub fn scan(duration: Duration) -> zbus::Result<Vec<BleDevice>> {
+    let connection = Connection::system()?;
+    let adapter = managed_objects(&connection)?
+        .into_iter()
+        .find(|(_, interfaces)| interfaces.contains_key(ADAPTER_IFACE))
+        .map(|(path, _)| path)
+        .ok_or_else(|| zbus::Error::Failure("no Bluetooth adapter".to_string()))?;
+    let adapter = Proxy::new(&connection, BLUEZ, adapter, ADAPTER_IFACE)?;
+    let mut filter: HashMap<&str, Value> = HashMap::new();
+    filter.insert("Transport", Value::from("le"));
+    filter.insert("UUIDs", Value::from(vec![MIDI_SERVICE_UUID]));
+    adapter.call_method("SetDiscoveryFilter", &(filter,))?;
+    adapter.call_method("StartDiscovery", &())?;
+    thread::sleep(duration);
+    // Fails if something else stopped it already; the results are in either way
+    let _ = adapter.call_method("StopDiscovery", &());
+    Ok(midi_devices(&managed_objects(&connection)?))
+}
+
+/// An open BLE-MIDI device. Dropping it stops reading and disconnects.
+pub struct BleConnection {
+    pub name: String,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for BleConnection {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// Pairs and connects, waits for the services, and finds the MIDI characteristic
+fn open_characteristic(connection: &Connection, device: &BleDevice) -> zbus::Result<OwnedObjectPath> {
+    let proxy = Proxy::new(connection, BLUEZ, device.path.as_str(), DEVICE_IFACE)?;
+    if !proxy.get_property::<bool>("Paired")? {
+        proxy.call_method("Pair", &())?;
+    }
+    if !proxy.get_property::<bool>("Connected")? {
+        proxy.call_method("Connect", &())?;
+    }
+    let deadline = Instant::now() + RESOLVE_TIMEOUT;
+    while !proxy.get_property::<bool>("ServicesResolved")? {
+        if Instant::now() > deadline {
+            return Err(zbus::Error::Failure(format!("{} didn't list its services in time", device.name)));
+        }
+        thread::sleep(POLL);
+    }
+    let prefix = format!("{}/", device.path);
+    managed_objects(connection)?
+        .into_iter()
+        .find(|(path, interfaces)| {
+            path.as_str().starts_with(&prefix)
+                && interfaces.get(CHARACTERISTIC_IFACE).is_some_and(|c| string_of(c, "UUID").is_some_and(|u| u.eq_ignore_ascii_case(MIDI_CHARACTERISTIC_UUID)))
+        })
+        .map(|(path, _)| path)
+        .ok_or_else(|| zbus::Error::Failure(format!("{} has no MIDI characteristic", device.name)))
+}
+
+/// Connects to `device` and queues its MIDI on a background thread until the connection is
+/// dropped or the device goes away (which lets go of held keys, like an unplugged port).
+pub fn connect(device: &BleDevice, engine: Arc<Engine>, queue: MidiQueue) -> zbus::Result<BleConnection> {
+    let connection = Connection::system()?;
+    let characteristic = open_characteristic(&connection, device)?;
+    let characteristic = Proxy::new(&connection, BLUEZ, characteristic, CHARACTERISTIC_IFACE)?;
+    let (fd, _mtu): (OwnedFd, u16) = characteristic.call("AcquireNotify", &(HashMap::<&str, Value>::new(),))?;
+    let socket = UnixDatagram::from(std::os::fd::OwnedFd::from(fd));
+    socket.set_read_timeout(Some(POLL)).map_err(|e| zbus::Error::Failure(e.to_string()))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (name, path, stopped) = (device.name.clone(), device.path.clone(), stop.clone());
+    thread::spawn(move || {
+        let mut running = None;
+        let mut packet = [0; 512];
+        while !stopped.load(Ordering::Relaxed) {
+            match socket.recv(&mut packet) {
+                // The device closed the socket
+                Ok(0) => break,
+                Ok(size) => {
+                    for message in parse_packet(&packet[..size], &mut running) {
+                        queue.push(&message);
+                    }
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(_) => break,
+            }
+        }
+        if stopped.load(Ordering::Relaxed) {
+            let device = Proxy::new(&connection, BLUEZ, path.as_str(), DEVICE_IFACE);
+            let _ = device.and_then(|d| d.call_method("Disconnect", &()));
+        } else {
+            eprintln!("BLE-MIDI: lost {}", name);
+            // Its note-offs are never coming
+            let _ = engine.router.lock().unwrap().release_held();
+            engine.request_repaint();
+        }
+    });
+    Ok(BleConnection { name: device.name.clone(), stop })
+}
+
+/// What the frontends show of Bluetooth: the last scan's devices, the open connections, and
+/// news from the background (a scan done, a connection made or failed) not shown yet.
+#[derive(Default)]
+pub struct BleState {
+    pub devices: Vec<BleDevice>,
+    pub connections: Vec<BleConnection>,
+    pub busy: bool, // Scanning or connecting
+    pub news: Option<String>,
+}
+
+pub type SharedBle = Arc<Mutex<BleState>>;
+
+/// Scans on a background thread, into `state`.
+pub fn scan_in_background(state: &SharedBle, engine: Arc<Engine>) {
+    state.lock().unwrap().busy = true;
+    let state = state.clone();
+    thread::spawn(move || {
+        let result = scan(SCAN_TIME);
+        let mut state = state.lock().unwrap();
+        state.busy = false;
+        state.news = Some(match result {
+            Ok(devices) => {
+                state.devices = devices;
+                format!("Found {} Bluetooth MIDI device(s)", state.devices.len())
+            }
+            Err(e) => format!("Bluetooth scan failed: {}", e),
+        });
+        engine.request_repaint();
+    });
+}
+
+/// Connects on a background thread to the first device whose name contains `wanted` (any
+/// case), scanning first when the last scan didn't find one.
+pub fn connect_in_background(state: &SharedBle, wanted: String, engine: Arc<Engine>, queue: MidiQueue) {
+    state.lock().unwrap().busy = true;
+    let state = state.clone();
+    thread::spawn(move || {
+        let pick = |devices: &[BleDevice]| {
+            let names: Vec<&String> = devices.iter().map(|d| &d.name).collect();
+            find_port(Some(&wanted), names.iter().copied()).and_then(|name| devices.iter().find(|d| &d.name == name)).cloned()
+        };
+        let mut device = pick(&state.lock().unwrap().devices);
+        if device.is_none() {
+            match scan(SCAN_TIME) {
+                Ok(devices) => {
+                    device = pick(&devices);
+                    state.lock().unwrap().devices = devices;
+                }
+                Err(e) => {
+                    let mut state = state.lock().unwrap();
+                    (state.busy, state.news) = (false, Some(format!("Bluetooth scan failed: {}", e)));
+                    engine.request_repaint();
+                    return;
+                }
+            }
+        }
+        let result = match &device {
+            Some(device) => connect(device, engine.clone(), queue).map_err(|e| e.to_string()),
+            None => Err(format!("no Bluetooth MIDI device matching '{}'", wanted)),
+        };
+        let mut state = state.lock().unwrap();
+        state.busy = false;
+        state.news = Some(match result {
+            Ok(connection) => {
+                let news = format!("Connected to {} over Bluetooth", connection.name);
+                state.connections.push(connection);
+                news
+            }
+            Err(e) => format!("Bluetooth connect failed: {}", e),
+        });
+        engine.request_repaint();
+    });
+}
+
+// Data bytes after a status byte
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+        0x80..=0xBF | 0xE0..=0xEF | 0xF2 => 2,
+        _ => 0,
+    }
+}
+
+// `status` and its data bytes from `packet[i..]`, and where the next piece starts
+fn message_at(packet: &[u8], i: usize, status: u8) -> Option<(Vec<u8>, usize)> {
+    let data = packet.get(i..i + data_len(status))?;
+    if data.iter().any(|b| b & 0x80 != 0) {
+        return None;
+    }
+    Some(([&[status], data].concat(), i + data.len()))
+}
+
+/// The MIDI messages in one BLE-MIDI packet, timestamps stripped. `running` is the running
+/// status, carried from one packet to the next. System exclusive is skipped.
+pub fn parse_packet(packet: &[u8], running: &mut Option<u8>) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    // The header byte has its top bit set; anything else isn't BLE-MIDI
+    if packet.first().is_none_or(|header| header & 0x80 == 0) {
+        return messages;
+    }
+    let mut i = 1;
+    let mut in_sysex = false;
+    while i < packet.len() {
+        let mut status = None;
+        if packet[i] & 0x80 != 0 {
+            // A timestamp, then maybe a status byte
+            i += 1;
+            if let Some(&byte) = packet.get(i)
+                && byte & 0x80 != 0
+            {
+                status = Some(byte);
+                i += 1;
+            }
+        }
+        match status {
+            Some(0xF0) => in_sysex = true,
+            Some(0xF7) => in_sysex = false,
+            // Real-time messages come between anything, running status included
+            Some(byte @ 0xF8..) => messages.push(vec![byte]),
+            Some(byte) => {
+                in_sysex = false;
+                *running = (byte < 0xF0).then_some(byte);
+                let Some((message, next)) = message_at(packet, i, byte) else { return messages };
+                messages.push(message);
+                i = next;
+            }
+            None if in_sysex => {
+                while i < packet.len() && packet[i] & 0x80 == 0 {
+                    i += 1;
+                }
+            }
+            None => {
+                // Data bytes on their own: another message with the last status
+                let Some(byte) = *running else {
+                    i += 1;
+                    continue;
+                };
+                let Some((message, next)) = message_at(packet, i, byte) else { return messages };
+                messages.push(message);
+                i = next;
+            }
+        }
+    }
+    messages
+}
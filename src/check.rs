@@ -0,0 +1,402 @@
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, BlackKeys, ChannelRoute, GameMode, Hotkeys, ModifierRole, QuantizeMode, Settings, Trigger, VolumeSource, WindowRule, SCHEMA_VERSION};
+use crate::hotkeys::{self, HotkeyAction};
+use crate::mappings::{MappingSet, KEY_NAMES};
+use crate::midifile;
+use crate::pipeline;
+use crate::quantize;
+use crate::scheduler;
+
+// Validation behind --check-config: profiles, window rules and mapping files, with messages
+// that say which setting is wrong and what to do about it, not just where serde gave up.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,   // The file won't load, or won't do what it says
+    Warning, // Loads fine but something in it has no effect
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, message: message.into() }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, message: message.into() }
+    }
+}
+
+pub struct FileReport {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl FileReport {
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+}
+
+pub fn check_profile(json: &str) -> Vec<Problem> {
+    let value: Value = match serde_json::from_str(json) {
+        Ok(v) => v,
+        Err(e) => return vec![Problem::error(format!("not valid JSON: {}", e))],
+    };
+    let Some(object) = value.as_object() else {
+        return vec![Problem::error("expected an object of settings, like {\"base_mapping_enabled\": true}")];
+    };
+
+    // Field names and types come from the defaults, so this can't drift from Settings
+    let Ok(Value::Object(defaults)) = serde_json::to_value(Settings::default()) else {
+        unreachable!("Settings serializes to an object");
+    };
+    let mut problems = Vec::new();
+    for (key, value) in object {
+        match defaults.get(key) {
+            None => problems.push(Problem::warning(format!("unknown setting \"{}\" is ignored; check the spelling", key))),
+            Some(Value::Bool(_)) if !value.is_boolean() => {
+                problems.push(Problem::error(format!("\"{}\" must be true or false, found {}", key, value)))
+            }
+            Some(Value::Number(_)) if value.as_u64().is_none() => {
+                problems.push(Problem::error(format!("\"{}\" must be a whole number of 0 or more, found {}", key, value)))
+            }
+            _ => {}
+        }
+    }
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        return problems;
+    }
+
+    let settings: Settings = match serde_json::from_value(value) {
+        Ok(s) => s,
+        Err(e) => {
+            problems.push(Problem::error(e.to_string()));
+            return problems;
+        }
+    };
+    if settings.version > SCHEMA_VERSION {
+        problems.push(Problem::error(format!(
+            "written for profile version {}, this build reads up to {}; update miditoroblox",
+            settings.version, SCHEMA_VERSION
+        )));
+    }
+    if settings.quantize_enabled && settings.quantize_mode == QuantizeMode::Tempo && !(quantize::MIN_BPM..=quantize::MAX_BPM).contains(&settings.quantize_bpm) {
+        problems.push(Problem::warning(format!(
+            "quantize_bpm {} is outside {}-{} and plays as the nearest of those",
+            settings.quantize_bpm,
+            quantize::MIN_BPM,
+            quantize::MAX_BPM
+        )));
+    }
+    if !(quantize::MIN_SWING..=quantize::MAX_SWING).contains(&settings.quantize_swing) {
+        problems.push(Problem::warning(format!(
+            "quantize_swing {} is outside {}-{} and plays as the nearest of those",
+            settings.quantize_swing,
+            quantize::MIN_SWING,
+            quantize::MAX_SWING
+        )));
+    }
+    if settings.humanize_ms > scheduler::MAX_HUMANIZE_MS {
+        problems.push(Problem::warning(format!("humanize_ms {} is over {} and plays as {}", settings.humanize_ms, scheduler::MAX_HUMANIZE_MS, scheduler::MAX_HUMANIZE_MS)));
+    }
+    if settings.chord_roll_ms > scheduler::MAX_CHORD_ROLL_MS {
+        problems.push(Problem::warning(format!("chord_roll_ms {} is over {} and plays as {}", settings.chord_roll_ms, scheduler::MAX_CHORD_ROLL_MS, scheduler::MAX_CHORD_ROLL_MS)));
+    }
+    if let Some(key) = settings.snap_key
+        && key > 11
+    {
+        problems.push(Problem::warning(format!("snap_key {} is over 11 and plays as {} ({})", key, key % 12, midifile::PITCH_CLASSES[(key % 12) as usize])));
+    }
+    if settings.quantize_enabled && settings.quantize_mode == QuantizeMode::Milliseconds && settings.quantize_ms == 0 {
+        problems.push(Problem::error("quantize_enabled is on but quantize_ms is 0; set a grid like 100"));
+    }
+    if !settings.solver_enabled
+        && settings.game_mode == GameMode::Full88
+        && !settings.base_mapping_enabled
+        && !settings.low_mapping_enabled
+        && !settings.high_mapping_enabled
+    {
+        problems.push(Problem::error("no mapping range is enabled and the solver is off, so every note is dropped"));
+    }
+    if settings.lazy_transpose_enabled && !settings.experimental_transpose_enabled {
+        problems.push(Problem::warning("lazy_transpose_enabled does nothing without experimental_transpose_enabled"));
+    }
+    if settings.experimental_hold_ctrl_enabled && !settings.low_mapping_enabled && !settings.high_mapping_enabled {
+        problems.push(Problem::warning(
+            "experimental_hold_ctrl_enabled only affects the low and high ranges, and both are off",
+        ));
+    }
+    if settings.ctrl_role != ModifierRole::Range && (settings.low_mapping_enabled || settings.high_mapping_enabled) {
+        problems.push(Problem::warning(
+            "ctrl_role isn't \"range\", so the low and high ranges (which need Ctrl) are dropped unless the solver is on",
+        ));
+    }
+    if settings.shift_role != ModifierRole::Range
+        && !settings.solver_enabled
+        && !settings.experimental_transpose_enabled
+        && settings.black_keys == BlackKeys::Mapped
+    {
+        problems.push(Problem::warning(
+            "shift_role isn't \"range\", so black keys are dropped; turn on the solver, experimental_transpose_enabled or black_keys folding",
+        ));
+    }
+    for (name, key) in [("transpose_up_key", &settings.transpose_up_key), ("transpose_down_key", &settings.transpose_down_key)] {
+        if !KEY_NAMES.iter().any(|(known, _)| known == key) {
+            problems.push(Problem::error(format!("{} \"{}\" isn't a key name, so the arrow key is tapped instead; use one like \"KEY_UP\"", name, key)));
+        }
+    }
+    if settings.transpose_up_key == settings.transpose_down_key {
+        problems.push(Problem::error("transpose_up_key and transpose_down_key are the same key, so the game can't tell up from down"));
+    }
+    if settings.volume_source != VolumeSource::Off {
+        for (name, key) in [("volume_up_key", &settings.volume_up_key), ("volume_down_key", &settings.volume_down_key)] {
+            if !KEY_NAMES.iter().any(|(known, _)| known == key) {
+                problems.push(Problem::error(format!("{} \"{}\" isn't a key name; use one like \"KEY_RIGHT\"", name, key)));
+            }
+        }
+        if settings.volume_steps < 2 {
+            problems.push(Problem::error("volume_steps must be at least 2 for the volume keys to do anything"));
+        }
+    }
+    if settings.keep_alive_minutes > 0 && !KEY_NAMES.iter().any(|(known, _)| *known == settings.keep_alive_key) {
+        problems.push(Problem::error(format!(
+            "keep_alive_key \"{}\" isn't a key name; use one like \"KEY_PAGEDOWN\"",
+            settings.keep_alive_key
+        )));
+    }
+    if settings.sustain_key_enabled && !KEY_NAMES.iter().any(|(known, _)| *known == settings.sustain_key) {
+        problems.push(Problem::error(format!("sustain_key \"{}\" isn't a key name; use one like \"KEY_SPACE\"", settings.sustain_key)));
+    }
+    if settings.sustain_key_enabled
+        && let Some(mapping) = MappingSet::builtin().iter().find(|m| KEY_NAMES.iter().any(|(n, k)| *n == settings.sustain_key && *k == m.key_code))
+    {
+        problems.push(Problem::warning(format!(
+            "sustain_key \"{}\" also plays note {} in the built-in mappings, so the pedal will hold that note's key",
+            settings.sustain_key, mapping.midi_note
+        )));
+    }
+    let colors = [
+        ("accent_color", &settings.accent_color),
+        ("input_key_color", &settings.input_key_color),
+        ("output_key_color", &settings.output_key_color),
+        ("white_key_color", &settings.white_key_color),
+        ("black_key_color", &settings.black_key_color),
+    ];
+    for (name, color) in colors {
+        if config::parse_color(color).is_none() {
+            problems.push(Problem::error(format!("{} \"{}\" isn't a color; use one like \"#00ff00\"", name, color)));
+        }
+    }
+    if !settings.mappings_file.is_empty()
+        && let Err(e) = MappingSet::from_file(std::path::Path::new(&settings.mappings_file))
+    {
+        problems.push(Problem::error(format!("mappings_file can't be used, so the default mappings play instead: {}", e)));
+    }
+    #[cfg(feature = "scripting")]
+    if !settings.script_file.is_empty()
+        && let Err(e) = crate::script::Script::load(std::path::Path::new(&settings.script_file))
+    {
+        problems.push(Problem::error(format!("script_file can't be used, so messages play unscripted: {}", e)));
+    }
+    #[cfg(not(feature = "scripting"))]
+    if !settings.script_file.is_empty() {
+        problems.push(Problem::warning("script_file is set, but this build has no scripting (the `scripting` feature)"));
+    }
+    if settings.keep_alive_minutes >= 20 {
+        problems.push(Problem::warning("keep_alive_minutes is 20 or more; Roblox disconnects idle players after 20 minutes"));
+    }
+    // Controls the engine checks in this order; a CC only reaches the first one bound to it
+    let mut bound_ccs: Vec<(u8, &str)> = Vec::new();
+    if let Trigger::Cc(cc) = settings.panic_trigger {
+        bound_ccs.push((cc, "panic_trigger"));
+    }
+    if let Trigger::Cc(cc) = settings.sostenuto_trigger {
+        bound_ccs.push((cc, "sostenuto_trigger"));
+    }
+    for (name, trigger) in [
+        ("transpose_up_trigger", settings.transpose_up_trigger),
+        ("transpose_down_trigger", settings.transpose_down_trigger),
+        ("transpose_octave_up_trigger", settings.transpose_octave_up_trigger),
+        ("transpose_octave_down_trigger", settings.transpose_octave_down_trigger),
+    ] {
+        if let Trigger::Cc(cc) = trigger {
+            bound_ccs.push((cc, name));
+        }
+    }
+    if let VolumeSource::Cc(cc) = settings.volume_source {
+        bound_ccs.push((cc, "volume_source"));
+    }
+    if let Some(cc) = settings.transpose_cc {
+        bound_ccs.push((cc, "transpose_cc"));
+    }
+    if settings.ctrl_role == ModifierRole::Sustain || settings.shift_role == ModifierRole::Sustain {
+        bound_ccs.push((64, "the sustain role"));
+    }
+    for (i, (cc, name)) in bound_ccs.iter().enumerate() {
+        if let Some((_, first)) = bound_ccs[..i].iter().find(|(other, _)| other == cc) {
+            problems.push(Problem::warning(format!("CC {} is used by both {} and {}; only {} gets it", cc, first, name, first)));
+        }
+    }
+    for (name, trigger) in [
+        ("panic_trigger", settings.panic_trigger),
+        ("sostenuto_trigger", settings.sostenuto_trigger),
+        ("transpose_up_trigger", settings.transpose_up_trigger),
+        ("transpose_down_trigger", settings.transpose_down_trigger),
+        ("transpose_octave_up_trigger", settings.transpose_octave_up_trigger),
+        ("transpose_octave_down_trigger", settings.transpose_octave_down_trigger),
+    ] {
+        if let Trigger::Note(note) = trigger
+            && settings.game_mode.key_range().contains(&note)
+        {
+            problems.push(Problem::warning(format!(
+                "{} note {} is inside the game's range and won't be played as a note",
+                name,
+                midifile::note_name(note)
+            )));
+        }
+    }
+    for (channel, dynamics) in settings.channel_dynamics.iter().enumerate() {
+        if dynamics.compression > 100 {
+            problems.push(Problem::warning(format!(
+                "channel_dynamics for channel {} has compression {}; anything above 100 acts as 100",
+                channel + 1,
+                dynamics.compression
+            )));
+        }
+        if dynamics.gain == 0 {
+            problems.push(Problem::warning(format!(
+                "channel_dynamics for channel {} has gain 0, so every note on it plays at the lowest velocity",
+                channel + 1
+            )));
+        }
+    }
+    for (channel, &offset) in settings.channel_transpose.iter().enumerate() {
+        if offset.abs() > pipeline::MAX_CHANNEL_TRANSPOSE {
+            problems.push(Problem::warning(format!(
+                "channel_transpose for channel {} is {}; anything past +/-{} acts as {}",
+                channel + 1,
+                offset,
+                pipeline::MAX_CHANNEL_TRANSPOSE,
+                offset.clamp(-pipeline::MAX_CHANNEL_TRANSPOSE, pipeline::MAX_CHANNEL_TRANSPOSE)
+            )));
+        }
+    }
+    if settings.dual_drums_enabled && settings.game_mode == GameMode::Drums {
+        problems.push(Problem::warning("dual_drums_enabled does nothing in drums mode, where every channel is drums already"));
+    }
+    if !settings.channel_routes.iter().any(|r| matches!(r, ChannelRoute::Play | ChannelRoute::Drums)) {
+        problems.push(Problem::warning("channel_routes plays no channel; every note is dropped"));
+    } else if settings.dual_drums_enabled
+        && settings.game_mode != GameMode::Drums
+        && !settings.channel_routes.contains(&ChannelRoute::Drums)
+    {
+        problems.push(Problem::warning("dual_drums_enabled does nothing with no channel set to drums in channel_routes"));
+    }
+    problems
+}
+
+/// `profiles` are the names of the saved profiles the rules may refer to.
+pub fn check_window_rules(json: &str, profiles: &[String]) -> Vec<Problem> {
+    let rules: Vec<WindowRule> = match serde_json::from_str(json) {
+        Ok(r) => r,
+        Err(e) => return vec![Problem::error(format!("expected a list of {{\"title_contains\", \"profile\"}} rules: {}", e))],
+    };
+    let mut problems = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.title_contains.trim().is_empty() {
+            problems.push(Problem::warning(format!("rule {} has no title text and never matches", i + 1)));
+        }
+        if !profiles.contains(&rule.profile) {
+            problems.push(Problem::error(format!(
+                "rule {} switches to profile \"{}\", which doesn't exist; save it first or fix the name",
+                i + 1,
+                rule.profile
+            )));
+        }
+    }
+    problems
+}
+
+pub fn check_hotkeys(json: &str) -> Vec<Problem> {
+    let hotkeys: Hotkeys = match serde_json::from_str(json) {
+        Ok(h) => h,
+        Err(e) => return vec![Problem::error(format!("expected {{\"octave_up\": \"KEY_KPPLUS\", ...}}: {}", e))],
+    };
+    HotkeyAction::ALL
+        .into_iter()
+        .map(|action| (action.name(), action.key(&hotkeys)))
+        .filter(|(_, key)| !key.is_empty() && !hotkeys::is_known_key(key))
+        .map(|(name, key)| Problem::error(format!("{} \"{}\" isn't an evdev key name; use one like \"KEY_F8\"", name, key)))
+        .collect()
+}
+
+pub fn check_mappings(json: &str) -> Vec<Problem> {
+    let set = match MappingSet::from_json(json) {
+        Ok(s) => s,
+        Err(e) => return vec![Problem::error(e.to_string())],
+    };
+    set.validate()
+        .into_iter()
+        .map(|issue| if issue.is_error() { Problem::error(issue.describe()) } else { Problem::warning(issue.describe()) })
+        .collect()
+}
+
+/// Checks one file, telling profiles, window rules, hotkeys and mappings apart by name and shape.
+pub fn check_file(path: &Path) -> FileReport {
+    let problems = match fs::read_to_string(path) {
+        Err(e) => vec![Problem::error(format!("can't read: {}", e))],
+        Ok(json) if path.file_name().is_some_and(|n| n == "window_rules.json") => {
+            check_window_rules(&json, &config::list_profiles())
+        }
+        Ok(json) if path.file_name().is_some_and(|n| n == "hotkeys.json") => check_hotkeys(&json),
+        // Mapping files are lists, profiles are objects
+        Ok(json) if json.trim_start().starts_with('[') => check_mappings(&json),
+        Ok(json) => check_profile(&json),
+    };
+    FileReport { path: path.to_path_buf(), problems }
+}
+
+/// Everything in the config dir: each saved profile, the window rules and hotkeys if present.
+pub fn check_config_dir() -> Vec<FileReport> {
+    let dir = config::config_dir();
+    let mut paths: Vec<PathBuf> = config::list_profiles()
+        .iter()
+        .map(|name| dir.join("profiles").join(format!("{}.json", name)))
+        .collect();
+    for file in ["window_rules.json", "hotkeys.json"] {
+        if dir.join(file).exists() {
+            paths.push(dir.join(file));
+        }
+    }
+    paths.iter().map(|p| check_file(p)).collect()
+}
+
+pub fn format_reports(reports: &[FileReport]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        if report.problems.is_empty() {
+            out.push_str(&format!("ok       {}\n", report.path.display()));
+            continue;
+        }
+        for problem in &report.problems {
+            let label = match problem.severity {
+                Severity::Error => "error  ",
+                Severity::Warning => "warning",
+            };
+            out.push_str(&format!("{}  {}: {}\n", label, report.path.display(), problem.message));
+        }
+    }
+    if reports.is_empty() {
+        out.push_str("No config files found\n");
+    }
+    out
+}
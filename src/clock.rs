@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Time source for the engine. The real clock sleeps; a virtual clock just moves forward, so
+// offline runs are instant and come out the same every time.
+
+#[derive(Clone, Default)]
+pub enum Clock {
+    #[default]
+    Real,
+    Virtual(Arc<AtomicU64>), // Microseconds
+}
+
+impl Clock {
+    pub fn new_virtual() -> Self {
+        Clock::Virtual(Arc::new(AtomicU64::new(0)))
+    }
+
+    /// Microseconds: since the Unix epoch for the real clock, since start for a virtual one.
+    pub fn now_us(&self) -> u64 {
+        match self {
+            Clock::Real => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0),
+            Clock::Virtual(us) => us.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        match self {
+            Clock::Real => thread::sleep(duration),
+            Clock::Virtual(us) => {
+                us.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Moves a virtual clock forward to `at_us` (never backwards). No-op for the real clock.
+    pub fn advance_to(&self, at_us: u64) {
+        if let Clock::Virtual(us) = self {
+            us.fetch_max(at_us, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,714 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+// Version of the profile format (schema/profile.schema.json). Bump when a setting changes
+// meaning or type; files without a version are from before versioning and read as 1.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Which in-game instrument we're playing to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    #[default]
+    Full88, // 88-key piano, ranges as configured
+    Keys61, // 61-key piano: C2-C7 only, notes outside folded in by octaves
+    Drums,  // Drum kit: every note is a pad from the drum map, on any channel
+    Guitar, // Guitar/bass: notes hold fret keys, a strum key sounds them
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [GameMode::Full88, GameMode::Keys61, GameMode::Drums, GameMode::Guitar];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameMode::Full88 => "88-Key Piano",
+            GameMode::Keys61 => "61-Key Piano",
+            GameMode::Drums => "Drums",
+            GameMode::Guitar => "Guitar / Bass",
+        }
+    }
+
+    /// MIDI notes the game's keyboard has.
+    pub fn key_range(&self) -> RangeInclusive<u8> {
+        match self {
+            GameMode::Full88 => 21..=108,
+            GameMode::Keys61 => 36..=96,
+            GameMode::Drums => 35..=81, // General MIDI percussion
+            GameMode::Guitar => 28..=88, // Low E on a bass to the 24th fret on a guitar
+        }
+    }
+
+    /// The notes the game's keyboard sounds with the game transposed by `transpose`
+    /// semitones, or None if that's past the ends of MIDI.
+    pub fn reachable_range(&self, transpose: i32) -> Option<RangeInclusive<u8>> {
+        let keys = self.key_range();
+        let (low, high) = (*keys.start() as i32 + transpose, *keys.end() as i32 + transpose);
+        (high >= 0 && low <= 127).then(|| low.max(0) as u8..=high.min(127) as u8)
+    }
+}
+
+// What the game does with Ctrl or Shift.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModifierRole {
+    #[default]
+    Range,   // Picks other notes with the same letter (Shift = black keys, Ctrl = outer octaves)
+    Sustain, // Toggles the game's sustain; driven by the sustain pedal (CC 64)
+    Accent,  // Held with loud notes to play them accented
+}
+
+impl ModifierRole {
+    pub const ALL: [ModifierRole; 3] = [ModifierRole::Range, ModifierRole::Sustain, ModifierRole::Accent];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ModifierRole::Range => "Range",
+            ModifierRole::Sustain => "Sustain Toggle",
+            ModifierRole::Accent => "Velocity Accent",
+        }
+    }
+}
+
+// How black keys are played, for games whose piano has them or doesn't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlackKeys {
+    #[default]
+    Mapped,   // Through their own mappings (Shift, or the transpose tricks)
+    FoldDown, // As the white key a semitone below
+    FoldUp,   // As the white key a semitone above
+}
+
+impl BlackKeys {
+    pub const ALL: [BlackKeys; 3] = [BlackKeys::Mapped, BlackKeys::FoldDown, BlackKeys::FoldUp];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BlackKeys::Mapped => "Mapped",
+            BlackKeys::FoldDown => "Nearest White Below",
+            BlackKeys::FoldUp => "Nearest White Above",
+        }
+    }
+}
+
+// What the in-game volume keys follow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeSource {
+    #[default]
+    Off,
+    Velocity, // Running average of note-on velocities
+    Cc(u8),   // A controller, e.g. 7 (volume) or 11 (expression)
+}
+
+// Where typed notes come from (typing.rs), if anywhere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypingSource {
+    #[default]
+    Off,
+    Window,   // Keys pressed while the Miditoroblox window has focus
+    Keyboard, // Keys on the hotkeys' keyboard, read through evdev with any window focused
+}
+
+impl TypingSource {
+    pub const ALL: [TypingSource; 3] = [TypingSource::Off, TypingSource::Window, TypingSource::Keyboard];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TypingSource::Off => "Off",
+            TypingSource::Window => "This Window",
+            TypingSource::Keyboard => "Hotkey Keyboard",
+        }
+    }
+}
+
+// How the quantizer's grid is laid out (quantize.rs).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantizeMode {
+    #[default]
+    Milliseconds, // Every `quantize_ms` of the clock
+    Tempo,        // `quantize_subdivision` lines per beat at `quantize_bpm`
+    MidiClock,    // `quantize_subdivision` lines per beat of an external sequencer's clock
+}
+
+impl QuantizeMode {
+    pub const ALL: [QuantizeMode; 3] = [QuantizeMode::Milliseconds, QuantizeMode::Tempo, QuantizeMode::MidiClock];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            QuantizeMode::Milliseconds => "Milliseconds",
+            QuantizeMode::Tempo => "Tempo",
+            QuantizeMode::MidiClock => "MIDI Clock",
+        }
+    }
+}
+
+// Which notes the polyphony limiter keeps when there are too many (polyphony.rs).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotePriority {
+    #[default]
+    MelodyOnTop, // The highest note, then the newest of the rest
+    Highest,     // The highest notes; a lower one past the limit is dropped
+    Lowest,      // The lowest notes
+    MostRecent,  // The newest notes; the oldest held one is let go
+}
+
+impl NotePriority {
+    pub const ALL: [NotePriority; 4] = [NotePriority::MelodyOnTop, NotePriority::Highest, NotePriority::Lowest, NotePriority::MostRecent];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NotePriority::MelodyOnTop => "Melody on Top",
+            NotePriority::Highest => "Highest Notes",
+            NotePriority::Lowest => "Lowest Notes",
+            NotePriority::MostRecent => "Most Recent",
+        }
+    }
+}
+
+// The order the arpeggiator plays a held chord in (arpeggiator.rs).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArpPattern {
+    #[default]
+    Up,     // Lowest to highest, over and over
+    Down,   // Highest to lowest
+    UpDown, // Up, then back down
+    Random, // Any held note each step
+}
+
+impl ArpPattern {
+    pub const ALL: [ArpPattern; 4] = [ArpPattern::Up, ArpPattern::Down, ArpPattern::UpDown, ArpPattern::Random];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ArpPattern::Up => "Up",
+            ArpPattern::Down => "Down",
+            ArpPattern::UpDown => "Up-Down",
+            ArpPattern::Random => "Random",
+        }
+    }
+}
+
+// Which way a chord roll goes (scheduler.rs).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RollDirection {
+    #[default]
+    Up,   // Lowest note first
+    Down, // Highest note first
+}
+
+impl RollDirection {
+    pub const ALL: [RollDirection; 2] = [RollDirection::Up, RollDirection::Down];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RollDirection::Up => "Up",
+            RollDirection::Down => "Down",
+        }
+    }
+}
+
+// Which scale incoming notes are snapped to (pipeline::snap_to_scale).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleMode {
+    #[default]
+    Major,
+    Minor, // Natural minor
+    MajorPentatonic,
+    MinorPentatonic,
+}
+
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 4] = [ScaleMode::Major, ScaleMode::Minor, ScaleMode::MajorPentatonic, ScaleMode::MinorPentatonic];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScaleMode::Major => "Major",
+            ScaleMode::Minor => "Minor",
+            ScaleMode::MajorPentatonic => "Major Pentatonic",
+            ScaleMode::MinorPentatonic => "Minor Pentatonic",
+        }
+    }
+
+    /// The scale's tones as semitones above the tonic.
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ScaleMode::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleMode::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleMode::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleMode::MinorPentatonic => &[0, 3, 5, 7, 10],
+        }
+    }
+}
+
+// What's behind the keys in the stream overlay window, for the capture to key out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayBackground {
+    #[default]
+    Magenta,     // Solid, for a chroma key filter
+    Transparent, // Nothing, for captures that keep the window's alpha
+}
+
+impl OverlayBackground {
+    pub const ALL: [OverlayBackground; 2] = [OverlayBackground::Magenta, OverlayBackground::Transparent];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OverlayBackground::Magenta => "Magenta",
+            OverlayBackground::Transparent => "Transparent",
+        }
+    }
+}
+
+// What happens to the notes on one MIDI channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelRoute {
+    #[default]
+    Play,
+    Drums,     // The drum kit in drums mode or with dual drums, dropped otherwise
+    Visualize, // Shown on the keyboard, never played
+    Ignore,    // Dropped before anything sees it, controls included
+}
+
+impl ChannelRoute {
+    pub const ALL: [ChannelRoute; 4] = [ChannelRoute::Play, ChannelRoute::Drums, ChannelRoute::Visualize, ChannelRoute::Ignore];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChannelRoute::Play => "Play",
+            ChannelRoute::Drums => "Drums",
+            ChannelRoute::Visualize => "Visualize Only",
+            ChannelRoute::Ignore => "Ignore",
+        }
+    }
+
+    /// The General MIDI layout: channel 10 is drums, everything else plays.
+    pub fn defaults() -> [ChannelRoute; 16] {
+        let mut routes = [ChannelRoute::Play; 16];
+        routes[9] = ChannelRoute::Drums;
+        routes
+    }
+}
+
+// A MIDI control assigned to a feature: a CC (pressed at 64 and up, like a pedal) or a note
+// (pressed while held), on any channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    #[default]
+    Off,
+    Cc(u8),
+    Note(u8),
+}
+
+impl Trigger {
+    /// Whether `message` presses (`Some(true)`) or releases (`Some(false)`) this trigger.
+    pub fn matches(&self, message: &[u8]) -> Option<bool> {
+        let &[status, number, value, ..] = message else { return None };
+        match (*self, status & 0xF0) {
+            (Trigger::Cc(cc), 0xB0) if number == cc => Some(value >= 64),
+            (Trigger::Note(note), 0x90) if number == note => Some(value > 0),
+            (Trigger::Note(note), 0x80) if number == note => Some(false),
+            _ => None,
+        }
+    }
+}
+
+// Velocity shaping for one MIDI channel, so parts recorded at different levels can be
+// balanced before anything that depends on velocity (accents, volume following) sees them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelDynamics {
+    pub gain: u64,        // Percent; 100 leaves velocities alone
+    pub compression: u64, // Percent pulled towards the middle (64); 100 plays everything at one level
+}
+
+impl Default for ChannelDynamics {
+    fn default() -> Self {
+        Self { gain: 100, compression: 0 }
+    }
+}
+
+impl ChannelDynamics {
+    /// Compresses, then applies the gain. A note-on stays a note-on.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let compression = self.compression.min(100) as i64;
+        let compressed = 64 + (velocity as i64 - 64) * (100 - compression) / 100;
+        (compressed * self.gain as i64 / 100).clamp(1, 127) as u8
+    }
+}
+
+// Snapshot of the user-facing playback settings. Profiles are just named copies of this.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub game_mode: GameMode,
+    pub base_mapping_enabled: bool,
+    pub low_mapping_enabled: bool,
+    pub high_mapping_enabled: bool,
+    pub auto_transpose_enabled: bool,
+    pub fold_low_notes: bool,  // Notes below the enabled ranges wrap up into them by octaves
+    pub fold_high_notes: bool, // And notes above them wrap down
+    pub snap_key: Option<u8>,  // Tonic pitch class (C = 0) of the scale notes snap to; None is off
+    pub snap_scale: ScaleMode,
+    pub experimental_transpose_enabled: bool,
+    pub experimental_hold_ctrl_enabled: bool,
+    pub transpose_delay_ms: u64,
+    pub lazy_transpose_enabled: bool,
+    pub quantize_enabled: bool,
+    pub quantize_ms: u64,
+    pub quantize_mode: QuantizeMode,
+    pub quantize_bpm: u64,
+    pub quantize_subdivision: u64, // Grid lines per beat: 2 = 8ths, 4 = 16ths, 3 = triplets
+    pub quantize_swing: u64,       // Percent; 50 is straight
+    pub humanize_ms: u64,          // Most random delay per note; 0 is off
+    pub chord_roll_ms: u64,        // A chord's first note to its last; 0 is off
+    pub chord_roll_direction: RollDirection,
+    pub arpeggiator_enabled: bool, // Held chords step on the tempo grid of `quantize_bpm`
+    pub arpeggiator_pattern: ArpPattern,
+    pub max_polyphony: u64, // Most notes held at once; 0 is no limit
+    pub polyphony_priority: NotePriority,
+    pub max_chord_notes: u64, // Files' chords with more notes get simplified; 0 is off
+    pub solver_enabled: bool,
+    pub solver_mode_efficiency: bool,
+    pub solver_max_jump: u64,
+    pub solver_hold_transpose: bool, // No transposing while keys are held
+    pub solver_drop_steals: bool, // Drop a note rather than cut off a held one for it
+    pub solver_plan_files: bool, // File playback follows a transpose plan for the whole song
+    pub transpose_range: u64,
+    pub strum_window_ms: u64, // Guitar mode: notes this close together are one chord
+    pub dual_drums_enabled: bool, // Piano/guitar modes: the drum channels play the drum kit alongside
+    pub sostenuto_trigger: Trigger, // Held keys stay down while this is pressed
+    pub panic_trigger: Trigger,     // Releases every key and resets the transpose when pressed
+    pub transpose_up_trigger: Trigger, // Each press taps the game's transpose a semitone up
+    pub transpose_down_trigger: Trigger,
+    pub transpose_octave_up_trigger: Trigger, // And these an octave
+    pub transpose_octave_down_trigger: Trigger,
+    pub ctrl_role: ModifierRole,
+    pub shift_role: ModifierRole,
+    pub black_keys: BlackKeys,
+    pub volume_source: VolumeSource,
+    pub volume_steps: u64, // Volume levels the game has
+    pub volume_up_key: String,
+    pub volume_down_key: String,
+    pub transpose_up_key: String, // The game's transpose keys, tapped by the solver and the transpose controls
+    pub transpose_down_key: String,
+    pub transpose_cc: Option<u8>, // Knob/fader driving the game's transpose
+    pub transpose_cc_range: u64,  // Semitones either way at the ends of its travel
+    pub pitch_bend_range: u64,    // Semitones of transpose at full pitch bend; 0 = bends ignored
+    pub min_velocity: u64, // Note-ons softer than this are dropped as ghost notes; 0 = off
+    pub channel_dynamics: [ChannelDynamics; 16],
+    pub channel_transpose: [i32; 16], // Semitones added to each channel's notes
+    pub channel_routes: [ChannelRoute; 16],
+    pub keep_alive_minutes: u64, // Idle time before the keep-alive tap; 0 = off
+    pub keep_alive_key: String,
+    pub sustain_key_enabled: bool, // The sustain pedal holds `sustain_key` down while pressed
+    pub sustain_key: String,
+    pub sustain_hold_notes: bool,  // The sustain pedal holds note keys down through their note-offs
+    pub mappings_file: String, // Mapping file to play with; empty for the saved or built-in one
+    pub script_file: String,   // Rhai script that sees each MIDI message first; empty for none
+    pub typing_source: TypingSource,
+    pub typing_base_note: u64, // Note the Z key plays; Q plays an octave up
+    pub typing_velocity: u64,
+    pub overlay_background: OverlayBackground,
+    pub light_mode: bool,
+    pub accent_color: String,     // "#rrggbb", as are the colors below
+    pub input_key_color: String,  // Visualizer keys held on the MIDI input
+    pub output_key_color: String, // Visualizer keys the game is playing
+    pub white_key_color: String,
+    pub black_key_color: String,
+    pub ui_scale_percent: u64, // Zoom of the whole window; 100 = egui's own size
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            game_mode: GameMode::Full88,
+            base_mapping_enabled: false,
+            low_mapping_enabled: false,
+            high_mapping_enabled: false,
+            auto_transpose_enabled: false,
+            fold_low_notes: false,
+            fold_high_notes: false,
+            snap_key: None,
+            snap_scale: ScaleMode::Major,
+            experimental_transpose_enabled: false,
+            experimental_hold_ctrl_enabled: false,
+            transpose_delay_ms: 0,
+            lazy_transpose_enabled: false,
+            quantize_enabled: false,
+            quantize_ms: 100,
+            quantize_mode: QuantizeMode::Milliseconds,
+            quantize_bpm: 120,
+            quantize_subdivision: 4,
+            quantize_swing: 50,
+            humanize_ms: 0,
+            chord_roll_ms: 0,
+            chord_roll_direction: RollDirection::Up,
+            arpeggiator_enabled: false,
+            arpeggiator_pattern: ArpPattern::Up,
+            max_polyphony: 0,
+            polyphony_priority: NotePriority::MelodyOnTop,
+            max_chord_notes: 0,
+            solver_enabled: false,
+            solver_mode_efficiency: true,
+            solver_max_jump: 12,
+            solver_hold_transpose: false,
+            solver_drop_steals: false,
+            solver_plan_files: true,
+            transpose_range: 24,
+            strum_window_ms: 30,
+            dual_drums_enabled: false,
+            sostenuto_trigger: Trigger::Off,
+            panic_trigger: Trigger::Off,
+            transpose_up_trigger: Trigger::Off,
+            transpose_down_trigger: Trigger::Off,
+            transpose_octave_up_trigger: Trigger::Off,
+            transpose_octave_down_trigger: Trigger::Off,
+            ctrl_role: ModifierRole::Range,
+            shift_role: ModifierRole::Range,
+            black_keys: BlackKeys::Mapped,
+            volume_source: VolumeSource::Off,
+            volume_steps: 10,
+            volume_up_key: "KEY_RIGHT".to_string(),
+            volume_down_key: "KEY_LEFT".to_string(),
+            transpose_up_key: "KEY_UP".to_string(),
+            transpose_down_key: "KEY_DOWN".to_string(),
+            transpose_cc: None,
+            transpose_cc_range: 12,
+            pitch_bend_range: 0,
+            min_velocity: 0,
+            channel_dynamics: [ChannelDynamics::default(); 16],
+            channel_transpose: [0; 16],
+            channel_routes: ChannelRoute::defaults(),
+            keep_alive_minutes: 0,
+            keep_alive_key: "KEY_PAGEDOWN".to_string(),
+            sustain_key_enabled: false,
+            sustain_key: "KEY_SPACE".to_string(),
+            sustain_hold_notes: false,
+            mappings_file: String::new(),
+            script_file: String::new(),
+            typing_source: TypingSource::Off,
+            typing_base_note: 48,
+            typing_velocity: 100,
+            overlay_background: OverlayBackground::Magenta,
+            light_mode: false,
+            accent_color: "#00b4ff".to_string(),
+            input_key_color: "#00ff00".to_string(),
+            output_key_color: "#0064ff".to_string(),
+            white_key_color: "#ffffff".to_string(),
+            black_key_color: "#000000".to_string(),
+            ui_scale_percent: 100,
+        }
+    }
+}
+
+/// Zoom range the window allows, in percent.
+pub const MIN_UI_SCALE: u64 = 50;
+pub const MAX_UI_SCALE: u64 = 300;
+
+/// "#rrggbb" as red, green and blue, if it's that.
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii())?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn format_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+// "If the focused window title contains `title_contains`, switch to `profile`"
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WindowRule {
+    pub title_contains: String,
+    pub profile: String,
+}
+
+pub fn config_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("miditoroblox")
+}
+
+fn profiles_dir() -> PathBuf {
+    config_dir().join("profiles")
+}
+
+// `<name>.json` in `dir`, with the name kept from escaping the directory
+pub(crate) fn named_file(dir: &Path, name: &str) -> PathBuf {
+    let file: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' { c } else { '_' })
+        .collect();
+    dir.join(format!("{}.json", file))
+}
+
+// Names of the JSON files in `dir`, sorted
+pub(crate) fn named_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+fn profile_path(name: &str) -> PathBuf {
+    named_file(&profiles_dir(), name)
+}
+
+pub fn save_profile(name: &str, settings: &Settings) -> Result<()> {
+    fs::create_dir_all(profiles_dir())?;
+    let json = serde_json::to_string_pretty(settings)?;
+    Ok(fs::write(profile_path(name), json)?)
+}
+
+pub fn load_profile(name: &str) -> Result<Settings> {
+    let data = fs::read_to_string(profile_path(name))?;
+    let settings: Settings = serde_json::from_str(&data)?;
+    // Settings from a newer format may mean something else here; don't guess
+    if settings.version > SCHEMA_VERSION {
+        return Err(Error::Parse(format!(
+            "profile '{}' is version {}, this build reads up to {}",
+            name, settings.version, SCHEMA_VERSION
+        )));
+    }
+    Ok(settings)
+}
+
+pub fn delete_profile(name: &str) -> Result<()> {
+    Ok(fs::remove_file(profile_path(name))?)
+}
+
+/// Names of all saved profiles, sorted.
+pub fn list_profiles() -> Vec<String> {
+    named_files(&profiles_dir())
+}
+
+pub fn load_window_rules() -> Vec<WindowRule> {
+    fs::read_to_string(config_dir().join("window_rules.json"))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_window_rules(rules: &[WindowRule]) -> Result<()> {
+    fs::create_dir_all(config_dir())?;
+    let json = serde_json::to_string_pretty(rules)?;
+    Ok(fs::write(config_dir().join("window_rules.json"), json)?)
+}
+
+// Where the main window was and how it looked when the app last closed, so it opens the
+// same way. Positions and sizes are in logical pixels, at 100% zoom; no position means the
+// platform didn't say (Wayland) and the window manager places it. Per machine rather than
+// per profile; stored as window_state.json in the config dir.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowState {
+    pub width: f32,
+    pub height: f32,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub opacity: f32,
+    pub always_on_top: bool,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        Self { width: 1000.0, height: 600.0, x: None, y: None, opacity: 1.0, always_on_top: false }
+    }
+}
+
+pub fn load_window_state() -> WindowState {
+    fs::read_to_string(config_dir().join("window_state.json"))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_window_state(state: &WindowState) -> Result<()> {
+    fs::create_dir_all(config_dir())?;
+    let json = serde_json::to_string_pretty(state)?;
+    Ok(fs::write(config_dir().join("window_state.json"), json)?)
+}
+
+// Physical keys that control the program mid-song, by evdev key name ("KEY_KPPLUS"); empty
+// binds nothing. Global rather than per profile; stored as hotkeys.json in the config dir.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hotkeys {
+    pub octave_up: String,
+    pub octave_down: String,
+    pub transpose_up: String,
+    pub transpose_down: String,
+    pub transpose_octave_up: String,
+    pub transpose_octave_down: String,
+    pub toggle_solver: String,
+    pub pause_playback: String,
+    pub panic: String,
+    pub keyboard: String, // Part of the name of the only keyboard to read them from; empty for all
+    pub grab: bool,       // Keep the keyboard's hotkeys from the game (hotkeys.rs)
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self {
+            octave_up: "KEY_KPPLUS".to_string(),
+            octave_down: "KEY_KPMINUS".to_string(),
+            transpose_up: String::new(),
+            transpose_down: String::new(),
+            transpose_octave_up: String::new(),
+            transpose_octave_down: String::new(),
+            toggle_solver: String::new(),
+            pause_playback: String::new(),
+            panic: "KEY_PAUSE".to_string(),
+            keyboard: String::new(),
+            grab: false,
+        }
+    }
+}
+
+pub fn load_hotkeys() -> Hotkeys {
+    fs::read_to_string(config_dir().join("hotkeys.json"))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_hotkeys(hotkeys: &Hotkeys) -> Result<()> {
+    fs::create_dir_all(config_dir())?;
+    let json = serde_json::to_string_pretty(hotkeys)?;
+    Ok(fs::write(config_dir().join("hotkeys.json"), json)?)
+}
+
+/// First rule whose pattern appears in the title (case-insensitive).
+pub fn match_window_rule<'a>(rules: &'a [WindowRule], title: &str) -> Option<&'a WindowRule> {
+    let title = title.to_lowercase();
+    rules
+        .iter()
+        .find(|r| !r.title_contains.is_empty() && title.contains(&r.title_contains.to_lowercase()))
+}
@@ -0,0 +1,70 @@
+use evdev::KeyCode;
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::mappings::parse_key_str;
+
+// Drum pad -> key mappings for drum games. A pad can list several keys; hits cycle through
+// them so a fast roll doesn't run into the game's per-key repeat limit. The built-in kit
+// comes from drums.json and follows General MIDI drum numbering.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrumPad {
+    pub midi_note: u8,
+    pub name: String,
+    pub keys: Vec<KeyCode>, // Alternated on successive hits
+}
+
+#[derive(Deserialize)]
+struct JsonDrumPad {
+    midi_note: u8,
+    #[serde(default)]
+    name: String,
+    keys: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrumMap {
+    pads: Vec<DrumPad>,
+}
+
+impl DrumMap {
+    /// The kit shipped in drums.json.
+    pub fn builtin() -> Self {
+        Self::from_json(include_str!("../drums.json")).expect("Failed to parse drums.json")
+    }
+
+    /// `[{"midi_note": 38, "name": "Snare", "keys": ["KEY_F", "KEY_J"]}, ...]`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let pads: Vec<JsonDrumPad> =
+            serde_json::from_str(json).map_err(|e| Error::Parse(format!("Invalid drum map: {}", e)))?;
+        let mut map = Self::default();
+        for pad in pads {
+            let keys: Vec<KeyCode> = pad.keys.iter().map(|k| parse_key_str(k)).collect();
+            if keys.is_empty() || keys.contains(&KeyCode::KEY_RESERVED) {
+                return Err(Error::Parse(format!("Drum pad {} needs one or more known keys", pad.midi_note)));
+            }
+            map.pads.push(DrumPad { midi_note: pad.midi_note, name: pad.name, keys });
+        }
+        Ok(map)
+    }
+
+    pub fn get(&self, note: u8) -> Option<&DrumPad> {
+        self.pads.iter().find(|p| p.midi_note == note)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DrumPad> {
+        self.pads.iter()
+    }
+
+    /// Every key any pad uses, for registering with the virtual device.
+    pub fn keys(&self) -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = Vec::new();
+        for key in self.pads.iter().flat_map(|p| &p.keys) {
+            if !keys.contains(key) {
+                keys.push(*key);
+            }
+        }
+        keys
+    }
+}
@@ -0,0 +1,1969 @@
+use evdev::{EventType, InputEvent, KeyCode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::time::{self, Instant};
+
+use crate::arpeggiator::Arpeggiator;
+use crate::clock::Clock;
+use crate::config::{self, ArpPattern, BlackKeys, ChannelDynamics, ChannelRoute, GameMode, Hotkeys, ModifierRole, NotePriority, OverlayBackground, QuantizeMode, RollDirection, ScaleMode, Settings, Trigger, TypingSource, VolumeSource, WindowRule};
+use crate::drums::{DrumMap, DrumPad};
+use crate::error::{Error, Result};
+use crate::guitar::GuitarMap;
+use crate::inspector::{Decision, EventLog};
+use crate::mappings::{self, KeyMapping, LearnStep, MappingSet};
+use crate::metronome::Metronome;
+use crate::midiclock::MidiClock;
+use crate::midifile::FileEvent;
+use crate::output::{self, KeyOutput, OutputBackend};
+use crate::pipeline::{self, Routing};
+use crate::polyphony::{Admit, Polyphony};
+use crate::practice::PracticeSession;
+use crate::processor::{NoteEvent, Processor, ProcessorChain};
+use crate::quantize::{self, Grid, TapTempo};
+use crate::recording::KeyRecorder;
+use crate::remote::RemoteRequest;
+use crate::roll::NoteHistory;
+use crate::scheduler::{self, Scheduler, Spread};
+#[cfg(feature = "scripting")]
+use crate::script::{Script, ScriptAction, ScriptContext};
+use crate::solver::{self, DecisionLog, Solver, SolverMode};
+use crate::stats::{DropReason, SessionStats};
+use crate::thru::ThruBackend;
+use crate::typing;
+use crate::volume::VolumeFollower;
+
+/// The output side of the engine: the key output plus everything that tracks what is
+/// currently pressed on it (solver key state, transposition). Locked for the duration of
+/// each message so key sequences never interleave.
+pub struct Router {
+    pub output: KeyOutput,
+    // Separate device for drum hits in dual-instrument mode; drums share `output` without one
+    pub drum_output: Option<KeyOutput>,
+    pub current_transpose_offset: i32,
+    pub solver: Solver,
+    pub recorder: Option<KeyRecorder>,
+    // Key events emitted while the inspector is tracing a MIDI message
+    pub trace: Option<Vec<(u16, i32)>>,
+    // Keys currently pressed on the output, as far as we've told it
+    pub held: HashSet<KeyCode>,
+    // Key-downs sent so far per key, so the keyboard view can light taps too short to see held
+    pub key_downs: HashMap<KeyCode, u64>,
+    // Hits so far per drum pad, for alternating its keys
+    pub drum_hits: HashMap<u8, usize>,
+    // Guitar mode: fret keys down and the notes holding each, plus the pending strum
+    pub frets: BTreeMap<KeyCode, HashSet<u8>>,
+    pub strum_due_us: Option<u64>,
+    // Sostenuto: keys frozen down while the trigger is pressed, and those whose release was held back
+    pub sostenuto: HashSet<KeyCode>,
+    pub sostenuto_released: HashSet<KeyCode>,
+    // Whether the sustain pedal is down, when anything follows it
+    pub sustain_on: bool,
+    // Pedal holding note keys through their note-offs, and the sustain key it's holding down
+    pub sustain_hold: bool,
+    pub sustain_key_down: Option<KeyCode>,
+    pub volume: VolumeFollower,
+    // Transpose the game is at from the transpose CC, on top of whatever the routing taps
+    pub cc_transpose: i32,
+    // Same for the pitch bend, which the solver also counts in its own transpose
+    pub bend_transpose: i32,
+    // Clock time of the last note or keep-alive tap; None until the keep-alive first looks
+    pub last_activity_us: Option<u64>,
+    // (channel, note) of note-ons the velocity gate dropped, whose note-offs go with them
+    pub gated: HashSet<(u8, u8)>,
+    // MIDI thru port, and the note sounding for each (channel, note) played through it
+    pub thru: Option<Box<dyn ThruBackend>>,
+    pub thru_notes: HashMap<(u8, u8), u8>,
+    // Key events of the sequence `batch` is collecting, each followed by its SYN_REPORT
+    frames: Option<Vec<InputEvent>>,
+}
+
+impl Router {
+    pub fn new(output: KeyOutput) -> Self {
+        Self {
+            output,
+            drum_output: None,
+            current_transpose_offset: 0,
+            solver: Solver::new(),
+            recorder: None,
+            trace: None,
+            held: HashSet::new(),
+            key_downs: HashMap::new(),
+            drum_hits: HashMap::new(),
+            frets: BTreeMap::new(),
+            strum_due_us: None,
+            sostenuto: HashSet::new(),
+            sostenuto_released: HashSet::new(),
+            sustain_on: false,
+            sustain_hold: false,
+            sustain_key_down: None,
+            volume: VolumeFollower::new(),
+            cc_transpose: 0,
+            bend_transpose: 0,
+            last_activity_us: None,
+            gated: HashSet::new(),
+            thru: None,
+            thru_notes: HashMap::new(),
+            frames: None,
+        }
+    }
+
+    /// All key output goes through here so the session recording sees exactly what was sent.
+    pub fn emit(&mut self, events: &[InputEvent]) -> Result<()> {
+        self.emit_on(false, events)
+    }
+
+    /// Like `emit`, but to the drum device when there is one.
+    pub fn emit_drum(&mut self, events: &[InputEvent]) -> Result<()> {
+        self.emit_on(true, events)
+    }
+
+    fn emit_on(&mut self, drums: bool, events: &[InputEvent]) -> Result<()> {
+        // Frozen keys ignore their releases until the sostenuto (or sustain pedal) lets go
+        let held_back: Vec<InputEvent>;
+        let events = if (self.sostenuto.is_empty() && !self.sustain_hold) || drums {
+            events
+        } else {
+            held_back = events.iter().copied().filter(|e| !self.hold_back(e)).collect();
+            if held_back.is_empty() {
+                return Ok(());
+            }
+            &held_back[..]
+        };
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(events);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.extend(events.iter().filter(|e| e.event_type() == EventType::KEY).map(|e| (e.code(), e.value())));
+        }
+        // Drum hits are taps, so nothing stays held on the drum device
+        if drums && let Some(drum_output) = self.drum_output.as_mut() {
+            return drum_output.emit(events).map_err(Error::Device);
+        }
+        match self.frames.as_mut() {
+            Some(frames) => {
+                for event in events {
+                    frames.push(*event);
+                    frames.push(output::syn_report());
+                }
+            }
+            None => self.output.emit(events).map_err(Error::Device)?,
+        }
+        for event in events.iter().filter(|e| e.event_type() == EventType::KEY) {
+            if event.value() == 0 {
+                self.held.remove(&KeyCode::new(event.code()));
+            } else {
+                self.held.insert(KeyCode::new(event.code()));
+                *self.key_downs.entry(KeyCode::new(event.code())).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // True for a release of a frozen key, which is remembered instead of sent
+    fn hold_back(&mut self, event: &InputEvent) -> bool {
+        let key = KeyCode::new(event.code());
+        // The sustain pedal freezes note keys only, so taps of other keys still go through
+        let sustained = self.sustain_hold && self.solver.mappings.iter().any(|m| m.key_code == key);
+        if event.event_type() != EventType::KEY || !(self.sostenuto.contains(&key) || sustained) {
+            return false;
+        }
+        if event.value() == 0 {
+            self.sostenuto_released.insert(key);
+            true
+        } else {
+            // Played again; it's wanted down after the sostenuto too
+            self.sostenuto_released.remove(&key);
+            false
+        }
+    }
+
+    /// Sostenuto down: the note keys held right now stay down until `release_sostenuto`.
+    /// `transpose_keys` are the game's transpose keys, which aren't note keys.
+    pub fn hold_sostenuto(&mut self, transpose_keys: [KeyCode; 2]) {
+        let modifiers = [KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_LEFTCTRL, transpose_keys[0], transpose_keys[1]];
+        let sustain_key = self.sustain_key_down;
+        self.sostenuto = self.held.iter().copied().filter(|k| !modifiers.contains(k) && Some(*k) != sustain_key).collect();
+    }
+
+    /// Sostenuto up: releases the frozen keys whose notes ended in the meantime.
+    pub fn release_sostenuto(&mut self) -> Result<()> {
+        self.sostenuto.clear();
+        self.release_held_back()
+    }
+
+    /// Sustain pedal up: lets go of the sustain key and the note keys it held.
+    pub fn release_sustain(&mut self) -> Result<()> {
+        self.sustain_hold = false;
+        if let Some(key) = self.sustain_key_down.take() {
+            self.key_up(key)?;
+        }
+        self.release_held_back()
+    }
+
+    // Sends the releases held back, except for keys sostenuto still has frozen
+    fn release_held_back(&mut self) -> Result<()> {
+        let mut released: Vec<KeyCode> = self.sostenuto_released.iter().copied().filter(|k| !self.sostenuto.contains(k)).collect();
+        released.sort_by_key(|k| k.code());
+        for key in released {
+            self.sostenuto_released.remove(&key);
+            self.key_up(key)?;
+        }
+        Ok(())
+    }
+
+    /// Releases every key held down, sostenuto or not, and forgets the solver's key state.
+    pub fn release_held(&mut self) -> Result<()> {
+        self.sostenuto.clear();
+        self.sostenuto_released.clear();
+        self.sustain_key_down = None;
+        self.solver.reset_keys();
+        self.thru_all_off();
+        let mut held: Vec<KeyCode> = self.held.iter().copied().collect();
+        held.sort_by_key(|k| k.code());
+        for key in held {
+            self.key_up(key)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a played note to the thru port as `sounding`, the note the game will sound.
+    pub fn thru_on(&mut self, channel: u8, note: u8, sounding: i32, velocity: u8) {
+        let Ok(sounding) = u8::try_from(sounding) else { return };
+        if sounding > 127 || self.thru.is_none() {
+            return;
+        }
+        // A retrigger of a note still sounding ends the old one first
+        self.thru_off(channel, note);
+        self.thru_notes.insert((channel, note), sounding);
+        self.thru_send(&[0x90 | channel, sounding, velocity]);
+    }
+
+    /// Ends whatever `note` on `channel` sent to the thru port.
+    pub fn thru_off(&mut self, channel: u8, note: u8) {
+        if let Some(sounding) = self.thru_notes.remove(&(channel, note)) {
+            self.thru_send(&[0x80 | channel, sounding, 0]);
+        }
+    }
+
+    /// Ends every note sent to the thru port.
+    pub fn thru_all_off(&mut self) {
+        let mut notes: Vec<_> = self.thru_notes.keys().copied().collect();
+        notes.sort();
+        for (channel, note) in notes {
+            self.thru_off(channel, note);
+        }
+    }
+
+    // A port that stops taking messages is dropped rather than failing every note after it
+    fn thru_send(&mut self, message: &[u8]) {
+        if let Some(thru) = self.thru.as_mut()
+            && let Err(e) = thru.send(message)
+        {
+            eprintln!("MIDI thru stopped: {}", e);
+            self.thru = None;
+            self.thru_notes.clear();
+        }
+    }
+
+    /// Runs `f` with its key events collected and sent as one write at the end, each in a
+    /// frame of its own (a SYN_REPORT after every event), so a modifier and its key reach the
+    /// game together and in order. Only for sequences without sleeps in them; a batch inside
+    /// a batch joins the outer one.
+    pub fn batch<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.frames.is_some() {
+            return f(self);
+        }
+        self.frames = Some(Vec::new());
+        let result = f(self);
+        // Whatever was collected goes out even if `f` failed halfway, so held keys stay true
+        let mut frames = self.frames.take().unwrap_or_default();
+        // The output ends every emit with a SYN_REPORT of its own
+        frames.pop();
+        if !frames.is_empty() {
+            self.output.emit(&frames).map_err(Error::Device)?;
+        }
+        result
+    }
+
+    pub fn key_down(&mut self, key: KeyCode) -> Result<()> {
+        self.emit(&[output::key_event(key, 1)])
+    }
+
+    pub fn key_up(&mut self, key: KeyCode) -> Result<()> {
+        self.emit(&[output::key_event(key, 0)])
+    }
+
+    pub fn tap(&mut self, key: KeyCode) -> Result<()> {
+        self.batch(|state| {
+            state.key_down(key)?;
+            state.key_up(key)
+        })
+    }
+
+    /// Taps a drum pad key, on the drum device when there is one.
+    pub fn tap_drum(&mut self, key: KeyCode) -> Result<()> {
+        self.batch(|state| {
+            state.emit_drum(&[output::key_event(key, 1)])?;
+            state.emit_drum(&[output::key_event(key, 0)])
+        })
+    }
+
+    /// Presses a note key, holding the `accent` modifiers around the press.
+    pub fn press(&mut self, key: KeyCode, accent: &[KeyCode]) -> Result<()> {
+        self.batch(|state| {
+            for modifier in accent {
+                state.key_down(*modifier)?;
+            }
+            state.key_down(key)?;
+            for modifier in accent {
+                state.key_up(*modifier)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Taps the volume keys; true = up.
+    pub fn tap_volume(&mut self, taps: &[bool], up_key: &str, down_key: &str) -> Result<()> {
+        let up = mappings::parse_key_str(up_key);
+        let down = mappings::parse_key_str(down_key);
+        if !taps.is_empty() && (up == KeyCode::KEY_RESERVED || down == KeyCode::KEY_RESERVED) {
+            return Err(Error::Parse(format!("Unknown volume keys {} / {}", up_key, down_key)));
+        }
+        for &tap in taps {
+            let key = if tap { up } else { down };
+            self.tap(key)?;
+        }
+        Ok(())
+    }
+
+    /// Key for the next hit on `pad`, rotating through its keys.
+    pub fn next_drum_key(&mut self, pad: &DrumPad) -> KeyCode {
+        let hits = self.drum_hits.entry(pad.midi_note).or_insert(0);
+        let key = pad.keys[*hits % pad.keys.len()];
+        *hits += 1;
+        key
+    }
+}
+
+/// What the engine looks like from the outside at one point in time; see [`Engine::snapshot`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineSnapshot {
+    pub at_us: u64,
+    pub held_keys: Vec<KeyCode>,   // Pressed on the output, sorted by code
+    pub active_notes: Vec<u8>,     // MIDI notes currently held on the input
+    pub output_notes: Vec<u8>,     // Notes the engine is currently playing
+    pub transpose: i32,            // In-game transpose as the engine last set it
+    pub solver_keys: Vec<KeyCode>, // Keys the solver considers held
+}
+
+/// Settings and session state shared between the MIDI thread, background workers and
+/// whatever frontend is attached. Feed it raw MIDI with [`Engine::handle_message`].
+pub struct Engine {
+    pub router: Mutex<Router>,
+    pub clock: Clock,
+    pub base_mapping_enabled: AtomicBool,
+    pub low_mapping_enabled: AtomicBool,
+    pub high_mapping_enabled: AtomicBool,
+    pub auto_transpose_enabled: AtomicBool,
+    pub fold_low_notes: AtomicBool,
+    pub fold_high_notes: AtomicBool,
+    pub snap_key: Mutex<Option<u8>>,
+    pub snap_scale: Mutex<ScaleMode>,
+    pub experimental_transpose_enabled: AtomicBool,
+    pub experimental_hold_ctrl_enabled: AtomicBool,
+    pub transpose_delay_ms: AtomicU64,
+    pub lazy_transpose_enabled: AtomicBool,
+    pub quantize_enabled: AtomicBool,
+    pub quantize_ms: AtomicU64,
+    pub quantize_mode: Mutex<QuantizeMode>,
+    pub quantize_bpm: AtomicU64,
+    pub quantize_subdivision: AtomicU64,
+    pub quantize_swing: AtomicU64,
+    pub humanize_ms: AtomicU64,
+    pub chord_roll_ms: AtomicU64,
+    pub chord_roll_direction: Mutex<RollDirection>,
+    // Notes held back by the humanizer and chord roll
+    pub scheduler: Mutex<Scheduler>,
+    pub arpeggiator_enabled: AtomicBool,
+    pub arpeggiator_pattern: Mutex<ArpPattern>,
+    pub arpeggiator: Mutex<Arpeggiator>,
+    pub max_polyphony: AtomicU64,
+    pub polyphony_priority: Mutex<NotePriority>,
+    pub polyphony: Mutex<Polyphony>,
+    pub max_chord_notes: AtomicU64,
+    // Where the tempo grid has a line: playback's start, the first note or the last tap
+    pub quantize_anchor_us: Mutex<Option<u64>>,
+    pub tap_tempo: Mutex<TapTempo>,
+    // An external sequencer's clock, for the MIDI clock grid
+    pub midi_clock: Mutex<MidiClock>,
+    // Solver Settings
+    pub solver_enabled: AtomicBool,
+    pub solver_mode_efficiency: AtomicBool, // true = Efficiency, false = Accuracy
+    pub solver_max_jump: AtomicU64,
+    pub solver_hold_transpose: AtomicBool,
+    pub solver_drop_steals: AtomicBool,
+    pub solver_plan_files: AtomicBool,
+    pub transpose_range: AtomicU64,
+    pub game_mode: Mutex<GameMode>,
+    pub mappings: Mutex<MappingSet>,
+    pub mappings_file: Mutex<String>, // Where `mappings` came from; empty for the saved or built-in set
+    pub script_file: Mutex<String>,   // Where `script` came from; empty for no script
+    pub typing_source: Mutex<TypingSource>,
+    pub typing_base_note: AtomicU64,
+    pub typing_velocity: AtomicU64,
+    pub overlay_background: Mutex<OverlayBackground>,
+    pub light_mode: AtomicBool,
+    pub accent_color: Mutex<String>,
+    pub input_key_color: Mutex<String>,
+    pub output_key_color: Mutex<String>,
+    pub white_key_color: Mutex<String>,
+    pub black_key_color: Mutex<String>,
+    pub ui_scale_percent: AtomicU64,
+    #[cfg(feature = "scripting")]
+    pub script: Mutex<Option<Script>>,
+    pub mapping_learn: Mutex<LearnStep>,
+    pub drum_map: Mutex<DrumMap>,
+    pub guitar_map: Mutex<GuitarMap>,
+    pub strum_window_ms: AtomicU64,
+    pub dual_drums_enabled: AtomicBool,
+    pub sostenuto_trigger: Mutex<Trigger>,
+    pub panic_trigger: Mutex<Trigger>,
+    pub transpose_up_trigger: Mutex<Trigger>,
+    pub transpose_down_trigger: Mutex<Trigger>,
+    pub transpose_octave_up_trigger: Mutex<Trigger>,
+    pub transpose_octave_down_trigger: Mutex<Trigger>,
+    pub ctrl_role: Mutex<ModifierRole>,
+    pub shift_role: Mutex<ModifierRole>,
+    pub black_keys: Mutex<BlackKeys>,
+    pub volume_source: Mutex<VolumeSource>,
+    pub volume_steps: AtomicU64,
+    pub volume_up_key: Mutex<String>,
+    pub volume_down_key: Mutex<String>,
+    pub transpose_up_key: Mutex<String>,
+    pub transpose_down_key: Mutex<String>,
+    pub transpose_cc: Mutex<Option<u8>>,
+    pub transpose_cc_range: AtomicU64,
+    pub pitch_bend_range: AtomicU64,
+    pub min_velocity: AtomicU64,
+    pub channel_dynamics: Mutex<[ChannelDynamics; 16]>,
+    pub channel_transpose: Mutex<[i32; 16]>,
+    pub channel_routes: Mutex<[ChannelRoute; 16]>,
+    pub keep_alive_minutes: AtomicU64,
+    pub keep_alive_key: Mutex<String>,
+    pub sustain_key_enabled: AtomicBool,
+    pub sustain_key: Mutex<String>,
+    pub sustain_hold_notes: AtomicBool,
+    pub active_notes: Mutex<HashSet<u8>>,
+    // Keys actually held down (Visualizer output) - tracking specific keys / notes
+
+    pub active_output_notes: Mutex<HashSet<u8>>,
+    
+    pub visualizer_enabled: AtomicBool,
+    pub visualizer_show_midi: AtomicBool,
+    pub visualizer_show_roblox: AtomicBool,
+    pub visualizer_show_heatmap: AtomicBool,
+    pub visualizer_show_names: AtomicBool,
+    pub visualizer_show_keyboard: AtomicBool,
+    pub visualizer_show_roll: AtomicBool,
+    pub note_history: Mutex<NoteHistory>, // Recent input notes, for the piano roll
+
+    // Game auto-detection (window title -> profile)
+    pub auto_profile_enabled: AtomicBool,
+    pub active_window_title: Mutex<String>,
+    pub midi_ports: Mutex<Vec<String>>, // Input port names, kept current by hotplug::spawn
+    pub remote_requests: Mutex<Vec<RemoteRequest>>, // For the frontend to carry out, oldest first
+    pub window_rules: Mutex<Vec<WindowRule>>,
+    pub active_profile: Mutex<Option<String>>,
+
+    // Session statistics
+    pub stats: Mutex<SessionStats>,
+    pub export_stats_on_disconnect: AtomicBool,
+    pub record_keys_enabled: AtomicBool,
+    pub replay_running: AtomicBool,
+    pub replay_stop: AtomicBool,
+    // MIDI file playback: running, asked to stop, and how far into the file it is
+    pub playback_running: AtomicBool,
+    pub playback_stop: AtomicBool,
+    pub playback_position_us: AtomicU64,
+    pub playback_song: AtomicU64, // Index into the playlist
+    pub playback_titles: Mutex<Vec<String>>, // The playlist's song names
+    pub playback_events: Mutex<Arc<Vec<FileEvent>>>, // The song playing, for the piano roll
+    pub playback_duration_us: AtomicU64,
+    // Transport: paused, skip to the next song, a pending jump to a file time, and the speed in percent
+    pub playback_paused: AtomicBool,
+    pub playback_skip: AtomicBool,
+    pub playback_seek_us: Mutex<Option<u64>>,
+    pub playback_rate_percent: AtomicU64,
+
+    pub practice: Mutex<PracticeSession>,
+
+    pub inspector_enabled: AtomicBool,
+    pub inspector: Mutex<EventLog>,
+    // What the solver weighed for each note, while enabled
+    pub solver_log_enabled: AtomicBool,
+    pub solver_log: Mutex<DecisionLog>,
+
+    pub processors: Mutex<ProcessorChain>,
+
+    pub metronome: Mutex<Metronome>,
+
+    // Live octave shift of the input, set from hotkeys or the GUI
+    pub octave_shift: AtomicI32,
+    pub hotkeys: Mutex<Hotkeys>,
+    // (channel, note) -> note it was shifted to, so its note-off follows even if the shift changed
+    shifted_notes: Mutex<HashMap<(u8, u8), u8>>,
+
+    repaint_hook: Mutex<Option<Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl Engine {
+    pub fn new(output: KeyOutput) -> Self {
+        Self::with_clock(output, Clock::Real)
+    }
+
+    /// Engine on a given time source; a virtual clock makes every delay free and deterministic.
+    pub fn with_clock(output: KeyOutput, clock: Clock) -> Self {
+        Self {
+            router: Mutex::new(Router::new(output)),
+            clock,
+            base_mapping_enabled: AtomicBool::new(false),
+            low_mapping_enabled: AtomicBool::new(false),
+            high_mapping_enabled: AtomicBool::new(false),
+            auto_transpose_enabled: AtomicBool::new(false),
+            fold_low_notes: AtomicBool::new(false),
+            fold_high_notes: AtomicBool::new(false),
+            snap_key: Mutex::new(None),
+            snap_scale: Mutex::new(ScaleMode::Major),
+            experimental_transpose_enabled: AtomicBool::new(false),
+            experimental_hold_ctrl_enabled: AtomicBool::new(false),
+            transpose_delay_ms: AtomicU64::new(0),
+            lazy_transpose_enabled: AtomicBool::new(false),
+            quantize_enabled: AtomicBool::new(false),
+            quantize_ms: AtomicU64::new(100),
+            quantize_mode: Mutex::new(QuantizeMode::Milliseconds),
+            quantize_bpm: AtomicU64::new(120),
+            quantize_subdivision: AtomicU64::new(4),
+            quantize_swing: AtomicU64::new(quantize::MIN_SWING),
+            humanize_ms: AtomicU64::new(0),
+            chord_roll_ms: AtomicU64::new(0),
+            chord_roll_direction: Mutex::new(RollDirection::Up),
+            scheduler: Mutex::new(Scheduler::default()),
+            arpeggiator_enabled: AtomicBool::new(false),
+            arpeggiator_pattern: Mutex::new(ArpPattern::Up),
+            arpeggiator: Mutex::new(Arpeggiator::default()),
+            max_polyphony: AtomicU64::new(0),
+            polyphony_priority: Mutex::new(NotePriority::MelodyOnTop),
+            polyphony: Mutex::new(Polyphony::default()),
+            max_chord_notes: AtomicU64::new(0),
+            quantize_anchor_us: Mutex::new(None),
+            tap_tempo: Mutex::new(TapTempo::default()),
+            midi_clock: Mutex::new(MidiClock::default()),
+            solver_enabled: AtomicBool::new(false),
+            solver_mode_efficiency: AtomicBool::new(true),
+            solver_max_jump: AtomicU64::new(12),
+            solver_hold_transpose: AtomicBool::new(false),
+            solver_drop_steals: AtomicBool::new(false),
+            solver_plan_files: AtomicBool::new(true),
+            transpose_range: AtomicU64::new(24),
+            game_mode: Mutex::new(GameMode::Full88),
+            mappings: Mutex::new(MappingSet::builtin()),
+            mappings_file: Mutex::new(String::new()),
+            script_file: Mutex::new(String::new()),
+            typing_source: Mutex::new(TypingSource::Off),
+            typing_base_note: AtomicU64::new(48),
+            typing_velocity: AtomicU64::new(100),
+            overlay_background: Mutex::new(OverlayBackground::Magenta),
+            light_mode: AtomicBool::new(false),
+            accent_color: Mutex::new("#00b4ff".to_string()),
+            input_key_color: Mutex::new("#00ff00".to_string()),
+            output_key_color: Mutex::new("#0064ff".to_string()),
+            white_key_color: Mutex::new("#ffffff".to_string()),
+            black_key_color: Mutex::new("#000000".to_string()),
+            ui_scale_percent: AtomicU64::new(100),
+            #[cfg(feature = "scripting")]
+            script: Mutex::new(None),
+            mapping_learn: Mutex::new(LearnStep::Off),
+            drum_map: Mutex::new(DrumMap::builtin()),
+            guitar_map: Mutex::new(GuitarMap::builtin()),
+            strum_window_ms: AtomicU64::new(30),
+            dual_drums_enabled: AtomicBool::new(false),
+            sostenuto_trigger: Mutex::new(Trigger::Off),
+            panic_trigger: Mutex::new(Trigger::Off),
+            transpose_up_trigger: Mutex::new(Trigger::Off),
+            transpose_down_trigger: Mutex::new(Trigger::Off),
+            transpose_octave_up_trigger: Mutex::new(Trigger::Off),
+            transpose_octave_down_trigger: Mutex::new(Trigger::Off),
+            ctrl_role: Mutex::new(ModifierRole::Range),
+            shift_role: Mutex::new(ModifierRole::Range),
+            black_keys: Mutex::new(BlackKeys::Mapped),
+            volume_source: Mutex::new(VolumeSource::Off),
+            volume_steps: AtomicU64::new(10),
+            volume_up_key: Mutex::new("KEY_RIGHT".to_string()),
+            volume_down_key: Mutex::new("KEY_LEFT".to_string()),
+            transpose_up_key: Mutex::new("KEY_UP".to_string()),
+            transpose_down_key: Mutex::new("KEY_DOWN".to_string()),
+            transpose_cc: Mutex::new(None),
+            transpose_cc_range: AtomicU64::new(12),
+            pitch_bend_range: AtomicU64::new(0),
+            min_velocity: AtomicU64::new(0),
+            channel_dynamics: Mutex::new([ChannelDynamics::default(); 16]),
+            channel_transpose: Mutex::new([0; 16]),
+            channel_routes: Mutex::new(ChannelRoute::defaults()),
+            keep_alive_minutes: AtomicU64::new(0),
+            keep_alive_key: Mutex::new("KEY_PAGEDOWN".to_string()),
+            sustain_key_enabled: AtomicBool::new(false),
+            sustain_key: Mutex::new("KEY_SPACE".to_string()),
+            sustain_hold_notes: AtomicBool::new(false),
+            active_notes: Mutex::new(HashSet::new()),
+            active_output_notes: Mutex::new(HashSet::new()),
+            visualizer_enabled: AtomicBool::new(true),
+            visualizer_show_midi: AtomicBool::new(true),
+            visualizer_show_roblox: AtomicBool::new(true),
+            visualizer_show_heatmap: AtomicBool::new(false),
+            visualizer_show_names: AtomicBool::new(true),
+            visualizer_show_keyboard: AtomicBool::new(false),
+            visualizer_show_roll: AtomicBool::new(false),
+            note_history: Mutex::new(NoteHistory::default()),
+            auto_profile_enabled: AtomicBool::new(false),
+            active_window_title: Mutex::new(String::new()),
+            midi_ports: Mutex::new(Vec::new()),
+            remote_requests: Mutex::new(Vec::new()),
+            window_rules: Mutex::new(config::load_window_rules()),
+            active_profile: Mutex::new(None),
+            stats: Mutex::new(SessionStats::new()),
+            export_stats_on_disconnect: AtomicBool::new(false),
+            record_keys_enabled: AtomicBool::new(false),
+            replay_running: AtomicBool::new(false),
+            replay_stop: AtomicBool::new(false),
+            playback_running: AtomicBool::new(false),
+            playback_stop: AtomicBool::new(false),
+            playback_position_us: AtomicU64::new(0),
+            playback_song: AtomicU64::new(0),
+            playback_titles: Mutex::new(Vec::new()),
+            playback_events: Mutex::new(Arc::new(Vec::new())),
+            playback_duration_us: AtomicU64::new(0),
+            playback_paused: AtomicBool::new(false),
+            playback_skip: AtomicBool::new(false),
+            playback_seek_us: Mutex::new(None),
+            playback_rate_percent: AtomicU64::new(100),
+            practice: Mutex::new(PracticeSession::default()),
+            inspector_enabled: AtomicBool::new(false),
+            inspector: Mutex::new(EventLog::new()),
+            solver_log_enabled: AtomicBool::new(false),
+            solver_log: Mutex::new(DecisionLog::default()),
+            processors: Mutex::new(ProcessorChain::new()),
+            metronome: Mutex::new(Metronome::new()),
+            octave_shift: AtomicI32::new(0),
+            hotkeys: Mutex::new(config::load_hotkeys()),
+            shifted_notes: Mutex::new(HashMap::new()),
+            repaint_hook: Mutex::new(None),
+        }
+    }
+
+    /// Processes one raw MIDI message (note on/off and friends) end to end. A note that can't
+    /// be played comes back as [`Error::Mapping`] or [`Error::Solver`] after it was counted
+    /// as a drop; [`Error::Device`] means the key output itself failed.
+    pub fn handle_message(&self, message: &[u8]) -> Result<()> {
+        self.handle_received(message, Instant::now())
+    }
+
+    /// Like `handle_message`, for a message that arrived at `received_at` and waited in a
+    /// queue since; the latency stats count from then.
+    pub fn handle_received(&self, message: &[u8], received_at: Instant) -> Result<()> {
+        // Clock comes 24 times a beat and plays nothing; kept away from scripts and the inspector
+        if self.midi_clock.lock().unwrap().message(message, self.clock.now_us()) {
+            return Ok(());
+        }
+        // Bracket the message so the inspector sees exactly the keys it caused
+        let inspect = self.inspector_enabled.load(Ordering::Relaxed);
+        if inspect {
+            self.inspector.lock().unwrap().begin(message);
+            self.router.lock().unwrap().trace = Some(Vec::new());
+        }
+        let result = self.script_and_route(message, received_at);
+        if inspect {
+            let output = self.router.lock().unwrap().trace.take().unwrap_or_default();
+            self.inspector.lock().unwrap().finish(output);
+        }
+        result
+    }
+
+    // The script, if there is one, decides what's played in the message's place
+    #[cfg(feature = "scripting")]
+    fn script_and_route(&self, message: &[u8], received_at: Instant) -> Result<()> {
+        // Taken out of the lock before playing: what it emits may take a while
+        let actions = self.script.lock().unwrap().as_mut().map(|script| {
+            let mut active_notes: Vec<u8> = self.active_notes.lock().unwrap().iter().copied().collect();
+            active_notes.sort();
+            let context = ScriptContext { at_us: self.clock.now_us(), active_notes, transpose: self.current_transpose() };
+            script.run(message, &context)
+        });
+        let Some(actions) = actions else { return self.process_and_route(message, received_at) };
+        let mut result = Ok(());
+        for action in actions {
+            let played = match action {
+                ScriptAction::Midi(message) => self.process_and_route(&message, received_at),
+                ScriptAction::Tap(key) => self.router.lock().unwrap().tap(key),
+            };
+            result = result.and(played);
+        }
+        result
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn script_and_route(&self, message: &[u8], received_at: Instant) -> Result<()> {
+        self.process_and_route(message, received_at)
+    }
+
+    // Notes go through the processor chain first; everything else straight to routing
+    fn process_and_route(&self, message: &[u8], received_at: Instant) -> Result<()> {
+        // The panic trigger works whatever else is going on
+        if let Some(pressed) = self.panic_trigger.lock().unwrap().matches(message) {
+            return if pressed { self.panic() } else { Ok(()) };
+        }
+        // While learning a mapping the controller is picking notes, not playing them
+        if self.learn_note(message) {
+            return Ok(());
+        }
+        // An ignored channel goes nowhere: not played, not shown, its controls not followed
+        if let Some(&status) = message.first()
+            && (0x80..0xF0).contains(&status)
+            && self.channel_routes.lock().unwrap()[(status & 0x0F) as usize] == ChannelRoute::Ignore
+        {
+            if let &[status, note, velocity, ..] = message
+                && status & 0xF0 == 0x90
+                && velocity > 0
+            {
+                self.stats.lock().unwrap().dropped(note, DropReason::ChannelFiltered);
+                self.inspect(Decision::Dropped(DropReason::ChannelFiltered));
+            }
+            return Ok(());
+        }
+        // The sostenuto trigger is a control, not something to play
+        if let Some(pressed) = self.sostenuto_trigger.lock().unwrap().matches(message) {
+            let transpose_keys = [self.transpose_key(true), self.transpose_key(false)];
+            let mut state = self.router.lock().unwrap();
+            if pressed {
+                state.hold_sostenuto(transpose_keys);
+            } else {
+                state.release_sostenuto()?;
+            }
+            return Ok(());
+        }
+        // Transpose buttons and pads nudge the game's transpose on each press
+        let transpose_triggers = [
+            (*self.transpose_up_trigger.lock().unwrap(), 1),
+            (*self.transpose_down_trigger.lock().unwrap(), -1),
+            (*self.transpose_octave_up_trigger.lock().unwrap(), 12),
+            (*self.transpose_octave_down_trigger.lock().unwrap(), -12),
+        ];
+        for (trigger, semitones) in transpose_triggers {
+            match trigger.matches(message) {
+                Some(true) => return self.nudge_transpose(semitones),
+                Some(false) => return Ok(()),
+                None => {}
+            }
+        }
+        // A volume CC turns into volume key taps
+        let volume_source = *self.volume_source.lock().unwrap();
+        if let VolumeSource::Cc(cc) = volume_source
+            && let &[status, number, value, ..] = message
+            && status & 0xF0 == 0xB0
+            && number == cc
+        {
+            let mut state = self.router.lock().unwrap();
+            let taps = state.volume.on_cc(value, self.volume_steps.load(Ordering::Relaxed) as u32);
+            return state.tap_volume(&taps, &self.volume_up_key.lock().unwrap(), &self.volume_down_key.lock().unwrap());
+        }
+        // The transpose knob: its travel maps onto -range..=+range semitones
+        let transpose_delay_ms = self.transpose_delay_ms.load(Ordering::Relaxed);
+        let transpose_cc = *self.transpose_cc.lock().unwrap();
+        if let Some(cc) = transpose_cc
+            && let &[status, number, value, ..] = message
+            && status & 0xF0 == 0xB0
+            && number == cc
+        {
+            let range = self.transpose_cc_range.load(Ordering::Relaxed) as i32;
+            let target = ((value as i32 - 64) * range + 32).div_euclid(64).clamp(-range, range);
+            return self.tap_transpose_to(target, transpose_delay_ms, false);
+        }
+        // Pitch bend as a temporary transpose: the nearest semitone at this bend, back to none
+        // when the wheel centers. Followed even with the range at 0, so a bend in progress
+        // when it's turned off still gets undone.
+        if let &[status, lsb, msb, ..] = message
+            && status & 0xF0 == 0xE0
+        {
+            let bend = ((msb as i32) << 7 | lsb as i32) - 8192;
+            let range = self.pitch_bend_range.load(Ordering::Relaxed) as i32;
+            let target = (bend * range * 2 + 8192 * bend.signum()) / (8192 * 2);
+            return self.tap_transpose_to(target, transpose_delay_ms, true);
+        }
+        // The sustain pedal toggles a modifier in the sustain role, holds the sustain key
+        // and/or holds notes, whichever are set
+        let sustain_toggles = self.modifiers_with(ModifierRole::Sustain);
+        let sustain_key = self.sustain_key_enabled.load(Ordering::Relaxed)
+            .then(|| mappings::parse_key_str(&self.sustain_key.lock().unwrap()))
+            .filter(|k| *k != KeyCode::KEY_RESERVED);
+        let sustain_hold_notes = self.sustain_hold_notes.load(Ordering::Relaxed);
+        let mut state = self.router.lock().unwrap();
+        // A pedal that's down stays followed until it comes up, even if the settings change
+        let followed = !sustain_toggles.is_empty() || sustain_key.is_some() || sustain_hold_notes || state.sustain_on;
+        if followed && let Some(pressed) = SUSTAIN_PEDAL.matches(message) {
+            if pressed != state.sustain_on {
+                state.sustain_on = pressed;
+                for key in sustain_toggles {
+                    state.tap(key)?;
+                }
+                if pressed {
+                    state.sustain_hold = sustain_hold_notes;
+                    if let Some(key) = sustain_key {
+                        state.key_down(key)?;
+                        state.sustain_key_down = Some(key);
+                    }
+                } else {
+                    state.release_sustain()?;
+                }
+            }
+            return Ok(());
+        }
+        drop(state);
+        let events = match NoteEvent::from_message(message) {
+            Some(event) => {
+                if self.velocity_gate(event) {
+                    return Ok(());
+                }
+                self.router.lock().unwrap().last_activity_us = Some(self.clock.now_us());
+                // Per-channel dynamics come first, so everything after sees the balanced velocity
+                let mut balanced = event;
+                if event.is_on() {
+                    balanced.velocity = self.channel_dynamics.lock().unwrap()[event.channel as usize].apply(event.velocity);
+                }
+                let shifted = self.shift_note(balanced);
+                let mut chain = self.processors.lock().unwrap();
+                if !chain.is_active() {
+                    drop(chain);
+                    if shifted == event {
+                        return route_message(message, self, received_at);
+                    }
+                    return route_message(&shifted.to_message(), self, received_at);
+                }
+                let event = shifted;
+                chain.process(self.clock.now_us(), event)
+            }
+            None => return route_message(message, self, received_at),
+        };
+        // Play everything the processors asked for; the first failure is the one reported
+        let mut result = Ok(());
+        for event in events {
+            let routed = route_message(&event.to_message(), self, received_at);
+            if result.is_ok() {
+                result = routed;
+            }
+        }
+        result
+    }
+
+    // Applies the channel's transpose and the live octave shift, then snaps to the scale; a
+    // note-off gets whatever its note-on got. Drums aren't pitched, so the drum channels and
+    // drum mode are left alone.
+    fn shift_note(&self, event: NoteEvent) -> NoteEvent {
+        let routing = self.routing();
+        if self.channel_routes.lock().unwrap()[event.channel as usize] == ChannelRoute::Drums || routing.game_mode == GameMode::Drums {
+            return event;
+        }
+        let mut shifted_notes = self.shifted_notes.lock().unwrap();
+        let key = (event.channel, event.note);
+        let note = if event.is_on() {
+            let octaves = self.octave_shift.load(Ordering::Relaxed);
+            let note = pipeline::transpose_channel(&routing, event.channel, event.note);
+            // Past either end of the MIDI range the note stays where it was
+            let note = u8::try_from(note as i32 + octaves * 12).ok().filter(|n| *n < 128).unwrap_or(note);
+            let note = pipeline::snap_note(&routing, note);
+            if note == event.note {
+                shifted_notes.remove(&key);
+            } else {
+                shifted_notes.insert(key, note);
+            }
+            note
+        } else {
+            shifted_notes.remove(&key).unwrap_or(event.note)
+        };
+        NoteEvent { note, ..event }
+    }
+
+    /// Moves the live octave shift by `octaves`, within +/- MAX_OCTAVE_SHIFT.
+    pub fn shift_octaves(&self, octaves: i32) {
+        let shift = (self.octave_shift.load(Ordering::Relaxed) + octaves).clamp(-MAX_OCTAVE_SHIFT, MAX_OCTAVE_SHIFT);
+        self.octave_shift.store(shift, Ordering::Relaxed);
+        self.request_repaint();
+    }
+
+    // True for a note-on too soft to play (a ghost note) and for its note-off
+    fn velocity_gate(&self, event: NoteEvent) -> bool {
+        let mut state = self.router.lock().unwrap();
+        let key = (event.channel, event.note);
+        if !event.is_on() {
+            return state.gated.remove(&key);
+        }
+        if (event.velocity as u64) >= self.min_velocity.load(Ordering::Relaxed) {
+            // A real press after a ghost one; its note-off is this one's
+            state.gated.remove(&key);
+            return false;
+        }
+        state.gated.insert(key);
+        drop(state);
+        self.stats.lock().unwrap().dropped(event.note, DropReason::TooSoft);
+        self.inspect(Decision::Dropped(DropReason::TooSoft));
+        true
+    }
+
+    /// Lets go of everything: every held key (Shift and Ctrl included, whatever we think
+    /// their state is), sostenuto and pedal holds and thru notes, then taps the game's
+    /// transpose back to where it started.
+    pub fn panic(&self) -> Result<()> {
+        let solver_enabled = self.solver_enabled.load(Ordering::Relaxed);
+        let mut state = self.router.lock().unwrap();
+        state.release_held()?;
+        state.key_up(KeyCode::KEY_LEFTSHIFT)?;
+        state.key_up(KeyCode::KEY_LEFTCTRL)?;
+        state.frets.clear();
+        state.strum_due_us = None;
+        state.gated.clear();
+        self.scheduler.lock().unwrap().clear();
+        self.arpeggiator.lock().unwrap().clear();
+        self.polyphony.lock().unwrap().clear();
+        self.active_output_notes.lock().unwrap().clear();
+
+        let game_transpose = Self::game_transpose_of(&state, solver_enabled);
+        let key = self.transpose_key(game_transpose < 0);
+        for _ in 0..game_transpose.abs() {
+            state.tap(key)?;
+            self.clock.sleep(time::Duration::from_millis(5));
+        }
+        state.solver.reset_transpose();
+        state.current_transpose_offset = 0;
+        state.cc_transpose = 0;
+        state.bend_transpose = 0;
+        Ok(())
+    }
+
+    /// Lets go of every key held on the outputs, for when the program is going away. Never
+    /// waits long for the router: a panic hook may be running on the thread that holds it.
+    pub fn release_on_exit(&self) {
+        for _ in 0..20 {
+            let mut state = match self.router.try_lock() {
+                Ok(state) => state,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    std::thread::sleep(time::Duration::from_millis(10));
+                    continue;
+                }
+            };
+            // Nothing held back for a pedal anymore, everything goes up now
+            state.sustain_hold = false;
+            let _ = state.release_held();
+            let _ = state.key_up(KeyCode::KEY_LEFTSHIFT);
+            let _ = state.key_up(KeyCode::KEY_LEFTCTRL);
+            return;
+        }
+    }
+
+    // Taps Up/Down until the transpose CC's (or the pitch bend's) share of the game's transpose
+    // is `target`. The bend is also counted in the solver's transpose, so what it solves next
+    // still comes out at the pitch played.
+    fn tap_transpose_to(&self, target: i32, delay_ms: u64, bend: bool) -> Result<()> {
+        loop {
+            let mut state = self.router.lock().unwrap();
+            let current = if bend { state.bend_transpose } else { state.cc_transpose };
+            let diff = target - current;
+            if diff == 0 {
+                return Ok(());
+            }
+            let key = self.transpose_key(diff > 0);
+            state.tap(key)?;
+            if bend {
+                state.bend_transpose += diff.signum();
+                state.solver.current_transpose += diff.signum();
+            } else {
+                state.cc_transpose += diff.signum();
+            }
+            drop(state);
+            if delay_ms > 0 {
+                self.clock.sleep(time::Duration::from_millis(delay_ms));
+            }
+        }
+    }
+
+    /// The key that taps the game's transpose up (or down). A name that isn't a key plays
+    /// the arrow key instead; --check-config reports it.
+    pub fn transpose_key(&self, up: bool) -> KeyCode {
+        let (name, arrow) = if up { (&self.transpose_up_key, KeyCode::KEY_UP) } else { (&self.transpose_down_key, KeyCode::KEY_DOWN) };
+        Some(mappings::parse_key_str(&name.lock().unwrap())).filter(|k| *k != KeyCode::KEY_RESERVED).unwrap_or(arrow)
+    }
+
+    /// Taps the game's transpose `semitones` up (or down) from wherever it is, counting it in
+    /// routing's transpose so the solver and the legacy path both know where the game is.
+    pub fn nudge_transpose(&self, semitones: i32) -> Result<()> {
+        let delay_ms = self.transpose_delay_ms.load(Ordering::Relaxed);
+        let key = self.transpose_key(semitones > 0);
+        for _ in 0..semitones.abs() {
+            let mut state = self.router.lock().unwrap();
+            state.tap(key)?;
+            state.current_transpose_offset += semitones.signum();
+            state.solver.current_transpose += semitones.signum();
+            drop(state);
+            if delay_ms > 0 {
+                self.clock.sleep(time::Duration::from_millis(delay_ms));
+            }
+        }
+        self.request_repaint();
+        Ok(())
+    }
+
+    /// Taps the game's transpose to `semitones` off from where routing put it. It's the share
+    /// the transpose knob moves, so a remote and the knob set the same thing.
+    pub fn set_transpose(&self, semitones: i32) -> Result<()> {
+        self.tap_transpose_to(semitones, self.transpose_delay_ms.load(Ordering::Relaxed), false)
+    }
+
+    /// Adds a processor to the end of the chain, enabled.
+    pub fn add_processor(&self, processor: impl Processor + 'static) {
+        self.processors.lock().unwrap().add(Box::new(processor));
+    }
+
+    /// Feeds `message` as if it arrived at `at_us` on the engine's clock. A virtual clock is
+    /// moved forward to that time first; on the real clock the timestamp is ignored.
+    pub fn inject(&self, at_us: u64, message: &[u8]) -> Result<()> {
+        self.advance_to(at_us)?;
+        self.handle_message(message)
+    }
+
+    /// Moves a virtual clock forward, doing whatever was scheduled on the way (a pending
+    /// guitar strum, held-back notes or arpeggio steps go out at their due time). No-op on the
+    /// real clock.
+    pub fn advance_to(&self, at_us: u64) -> Result<()> {
+        loop {
+            let strum = self.router.lock().unwrap().strum_due_us;
+            let held = self.scheduler.lock().unwrap().next_due();
+            let step = self.arpeggiator.lock().unwrap().next_due();
+            let Some(due) = strum.into_iter().chain(held).chain(step).min().filter(|due| *due <= at_us) else { break };
+            self.clock.advance_to(due);
+            self.flush_strum()?;
+            self.flush_scheduled()?;
+            self.flush_arpeggio()?;
+        }
+        self.clock.advance_to(at_us);
+        Ok(())
+    }
+
+    /// Plays the arpeggiator's step if one is due, on the tempo grid: the quantize BPM and
+    /// subdivision, or the MIDI clock's while it plays. Turned off, it lets go of its note.
+    pub fn flush_arpeggio(&self) -> Result<()> {
+        let now_us = self.clock.now_us();
+        let notes = if self.arpeggiator_enabled.load(Ordering::Relaxed) {
+            let start_us = self.arpeggiator.lock().unwrap().start_us();
+            let clock_grid = if *self.quantize_mode.lock().unwrap() == QuantizeMode::MidiClock { self.quantize_grid() } else { None };
+            let grid = clock_grid.unwrap_or_else(|| {
+                let tempo = Grid::tempo(self.quantize_bpm.load(Ordering::Relaxed), self.quantize_subdivision.load(Ordering::Relaxed), start_us);
+                tempo.swung(self.quantize_swing.load(Ordering::Relaxed))
+            });
+            let pattern = *self.arpeggiator_pattern.lock().unwrap();
+            self.arpeggiator.lock().unwrap().tick(now_us, grid, pattern)
+        } else {
+            self.arpeggiator.lock().unwrap().clear().into_iter().collect()
+        };
+        let mut result = Ok(());
+        for event in notes {
+            // The steps are on the grid already
+            let played = play_message(&event.to_message(), self, Instant::now());
+            if result.is_ok() {
+                result = played;
+            }
+        }
+        result
+    }
+
+    /// Plays the notes the scheduler held back whose time has come.
+    pub fn flush_scheduled(&self) -> Result<()> {
+        let due = self.scheduler.lock().unwrap().take_due(self.clock.now_us());
+        let mut result = Ok(());
+        for (event, received_at) in due {
+            // Already on their quantize line
+            let played = play_message(&event.to_message(), self, received_at);
+            // Its note-off may go out now, played or dropped
+            self.scheduler.lock().unwrap().played(event);
+            if result.is_ok() {
+                result = played;
+            }
+        }
+        result
+    }
+
+    /// Taps the strum key if a chord's window has closed.
+    pub fn flush_strum(&self) -> Result<()> {
+        let mut state = self.router.lock().unwrap();
+        if state.strum_due_us.is_none_or(|due| self.clock.now_us() < due) {
+            return Ok(());
+        }
+        state.strum_due_us = None;
+        let strum = self.guitar_map.lock().unwrap().strum;
+        state.tap(strum)
+    }
+
+    /// Taps the keep-alive key if nothing has been played for the configured time. Returns
+    /// whether it tapped.
+    pub fn keep_alive(&self) -> Result<bool> {
+        let minutes = self.keep_alive_minutes.load(Ordering::Relaxed);
+        let now = self.clock.now_us();
+        let mut state = self.router.lock().unwrap();
+        // Off, or just switched on: the idle time counts from here
+        let Some(last) = state.last_activity_us.filter(|_| minutes > 0) else {
+            state.last_activity_us = Some(now);
+            return Ok(false);
+        };
+        if now.saturating_sub(last) < minutes * 60_000_000 {
+            return Ok(false);
+        }
+        state.last_activity_us = Some(now);
+        let name = self.keep_alive_key.lock().unwrap().clone();
+        let key = mappings::parse_key_str(&name);
+        if key == KeyCode::KEY_RESERVED {
+            return Err(Error::Parse(format!("Unknown keep-alive key {}", name)));
+        }
+        state.tap(key)?;
+        Ok(true)
+    }
+
+    // The transpose being played with: the solver's own, or the octave offset without it
+    fn transpose_of(router: &Router, solver_enabled: bool) -> i32 {
+        if solver_enabled { router.solver.current_transpose } else { router.current_transpose_offset }
+    }
+
+    // Everything the game is transposed by: routing's share plus the knob's. The solver's
+    // transpose already counts the bend; the legacy offset doesn't.
+    fn game_transpose_of(router: &Router, solver_enabled: bool) -> i32 {
+        let routed = if solver_enabled { router.solver.current_transpose } else { router.current_transpose_offset + router.bend_transpose };
+        routed + router.cc_transpose
+    }
+
+    /// The quantizer's grid as the settings have it now; in tempo mode it's anchored at 0
+    /// until something anchors it. None in MIDI clock mode while the sequencer isn't playing.
+    /// Swing is for the musical grids; milliseconds have no off-beats to swing.
+    pub fn quantize_grid(&self) -> Option<Grid> {
+        let subdivision = self.quantize_subdivision.load(Ordering::Relaxed);
+        let swing = self.quantize_swing.load(Ordering::Relaxed);
+        match *self.quantize_mode.lock().unwrap() {
+            QuantizeMode::Milliseconds => Some(Grid::millis(self.quantize_ms.load(Ordering::Relaxed))),
+            QuantizeMode::Tempo => Some(
+                Grid::tempo(self.quantize_bpm.load(Ordering::Relaxed), subdivision, self.quantize_anchor_us.lock().unwrap().unwrap_or(0)).swung(swing),
+            ),
+            QuantizeMode::MidiClock => {
+                let grid = self.midi_clock.lock().unwrap().grid(subdivision.clamp(1, quantize::MAX_SUBDIVISION), self.clock.now_us());
+                grid.map(|grid| grid.swung(swing))
+            }
+        }
+    }
+
+    /// The beats of the tempo grid, in tempo mode; the metronome clicks on these.
+    pub fn tempo_beats(&self) -> Option<Grid> {
+        if *self.quantize_mode.lock().unwrap() != QuantizeMode::Tempo {
+            return None;
+        }
+        let anchor_us = self.quantize_anchor_us.lock().unwrap().unwrap_or(0);
+        Some(Grid::tempo(self.quantize_bpm.load(Ordering::Relaxed), 1, anchor_us))
+    }
+
+    /// Puts a line of the tempo grid at `at_us`, e.g. where playback starts; None leaves it
+    /// for the next note.
+    pub fn anchor_quantize(&self, at_us: Option<u64>) {
+        *self.quantize_anchor_us.lock().unwrap() = at_us;
+    }
+
+    /// A tap of the tempo button: the tempo from the taps so far becomes the grid's, with a
+    /// line on this tap. Returns the tempo once there is one.
+    pub fn tap_tempo(&self) -> Option<u64> {
+        let now_us = self.clock.now_us();
+        let bpm = self.tap_tempo.lock().unwrap().tap(now_us)?;
+        self.quantize_bpm.store(bpm, Ordering::Relaxed);
+        self.anchor_quantize(Some(now_us));
+        Some(bpm)
+    }
+
+    /// How many semitones the game is transposed by right now, from every source.
+    pub fn game_transpose(&self) -> i32 {
+        Self::game_transpose_of(&self.router.lock().unwrap(), self.solver_enabled.load(Ordering::Relaxed))
+    }
+
+    #[cfg(feature = "scripting")]
+    fn current_transpose(&self) -> i32 {
+        Self::transpose_of(&self.router.lock().unwrap(), self.solver_enabled.load(Ordering::Relaxed))
+    }
+
+    /// Output and bookkeeping state right now, for tests and diagnostics.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let router = self.router.lock().unwrap();
+        let mut held_keys: Vec<KeyCode> = router.held.iter().copied().collect();
+        held_keys.sort_by_key(|k| k.code());
+        let mut solver_keys: Vec<KeyCode> = router
+            .solver
+            .active_keys
+            .iter()
+            .filter(|(_, notes)| !notes.is_empty())
+            .map(|(key, _)| *key)
+            .collect();
+        solver_keys.sort_by_key(|k| k.code());
+        let transpose = Self::transpose_of(&router, self.solver_enabled.load(Ordering::Relaxed));
+        let sorted = |notes: &HashSet<u8>| {
+            let mut notes: Vec<u8> = notes.iter().copied().collect();
+            notes.sort();
+            notes
+        };
+        EngineSnapshot {
+            at_us: self.clock.now_us(),
+            held_keys,
+            active_notes: sorted(&self.active_notes.lock().unwrap()),
+            output_notes: sorted(&self.active_output_notes.lock().unwrap()),
+            transpose,
+            solver_keys,
+        }
+    }
+
+    /// Snapshot of the current playback settings.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            version: config::SCHEMA_VERSION,
+            game_mode: *self.game_mode.lock().unwrap(),
+            base_mapping_enabled: self.base_mapping_enabled.load(Ordering::Relaxed),
+            low_mapping_enabled: self.low_mapping_enabled.load(Ordering::Relaxed),
+            high_mapping_enabled: self.high_mapping_enabled.load(Ordering::Relaxed),
+            auto_transpose_enabled: self.auto_transpose_enabled.load(Ordering::Relaxed),
+            fold_low_notes: self.fold_low_notes.load(Ordering::Relaxed),
+            fold_high_notes: self.fold_high_notes.load(Ordering::Relaxed),
+            snap_key: *self.snap_key.lock().unwrap(),
+            snap_scale: *self.snap_scale.lock().unwrap(),
+            experimental_transpose_enabled: self.experimental_transpose_enabled.load(Ordering::Relaxed),
+            experimental_hold_ctrl_enabled: self.experimental_hold_ctrl_enabled.load(Ordering::Relaxed),
+            transpose_delay_ms: self.transpose_delay_ms.load(Ordering::Relaxed),
+            lazy_transpose_enabled: self.lazy_transpose_enabled.load(Ordering::Relaxed),
+            quantize_enabled: self.quantize_enabled.load(Ordering::Relaxed),
+            quantize_ms: self.quantize_ms.load(Ordering::Relaxed),
+            quantize_mode: *self.quantize_mode.lock().unwrap(),
+            quantize_bpm: self.quantize_bpm.load(Ordering::Relaxed),
+            quantize_subdivision: self.quantize_subdivision.load(Ordering::Relaxed),
+            quantize_swing: self.quantize_swing.load(Ordering::Relaxed),
+            humanize_ms: self.humanize_ms.load(Ordering::Relaxed),
+            chord_roll_ms: self.chord_roll_ms.load(Ordering::Relaxed),
+            chord_roll_direction: *self.chord_roll_direction.lock().unwrap(),
+            arpeggiator_enabled: self.arpeggiator_enabled.load(Ordering::Relaxed),
+            arpeggiator_pattern: *self.arpeggiator_pattern.lock().unwrap(),
+            max_polyphony: self.max_polyphony.load(Ordering::Relaxed),
+            polyphony_priority: *self.polyphony_priority.lock().unwrap(),
+            max_chord_notes: self.max_chord_notes.load(Ordering::Relaxed),
+            solver_enabled: self.solver_enabled.load(Ordering::Relaxed),
+            solver_mode_efficiency: self.solver_mode_efficiency.load(Ordering::Relaxed),
+            solver_max_jump: self.solver_max_jump.load(Ordering::Relaxed),
+            solver_hold_transpose: self.solver_hold_transpose.load(Ordering::Relaxed),
+            solver_drop_steals: self.solver_drop_steals.load(Ordering::Relaxed),
+            solver_plan_files: self.solver_plan_files.load(Ordering::Relaxed),
+            transpose_range: self.transpose_range.load(Ordering::Relaxed),
+            strum_window_ms: self.strum_window_ms.load(Ordering::Relaxed),
+            dual_drums_enabled: self.dual_drums_enabled.load(Ordering::Relaxed),
+            sostenuto_trigger: *self.sostenuto_trigger.lock().unwrap(),
+            panic_trigger: *self.panic_trigger.lock().unwrap(),
+            transpose_up_trigger: *self.transpose_up_trigger.lock().unwrap(),
+            transpose_down_trigger: *self.transpose_down_trigger.lock().unwrap(),
+            transpose_octave_up_trigger: *self.transpose_octave_up_trigger.lock().unwrap(),
+            transpose_octave_down_trigger: *self.transpose_octave_down_trigger.lock().unwrap(),
+            ctrl_role: *self.ctrl_role.lock().unwrap(),
+            shift_role: *self.shift_role.lock().unwrap(),
+            black_keys: *self.black_keys.lock().unwrap(),
+            volume_source: *self.volume_source.lock().unwrap(),
+            volume_steps: self.volume_steps.load(Ordering::Relaxed),
+            volume_up_key: self.volume_up_key.lock().unwrap().clone(),
+            volume_down_key: self.volume_down_key.lock().unwrap().clone(),
+            transpose_up_key: self.transpose_up_key.lock().unwrap().clone(),
+            transpose_down_key: self.transpose_down_key.lock().unwrap().clone(),
+            transpose_cc: *self.transpose_cc.lock().unwrap(),
+            transpose_cc_range: self.transpose_cc_range.load(Ordering::Relaxed),
+            pitch_bend_range: self.pitch_bend_range.load(Ordering::Relaxed),
+            min_velocity: self.min_velocity.load(Ordering::Relaxed),
+            channel_dynamics: *self.channel_dynamics.lock().unwrap(),
+            channel_transpose: *self.channel_transpose.lock().unwrap(),
+            channel_routes: *self.channel_routes.lock().unwrap(),
+            keep_alive_minutes: self.keep_alive_minutes.load(Ordering::Relaxed),
+            keep_alive_key: self.keep_alive_key.lock().unwrap().clone(),
+            sustain_key_enabled: self.sustain_key_enabled.load(Ordering::Relaxed),
+            sustain_key: self.sustain_key.lock().unwrap().clone(),
+            sustain_hold_notes: self.sustain_hold_notes.load(Ordering::Relaxed),
+            mappings_file: self.mappings_file.lock().unwrap().clone(),
+            script_file: self.script_file.lock().unwrap().clone(),
+            typing_source: *self.typing_source.lock().unwrap(),
+            typing_base_note: self.typing_base_note.load(Ordering::Relaxed),
+            typing_velocity: self.typing_velocity.load(Ordering::Relaxed),
+            overlay_background: *self.overlay_background.lock().unwrap(),
+            light_mode: self.light_mode.load(Ordering::Relaxed),
+            accent_color: self.accent_color.lock().unwrap().clone(),
+            input_key_color: self.input_key_color.lock().unwrap().clone(),
+            output_key_color: self.output_key_color.lock().unwrap().clone(),
+            white_key_color: self.white_key_color.lock().unwrap().clone(),
+            black_key_color: self.black_key_color.lock().unwrap().clone(),
+            ui_scale_percent: self.ui_scale_percent.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The settings a note is routed by, without copying the rest.
+    pub fn routing(&self) -> Routing {
+        Routing {
+            game_mode: *self.game_mode.lock().unwrap(),
+            base_mapping_enabled: self.base_mapping_enabled.load(Ordering::Relaxed),
+            low_mapping_enabled: self.low_mapping_enabled.load(Ordering::Relaxed),
+            high_mapping_enabled: self.high_mapping_enabled.load(Ordering::Relaxed),
+            auto_transpose_enabled: self.auto_transpose_enabled.load(Ordering::Relaxed),
+            fold_low_notes: self.fold_low_notes.load(Ordering::Relaxed),
+            fold_high_notes: self.fold_high_notes.load(Ordering::Relaxed),
+            snap_key: *self.snap_key.lock().unwrap(),
+            snap_scale: *self.snap_scale.lock().unwrap(),
+            channel_transpose: *self.channel_transpose.lock().unwrap(),
+            black_keys: *self.black_keys.lock().unwrap(),
+            shift_role: *self.shift_role.lock().unwrap(),
+            ctrl_role: *self.ctrl_role.lock().unwrap(),
+            experimental_transpose_enabled: self.experimental_transpose_enabled.load(Ordering::Relaxed),
+        }
+    }
+
+    // Ctrl and/or Shift, whichever the profile gives `role`
+    fn modifiers_with(&self, role: ModifierRole) -> Vec<KeyCode> {
+        let mut keys = Vec::new();
+        if *self.ctrl_role.lock().unwrap() == role {
+            keys.push(KeyCode::KEY_LEFTCTRL);
+        }
+        if *self.shift_role.lock().unwrap() == role {
+            keys.push(KeyCode::KEY_LEFTSHIFT);
+        }
+        keys
+    }
+
+    pub fn apply_settings(&self, s: &Settings) {
+        *self.game_mode.lock().unwrap() = s.game_mode;
+        self.base_mapping_enabled.store(s.base_mapping_enabled, Ordering::Relaxed);
+        self.low_mapping_enabled.store(s.low_mapping_enabled, Ordering::Relaxed);
+        self.high_mapping_enabled.store(s.high_mapping_enabled, Ordering::Relaxed);
+        self.auto_transpose_enabled.store(s.auto_transpose_enabled, Ordering::Relaxed);
+        self.fold_low_notes.store(s.fold_low_notes, Ordering::Relaxed);
+        self.fold_high_notes.store(s.fold_high_notes, Ordering::Relaxed);
+        *self.snap_key.lock().unwrap() = s.snap_key.map(|k| k % 12);
+        *self.snap_scale.lock().unwrap() = s.snap_scale;
+        self.experimental_transpose_enabled.store(s.experimental_transpose_enabled, Ordering::Relaxed);
+        self.experimental_hold_ctrl_enabled.store(s.experimental_hold_ctrl_enabled, Ordering::Relaxed);
+        self.transpose_delay_ms.store(s.transpose_delay_ms, Ordering::Relaxed);
+        self.lazy_transpose_enabled.store(s.lazy_transpose_enabled, Ordering::Relaxed);
+        self.quantize_enabled.store(s.quantize_enabled, Ordering::Relaxed);
+        self.quantize_ms.store(s.quantize_ms, Ordering::Relaxed);
+        *self.quantize_mode.lock().unwrap() = s.quantize_mode;
+        self.quantize_bpm.store(s.quantize_bpm.clamp(quantize::MIN_BPM, quantize::MAX_BPM), Ordering::Relaxed);
+        self.quantize_subdivision.store(s.quantize_subdivision.clamp(1, quantize::MAX_SUBDIVISION), Ordering::Relaxed);
+        self.quantize_swing.store(s.quantize_swing.clamp(quantize::MIN_SWING, quantize::MAX_SWING), Ordering::Relaxed);
+        self.humanize_ms.store(s.humanize_ms.min(scheduler::MAX_HUMANIZE_MS), Ordering::Relaxed);
+        self.chord_roll_ms.store(s.chord_roll_ms.min(scheduler::MAX_CHORD_ROLL_MS), Ordering::Relaxed);
+        *self.chord_roll_direction.lock().unwrap() = s.chord_roll_direction;
+        self.arpeggiator_enabled.store(s.arpeggiator_enabled, Ordering::Relaxed);
+        *self.arpeggiator_pattern.lock().unwrap() = s.arpeggiator_pattern;
+        self.max_polyphony.store(s.max_polyphony, Ordering::Relaxed);
+        *self.polyphony_priority.lock().unwrap() = s.polyphony_priority;
+        self.max_chord_notes.store(s.max_chord_notes, Ordering::Relaxed);
+        self.solver_enabled.store(s.solver_enabled, Ordering::Relaxed);
+        self.solver_mode_efficiency.store(s.solver_mode_efficiency, Ordering::Relaxed);
+        self.solver_max_jump.store(s.solver_max_jump, Ordering::Relaxed);
+        self.solver_hold_transpose.store(s.solver_hold_transpose, Ordering::Relaxed);
+        self.solver_drop_steals.store(s.solver_drop_steals, Ordering::Relaxed);
+        self.solver_plan_files.store(s.solver_plan_files, Ordering::Relaxed);
+        self.transpose_range.store(s.transpose_range, Ordering::Relaxed);
+        self.strum_window_ms.store(s.strum_window_ms, Ordering::Relaxed);
+        self.dual_drums_enabled.store(s.dual_drums_enabled, Ordering::Relaxed);
+        *self.sostenuto_trigger.lock().unwrap() = s.sostenuto_trigger;
+        *self.panic_trigger.lock().unwrap() = s.panic_trigger;
+        *self.transpose_up_trigger.lock().unwrap() = s.transpose_up_trigger;
+        *self.transpose_down_trigger.lock().unwrap() = s.transpose_down_trigger;
+        *self.transpose_octave_up_trigger.lock().unwrap() = s.transpose_octave_up_trigger;
+        *self.transpose_octave_down_trigger.lock().unwrap() = s.transpose_octave_down_trigger;
+        *self.ctrl_role.lock().unwrap() = s.ctrl_role;
+        *self.shift_role.lock().unwrap() = s.shift_role;
+        *self.black_keys.lock().unwrap() = s.black_keys;
+        *self.volume_source.lock().unwrap() = s.volume_source;
+        self.volume_steps.store(s.volume_steps, Ordering::Relaxed);
+        *self.volume_up_key.lock().unwrap() = s.volume_up_key.clone();
+        *self.volume_down_key.lock().unwrap() = s.volume_down_key.clone();
+        *self.transpose_up_key.lock().unwrap() = s.transpose_up_key.clone();
+        *self.transpose_down_key.lock().unwrap() = s.transpose_down_key.clone();
+        *self.transpose_cc.lock().unwrap() = s.transpose_cc;
+        self.transpose_cc_range.store(s.transpose_cc_range, Ordering::Relaxed);
+        self.pitch_bend_range.store(s.pitch_bend_range, Ordering::Relaxed);
+        self.min_velocity.store(s.min_velocity, Ordering::Relaxed);
+        *self.channel_dynamics.lock().unwrap() = s.channel_dynamics;
+        *self.channel_transpose.lock().unwrap() = s.channel_transpose.map(|t| t.clamp(-pipeline::MAX_CHANNEL_TRANSPOSE, pipeline::MAX_CHANNEL_TRANSPOSE));
+        *self.channel_routes.lock().unwrap() = s.channel_routes;
+        self.keep_alive_minutes.store(s.keep_alive_minutes, Ordering::Relaxed);
+        *self.keep_alive_key.lock().unwrap() = s.keep_alive_key.clone();
+        self.sustain_key_enabled.store(s.sustain_key_enabled, Ordering::Relaxed);
+        *self.sustain_key.lock().unwrap() = s.sustain_key.clone();
+        self.sustain_hold_notes.store(s.sustain_hold_notes, Ordering::Relaxed);
+        *self.typing_source.lock().unwrap() = s.typing_source;
+        self.typing_base_note.store(s.typing_base_note.min(typing::MAX_BASE_NOTE), Ordering::Relaxed);
+        self.typing_velocity.store(s.typing_velocity.clamp(1, 127), Ordering::Relaxed);
+        *self.overlay_background.lock().unwrap() = s.overlay_background;
+        self.light_mode.store(s.light_mode, Ordering::Relaxed);
+        *self.accent_color.lock().unwrap() = s.accent_color.clone();
+        *self.input_key_color.lock().unwrap() = s.input_key_color.clone();
+        *self.output_key_color.lock().unwrap() = s.output_key_color.clone();
+        *self.white_key_color.lock().unwrap() = s.white_key_color.clone();
+        *self.black_key_color.lock().unwrap() = s.black_key_color.clone();
+        self.ui_scale_percent.store(s.ui_scale_percent.clamp(config::MIN_UI_SCALE, config::MAX_UI_SCALE), Ordering::Relaxed);
+        // Re-read only on a change, so loading a profile doesn't undo unsaved edits
+        if *self.mappings_file.lock().unwrap() != s.mappings_file {
+            *self.mappings_file.lock().unwrap() = s.mappings_file.clone();
+            self.set_mappings(MappingSet::for_settings(s));
+        }
+        if *self.script_file.lock().unwrap() != s.script_file
+            && let Err(e) = self.use_script_file(&s.script_file)
+        {
+            eprintln!("Not running script {}: {}", s.script_file, e);
+        }
+    }
+
+    /// Starts a fresh recording file, replacing any running one. Returns a status line.
+    pub fn start_key_recording(&self) -> String {
+        match KeyRecorder::start() {
+            Ok(recorder) => {
+                let message = format!("Recording key events to {}", recorder.path().display());
+                self.router.lock().unwrap().recorder = Some(recorder);
+                message
+            }
+            Err(e) => format!("Failed to start key recording: {}", e),
+        }
+    }
+
+    /// Plays with `mappings` from the next note on, with the solver and without. Keys held
+    /// under the old layout are released first, as their note-offs would look up the new one.
+    pub fn set_mappings(&self, mappings: MappingSet) {
+        let mut router = self.router.lock().unwrap();
+        if let Err(e) = router.release_held() {
+            eprintln!("Releasing keys for the new mappings: {}", e);
+        }
+        router.solver.mappings = mappings.clone();
+        *self.mappings.lock().unwrap() = mappings;
+    }
+
+    // Notes during MIDI-learn: a note-on is the note to map; note-offs are swallowed with it
+    fn learn_note(&self, message: &[u8]) -> bool {
+        let mut learn = self.mapping_learn.lock().unwrap();
+        if !matches!(*learn, LearnStep::WaitingForNote | LearnStep::WaitingForKey(_)) {
+            return false;
+        }
+        let &[status, note, velocity] = message else { return false };
+        match status & 0xF0 {
+            0x90 if velocity > 0 => {
+                *learn = LearnStep::WaitingForKey(note);
+                drop(learn);
+                self.request_repaint();
+                true
+            }
+            0x80 | 0x90 => true,
+            _ => false,
+        }
+    }
+
+    /// The key pressed during MIDI-learn, with the modifiers held at the time. Returns whether
+    /// it completed a mapping; modifiers alone, keys mappings can't name, or a key before any
+    /// note don't.
+    pub fn learn_key(&self, key: KeyCode, shift: bool, ctrl: bool) -> bool {
+        let mut learn = self.mapping_learn.lock().unwrap();
+        let LearnStep::WaitingForKey(midi_note) = *learn else { return false };
+        if mappings::key_name(key).is_none() {
+            return false;
+        }
+        *learn = LearnStep::Learned(KeyMapping { midi_note, key_code: key, shift, ctrl });
+        drop(learn);
+        self.request_repaint();
+        true
+    }
+
+    /// Plays with the mapping file at `path` (or the default set, for an empty path). A file
+    /// that can't be used changes nothing.
+    pub fn use_mappings_file(&self, path: &str) -> Result<usize> {
+        let mappings = if path.is_empty() { MappingSet::load() } else { MappingSet::from_file(Path::new(path))? };
+        let count = mappings.len();
+        self.set_mappings(mappings);
+        *self.mappings_file.lock().unwrap() = path.to_string();
+        Ok(count)
+    }
+
+    /// Runs every MIDI message through the script at `path` from now on (none, for an empty
+    /// path). A script that doesn't compile changes nothing.
+    pub fn use_script_file(&self, path: &str) -> Result<()> {
+        #[cfg(feature = "scripting")]
+        {
+            let script = if path.is_empty() { None } else { Some(Script::load(Path::new(path))?) };
+            *self.script.lock().unwrap() = script;
+        }
+        #[cfg(not(feature = "scripting"))]
+        if !path.is_empty() {
+            return Err(Error::Parse("this build has no scripting (the `scripting` feature)".to_string()));
+        }
+        *self.script_file.lock().unwrap() = path.to_string();
+        Ok(())
+    }
+
+    pub fn inspect(&self, decision: Decision) {
+        if self.inspector_enabled.load(Ordering::Relaxed) {
+            self.inspector.lock().unwrap().decide(decision);
+        }
+    }
+
+    /// Called whenever state the frontend displays has changed.
+    pub fn set_repaint_hook(&self, hook: impl Fn() + Send + Sync + 'static) {
+        *self.repaint_hook.lock().unwrap() = Some(Box::new(hook));
+    }
+
+    pub fn request_repaint(&self) {
+        if let Some(hook) = self.repaint_hook.lock().unwrap().as_ref() {
+            hook();
+        }
+    }
+}
+
+pub const MAX_OCTAVE_SHIFT: i32 = 3;
+const SUSTAIN_PEDAL: Trigger = Trigger::Cc(64);
+// Note-ons at least this loud get the accent modifiers
+const ACCENT_VELOCITY: u8 = 100;
+
+/// The transpose triggers with the semitones each press taps.
+pub fn transpose_triggers(settings: &Settings) -> [(Trigger, i32); 4] {
+    [
+        (settings.transpose_up_trigger, 1),
+        (settings.transpose_down_trigger, -1),
+        (settings.transpose_octave_up_trigger, 12),
+        (settings.transpose_octave_down_trigger, -12),
+    ]
+}
+
+// Everything that happens for one incoming MIDI message: visualizer/stats bookkeeping,
+// routing (solver or legacy mappings) and the resulting key output.
+fn route_message(message: &[u8], engine: &Engine, received_at: Instant) -> Result<()> {
+    // Held chords are the arpeggiator's to play, a step at a time
+    if engine.arpeggiator_enabled.load(Ordering::Relaxed)
+        && let Some(event) = NoteEvent::from_message(message)
+    {
+        if event.is_on() {
+            engine.arpeggiator.lock().unwrap().press(event, engine.clock.now_us());
+            return engine.flush_arpeggio();
+        }
+        let off = engine.arpeggiator.lock().unwrap().release(event);
+        return match off {
+            Some(off) => play_message(&off.to_message(), engine, received_at),
+            None => Ok(()),
+        };
+    }
+    // Notes waiting for the quantize grid, humanized and rolled notes are held back by the
+    // scheduler rather than slept on, so what arrives behind them isn't held up. A note-off
+    // whose note-on is still held back waits for it.
+    if let Some(event) = NoteEvent::from_message(message) {
+        let spread = Spread {
+            humanize_us: engine.humanize_ms.load(Ordering::Relaxed) * 1000,
+            roll_us: engine.chord_roll_ms.load(Ordering::Relaxed) * 1000,
+            roll: *engine.chord_roll_direction.lock().unwrap(),
+        };
+        let now_us = engine.clock.now_us();
+        let at_us = if event.is_on() { now_us + quantize_wait_us(engine, now_us) } else { now_us };
+        let mut scheduler = engine.scheduler.lock().unwrap();
+        if spread.humanize_us > 0 || spread.roll_us > 0 || at_us > now_us || scheduler.holds_note(event) {
+            scheduler.hold(event, at_us, received_at, spread);
+            return Ok(());
+        }
+    }
+    play_message(message, engine, received_at)
+}
+
+// How long a note-on arriving at `now_us` waits for the quantize grid; 0 with quantizing off
+fn quantize_wait_us(engine: &Engine, now_us: u64) -> u64 {
+    if !engine.quantize_enabled.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let mode = *engine.quantize_mode.lock().unwrap();
+    if mode == QuantizeMode::Milliseconds {
+        let grid = engine.quantize_ms.load(Ordering::Relaxed);
+        return match (now_us / 1000).checked_rem(grid) {
+            Some(rem) if rem > 0 => (grid - rem) * 1000,
+            _ => 0,
+        };
+    }
+    if mode == QuantizeMode::Tempo {
+        // With nothing to go by yet, this note starts the grid
+        engine.quantize_anchor_us.lock().unwrap().get_or_insert(now_us);
+    }
+    // No clock grid while the sequencer is stopped: notes play as they come
+    engine.quantize_grid().map_or(0, |grid| grid.wait_us(now_us))
+}
+
+// Plays one message now
+fn play_message(message: &[u8], engine: &Engine, received_at: Instant) -> Result<()> {
+    if message.is_empty() {
+        return Err(Error::Midi("empty message".to_string()));
+    }
+    // Program changes, clock and the like; nothing to play
+    if message.len() < 3 { return Ok(()); }
+    let status = message[0] & 0xF0;
+    let channel = message[0] & 0x0F;
+    let note_original = message[1];
+    let velocity = message[2];
+    let is_note_on = status == 0x90 && velocity > 0;
+    let drop_note = |reason: DropReason| {
+        if is_note_on {
+            engine.stats.lock().unwrap().dropped(note_original, reason);
+        }
+        engine.inspect(Decision::Dropped(reason));
+    };
+
+    // Update Visualizer State (Input)
+    if status == 0x90 && velocity > 0 {
+        if let Ok(mut notes) = engine.active_notes.lock() {
+            notes.insert(note_original);
+        }
+        engine.note_history.lock().unwrap().note_on(engine.clock.now_us(), channel, note_original);
+        engine.stats.lock().unwrap().note_in();
+        engine.practice.lock().unwrap().record(note_original);
+        // Real output tracking happens below when we emit keys.
+
+        // Request UI Repaint
+        engine.request_repaint();
+    } else if status == 0x80 || (status == 0x90 && velocity == 0) {
+        if let Ok(mut notes) = engine.active_notes.lock() {
+            notes.remove(&note_original);
+        }
+        engine.note_history.lock().unwrap().note_off(engine.clock.now_us(), channel, note_original);
+        // Note Off Repaint
+        engine.request_repaint();
+    }
+
+    let routing = engine.routing();
+
+    // Follow the dynamics before the note sounds, so it plays at the new level
+    if is_note_on && *engine.volume_source.lock().unwrap() == VolumeSource::Velocity {
+        let mut state = engine.router.lock().unwrap();
+        let taps = state.volume.on_velocity(velocity, engine.volume_steps.load(Ordering::Relaxed) as u32);
+        state.tap_volume(&taps, &engine.volume_up_key.lock().unwrap(), &engine.volume_down_key.lock().unwrap())?;
+    }
+
+    // Shown on the keyboard above, and that's all
+    let route = engine.channel_routes.lock().unwrap()[channel as usize];
+    if route == ChannelRoute::Visualize {
+        drop_note(DropReason::ChannelFiltered);
+        return Ok(());
+    }
+
+    // Drum games take every channel; in dual-instrument mode the drum channels play the drum
+    // kit and the rest stays on the piano/guitar. A hit is a tap, so note-offs have nothing to do.
+    let is_drum_hit = routing.game_mode == GameMode::Drums || (route == ChannelRoute::Drums && engine.dual_drums_enabled.load(Ordering::Relaxed));
+    if is_drum_hit {
+        if !is_note_on {
+            return Ok(());
+        }
+        let pad = engine.drum_map.lock().unwrap().get(note_original).cloned();
+        let Some(pad) = pad else {
+            drop_note(DropReason::NoMapping);
+            return Err(Error::Mapping { note: note_original });
+        };
+        let mut state = engine.router.lock().unwrap();
+        let key = state.next_drum_key(&pad);
+        state.tap_drum(key)?;
+        engine.stats.lock().unwrap().played(note_original, key, received_at.elapsed());
+        engine.inspect(Decision::Played { note: note_original, key, transpose: 0 });
+        return Ok(());
+    }
+
+    // Drum channels with no drum kit to play them
+    if route == ChannelRoute::Drums {
+        drop_note(DropReason::DrumChannel);
+        return Ok(());
+    }
+
+    // Past the polyphony limit the new note is dropped, or a held one let go to make room
+    if is_note_on {
+        let (limit, priority) = (engine.max_polyphony.load(Ordering::Relaxed) as usize, *engine.polyphony_priority.lock().unwrap());
+        let admit = engine.polyphony.lock().unwrap().on(channel, note_original, limit, priority);
+        match admit {
+            Admit::Play => {}
+            Admit::Drop => {
+                drop_note(DropReason::Polyphony);
+                return Ok(());
+            }
+            Admit::Steal { channel, note } => {
+                play_message(&[0x80 | channel, note, 0], engine, received_at)?;
+                engine.polyphony.lock().unwrap().stole(channel, note);
+            }
+        }
+    } else if (status == 0x80 || status == 0x90) && !engine.polyphony.lock().unwrap().off(channel, note_original) {
+        return Ok(());
+    }
+
+    // Guitar games: the note holds its fret key, and the strum follows once the chord is in
+    if routing.game_mode == GameMode::Guitar {
+        let strum_window_ms = engine.strum_window_ms.load(Ordering::Relaxed);
+        let is_note_off = status == 0x80 || (status == 0x90 && velocity == 0);
+        let fret = engine.guitar_map.lock().unwrap().fret(note_original);
+        let Some(fret) = fret else {
+            drop_note(DropReason::NoMapping);
+            return if is_note_on { Err(Error::Mapping { note: note_original }) } else { Ok(()) };
+        };
+        let mut state = engine.router.lock().unwrap();
+        if is_note_on {
+            let notes = state.frets.entry(fret).or_default();
+            let first = notes.is_empty();
+            notes.insert(note_original);
+            if first {
+                state.key_down(fret)?;
+            }
+            if state.strum_due_us.is_none() {
+                let window_us = strum_window_ms * 1000;
+                state.strum_due_us = Some(engine.clock.now_us() + window_us);
+            }
+            if let Ok(mut out_notes) = engine.active_output_notes.lock() { out_notes.insert(note_original); }
+            engine.stats.lock().unwrap().played(note_original, fret, received_at.elapsed());
+            engine.inspect(Decision::Played { note: note_original, key: fret, transpose: 0 });
+            drop(state);
+            // Without a window the chord is just this note
+            if strum_window_ms == 0 {
+                engine.flush_strum()?;
+            }
+        } else if is_note_off {
+            if let Ok(mut out_notes) = engine.active_output_notes.lock() { out_notes.remove(&note_original); }
+            let notes = state.frets.entry(fret).or_default();
+            if notes.remove(&note_original) && notes.is_empty() {
+                state.key_up(fret)?;
+                engine.inspect(Decision::Released { key: fret });
+            }
+        }
+        return Ok(());
+    }
+
+    // Validate Note
+    let use_solver = engine.solver_enabled.load(Ordering::Relaxed);
+    let accent = if velocity >= ACCENT_VELOCITY { engine.modifiers_with(ModifierRole::Accent) } else { Vec::new() };
+
+    let final_note = if use_solver {
+        note_original
+    } else {
+        match pipeline::resolve_note(&routing, note_original) {
+            Ok(n) => n,
+            Err(reason) => {
+                drop_note(reason);
+                return Ok(());
+            }
+        }
+    };
+
+    if use_solver {
+        let mut state = engine.router.lock().unwrap();
+        if status == 0x90 && velocity > 0 {
+            let mode = if engine.solver_mode_efficiency.load(Ordering::Relaxed) { SolverMode::Efficiency } else { SolverMode::Accuracy };
+            let max_jump = engine.solver_max_jump.load(Ordering::Relaxed) as i32;
+            let range = engine.transpose_range.load(Ordering::Relaxed) as i32;
+            state.solver.key_range = routing.game_mode.key_range();
+            state.solver.allow_shift = pipeline::solver_allows_shift(&routing);
+            state.solver.allow_ctrl = routing.ctrl_role == ModifierRole::Range;
+            state.solver.allow_steal = !engine.solver_drop_steals.load(Ordering::Relaxed);
+            state.solver.hold_transpose = engine.solver_hold_transpose.load(Ordering::Relaxed);
+
+            let at_us = engine.clock.now_us();
+            // A folded black key is solved as its white key, but tracked as the note played
+            let solved_note = pipeline::fold_black_key(&routing, note_original);
+            if engine.solver_log_enabled.load(Ordering::Relaxed) {
+                let decision = state.solver.explain(solved_note, at_us, mode, max_jump, range);
+                engine.solver_log.lock().unwrap().push(decision);
+            }
+            if let Some((delta, mapping)) = state.solver.solve(solved_note, at_us, mode, max_jump, range) {
+                // Track Output
+                if let Ok(mut out_notes) = engine.active_output_notes.lock() {
+                    out_notes.insert(note_original);
+                }
+
+                // Adjust Transpose
+                let current = state.solver.current_transpose;
+                if delta != current {
+                    let diff = delta - current;
+                    let key = engine.transpose_key(diff > 0);
+                    for _ in 0..diff.abs() {
+                        state.tap(key)?;
+                        engine.clock.sleep(time::Duration::from_micros(solver::TRANSPOSE_TAP_US));
+                    }
+                    state.current_transpose_offset = delta;
+                    engine.stats.lock().unwrap().transposed(delta, diff.unsigned_abs() as u64);
+                }
+
+                // Press Note
+                // Handle Active Key "Stealing"
+                // The solver now allows returning a busy key with a penalty.
+                // Check if key is physically held?
+                // state.solver.active_keys tracks keys with active notes.
+                if state.solver.active_keys.contains_key(&mapping.key_code) && !state.solver.active_keys[&mapping.key_code].is_empty() {
+                     // Force Release first
+                     state.key_up(mapping.key_code)?;
+                     engine.clock.sleep(time::Duration::from_millis(5)); // Brief pause
+                     engine.stats.lock().unwrap().stolen();
+                }
+
+                // Modifiers and the key in one write, so the game never sees one without the other
+                state.batch(|state| {
+                    if mapping.shift && !state.solver.shift_active {
+                        state.key_down(KeyCode::KEY_LEFTSHIFT)?;
+                    } else if !mapping.shift && state.solver.shift_active {
+                        state.key_up(KeyCode::KEY_LEFTSHIFT)?;
+                    }
+
+                    if mapping.ctrl && !state.solver.ctrl_active {
+                        state.key_down(KeyCode::KEY_LEFTCTRL)?;
+                    } else if !mapping.ctrl && state.solver.ctrl_active {
+                        state.key_up(KeyCode::KEY_LEFTCTRL)?;
+                    }
+
+                    state.press(mapping.key_code, &accent)
+                })?;
+                state.solver.register_note_on(mapping.key_code, note_original, at_us, delta, mapping.shift, mapping.ctrl);
+                state.thru_on(channel, note_original, mapping.midi_note as i32 + delta, velocity);
+                engine.stats.lock().unwrap().played(note_original, mapping.key_code, received_at.elapsed());
+                engine.inspect(Decision::Played { note: note_original, key: mapping.key_code, transpose: delta });
+            } else {
+                let busy = state.solver.needs_steal(solved_note, at_us, mode, max_jump, range);
+                drop_note(if busy { DropReason::KeyBusy } else { DropReason::NoSolverMatch });
+                return Err(Error::Solver { note: note_original });
+            }
+        } else if status == 0x80 || (status == 0x90 && velocity == 0) {
+            // Track Output Removel, even if another note still holds the same key
+            if let Ok(mut out_notes) = engine.active_output_notes.lock() {
+                out_notes.remove(&note_original);
+            }
+
+            state.thru_off(channel, note_original);
+            let keys = state.solver.register_note_off(note_original);
+            if !keys.is_empty() {
+                for &key in &keys {
+                    state.key_up(key)?;
+                    engine.inspect(Decision::Released { key });
+                }
+
+                // Modifiers cleanup (only those the solver drives)
+                if !state.solver.shift_active && routing.shift_role == ModifierRole::Range {
+                    state.key_up(KeyCode::KEY_LEFTSHIFT)?;
+                }
+                if !state.solver.ctrl_active && routing.ctrl_role == ModifierRole::Range {
+                    state.key_up(KeyCode::KEY_LEFTCTRL)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Legacy Logic
+    let use_experimental_transpose = engine.experimental_transpose_enabled.load(Ordering::Relaxed);
+    let use_hold_ctrl = engine.experimental_hold_ctrl_enabled.load(Ordering::Relaxed);
+
+    let mapping = engine.mappings.lock().unwrap().get(final_note).copied();
+    if let Some(mapping) = mapping.filter(|m| pipeline::is_mapping_usable(&routing, m)) {
+        let mut state = engine.router.lock().unwrap();
+        let mapping_code = mapping.key_code;
+        let mapping_shift = mapping.shift;
+        let mapping_ctrl = mapping.ctrl;
+
+        if status == 0x90 && velocity > 0 {
+            if let Ok(mut out_notes) = engine.active_output_notes.lock() { out_notes.insert(note_original); }
+
+            let mut handled_transpose = false;
+
+            if use_experimental_transpose {
+                let use_lazy = engine.lazy_transpose_enabled.load(Ordering::Relaxed);
+                if use_lazy {
+                    let target_offset = if mapping_shift && !mapping_ctrl { 1 } else { 0 };
+                    let current_offset = state.current_transpose_offset;
+                    if target_offset != current_offset {
+                        let delay_ms = engine.transpose_delay_ms.load(Ordering::Relaxed);
+                        if target_offset > current_offset {
+                            state.tap(engine.transpose_key(true))?;
+                        } else {
+                            state.tap(engine.transpose_key(false))?;
+                        }
+                        if delay_ms > 0 {
+                            drop(state);
+                            engine.clock.sleep(time::Duration::from_millis(delay_ms));
+                            state = engine.router.lock().unwrap();
+                        }
+                        state.current_transpose_offset = target_offset;
+                        engine.stats.lock().unwrap().transposed(target_offset, 1);
+                    }
+                    handled_transpose = true;
+                } else {
+                    state.current_transpose_offset = 0; 
+                }
+            }
+
+            if mapping_ctrl {
+                state.batch(|state| {
+                    state.key_down(KeyCode::KEY_LEFTCTRL)?;
+                    state.press(mapping_code, &accent)?;
+                    if !use_hold_ctrl {
+                        state.key_up(mapping_code)?;
+                    }
+                    state.key_up(KeyCode::KEY_LEFTCTRL)
+                })?;
+            } else if mapping_shift {
+                if use_experimental_transpose {
+                    if handled_transpose {
+                        state.press(mapping_code, &accent)?;
+                    } else {
+                        let delay_ms = engine.transpose_delay_ms.load(Ordering::Relaxed);
+                        state.tap(engine.transpose_key(true))?;
+                        if delay_ms > 0 { drop(state); engine.clock.sleep(time::Duration::from_millis(delay_ms)); state = engine.router.lock().unwrap(); }
+                        state.press(mapping_code, &accent)?;
+                        if delay_ms > 0 { drop(state); engine.clock.sleep(time::Duration::from_millis(delay_ms)); state = engine.router.lock().unwrap(); }
+                        state.tap(engine.transpose_key(false))?;
+                        // Up for the note, then back down
+                        let mut stats = engine.stats.lock().unwrap();
+                        stats.transposed(1, 1);
+                        stats.transposed(0, 1);
+                    }
+                } else {
+                    state.batch(|state| {
+                        state.key_down(KeyCode::KEY_LEFTSHIFT)?;
+                        state.press(mapping_code, &accent)?;
+                        state.key_up(mapping_code)?;
+                        state.key_up(KeyCode::KEY_LEFTSHIFT)
+                    })?;
+                }
+            } else {
+                 state.press(mapping_code, &accent)?;
+            }
+            state.thru_on(channel, note_original, final_note as i32, velocity);
+            engine.stats.lock().unwrap().played(note_original, mapping_code, received_at.elapsed());
+            engine.inspect(Decision::Played { note: final_note, key: mapping_code, transpose: state.current_transpose_offset });
+        }
+        else if status == 0x80 || (status == 0x90 && velocity == 0) {
+             if let Ok(mut out_notes) = engine.active_output_notes.lock() { out_notes.remove(&note_original); }
+             state.thru_off(channel, note_original);
+
+             // Only keys that were left held on press need a release
+             let held = (mapping_ctrl && use_hold_ctrl)
+                 || (mapping_shift && use_experimental_transpose)
+                 || (!mapping_shift && !mapping_ctrl);
+             if held {
+                 state.key_up(mapping_code)?;
+                 engine.inspect(Decision::Released { key: mapping_code });
+             }
+        }
+    } else {
+        drop_note(DropReason::NoMapping);
+        if is_note_on {
+            return Err(Error::Mapping { note: final_note });
+        }
+    }
+    Ok(())
+}
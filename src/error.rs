@@ -0,0 +1,60 @@
+use std::fmt;
+use std::io;
+
+use crate::midifile;
+
+// Crate-wide error type. Variants say which part failed so callers can pick what to
+// recover from: a dropped note is routine, a dead output device is not.
+
+#[derive(Debug)]
+pub enum Error {
+    Midi(String),         // Malformed MIDI message or file, MIDI port trouble
+    Device(io::Error),    // Sending key events failed
+    Mapping { note: u8 }, // No key mapped for the note
+    Solver { note: u8 },  // The solver found no key/transpose within its limits
+    Parse(String),        // A file we read (profile, mappings, recording) is malformed
+    Io(io::Error),        // Reading or writing files
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The note just couldn't be played; already counted as a drop in the session stats.
+    pub fn is_dropped_note(&self) -> bool {
+        matches!(self, Error::Mapping { .. } | Error::Solver { .. })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Midi(msg) => write!(f, "MIDI error: {}", msg),
+            Error::Device(e) => write!(f, "Key output failed: {}", e),
+            Error::Mapping { note } => write!(f, "No mapping for {}", midifile::note_name(*note)),
+            Error::Solver { note } => write!(f, "Solver found no way to play {}", midifile::note_name(*note)),
+            Error::Parse(msg) => write!(f, "{}", msg),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Device(e) | Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
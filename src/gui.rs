@@ -0,0 +1,3020 @@
+use eframe::egui;
+use evdev::KeyCode;
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time;
+
+use miditoroblox_rs::config::{self, ArpPattern, BlackKeys, ChannelDynamics, ChannelRoute, GameMode, ModifierRole, NotePriority, OverlayBackground, QuantizeMode, RollDirection, ScaleMode, Trigger, TypingSource, VolumeSource, WindowRule};
+use miditoroblox_rs::hotkeys::HotkeyAction;
+use miditoroblox_rs::inspector::{self, Decision};
+use miditoroblox_rs::mappings::{self, KeyMapping, LearnStep, MappingSet, KEY_NAMES};
+use miditoroblox_rs::output::{self, KeyOutput};
+use miditoroblox_rs::piano::{self, PianoLayout};
+use miditoroblox_rs::processor::ParamValue;
+use miditoroblox_rs::stats::{self, DropReason, SessionStats};
+use miditoroblox_rs::metronome::{self, BeatSource};
+use miditoroblox_rs::engine::MAX_OCTAVE_SHIFT;
+use miditoroblox_rs::remote::RemoteRequest;
+use miditoroblox_rs::quantize;
+use miditoroblox_rs::qwerty::{self, KeyLights};
+use miditoroblox_rs::roll::{self, NoteSpan};
+use miditoroblox_rs::startup::{self, Startup};
+use miditoroblox_rs::typing::{self, TypedNotes};
+use miditoroblox_rs::worker::MidiQueue;
+use miditoroblox_rs::{arpeggiator, ble, guitar, hotkeys, hotplug, http, keepalive, midifile, mpris, osc, pipeline, playability, player, sheet, recording, scheduler, shutdown, simplify, solver, thru, wayland, window_watch, worker, Engine};
+
+struct MidiApp {
+    midi_input: Option<MidiInput>,
+    available_ports: Vec<(String, MidiInputPort)>,
+    selected_port_name: Option<String>,
+    connections: Vec<InputConnection>, // Every open port plays into the same engine
+    known_ports: Vec<String>, // Port list as of the last hot-plug check
+    lost_ports: Vec<(String, bool)>, // Unplugged while connected (with their enable state), reconnected when back
+    engine: Arc<Engine>,
+    queue: MidiQueue, // Every input port's messages go through this to the engine's worker
+    status_message: String,
+    window_opacity: f32,
+    window_state: config::WindowState, // Geometry as of the last frame, saved on exit
+    level_pending: bool, // Always-on-top restored from the last session, for the first frame to apply
+    ui_scale_percent: u64, // Zoom last applied to the window, to tell profile loads from Ctrl +/-
+    always_on_top: bool,
+    midi_file_path: String,
+    midi_file: Option<midifile::MidiFile>,
+    playability: Option<playability::PlayabilityReport>,
+    replay_path: String,
+    replay: Option<(String, Vec<recording::RecordedEvent>)>,
+    compare_profiles: (String, String),
+    comparison: Option<String>,
+    inspector_filter: String,
+    hotkey_keyboards: Vec<String>, // Keyboards the hotkeys listen on
+    partner: Option<Partner>,
+    viewer_open: bool, // Audience window with big note and chord names
+    overlay_open: bool, // Stream overlay window with just the visualizer
+    playlist: Vec<midifile::MidiFile>,
+    playlist_gap_s: u64,
+    profile_name: String, // Name the header's Save uses; the loaded profile's by default
+    mapping_draft: Vec<KeyMapping>, // The mapping editor's rows, played once applied
+    mappings_file_path: String,
+    script_file_path: String,
+    mapping_set_name: String, // Name "Save as Set" stores the editor's rows under
+    thru_ports: Vec<String>,
+    thru_port_name: Option<String>, // Output port picked for MIDI thru
+    thru_connected: Option<String>,
+    ble: ble::SharedBle, // Bluetooth MIDI devices found and connected, playing into `queue` too
+    typed: TypedNotes,   // Notes held by keys typed into this window
+    clicked_note: Option<u8>, // Visualizer key held down with the pointer
+    key_lights: KeyLights,    // Output keys pressed lately, for the keyboard view
+}
+
+// One open MIDI input port. A disabled port stays open, its messages are just dropped.
+struct InputConnection {
+    port_name: String,
+    enabled: Arc<AtomicBool>,
+    _connection: MidiInputConnection<(MidiQueue, Arc<AtomicBool>)>,
+}
+
+// The second player in duet mode: an engine of its own (solver, transpose, profile) on its
+// own MIDI port and virtual keyboard, so neither player moves the other's transpose.
+struct Partner {
+    engine: Arc<Engine>,
+    midi_input: Option<MidiInput>,
+    ports: Vec<(String, MidiInputPort)>,
+    selected_port_name: Option<String>,
+    connection: Option<MidiInputConnection<MidiQueue>>,
+    queue: MidiQueue,
+    profile: Option<String>,
+}
+
+impl Partner {
+    fn new(output: KeyOutput, settings: &config::Settings, mappings: MappingSet) -> Self {
+        let engine = Arc::new(Engine::new(output));
+        shutdown::register(&engine);
+        // Starts out playing like the first player until given a profile of its own
+        engine.apply_settings(settings);
+        engine.set_mappings(mappings);
+        guitar::spawn_strummer(engine.clone());
+        scheduler::spawn(engine.clone());
+        arpeggiator::spawn(engine.clone());
+        let mut partner = Self {
+            queue: worker::spawn(engine.clone()),
+            engine,
+            midi_input: MidiInput::new("Miditoroblox Player 2").ok(),
+            ports: Vec::new(),
+            selected_port_name: None,
+            connection: None,
+            profile: None,
+        };
+        partner.refresh_ports();
+        partner
+    }
+
+    fn refresh_ports(&mut self) {
+        let Some(midi_in) = &self.midi_input else { return };
+        self.ports = midi_in
+            .ports()
+            .into_iter()
+            .map(|port| (midi_in.port_name(&port).unwrap_or_else(|_| "Unknown".to_string()), port))
+            .collect();
+        if self.selected_port_name.as_ref().is_some_and(|selected| !self.ports.iter().any(|(n, _)| n == selected)) {
+            self.selected_port_name = None;
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.engine.processors.lock().unwrap().reset();
+        let _ = self.engine.router.lock().unwrap().release_sostenuto();
+        self.engine.router.lock().unwrap().volume.reset();
+        if self.midi_input.is_none() {
+            self.midi_input = MidiInput::new("Miditoroblox Player 2").ok();
+        }
+        self.refresh_ports();
+    }
+}
+
+// Off/CC/Note picker for a MIDI control; `cc` is what picking "CC" starts out as
+fn trigger_picker(ui: &mut egui::Ui, id: &str, shared: &std::sync::Mutex<Trigger>, cc: Trigger) {
+    let mut trigger = *shared.lock().unwrap();
+    let before = trigger;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(match trigger {
+            Trigger::Off => "Off",
+            Trigger::Cc(_) => "CC",
+            Trigger::Note(_) => "Note",
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut trigger, Trigger::Off, "Off");
+            ui.selectable_value(&mut trigger, cc, "CC");
+            ui.selectable_value(&mut trigger, Trigger::Note(21), "Note");
+        });
+    match &mut trigger {
+        Trigger::Cc(cc) => {
+            ui.add(egui::DragValue::new(cc).range(0..=127));
+        }
+        Trigger::Note(note) => {
+            ui.add(egui::DragValue::new(note).range(0..=127));
+            ui.label(midifile::note_name(*note));
+        }
+        Trigger::Off => {}
+    }
+    if trigger != before {
+        *shared.lock().unwrap() = trigger;
+    }
+}
+
+// The evdev key for a key typed into the window, if mappings can name it
+fn egui_key_code(key: egui::Key) -> Option<KeyCode> {
+    // egui's names match evdev's for letters, digits, arrows, Space, Minus and Page Up/Down
+    let name = match key {
+        egui::Key::Equals => "KEY_EQUAL".to_string(),
+        _ => format!("KEY_{}", key.name().to_uppercase()),
+    };
+    KEY_NAMES.iter().find(|(known, _)| *known == name).map(|(_, code)| *code)
+}
+
+// MIDI input callback, shared by both players: queued for the engine's worker, nothing more
+fn handle_midi(_stamp: u64, message: &[u8], queue: &mut MidiQueue) {
+    queue.push(message);
+}
+
+// MIDI input callback for the first player's ports, each with its own enable toggle
+fn handle_port_midi(stamp: u64, message: &[u8], (queue, enabled): &mut (MidiQueue, Arc<AtomicBool>)) {
+    if enabled.load(Ordering::Relaxed) {
+        handle_midi(stamp, message, queue);
+    }
+}
+
+// Closing the window: the engines outlive the app in their background threads, so their
+// keys have to be let go of here
+impl Drop for MidiApp {
+    fn drop(&mut self) {
+        self.engine.release_on_exit();
+        if let Some(partner) = &self.partner {
+            partner.engine.release_on_exit();
+        }
+        self.window_state.opacity = self.window_opacity;
+        self.window_state.always_on_top = self.always_on_top;
+        if let Err(e) = config::save_window_state(&self.window_state) {
+            eprintln!("Failed to save the window state: {}", e);
+        }
+    }
+}
+
+impl MidiApp {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        output: KeyOutput,
+        drum_output: Option<KeyOutput>,
+        startup: Startup,
+    ) -> Self {
+        let engine = Arc::new(Engine::new(output));
+        let window_state = config::load_window_state();
+        let mut app = Self {
+            midi_input: Some(MidiInput::new("Miditoroblox Input").unwrap()),
+            available_ports: Vec::new(),
+            selected_port_name: None,
+            connections: Vec::new(),
+            known_ports: Vec::new(),
+            lost_ports: Vec::new(),
+            queue: worker::spawn(engine.clone()),
+            engine,
+            status_message: "Ready".to_string(),
+            window_opacity: window_state.opacity,
+            level_pending: window_state.always_on_top,
+            always_on_top: window_state.always_on_top,
+            window_state,
+            ui_scale_percent: 100,
+            midi_file_path: String::new(),
+            midi_file: None,
+            playability: None,
+            replay_path: String::new(),
+            replay: None,
+            compare_profiles: (String::new(), String::new()),
+            comparison: None,
+            inspector_filter: String::new(),
+            hotkey_keyboards: Vec::new(),
+            partner: None,
+            viewer_open: false,
+            overlay_open: false,
+            playlist: Vec::new(),
+            playlist_gap_s: 5,
+            profile_name: String::new(),
+            mapping_draft: Vec::new(),
+            mappings_file_path: String::new(),
+            script_file_path: String::new(),
+            mapping_set_name: String::new(),
+            thru_ports: thru::port_names(),
+            thru_port_name: None,
+            thru_connected: None,
+            ble: ble::SharedBle::default(),
+            typed: TypedNotes::default(),
+            clicked_note: None,
+            key_lights: KeyLights::default(),
+        };
+        shutdown::register(&app.engine);
+        app.engine.router.lock().unwrap().drum_output = drum_output;
+        app.engine.set_mappings(MappingSet::load());
+        app.engine.apply_settings(&startup.settings);
+        if !startup.settings.mappings_file.is_empty() {
+            app.mappings_file_path = startup.settings.mappings_file.clone();
+            app.load_mappings_file();
+        }
+        app.script_file_path = app.engine.script_file.lock().unwrap().clone();
+        if let Some(profile) = &startup.profile {
+            app.profile_name = profile.clone();
+        }
+        *app.engine.active_profile.lock().unwrap() = startup.profile.clone();
+        app.mapping_draft = app.engine.mappings.lock().unwrap().iter().copied().collect();
+
+        // The theme and the opacity the window closed with, from the first frame
+        cc.egui_ctx.set_visuals(theme_visuals(&app.engine, app.window_opacity));
+
+        // Background threads (MIDI, replay, window watcher) ask for repaints through the engine
+        let ctx = cc.egui_ctx.clone();
+        app.engine.set_repaint_hook(move || ctx.request_repaint());
+
+        window_watch::spawn(app.engine.clone());
+        guitar::spawn_strummer(app.engine.clone());
+        scheduler::spawn(app.engine.clone());
+        arpeggiator::spawn(app.engine.clone());
+        metronome::spawn(app.engine.clone());
+        keepalive::spawn(app.engine.clone());
+        hotplug::spawn(app.engine.clone());
+        app.hotkey_keyboards = hotkeys::spawn(app.engine.clone());
+        if let Err(e) = mpris::spawn(app.engine.clone()) {
+            eprintln!("MPRIS: {}", e);
+        }
+        if let Some(addr) = &startup.osc {
+            app.status_message = match osc::spawn(addr, app.queue.clone()) {
+                Ok(local) => format!("Playing OSC sent to {}", local),
+                Err(e) => format!("Can't take OSC on {}: {}", addr, e),
+            };
+        }
+        if let Some(addr) = &startup.http {
+            app.status_message = match http::spawn(app.engine.clone(), addr, startup.token.clone()) {
+                Ok(local) => format!("Status and control on http://{}", local),
+                Err(e) => format!("HTTP server couldn't listen on {}: {}", addr, e),
+            };
+        }
+        #[cfg(feature = "remote")]
+        if let Some(addr) = &startup.websocket {
+            app.status_message = match miditoroblox_rs::websocket::spawn(app.engine.clone(), addr, startup.token.clone()) {
+                Ok(local) => format!("Remote control on ws://{}", local),
+                Err(e) => format!("Remote control couldn't listen on {}: {}", addr, e),
+            };
+        }
+
+        if let Some(wanted) = &startup.ble {
+            ble::connect_in_background(&app.ble, wanted.clone(), app.engine.clone(), app.queue.clone());
+            app.status_message = format!("Looking for Bluetooth MIDI device '{}'", wanted);
+        }
+
+        app.refresh_ports();
+        let port = startup.find_port(app.available_ports.iter().map(|(name, _)| name)).cloned();
+        match port {
+            Some(port) => {
+                app.selected_port_name = Some(port.clone());
+                if startup.connect && app.connect_port(&port) && app.engine.record_keys_enabled.load(Ordering::Relaxed) {
+                    app.status_message = app.engine.start_key_recording();
+                }
+            }
+            None if startup.port.is_some() || startup.connect => {
+                app.status_message = format!("No MIDI input matching '{}'", startup.port.as_deref().unwrap_or_default());
+            }
+            None => {}
+        }
+        app
+    }
+
+    fn refresh_ports(&mut self) {
+        let midi_in = match &self.midi_input {
+            Some(m) => m,
+            None => {
+                // If we don't have one (shouldn't happen unless we failed to create it earlier), try create one
+                 match MidiInput::new("Miditoroblox Input") {
+                     Ok(m) => {
+                         self.midi_input = Some(m);
+                         self.midi_input.as_ref().unwrap()
+                     },
+                     Err(e) => {
+                         self.status_message = format!("Failed to create MidiInput: {}", e);
+                         return;
+                     }
+                 }
+            }
+        };
+
+        self.available_ports.clear();
+        for port in midi_in.ports() {
+            let name = midi_in.port_name(&port).unwrap_or_else(|_| "Unknown".to_string());
+            self.available_ports.push((name, port));
+        }
+        
+        // Reset selection if invalid
+        if let Some(selected) = &self.selected_port_name
+            && !self.available_ports.iter().any(|(n, _)| n == selected)
+        {
+            self.selected_port_name = None;
+        }
+        
+        // Auto-select first if none selected and ports exist
+        if self.selected_port_name.is_none() && !self.available_ports.is_empty() {
+             self.selected_port_name = Some(self.available_ports[0].0.clone());
+        }
+    }
+
+    // Opens another input port. The port list comes from `midi_input`; every connection gets
+    // a client of its own, since midir gives the client up to the connection.
+    fn connect_port(&mut self, port_name: &str) -> bool {
+        let Some((_, port)) = self.available_ports.iter().find(|(n, _)| n == port_name) else {
+            return false;
+        };
+        let midi_in = match MidiInput::new("Miditoroblox Input") {
+            Ok(m) => m,
+            Err(e) => {
+                self.status_message = format!("Failed to create MidiInput: {}", e);
+                return false;
+            }
+        };
+        let enabled = Arc::new(AtomicBool::new(true));
+        match midi_in.connect(port, "miditoroblox-in", handle_port_midi, (self.queue.clone(), enabled.clone())) {
+            Ok(connection) => {
+                self.connections.push(InputConnection { port_name: port_name.to_string(), enabled, _connection: connection });
+                self.status_message = format!("Connected to {}", port_name);
+                true
+            }
+            Err(e) => {
+                self.status_message = format!("Error connecting: {}", e);
+                false
+            }
+        }
+    }
+
+    // The first input of a session: fresh stats, and a key recording if that's on
+    fn start_session(&mut self, port_name: &str) {
+        *self.engine.stats.lock().unwrap() = SessionStats::new();
+        if self.connect_port(port_name) && self.engine.record_keys_enabled.load(Ordering::Relaxed) {
+            self.status_message = self.engine.start_key_recording();
+        }
+    }
+
+    // Closes every input and wraps the session up
+    fn end_session(&mut self) {
+        self.connections.clear();
+        self.lost_ports.clear();
+        self.status_message = "Disconnected".to_string();
+        if self.engine.export_stats_on_disconnect.load(Ordering::Relaxed) {
+            self.export_stats();
+        }
+        self.engine.router.lock().unwrap().recorder = None;
+        self.engine.processors.lock().unwrap().reset();
+        let _ = self.engine.router.lock().unwrap().release_sostenuto();
+        self.engine.router.lock().unwrap().volume.reset();
+        self.refresh_ports();
+    }
+
+    // Connects and disconnects asked for by remote control (remote.rs)
+    fn follow_remote(&mut self) {
+        let requests = std::mem::take(&mut *self.engine.remote_requests.lock().unwrap());
+        for request in requests {
+            match request {
+                RemoteRequest::Connect(wanted) => {
+                    self.refresh_ports();
+                    let port = match &wanted {
+                        Some(wanted) => startup::find_port(Some(wanted), self.available_ports.iter().map(|(name, _)| name)).cloned(),
+                        None => self.selected_port_name.clone(),
+                    };
+                    let Some(port) = port else {
+                        self.status_message = format!("No MIDI input matching '{}'", wanted.unwrap_or_default());
+                        continue;
+                    };
+                    self.selected_port_name = Some(port.clone());
+                    if self.connections.is_empty() {
+                        self.start_session(&port);
+                    } else if !self.connections.iter().any(|c| c.port_name == port) {
+                        self.connect_port(&port);
+                    }
+                }
+                RemoteRequest::Disconnect if !self.connections.is_empty() => self.end_session(),
+                RemoteRequest::Disconnect => {}
+            }
+        }
+    }
+
+    // Follows the port watch: connections whose device went away are dropped, and come back
+    // once a port of the same device shows up again.
+    fn follow_hotplug(&mut self) {
+        let present = self.engine.midi_ports.lock().unwrap().clone();
+        if present == self.known_ports {
+            return;
+        }
+        let known = std::mem::replace(&mut self.known_ports, present.clone());
+        self.refresh_ports();
+
+        // Only ports seen going away count; one opened since the last poll just isn't listed yet
+        let (gone, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.connections)
+            .into_iter()
+            .partition(|c| known.contains(&c.port_name) && !present.contains(&c.port_name));
+        self.connections = kept;
+        if !gone.is_empty() {
+            // Their note-offs are never coming
+            let _ = self.engine.router.lock().unwrap().release_held();
+        }
+        for input in gone {
+            self.status_message = format!("Lost {}; reconnecting when it's back", input.port_name);
+            self.lost_ports.push((input.port_name, input.enabled.load(Ordering::Relaxed)));
+        }
+
+        for (name, enabled) in std::mem::take(&mut self.lost_ports) {
+            let back = present.iter().find(|p| hotplug::device_name(p) == hotplug::device_name(&name)).cloned();
+            match back {
+                Some(port_name) if self.connect_port(&port_name) => {
+                    if let Some(input) = self.connections.last() {
+                        input.enabled.store(enabled, Ordering::Relaxed);
+                    }
+                    self.status_message = format!("Reconnected to {}", port_name);
+                }
+                _ => self.lost_ports.push((name, enabled)),
+            }
+        }
+    }
+
+    fn load_midi_file(&mut self) {
+        match midifile::MidiFile::load(std::path::Path::new(self.midi_file_path.trim())) {
+            Ok(file) => {
+                self.status_message = format!("Loaded '{}' ({} notes)", file.name, file.note_ons().count());
+                self.midi_file = Some(file);
+                self.playability = None;
+            }
+            Err(e) => self.status_message = e.to_string(),
+        }
+    }
+
+    // Both players play the same layout
+    fn apply_mappings(&self, mappings: &MappingSet) {
+        self.engine.set_mappings(mappings.clone());
+        if let Some(partner) = &self.partner {
+            partner.engine.set_mappings(mappings.clone());
+        }
+    }
+
+    // Keys typed into this window as notes, while typing reads the window and no text field
+    // has the keys. Everything held is let go when the window loses focus: the releases
+    // would go to whatever has it instead.
+    fn follow_typing(&mut self, ctx: &egui::Context) {
+        let source = *self.engine.typing_source.lock().unwrap();
+        if source != TypingSource::Window && self.typed.is_empty() {
+            return;
+        }
+        let learning = matches!(*self.engine.mapping_learn.lock().unwrap(), LearnStep::WaitingForKey(_));
+        let typing = !ctx.wants_keyboard_input() && !learning;
+        let keys: Vec<(egui::Key, bool)> = ctx.input(|i| {
+            i.events
+                .iter()
+                .filter_map(|e| match e {
+                    egui::Event::Key { key, pressed, repeat: false, .. } if typing || !pressed => Some((*key, *pressed)),
+                    _ => None,
+                })
+                .collect()
+        });
+        for (key, pressed) in keys {
+            if let Some(c) = egui_key_code(key).and_then(typing::char_for_key)
+                && let Some(message) = self.typed.key(&self.engine, TypingSource::Window, c, pressed)
+            {
+                self.queue.push(&message);
+            }
+        }
+        if !ctx.input(|i| i.focused) {
+            for message in self.typed.release_all() {
+                self.queue.push(&message);
+            }
+        }
+    }
+
+    fn apply_window_level(&mut self, ctx: &egui::Context) {
+        let level = if self.always_on_top { egui::WindowLevel::AlwaysOnTop } else { egui::WindowLevel::Normal };
+        ctx.send_viewport_cmd(egui::ViewportCommand::WindowLevel(level));
+        // Wayland clients can't raise themselves; the compositor has to
+        if wayland::is_native()
+            && let Err(e) = wayland::set_keep_above(WINDOW_TITLE, self.always_on_top)
+        {
+            self.status_message = format!("Always On Top: {}", e);
+        }
+    }
+
+    // Keeps the window's size and position for saving on exit, and puts it back on top on the
+    // first frame if it was last time: the window has to exist for that, which it doesn't yet
+    // while the app is being created
+    fn follow_window_geometry(&mut self, ctx: &egui::Context) {
+        if self.level_pending {
+            self.level_pending = false;
+            self.apply_window_level(ctx);
+        }
+        // The viewport reports points, which shrink as the zoom grows
+        let zoom = ctx.zoom_factor();
+        let (inner, outer, minimized) = ctx.input(|i| (i.viewport().inner_rect, i.viewport().outer_rect, i.viewport().minimized));
+        // A minimized window's geometry isn't one to come back to
+        if minimized == Some(true) {
+            return;
+        }
+        if let Some(inner) = inner {
+            self.window_state.width = inner.width() * zoom;
+            self.window_state.height = inner.height() * zoom;
+        }
+        if let Some(outer) = outer {
+            self.window_state.x = Some(outer.min.x * zoom);
+            self.window_state.y = Some(outer.min.y * zoom);
+        }
+    }
+
+    // Zoom: the setting changed (slider, profile) sets the window's; Ctrl +/- in the window
+    // changes the setting, so it's saved with the profile either way
+    fn follow_ui_scale(&mut self, ctx: &egui::Context) {
+        let setting = self.engine.ui_scale_percent.load(Ordering::Relaxed);
+        let zoomed = (ctx.zoom_factor() * 100.0).round() as u64;
+        if setting != self.ui_scale_percent {
+            ctx.set_zoom_factor(setting as f32 / 100.0);
+            self.ui_scale_percent = setting;
+        } else if zoomed != setting {
+            let zoomed = zoomed.clamp(config::MIN_UI_SCALE, config::MAX_UI_SCALE);
+            self.engine.ui_scale_percent.store(zoomed, Ordering::Relaxed);
+            ctx.set_zoom_factor(zoomed as f32 / 100.0);
+            self.ui_scale_percent = zoomed;
+        }
+    }
+
+    // The QWERTY keys the virtual device is pressing, modifiers included, lit as the game
+    // gets them; keys held stay lit, taps flash. Keys off the layout are listed under it.
+    fn draw_computer_keyboard(&mut self, ui: &mut egui::Ui) {
+        let now_us = self.engine.clock.now_us();
+        let held = {
+            let router = self.engine.router.lock().unwrap();
+            self.key_lights.update(&router.key_downs, now_us);
+            router.held.clone()
+        };
+        let unit = ui.available_width() / qwerty::WIDTH;
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), unit * qwerty::ROWS as f32), egui::Sense::hover());
+        let rect = response.rect;
+        let layout = qwerty::layout();
+        let lit = |key: KeyCode| held.contains(&key) || self.key_lights.is_lit(key, now_us);
+        for key in &layout {
+            let cell = egui::Rect::from_min_size(
+                egui::pos2(rect.min.x + key.x * unit, rect.min.y + key.row as f32 * unit),
+                egui::vec2(key.width * unit, unit),
+            )
+            .shrink(1.5);
+            let (fill, text) = if lit(key.key) {
+                (setting_color(&self.engine.output_key_color), egui::Color32::WHITE)
+            } else {
+                (egui::Color32::from_gray(45), egui::Color32::LIGHT_GRAY)
+            };
+            painter.rect(cell, 3.0, fill, egui::Stroke::new(1.0, egui::Color32::GRAY), egui::StrokeKind::Inside);
+            painter.text(cell.center(), egui::Align2::CENTER_CENTER, key.label, egui::FontId::proportional((unit * 0.35).clamp(8.0, 14.0)), text);
+        }
+        let mut others: Vec<String> = held
+            .iter()
+            .filter(|key| !layout.iter().any(|k| k.key == **key))
+            .map(|key| format!("{:?}", key).trim_start_matches("KEY_").to_string())
+            .collect();
+        if !others.is_empty() {
+            others.sort();
+            ui.label(format!("Also held: {}", others.join(", ")));
+        }
+        if !held.is_empty() || self.key_lights.any_lit(now_us) {
+            ui.ctx().request_repaint();
+        }
+    }
+
+    // The visualizer as a piano: pressing a key plays it until the pointer lets go, and
+    // dragging slides from key to key. Lower on a key is louder, like pressing it harder.
+    fn follow_piano_clicks(&mut self, response: &egui::Response, layout: &PianoLayout) {
+        let rect = response.rect;
+        let pressed_at = response.interact_pointer_pos().filter(|_| response.is_pointer_button_down_on());
+        let at = pressed_at.map(|p| ((p.x - rect.min.x) / rect.width(), (p.y - rect.min.y) / rect.height()));
+        let note = at.and_then(|(x, y)| layout.note_at(x, y));
+        if note == self.clicked_note {
+            return;
+        }
+        if let Some(old) = self.clicked_note.take() {
+            self.queue.push(&[0x80, old, 0]);
+        }
+        if let (Some(note), Some((_, y))) = (note, at) {
+            let velocity = (30.0 + 97.0 * y / PianoLayout::key_height(note)).clamp(1.0, 127.0) as u8;
+            self.queue.push(&[0x90, note, velocity]);
+            self.clicked_note = Some(note);
+        }
+    }
+
+    // MIDI-learn: a note from the controller, then a key from any keyboard (read by the hotkey
+    // listeners) or typed into this window. Each pair is added and played right away, and
+    // learning carries on with the next note until stopped.
+    fn show_mapping_learn(&mut self, ui: &mut egui::Ui) {
+        let step = *self.engine.mapping_learn.lock().unwrap();
+        if let LearnStep::WaitingForKey(_) = step {
+            let pressed: Vec<(egui::Key, egui::Modifiers)> = ui.input(|i| {
+                i.events
+                    .iter()
+                    .filter_map(|e| match e {
+                        egui::Event::Key { key, pressed: true, repeat: false, modifiers, .. } => Some((*key, *modifiers)),
+                        _ => None,
+                    })
+                    .collect()
+            });
+            for (key, modifiers) in pressed {
+                if let Some(code) = egui_key_code(key) {
+                    self.engine.learn_key(code, modifiers.shift, modifiers.ctrl);
+                }
+            }
+        }
+        if let LearnStep::Learned(mapping) = step {
+            match self.mapping_draft.iter_mut().find(|m| m.midi_note == mapping.midi_note) {
+                Some(row) => *row = mapping,
+                None => self.mapping_draft.push(mapping),
+            }
+            let draft = MappingSet::from_mappings(self.mapping_draft.iter().copied());
+            let playable = !draft.validate().iter().any(|i| i.is_error());
+            if playable {
+                self.apply_mappings(&draft);
+            }
+            self.status_message = format!(
+                "Learned {} -> {}{}{}{}",
+                midifile::note_name(mapping.midi_note),
+                if mapping.ctrl { "Ctrl+" } else { "" },
+                if mapping.shift { "Shift+" } else { "" },
+                mappings::key_name(mapping.key_code).unwrap_or("?"),
+                if playable { "" } else { " (not applied: fix the problems below)" },
+            );
+            *self.engine.mapping_learn.lock().unwrap() = LearnStep::WaitingForNote;
+        }
+
+        ui.horizontal(|ui| {
+            let step = *self.engine.mapping_learn.lock().unwrap();
+            if step == LearnStep::Off {
+                if ui.button("Learn").on_hover_text("Play a note, then press the key for it").clicked() {
+                    *self.engine.mapping_learn.lock().unwrap() = LearnStep::WaitingForNote;
+                }
+                return;
+            }
+            if ui.button("Stop Learning").clicked() {
+                *self.engine.mapping_learn.lock().unwrap() = LearnStep::Off;
+            }
+            match step {
+                LearnStep::WaitingForKey(note) => {
+                    ui.label(format!("{}: now press its key (hold Shift or Ctrl for those layers)", midifile::note_name(note)))
+                }
+                _ => ui.label("Play a note on the MIDI keyboard"),
+            };
+        });
+    }
+
+    // Note -> key rows, played from the next note once applied and kept across restarts once saved
+    fn show_mapping_editor(&mut self, ui: &mut egui::Ui) {
+        // Named sets, e.g. one per game's layout; switching releases whatever the old one held
+        let active_file = self.engine.mappings_file.lock().unwrap().clone();
+        let active_set = mappings::set_name(&active_file);
+        ui.horizontal(|ui| {
+            ui.label("Mapping Set:");
+            let selected = match (&active_set, active_file.is_empty()) {
+                (Some(name), _) => name.as_str(),
+                (None, true) => "Default",
+                (None, false) => "Other File",
+            };
+            let mut choice = None;
+            egui::ComboBox::from_id_salt("mapping_set").selected_text(selected).show_ui(ui, |ui| {
+                if ui.selectable_label(active_file.is_empty(), "Default").clicked() {
+                    choice = Some(String::new());
+                }
+                for name in mappings::list_sets() {
+                    if ui.selectable_label(active_set.as_ref() == Some(&name), &name).clicked() {
+                        choice = Some(mappings::set_path(&name).to_string_lossy().into_owned());
+                    }
+                }
+            });
+            if let Some(path) = choice {
+                self.mappings_file_path = path;
+                self.load_mappings_file();
+            }
+            if let Some(name) = &active_set
+                && ui.button("Delete Set").clicked()
+            {
+                self.status_message = match mappings::delete_set(name) {
+                    Ok(()) => format!("Deleted mapping set '{}'", name),
+                    Err(e) => format!("Failed to delete mapping set: {}", e),
+                };
+                // Its mappings keep playing until another set is picked; saving writes them back
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Mapping File:");
+            ui.add(egui::TextEdit::singleline(&mut self.mappings_file_path).hint_text("Default").desired_width(260.0));
+            if ui.button("Load").on_hover_text("Play with this file; leave it empty for the default mappings").clicked() {
+                self.load_mappings_file();
+            }
+        });
+        self.show_mapping_learn(ui);
+        let mut remove = None;
+        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            egui::Grid::new("mapping_editor").striped(true).show(ui, |ui| {
+                ui.label("Note");
+                ui.label("");
+                ui.label("Key");
+                ui.label("Shift");
+                ui.label("Ctrl");
+                ui.end_row();
+                for (i, mapping) in self.mapping_draft.iter_mut().enumerate() {
+                    ui.add(egui::DragValue::new(&mut mapping.midi_note).range(0..=127));
+                    ui.label(midifile::note_name(mapping.midi_note));
+                    egui::ComboBox::from_id_salt(("mapping_key", i))
+                        .selected_text(mappings::key_name(mapping.key_code).unwrap_or("Unknown"))
+                        .show_ui(ui, |ui| {
+                            for (name, key) in KEY_NAMES {
+                                ui.selectable_value(&mut mapping.key_code, *key, *name);
+                            }
+                        });
+                    ui.checkbox(&mut mapping.shift, "");
+                    ui.checkbox(&mut mapping.ctrl, "");
+                    if ui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(i) = remove {
+            self.mapping_draft.remove(i);
+        }
+
+        let draft = MappingSet::from_mappings(self.mapping_draft.iter().copied());
+        let issues = draft.validate();
+        for issue in &issues {
+            let color = if issue.is_error() { egui::Color32::from_rgb(255, 80, 80) } else { egui::Color32::from_rgb(255, 200, 0) };
+            ui.colored_label(color, issue.describe());
+        }
+        let playable = !issues.iter().any(|i| i.is_error());
+
+        ui.horizontal(|ui| {
+            if ui.button("Add Row").clicked() {
+                // The note after the highest one, on a key that's easy to change
+                let midi_note = self.mapping_draft.iter().map(|m| m.midi_note).max().map_or(60, |n| n.saturating_add(1).min(127));
+                self.mapping_draft.push(KeyMapping { midi_note, key_code: KeyCode::KEY_SPACE, shift: false, ctrl: false });
+            }
+            if ui.add_enabled(playable, egui::Button::new("Apply")).on_hover_text("Play with these mappings from the next note").clicked() {
+                self.apply_mappings(&draft);
+                self.status_message = format!("Applied {} mappings", draft.len());
+            }
+            if ui.add_enabled(playable, egui::Button::new("Save")).on_hover_text("Apply and keep them for next time").clicked() {
+                self.apply_mappings(&draft);
+                // Back into the loaded file, or the config dir's mappings.json without one
+                let file = self.engine.mappings_file.lock().unwrap().clone();
+                let saved = if file.is_empty() {
+                    draft.save()
+                } else {
+                    draft.save_to(std::path::Path::new(&file)).map(|()| file.into())
+                };
+                self.status_message = match saved {
+                    Ok(path) => format!("Saved mappings to {}", path.display()),
+                    Err(e) => format!("Failed to save mappings: {}", e),
+                };
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.mapping_set_name).hint_text("Set name").desired_width(120.0));
+            let name = self.mapping_set_name.trim().to_string();
+            if ui.add_enabled(playable && !name.is_empty(), egui::Button::new("Save as Set")).clicked() {
+                self.status_message = match draft.save_set(&name) {
+                    Ok(path) => {
+                        self.mappings_file_path = path.to_string_lossy().into_owned();
+                        self.load_mappings_file();
+                        format!("Saved mapping set '{}'", name)
+                    }
+                    Err(e) => format!("Failed to save mapping set: {}", e),
+                };
+            }
+            if ui.button("Revert").on_hover_text("Back to the mappings being played").clicked() {
+                self.mapping_draft = self.engine.mappings.lock().unwrap().iter().copied().collect();
+            }
+            if ui.button("Reset to Built-in").on_hover_text("Play the shipped mappings.json again and forget the saved ones").clicked() {
+                let builtin = MappingSet::builtin();
+                self.mapping_draft = builtin.iter().copied().collect();
+                self.apply_mappings(&builtin);
+                self.mappings_file_path.clear();
+                self.engine.mappings_file.lock().unwrap().clear();
+                self.status_message = match MappingSet::reset_saved() {
+                    Ok(()) => "Back to the built-in mappings".to_string(),
+                    Err(e) => format!("Failed to remove the saved mappings: {}", e),
+                };
+            }
+        });
+    }
+
+    // An empty path goes back to the default mappings
+    fn load_mappings_file(&mut self) {
+        let path = self.mappings_file_path.trim().to_string();
+        let count = match self.engine.use_mappings_file(&path) {
+            Ok(count) => count,
+            Err(e) => {
+                self.status_message = format!("Kept the current mappings: {}", e);
+                return;
+            }
+        };
+        self.status_message = if path.is_empty() {
+            format!("Using the default mappings ({})", count)
+        } else {
+            format!("Loaded {} mappings from '{}'", count, path)
+        };
+        let mappings = self.engine.mappings.lock().unwrap().clone();
+        self.mapping_draft = mappings.iter().copied().collect();
+        if let Some(partner) = &self.partner {
+            partner.engine.set_mappings(mappings);
+        }
+    }
+
+    fn export_stats(&mut self) {
+        let report = self.engine.stats.lock().unwrap().report();
+        self.status_message = match report.export() {
+            Ok(path) => format!("Session stats exported to {}", path.display()),
+            Err(e) => format!("Failed to export stats: {}", e),
+        };
+    }
+}
+
+impl eframe::App for MidiApp {
+    // A transparent overlay needs nothing under its panel. The main window gets the same,
+    // which only shows with its opacity turned down
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        if self.overlay_open && *self.engine.overlay_background.lock().unwrap() == OverlayBackground::Transparent {
+            [0.0; 4]
+        } else {
+            egui::Color32::from_rgba_unmultiplied(12, 12, 12, 180).to_normalized_gamma_f32()
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Close out a practice run once it has passed the end of the reference
+        {
+            let mut practice = self.engine.practice.lock().unwrap();
+            if practice.is_running() {
+                if practice.should_finish()
+                    && let Some(summary) = practice.finish()
+                {
+                    self.status_message = format!("Practice run finished: {:.0}%", summary.score_percent);
+                } else {
+                    ctx.request_repaint_after(time::Duration::from_millis(250));
+                }
+            }
+        }
+
+        // Theme and opacity, re-applied when either changes
+        let visuals = theme_visuals(&self.engine, self.window_opacity);
+        if ctx.style().visuals != visuals {
+            ctx.set_visuals(visuals);
+        }
+
+        self.follow_ui_scale(ctx);
+        self.follow_window_geometry(ctx);
+        self.follow_hotplug();
+        self.follow_remote();
+        self.follow_typing(ctx);
+        if let Some(news) = self.ble.lock().unwrap().news.take() {
+            self.status_message = news;
+        }
+
+        // Header Section (MIDI Selector & Window Settings)
+        egui::TopBottomPanel::top("header").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                // MIDI Selector
+                ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
+                    ui.label("Midi Device:");
+                    egui::ComboBox::from_id_salt("midi_selector_header")
+                        .selected_text(self.selected_port_name.as_deref().unwrap_or("Select MIDI Device"))
+                        .show_ui(ui, |ui| {
+                            for (port_name, _) in self.available_ports.iter() {
+                                if ui.selectable_value(&mut self.selected_port_name, Some(port_name.clone()), port_name).clicked() {
+                                    // Handle selection if needed
+                                }
+                            }
+                        });
+                    
+                    if ui.button("Refresh").clicked() {
+                        self.refresh_ports();
+                    }
+                    // Once connected, more ports can join: a pedal controller, a second keyboard
+                    if !self.connections.is_empty()
+                        && let Some(port_name) = self.selected_port_name.clone()
+                    {
+                        let open = self.connections.iter().any(|c| c.port_name == port_name);
+                        if ui.add_enabled(!open, egui::Button::new("Add Input")).on_hover_text("Play this device too, alongside the connected ones").clicked() {
+                            self.connect_port(&port_name);
+                        }
+                    }
+                });
+
+                // Window Settings (Opacity & Always On Top)
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                     // Always On Top
+                    if ui.checkbox(&mut self.always_on_top, "Always On Top").changed() {
+                        self.apply_window_level(ctx);
+                    }
+                    
+                    ui.add_space(10.0);
+
+                    ui.label("Opacity:");
+                    ui.add(egui::Slider::new(&mut self.window_opacity, 0.1..=1.0).show_value(false));
+
+                    ui.add_space(10.0);
+
+                    ui.label("Zoom:");
+                    let mut scale = self.engine.ui_scale_percent.load(Ordering::Relaxed);
+                    ui.add(egui::Slider::new(&mut scale, config::MIN_UI_SCALE..=config::MAX_UI_SCALE).suffix("%"));
+                    self.engine.ui_scale_percent.store(scale, Ordering::Relaxed);
+                });
+            });
+
+            // Named profiles: everything in the settings, saved under a name
+            ui.horizontal(|ui| {
+                ui.label("Profile:");
+                let active = self.engine.active_profile.lock().unwrap().clone();
+                egui::ComboBox::from_id_salt("profile_selector_header")
+                    .selected_text(active.as_deref().unwrap_or("Unsaved"))
+                    .show_ui(ui, |ui| {
+                        for name in config::list_profiles() {
+                            if ui.selectable_label(active.as_ref() == Some(&name), &name).clicked() {
+                                self.status_message = match config::load_profile(&name) {
+                                    Ok(settings) => {
+                                        self.engine.apply_settings(&settings);
+                                        *self.engine.active_profile.lock().unwrap() = Some(name.clone());
+                                        self.profile_name = name.clone();
+                                        self.script_file_path = self.engine.script_file.lock().unwrap().clone();
+                                        format!("Loaded profile '{}'", name)
+                                    }
+                                    Err(e) => format!("Failed to load profile: {}", e),
+                                };
+                            }
+                        }
+                    });
+                ui.add(egui::TextEdit::singleline(&mut self.profile_name).hint_text("Profile name").desired_width(140.0));
+                let name = self.profile_name.trim().to_string();
+                if ui.add_enabled(!name.is_empty(), egui::Button::new("Save")).clicked() {
+                    self.status_message = match config::save_profile(&name, &self.engine.settings()) {
+                        Ok(()) => {
+                            *self.engine.active_profile.lock().unwrap() = Some(name.clone());
+                            format!("Saved profile '{}'", name)
+                        }
+                        Err(e) => format!("Failed to save profile: {}", e),
+                    };
+                }
+                if let Some(active) = active
+                    && ui.button("Delete").on_hover_text(format!("Delete the saved profile '{}'", active)).clicked()
+                {
+                    self.status_message = match config::delete_profile(&active) {
+                        Ok(()) => {
+                            *self.engine.active_profile.lock().unwrap() = None;
+                            format!("Deleted profile '{}'", active)
+                        }
+                        Err(e) => format!("Failed to delete profile: {}", e),
+                    };
+                }
+            });
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+
+            // Connection controls
+            if !self.connections.is_empty() {
+                ui.horizontal(|ui| {
+                     ui.label(egui::RichText::new("Status: Connected").color(egui::Color32::GREEN));
+                     if ui.button("Panic").on_hover_text("Release every key and reset the transpose; also on the panic hotkey and trigger").clicked()
+                         && let Err(e) = self.engine.panic()
+                     {
+                         self.status_message = format!("Panic failed: {}", e);
+                     }
+                     if ui.button("Disconnect").clicked() {
+                         self.end_session();
+                     }
+                });
+
+                // The open ports, each of which can be muted or closed on its own
+                let mut removed = None;
+                for (i, input) in self.connections.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let mut enabled = input.enabled.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut enabled, &input.port_name).on_hover_text("Play what this device sends").changed() {
+                            input.enabled.store(enabled, Ordering::Relaxed);
+                            // Its notes won't get their note-offs through anymore
+                            if !enabled {
+                                let _ = self.engine.router.lock().unwrap().release_held();
+                            }
+                        }
+                        if self.connections.len() > 1 && ui.small_button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    let input = self.connections.remove(i);
+                    let _ = self.engine.router.lock().unwrap().release_held();
+                    self.status_message = format!("Disconnected {}", input.port_name);
+                }
+                
+                ui.separator();
+
+                // Settings Group
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    let mut game_mode = *self.engine.game_mode.lock().unwrap();
+                    ui.horizontal(|ui| {
+                        ui.label("Game:");
+                        egui::ComboBox::from_id_salt("game_mode")
+                            .selected_text(game_mode.label())
+                            .show_ui(ui, |ui| {
+                                for mode in GameMode::ALL {
+                                    if ui.selectable_value(&mut game_mode, mode, mode.label()).clicked() {
+                                        *self.engine.game_mode.lock().unwrap() = mode;
+                                    }
+                                }
+                            });
+                    });
+
+                    let mut base_enabled = self.engine.base_mapping_enabled.load(Ordering::Relaxed);
+                    let mut low_enabled = self.engine.low_mapping_enabled.load(Ordering::Relaxed);
+                    let mut high_enabled = self.engine.high_mapping_enabled.load(Ordering::Relaxed);
+
+                    if game_mode == GameMode::Keys61 {
+                        ui.label("C2-C7 only; notes outside are folded in by octaves.");
+                    } else if game_mode == GameMode::Drums {
+                        ui.label("Every note is a drum pad from drums.json; rolls alternate keys.");
+                    } else if game_mode == GameMode::Guitar {
+                        ui.label("Notes hold fret keys from guitar.json; each chord is strummed once.");
+                        let mut window = self.engine.strum_window_ms.load(Ordering::Relaxed);
+                        if ui.add(egui::Slider::new(&mut window, 0..=200).text("Strum Window (ms)")).changed() {
+                            self.engine.strum_window_ms.store(window, Ordering::Relaxed);
+                        }
+                    } else {
+                        ui.horizontal(|ui| {
+                            if ui.checkbox(&mut base_enabled, "Start (Middle Octaves)").changed() {
+                                self.engine.base_mapping_enabled.store(base_enabled, Ordering::Relaxed);
+                            }
+                            if ui.checkbox(&mut low_enabled, "Low Range").changed() {
+                                self.engine.low_mapping_enabled.store(low_enabled, Ordering::Relaxed);
+                            }
+                            if ui.checkbox(&mut high_enabled, "High Range").changed() {
+                                self.engine.high_mapping_enabled.store(high_enabled, Ordering::Relaxed);
+                            }
+                        });
+                    }
+                    if game_mode != GameMode::Drums {
+                        let mut dual_drums = self.engine.dual_drums_enabled.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut dual_drums, "Play Drum Channels").on_hover_text("Play the channels set to Drums on the drum kit from drums.json instead of dropping them").changed() {
+                            self.engine.dual_drums_enabled.store(dual_drums, Ordering::Relaxed);
+                        }
+                    }
+
+                    egui::CollapsingHeader::new("Mapping Editor").show(ui, |ui| self.show_mapping_editor(ui));
+
+                    // What the game does with the modifiers
+                    ui.horizontal(|ui| {
+                        for (label, role) in [("Ctrl:", &self.engine.ctrl_role), ("Shift:", &self.engine.shift_role)] {
+                            let mut current = *role.lock().unwrap();
+                            ui.label(label);
+                            egui::ComboBox::from_id_salt(label)
+                                .selected_text(current.label())
+                                .show_ui(ui, |ui| {
+                                    for option in ModifierRole::ALL {
+                                        if ui.selectable_value(&mut current, option, option.label()).clicked() {
+                                            *role.lock().unwrap() = option;
+                                        }
+                                    }
+                                });
+                        }
+                    });
+                    // Pianos without sharps play each black key on a white neighbour
+                    ui.horizontal(|ui| {
+                        ui.label("Black Keys:");
+                        let mut black_keys = self.engine.black_keys.lock().unwrap();
+                        egui::ComboBox::from_id_salt("black_keys").selected_text(black_keys.label()).show_ui(ui, |ui| {
+                            for option in BlackKeys::ALL {
+                                ui.selectable_value(&mut *black_keys, option, option.label());
+                            }
+                        });
+                    });
+
+                    // The sustain pedal as a key of its own and/or holding notes
+                    ui.horizontal(|ui| {
+                        ui.label("Sustain Pedal:");
+                        let mut key_enabled = self.engine.sustain_key_enabled.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut key_enabled, "Holds Key").on_hover_text("For games that sustain while a key is held").changed() {
+                            self.engine.sustain_key_enabled.store(key_enabled, Ordering::Relaxed);
+                        }
+                        if key_enabled {
+                            let mut key = self.engine.sustain_key.lock().unwrap();
+                            egui::ComboBox::from_id_salt("sustain_key").selected_text(key.as_str()).show_ui(ui, |ui| {
+                                for (name, _) in KEY_NAMES {
+                                    ui.selectable_value(&mut *key, name.to_string(), *name);
+                                }
+                            });
+                        }
+                        let mut hold_notes = self.engine.sustain_hold_notes.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut hold_notes, "Holds Notes").on_hover_text("Note keys stay down until the pedal comes up").changed() {
+                            self.engine.sustain_hold_notes.store(hold_notes, Ordering::Relaxed);
+                        }
+                    });
+
+                    // In-game volume keys following the dynamics
+                    ui.horizontal(|ui| {
+                        let mut source = *self.engine.volume_source.lock().unwrap();
+                        let before = source;
+                        ui.label("Volume Keys:");
+                        egui::ComboBox::from_id_salt("volume_source")
+                            .selected_text(match source {
+                                VolumeSource::Off => "Off",
+                                VolumeSource::Velocity => "Follow Velocity",
+                                VolumeSource::Cc(_) => "Follow CC",
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut source, VolumeSource::Off, "Off");
+                                ui.selectable_value(&mut source, VolumeSource::Velocity, "Follow Velocity");
+                                ui.selectable_value(&mut source, VolumeSource::Cc(7), "Follow CC");
+                            });
+                        if let VolumeSource::Cc(cc) = &mut source {
+                            ui.add(egui::DragValue::new(cc).range(0..=127));
+                        }
+                        if source != before {
+                            *self.engine.volume_source.lock().unwrap() = source;
+                            self.engine.router.lock().unwrap().volume.reset();
+                        }
+                    });
+                    if *self.engine.volume_source.lock().unwrap() != VolumeSource::Off {
+                        ui.horizontal(|ui| {
+                            let mut steps = self.engine.volume_steps.load(Ordering::Relaxed);
+                            if ui.add(egui::Slider::new(&mut steps, 2..=20).text("Levels")).changed() {
+                                self.engine.volume_steps.store(steps, Ordering::Relaxed);
+                                self.engine.router.lock().unwrap().volume.reset();
+                            }
+                            for (label, key) in [("Up:", &self.engine.volume_up_key), ("Down:", &self.engine.volume_down_key)] {
+                                let mut current = key.lock().unwrap().clone();
+                                ui.label(label);
+                                egui::ComboBox::from_id_salt(label).selected_text(current.as_str()).show_ui(ui, |ui| {
+                                    for (name, _) in KEY_NAMES {
+                                        if ui.selectable_value(&mut current, name.to_string(), *name).clicked() {
+                                            *key.lock().unwrap() = name.to_string();
+                                        }
+                                    }
+                                });
+                            }
+                        });
+                    }
+
+                    // Anti-AFK: a key tap after a while without notes
+                    ui.horizontal(|ui| {
+                        let mut minutes = self.engine.keep_alive_minutes.load(Ordering::Relaxed);
+                        let mut enabled = minutes > 0;
+                        if ui.checkbox(&mut enabled, "Keep-Alive").changed() {
+                            minutes = if enabled { 15 } else { 0 };
+                        }
+                        if enabled {
+                            ui.add(egui::Slider::new(&mut minutes, 1..=19).text("Idle Minutes"));
+                            let mut key = self.engine.keep_alive_key.lock().unwrap();
+                            egui::ComboBox::from_id_salt("keep_alive_key").selected_text(key.as_str()).show_ui(ui, |ui| {
+                                for (name, _) in KEY_NAMES {
+                                    ui.selectable_value(&mut *key, name.to_string(), *name);
+                                }
+                            });
+                        }
+                        self.engine.keep_alive_minutes.store(minutes, Ordering::Relaxed);
+                    });
+
+                    // Ghost notes from cheap keyboards
+                    ui.horizontal(|ui| {
+                        let mut min_velocity = self.engine.min_velocity.load(Ordering::Relaxed);
+                        let mut enabled = min_velocity > 0;
+                        if ui.checkbox(&mut enabled, "Velocity Gate").on_hover_text("Drop notes played softer than this").changed() {
+                            min_velocity = if enabled { 6 } else { 0 };
+                        }
+                        if enabled {
+                            ui.add(egui::Slider::new(&mut min_velocity, 1..=64).text("Minimum Velocity"));
+                        }
+                        self.engine.min_velocity.store(min_velocity, Ordering::Relaxed);
+                    });
+
+                    // Huge chords choke the game and the keyboard's rollover
+                    ui.horizontal(|ui| {
+                        let mut max_polyphony = self.engine.max_polyphony.load(Ordering::Relaxed);
+                        let mut enabled = max_polyphony > 0;
+                        if ui.checkbox(&mut enabled, "Polyphony Limit").on_hover_text("Hold at most this many notes at once").changed() {
+                            max_polyphony = if enabled { 6 } else { 0 };
+                        }
+                        if enabled {
+                            ui.add(egui::Slider::new(&mut max_polyphony, 1..=16).text("Notes"));
+                            let mut priority = self.engine.polyphony_priority.lock().unwrap();
+                            egui::ComboBox::from_id_salt("polyphony_priority").selected_text(priority.label()).show_ui(ui, |ui| {
+                                for option in NotePriority::ALL {
+                                    ui.selectable_value(&mut *priority, option, option.label());
+                                }
+                            });
+                        }
+                        self.engine.max_polyphony.store(max_polyphony, Ordering::Relaxed);
+                    });
+
+                    // The computer keyboard as a MIDI keyboard (typing.rs)
+                    egui::CollapsingHeader::new("Typing Keyboard").show(ui, |ui| {
+                        let mut source = *self.engine.typing_source.lock().unwrap();
+                        ui.horizontal(|ui| {
+                            ui.label("Play Notes From:");
+                            egui::ComboBox::from_id_salt("typing_source").selected_text(source.label()).show_ui(ui, |ui| {
+                                for option in TypingSource::ALL {
+                                    ui.selectable_value(&mut source, option, option.label());
+                                }
+                            });
+                        });
+                        *self.engine.typing_source.lock().unwrap() = source;
+                        let mut base = self.engine.typing_base_note.load(Ordering::Relaxed);
+                        ui.add(
+                            egui::Slider::new(&mut base, 0..=typing::MAX_BASE_NOTE)
+                                .text("Z Key Plays")
+                                .custom_formatter(|n, _| midifile::note_name(n as u8)),
+                        );
+                        self.engine.typing_base_note.store(base, Ordering::Relaxed);
+                        let mut velocity = self.engine.typing_velocity.load(Ordering::Relaxed);
+                        ui.add(egui::Slider::new(&mut velocity, 1..=127).text("Velocity"));
+                        self.engine.typing_velocity.store(velocity, Ordering::Relaxed);
+                        ui.label("Z to / and Q to ] are two octaves of piano keys, black keys on the row above each.");
+                        if source == TypingSource::Keyboard {
+                            ui.label("Keys are read from the Hotkeys keyboard; turn on Grab Keyboard there to keep them from the game.");
+                        }
+                    });
+
+                    // Window and visualizer colors, saved with the profile
+                    egui::CollapsingHeader::new("Theme").show(ui, |ui| {
+                        let mut light_mode = self.engine.light_mode.load(Ordering::Relaxed);
+                        ui.checkbox(&mut light_mode, "Light Mode");
+                        self.engine.light_mode.store(light_mode, Ordering::Relaxed);
+                        let colors = [
+                            ("Accent", &self.engine.accent_color),
+                            ("MIDI Input Keys", &self.engine.input_key_color),
+                            ("Game Keys", &self.engine.output_key_color),
+                            ("White Keys", &self.engine.white_key_color),
+                            ("Black Keys", &self.engine.black_key_color),
+                        ];
+                        egui::Grid::new("theme_colors").show(ui, |ui| {
+                            for (label, setting) in colors {
+                                ui.label(label);
+                                let [r, g, b, _] = setting_color(setting).to_array();
+                                let mut rgb = [r, g, b];
+                                if ui.color_edit_button_srgb(&mut rgb).changed() {
+                                    *setting.lock().unwrap() = config::format_color(rgb);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                        if ui.button("Reset Colors").clicked() {
+                            let defaults = config::Settings::default();
+                            self.engine.light_mode.store(defaults.light_mode, Ordering::Relaxed);
+                            *self.engine.accent_color.lock().unwrap() = defaults.accent_color;
+                            *self.engine.input_key_color.lock().unwrap() = defaults.input_key_color;
+                            *self.engine.output_key_color.lock().unwrap() = defaults.output_key_color;
+                            *self.engine.white_key_color.lock().unwrap() = defaults.white_key_color;
+                            *self.engine.black_key_color.lock().unwrap() = defaults.black_key_color;
+                        }
+                    });
+
+                    // Per-channel velocity gain/compression, ahead of accents and volume following
+                    egui::CollapsingHeader::new("Channel Dynamics").show(ui, |ui| {
+                        let mut dynamics = self.engine.channel_dynamics.lock().unwrap();
+                        egui::Grid::new("channel_dynamics").show(ui, |ui| {
+                            for (channel, d) in dynamics.iter_mut().enumerate() {
+                                ui.label(format!("Ch {}", channel + 1));
+                                ui.add(egui::Slider::new(&mut d.gain, 0..=400).text("Gain %"));
+                                ui.add(egui::Slider::new(&mut d.compression, 0..=100).text("Compression %"));
+                                if ui.small_button("Reset").clicked() {
+                                    *d = ChannelDynamics::default();
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+
+                    // Per-channel transpose, so a bass part and a melody each land where the game can play them
+                    egui::CollapsingHeader::new("Channel Transpose").show(ui, |ui| {
+                        let mut transpose = self.engine.channel_transpose.lock().unwrap();
+                        egui::Grid::new("channel_transpose").show(ui, |ui| {
+                            for (channel, offset) in transpose.iter_mut().enumerate() {
+                                ui.label(format!("Ch {}", channel + 1));
+                                ui.add(egui::Slider::new(offset, -pipeline::MAX_CHANNEL_TRANSPOSE..=pipeline::MAX_CHANNEL_TRANSPOSE).text("Semitones"));
+                                if ui.small_button("Reset").clicked() {
+                                    *offset = 0;
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+
+                    // Which channels play, go to the drum kit, only show up on the keyboard or are ignored
+                    egui::CollapsingHeader::new("Channels").show(ui, |ui| {
+                        let mut routes = self.engine.channel_routes.lock().unwrap();
+                        egui::Grid::new("channel_routes").show(ui, |ui| {
+                            ui.label("");
+                            for channel in 1..=16 {
+                                ui.label(channel.to_string());
+                            }
+                            ui.end_row();
+                            for route in ChannelRoute::ALL {
+                                ui.label(route.label());
+                                for current in routes.iter_mut() {
+                                    ui.radio_value(current, route, "");
+                                }
+                                ui.end_row();
+                            }
+                        });
+                        if ui.small_button("Reset").clicked() {
+                            *routes = ChannelRoute::defaults();
+                        }
+                    });
+
+                    // Sostenuto: a pedal CC or a note that keeps the held keys down
+                    ui.horizontal(|ui| {
+                        ui.label("Sostenuto:");
+                        trigger_picker(ui, "sostenuto_trigger", &self.engine.sostenuto_trigger, Trigger::Cc(66));
+                    });
+                    // Panic: a spare button or pad that lets go of everything mid-song
+                    ui.horizontal(|ui| {
+                        ui.label("Panic:");
+                        trigger_picker(ui, "panic_trigger", &self.engine.panic_trigger, Trigger::Cc(119));
+                    });
+
+                    // Where the game's transpose is, and buttons to nudge it mid-song
+                    ui.horizontal(|ui| {
+                        let transpose = self.engine.game_transpose();
+                        ui.label(egui::RichText::new(format!("Transpose {:+}", transpose)).size(20.0).strong());
+                        for (label, semitones) in [("-12", -12), ("-1", -1), ("+1", 1), ("+12", 12)] {
+                            if ui.button(label).clicked()
+                                && let Err(e) = self.engine.nudge_transpose(semitones)
+                            {
+                                self.status_message = format!("Transpose failed: {}", e);
+                            }
+                        }
+                    });
+                    // The keys the game transposes with; arrows unless it binds others
+                    ui.horizontal(|ui| {
+                        ui.label("Game's Transpose Keys:");
+                        for (label, id, key) in [("Up:", "transpose_up_key", &self.engine.transpose_up_key), ("Down:", "transpose_down_key", &self.engine.transpose_down_key)] {
+                            let mut current = key.lock().unwrap().clone();
+                            ui.label(label);
+                            egui::ComboBox::from_id_salt(id).selected_text(current.as_str()).show_ui(ui, |ui| {
+                                for (name, _) in KEY_NAMES {
+                                    if ui.selectable_value(&mut current, name.to_string(), *name).clicked() {
+                                        *key.lock().unwrap() = name.to_string();
+                                    }
+                                }
+                            });
+                        }
+                    });
+                    egui::CollapsingHeader::new("Transpose Buttons").show(ui, |ui| {
+                        let triggers = [
+                            ("+1:", "transpose_up_trigger", &self.engine.transpose_up_trigger),
+                            ("-1:", "transpose_down_trigger", &self.engine.transpose_down_trigger),
+                            ("+12:", "transpose_octave_up_trigger", &self.engine.transpose_octave_up_trigger),
+                            ("-12:", "transpose_octave_down_trigger", &self.engine.transpose_octave_down_trigger),
+                        ];
+                        for (label, id, trigger) in triggers {
+                            ui.horizontal(|ui| {
+                                ui.label(label);
+                                trigger_picker(ui, id, trigger, Trigger::Cc(20));
+                            });
+                        }
+                    });
+
+                    // Live octave shift of the input; also on the hotkeys so it works mid-song
+                    ui.horizontal(|ui| {
+                        let mut shift = self.engine.octave_shift.load(Ordering::Relaxed);
+                        if ui.add(egui::Slider::new(&mut shift, -MAX_OCTAVE_SHIFT..=MAX_OCTAVE_SHIFT).text("Octave Shift")).changed() {
+                            self.engine.octave_shift.store(shift, Ordering::Relaxed);
+                        }
+                    });
+                    // Wrong notes pulled onto the key the song is in
+                    ui.horizontal(|ui| {
+                        let mut key = self.engine.snap_key.lock().unwrap();
+                        let mut snapping = key.is_some();
+                        if ui
+                            .checkbox(&mut snapping, "Snap to Scale")
+                            .on_hover_text("Moves notes outside the key to its nearest note, so a slip onto a neighbouring key still sounds right")
+                            .changed()
+                        {
+                            *key = snapping.then_some(0);
+                        }
+                        if let Some(tonic) = &mut *key {
+                            egui::ComboBox::from_id_salt("snap_key").selected_text(midifile::PITCH_CLASSES[*tonic as usize]).show_ui(ui, |ui| {
+                                for (pitch_class, name) in midifile::PITCH_CLASSES.iter().enumerate() {
+                                    ui.selectable_value(tonic, pitch_class as u8, *name);
+                                }
+                            });
+                            let mut scale = self.engine.snap_scale.lock().unwrap();
+                            egui::ComboBox::from_id_salt("snap_scale").selected_text(scale.label()).show_ui(ui, |ui| {
+                                for option in ScaleMode::ALL {
+                                    ui.selectable_value(&mut *scale, option, option.label());
+                                }
+                            });
+                        }
+                    });
+                    // Knob/fader driving the game's transpose
+                    ui.horizontal(|ui| {
+                        let mut cc = *self.engine.transpose_cc.lock().unwrap();
+                        let mut bound = cc.is_some();
+                        if ui.checkbox(&mut bound, "Transpose from CC").changed() {
+                            cc = bound.then_some(1);
+                        }
+                        if let Some(number) = &mut cc {
+                            ui.add(egui::DragValue::new(number).range(0..=127));
+                            let mut range = self.engine.transpose_cc_range.load(Ordering::Relaxed);
+                            if ui.add(egui::Slider::new(&mut range, 1..=24).text("Range (+/-)")).changed() {
+                                self.engine.transpose_cc_range.store(range, Ordering::Relaxed);
+                            }
+                            ui.label(format!("Now {:+}", self.engine.router.lock().unwrap().cc_transpose));
+                        }
+                        *self.engine.transpose_cc.lock().unwrap() = cc;
+                    });
+                    // Pitch bend as a temporary transpose
+                    ui.horizontal(|ui| {
+                        let mut range = self.engine.pitch_bend_range.load(Ordering::Relaxed);
+                        let mut enabled = range > 0;
+                        if ui.checkbox(&mut enabled, "Pitch Bend Transposes").changed() {
+                            range = if enabled { 2 } else { 0 };
+                        }
+                        if enabled {
+                            ui.add(egui::Slider::new(&mut range, 1..=12).text("Semitones at Full Bend"));
+                            ui.label(format!("Now {:+}", self.engine.router.lock().unwrap().bend_transpose));
+                        }
+                        self.engine.pitch_bend_range.store(range, Ordering::Relaxed);
+                    });
+                    egui::CollapsingHeader::new("Hotkeys").show(ui, |ui| {
+                        if self.hotkey_keyboards.is_empty() {
+                            ui.colored_label(egui::Color32::YELLOW, "No keyboards readable; add yourself to the 'input' group");
+                        }
+                        let mut keys = self.engine.hotkeys.lock().unwrap();
+                        let keys = &mut *keys;
+                        let mut changed = false;
+                        ui.horizontal(|ui| {
+                            ui.label("Keyboard");
+                            egui::ComboBox::from_id_salt("hotkey_keyboard")
+                                .selected_text(if keys.keyboard.is_empty() { "Every keyboard" } else { keys.keyboard.as_str() })
+                                .show_ui(ui, |ui| {
+                                    changed |= ui.selectable_value(&mut keys.keyboard, String::new(), "Every keyboard").changed();
+                                    for name in &self.hotkey_keyboards {
+                                        changed |= ui.selectable_value(&mut keys.keyboard, name.clone(), name).changed();
+                                    }
+                                });
+                        });
+                        changed |= ui
+                            .checkbox(&mut keys.grab, "Grab Keyboard")
+                            .on_hover_text("Keep hotkeys from reaching the game; the keyboard's other keys still type. Starts once no key on it is held")
+                            .changed();
+                        for action in HotkeyAction::ALL {
+                            ui.horizontal(|ui| {
+                                ui.label(action.label());
+                                let name = action.key_mut(keys);
+                                changed |= ui.add(egui::TextEdit::singleline(name).hint_text("None").desired_width(120.0)).changed();
+                                if !name.is_empty() && !hotkeys::is_known_key(name) {
+                                    ui.colored_label(egui::Color32::RED, "unknown key");
+                                }
+                            });
+                        }
+                        if changed && let Err(e) = config::save_hotkeys(keys) {
+                            self.status_message = format!("Failed to save hotkeys: {}", e);
+                        }
+                    });
+
+                    let mut auto_transpose = self.engine.auto_transpose_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut auto_transpose, "Enable Auto-Octave Transposition").changed() {
+                        self.engine.auto_transpose_enabled.store(auto_transpose, Ordering::Relaxed);
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Fold Into Range:");
+                        let mut fold_low = self.engine.fold_low_notes.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut fold_low, "Low Notes").changed() {
+                            self.engine.fold_low_notes.store(fold_low, Ordering::Relaxed);
+                        }
+                        let mut fold_high = self.engine.fold_high_notes.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut fold_high, "High Notes").changed() {
+                            self.engine.fold_high_notes.store(fold_high, Ordering::Relaxed);
+                        }
+                    });
+
+                    ui.separator();
+                    
+                    // Experimental Section
+                    ui.label(egui::RichText::new("Experimental").strong());
+                    
+                    let mut exp_transpose = self.engine.experimental_transpose_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut exp_transpose, "Black Keys using Transpose").changed() {
+                        self.engine.experimental_transpose_enabled.store(exp_transpose, Ordering::Relaxed);
+                    }
+                    
+                    if exp_transpose {
+                        let mut delay = self.engine.transpose_delay_ms.load(Ordering::Relaxed);
+                        if ui.add(egui::Slider::new(&mut delay, 0..=1000).text("Transpose Delay (ms)")).changed() {
+                            self.engine.transpose_delay_ms.store(delay, Ordering::Relaxed);
+                        }
+                        let mut lazy = self.engine.lazy_transpose_enabled.load(Ordering::Relaxed);
+                        if ui.checkbox(&mut lazy, "Optimized Transpose").changed() {
+                            self.engine.lazy_transpose_enabled.store(lazy, Ordering::Relaxed);
+                        }
+                    }
+
+                    let mut exp_hold = self.engine.experimental_hold_ctrl_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut exp_hold, "Hold CTRL for Upper/Lower ranges").changed() {
+                        self.engine.experimental_hold_ctrl_enabled.store(exp_hold, Ordering::Relaxed);
+                    }
+
+                    let mut solver_en = self.engine.solver_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut solver_en, "Smart Solver").changed() {
+                        self.engine.solver_enabled.store(solver_en, Ordering::Relaxed);
+                    }
+                     
+                    if solver_en {
+                        ui.indent("solver_settings", |ui| {
+                            let mut is_efficiency = self.engine.solver_mode_efficiency.load(Ordering::Relaxed);
+                            ui.horizontal(|ui| {
+                                if ui.radio_value(&mut is_efficiency, true, "Efficiency (Least Clicks)").clicked() {
+                                    self.engine.solver_mode_efficiency.store(true, Ordering::Relaxed);
+                                }
+                                if ui.radio_value(&mut is_efficiency, false, "Accuracy (Best Match)").clicked() {
+                                    self.engine.solver_mode_efficiency.store(false, Ordering::Relaxed);
+                                }
+                            });
+                            
+                            let mut max_jump = self.engine.solver_max_jump.load(Ordering::Relaxed);
+                            if ui.add(egui::Slider::new(&mut max_jump, 1..=24).text("Max Jump Distance")).changed() {
+                                self.engine.solver_max_jump.store(max_jump, Ordering::Relaxed);
+                            }
+
+                            let mut hold_transpose = self.engine.solver_hold_transpose.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut hold_transpose, "Keep Transpose While Keys Are Held").changed() {
+                                self.engine.solver_hold_transpose.store(hold_transpose, Ordering::Relaxed);
+                            }
+
+                            let mut drop_steals = self.engine.solver_drop_steals.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut drop_steals, "Drop Notes Instead of Stealing Keys").changed() {
+                                self.engine.solver_drop_steals.store(drop_steals, Ordering::Relaxed);
+                            }
+
+                            let mut plan_files = self.engine.solver_plan_files.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut plan_files, "Plan Transposes for Files").changed() {
+                                self.engine.solver_plan_files.store(plan_files, Ordering::Relaxed);
+                            }
+                            
+                            let mut range = self.engine.transpose_range.load(Ordering::Relaxed);
+                            if ui.add(egui::Slider::new(&mut range, 12..=36).text("Transposition Range (+/-)")).changed() {
+                                self.engine.transpose_range.store(range, Ordering::Relaxed);
+                            }
+                            
+                            ui.horizontal(|ui| {
+                                if ui.button("Reset Solver").clicked() {
+                                     let mut state = self.engine.router.lock().unwrap();
+                                     state.solver.reset_transpose();
+                                     state.current_transpose_offset = 0;
+                                }
+                                if ui.button("Release Keys").clicked() {
+                                    let mut state = self.engine.router.lock().unwrap();
+                                    let keys = state.solver.reset_keys();
+                                    for k in keys {
+                                        let _ = state.key_up(k);
+                                    }
+                                    let _ = state.key_up(KeyCode::KEY_LEFTSHIFT);
+                                    let _ = state.key_up(KeyCode::KEY_LEFTCTRL);
+                                }
+                            });
+                        });
+                    }
+
+                    ui.separator();
+                    
+                    // Quantization
+                    let mut quant_enabled = self.engine.quantize_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut quant_enabled, "Enable Note Quantization").changed() {
+                        self.engine.quantize_enabled.store(quant_enabled, Ordering::Relaxed);
+                        // A fresh start: the next note (or song) puts the tempo grid down
+                        self.engine.anchor_quantize(None);
+                    }
+                    if quant_enabled {
+                        let mut mode = *self.engine.quantize_mode.lock().unwrap();
+                        ui.horizontal(|ui| {
+                            ui.label("Grid:");
+                            for option in QuantizeMode::ALL {
+                                ui.radio_value(&mut mode, option, option.label());
+                            }
+                        });
+                        if mode != *self.engine.quantize_mode.lock().unwrap() {
+                            *self.engine.quantize_mode.lock().unwrap() = mode;
+                            self.engine.anchor_quantize(None);
+                        }
+                        match mode {
+                            QuantizeMode::Milliseconds => {
+                                let mut ms = self.engine.quantize_ms.load(Ordering::Relaxed);
+                                if ui.add(egui::Slider::new(&mut ms, 10..=500).text("Quantize (ms)")).changed() {
+                                    self.engine.quantize_ms.store(ms, Ordering::Relaxed);
+                                }
+                            }
+                            QuantizeMode::Tempo | QuantizeMode::MidiClock => {
+                                ui.horizontal(|ui| {
+                                    if mode == QuantizeMode::Tempo {
+                                        let mut bpm = self.engine.quantize_bpm.load(Ordering::Relaxed);
+                                        if ui.add(egui::Slider::new(&mut bpm, quantize::MIN_BPM..=quantize::MAX_BPM).text("BPM")).changed() {
+                                            self.engine.quantize_bpm.store(bpm, Ordering::Relaxed);
+                                        }
+                                        if ui.button("Tap").on_hover_text("Tap along to set the tempo; the grid lines up with the last tap").clicked() {
+                                            self.engine.tap_tempo();
+                                        }
+                                    } else {
+                                        let now_us = self.engine.clock.now_us();
+                                        let clock = self.engine.midi_clock.lock().unwrap();
+                                        match clock.bpm() {
+                                            Some(bpm) if clock.is_running(now_us) => ui.label(format!("Following {:.1} BPM", bpm)),
+                                            _ => ui.weak("Waiting for the sequencer to play (notes aren't quantized until then)"),
+                                        };
+                                        ctx.request_repaint_after(time::Duration::from_millis(250));
+                                    }
+                                    let mut subdivision = self.engine.quantize_subdivision.load(Ordering::Relaxed);
+                                    egui::ComboBox::from_id_salt("quantize_subdivision")
+                                        .selected_text(quantize::subdivision_label(subdivision))
+                                        .show_ui(ui, |ui| {
+                                            for option in [1, 2, 3, 4, 6, 8] {
+                                                ui.selectable_value(&mut subdivision, option, quantize::subdivision_label(option));
+                                            }
+                                        });
+                                    self.engine.quantize_subdivision.store(subdivision, Ordering::Relaxed);
+                                    if mode == QuantizeMode::Tempo && ui.button("Restart Grid").on_hover_text("The next note puts the grid down again").clicked() {
+                                        self.engine.anchor_quantize(None);
+                                    }
+                                });
+                                let mut swing = self.engine.quantize_swing.load(Ordering::Relaxed);
+                                if ui
+                                    .add(egui::Slider::new(&mut swing, quantize::MIN_SWING..=quantize::MAX_SWING).text("Swing (%)"))
+                                    .on_hover_text("Delays the off-beat lines: 50% is straight, 66% a triplet shuffle")
+                                    .changed()
+                                {
+                                    self.engine.quantize_swing.store(swing, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    }
+
+                    let mut humanize_ms = self.engine.humanize_ms.load(Ordering::Relaxed);
+                    if ui
+                        .add(egui::Slider::new(&mut humanize_ms, 0..=scheduler::MAX_HUMANIZE_MS).text("Humanize (ms)"))
+                        .on_hover_text("Holds each note back by a random delay up to this, so chords and runs don't sound machine-perfect; 0 is off")
+                        .changed()
+                    {
+                        self.engine.humanize_ms.store(humanize_ms, Ordering::Relaxed);
+                    }
+                    ui.horizontal(|ui| {
+                        let mut roll_ms = self.engine.chord_roll_ms.load(Ordering::Relaxed);
+                        if ui
+                            .add(egui::Slider::new(&mut roll_ms, 0..=scheduler::MAX_CHORD_ROLL_MS).text("Chord Roll (ms)"))
+                            .on_hover_text("Spreads the notes of a chord over this long, like a strum; 0 is off")
+                            .changed()
+                        {
+                            self.engine.chord_roll_ms.store(roll_ms, Ordering::Relaxed);
+                        }
+                        let mut direction = self.engine.chord_roll_direction.lock().unwrap();
+                        for option in RollDirection::ALL {
+                            ui.radio_value(&mut *direction, option, option.label());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut arp_enabled = self.engine.arpeggiator_enabled.load(Ordering::Relaxed);
+                        if ui
+                            .checkbox(&mut arp_enabled, "Arpeggiator")
+                            .on_hover_text("While a chord is held, plays its notes one at a time instead of holding every key")
+                            .changed()
+                        {
+                            self.engine.arpeggiator_enabled.store(arp_enabled, Ordering::Relaxed);
+                        }
+                        let mut pattern = self.engine.arpeggiator_pattern.lock().unwrap();
+                        egui::ComboBox::from_id_salt("arpeggiator_pattern").selected_text(pattern.label()).show_ui(ui, |ui| {
+                            for option in ArpPattern::ALL {
+                                ui.selectable_value(&mut *pattern, option, option.label());
+                            }
+                        });
+                        if *self.engine.quantize_mode.lock().unwrap() == QuantizeMode::MidiClock {
+                            ui.weak(format!("{} of the MIDI clock", quantize::subdivision_label(self.engine.quantize_subdivision.load(Ordering::Relaxed))));
+                        } else {
+                            ui.weak(format!(
+                                "{} at {} BPM",
+                                quantize::subdivision_label(self.engine.quantize_subdivision.load(Ordering::Relaxed)),
+                                self.engine.quantize_bpm.load(Ordering::Relaxed)
+                            ));
+                        }
+                    });
+
+                    // Metronome, on the quantize grid or the loaded file's beats
+                    ui.horizontal(|ui| {
+                        let now_us = self.engine.clock.now_us();
+                        let mut metronome = self.engine.metronome.lock().unwrap();
+                        ui.checkbox(&mut metronome.enabled, "Metronome");
+                        ui.checkbox(&mut metronome.audible, "Click");
+                        ui.radio_value(&mut metronome.source, BeatSource::Quantize, "Quantize Grid");
+                        if let Some(file) = &self.midi_file
+                            && ui.radio(metronome.source == BeatSource::File, "File Tempo").on_hover_text("Follow the loaded file's beats, starting now").clicked()
+                        {
+                            metronome.start_file(file, now_us);
+                        }
+
+                        // Beat indicator: lit for a moment after each beat, brighter on the downbeat
+                        let (rect, _) = ui.allocate_exact_size(egui::vec2(14.0, 14.0), egui::Sense::hover());
+                        let lit = metronome.enabled && metronome.last_beat.is_some_and(|(at, _)| now_us.saturating_sub(at) < 120_000);
+                        let color = match metronome.last_beat {
+                            Some((_, true)) if lit => egui::Color32::from_rgb(255, 80, 80),
+                            Some(_) if lit => egui::Color32::from_rgb(80, 200, 80),
+                            _ => egui::Color32::from_gray(50),
+                        };
+                        ui.painter().circle_filled(rect.center(), 6.0, color);
+                        if metronome.enabled {
+                            ctx.request_repaint_after(time::Duration::from_millis(30));
+                        }
+                    });
+                    {
+                        let mut metronome = self.engine.metronome.lock().unwrap();
+                        let tempo_grid = *self.engine.quantize_mode.lock().unwrap() != QuantizeMode::Milliseconds;
+                        if metronome.enabled && metronome.source == BeatSource::Quantize {
+                            ui.horizontal(|ui| {
+                                // A tempo grid has its own beats
+                                if !tempo_grid {
+                                    ui.add(egui::Slider::new(&mut metronome.grid_steps, 1..=16).text("Grid Lines per Beat"));
+                                }
+                                ui.add(egui::Slider::new(&mut metronome.beats_per_bar, 1..=12).text("Beats per Bar"));
+                            });
+                        }
+                    }
+
+                    ui.separator();
+
+                    // Note processors registered through Engine::add_processor
+                    egui::CollapsingHeader::new("Processors").show(ui, |ui| {
+                        let mut chain = self.engine.processors.lock().unwrap();
+                        if chain.slots.is_empty() {
+                            ui.label("No processors registered.");
+                        }
+                        for (i, slot) in chain.slots.iter_mut().enumerate() {
+                            let name = slot.processor.name().to_string();
+                            ui.checkbox(&mut slot.enabled, &name);
+                            if !slot.enabled {
+                                continue;
+                            }
+                            ui.indent(("processor", i), |ui| {
+                                for param in slot.processor.params() {
+                                    match param.value {
+                                        ParamValue::Bool(mut value) => {
+                                            if ui.checkbox(&mut value, &param.name).changed() {
+                                                slot.processor.set_param(&param.name, ParamValue::Bool(value));
+                                            }
+                                        }
+                                        ParamValue::Int { mut value, min, max } => {
+                                            if ui.add(egui::Slider::new(&mut value, min..=max).text(&param.name)).changed() {
+                                                slot.processor.set_param(&param.name, ParamValue::Int { value, min, max });
+                                            }
+                                        }
+                                    }
+                                }
+                            });
+                        }
+                    });
+
+                    ui.separator();
+
+                    // A Rhai script that sees each MIDI message before it's mapped
+                    egui::CollapsingHeader::new("Script").show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Script File:");
+                            ui.add(egui::TextEdit::singleline(&mut self.script_file_path).hint_text("None").desired_width(260.0));
+                            if ui.button("Load").on_hover_text("Run messages through this script; leave it empty for none").clicked() {
+                                let path = self.script_file_path.trim().to_string();
+                                self.status_message = match self.engine.use_script_file(&path) {
+                                    Ok(()) if path.is_empty() => "Script off".to_string(),
+                                    Ok(()) => format!("Running script '{}'", path),
+                                    Err(e) => format!("Kept the current script: {}", e),
+                                };
+                            }
+                        });
+                        #[cfg(feature = "scripting")]
+                        {
+                            let mut script = self.engine.script.lock().unwrap();
+                            if let Some(script) = script.as_mut()
+                                && let Some(error) = &script.last_error
+                            {
+                                ui.colored_label(egui::Color32::from_rgb(255, 80, 80), error);
+                                if ui.small_button("Clear").clicked() {
+                                    script.last_error = None;
+                                }
+                            }
+                        }
+                        #[cfg(not(feature = "scripting"))]
+                        ui.label("This build has no scripting; build with the `scripting` feature to run scripts.");
+                    });
+
+                    ui.separator();
+
+                    // Session Statistics
+                    egui::CollapsingHeader::new("Session Statistics").show(ui, |ui| {
+                        let report = self.engine.stats.lock().unwrap().report();
+                        ui.label(format!(
+                            "Notes: {} in, {} played, {} dropped",
+                            report.notes_in, report.notes_played, report.notes_dropped
+                        ));
+                        for (reason, count) in &report.drops_by_reason {
+                            ui.label(format!("    {}: {}", reason.label(), count));
+                        }
+                        ui.label(format!(
+                            "Transposes: {} ({} taps), Steals: {}",
+                            report.transposes, report.transpose_taps, report.steals
+                        ));
+                        ui.label(format!(
+                            "Latency p50/p90/p99/max: {:.1} / {:.1} / {:.1} / {:.1} ms",
+                            report.latency_p50_us as f64 / 1000.0,
+                            report.latency_p90_us as f64 / 1000.0,
+                            report.latency_p99_us as f64 / 1000.0,
+                            report.latency_max_us as f64 / 1000.0
+                        ));
+                        let histogram = self.engine.stats.lock().unwrap().latency_histogram();
+                        ui.label(format!(
+                            "Latency Histogram ({} outliers over {:.1} ms):",
+                            histogram.outliers,
+                            histogram.outlier_threshold_us as f64 / 1000.0
+                        ));
+                        draw_latency_histogram(ui, &histogram);
+
+                        ui.label("Transpose Over Time:");
+                        let (history, elapsed) = {
+                            let stats = self.engine.stats.lock().unwrap();
+                            (stats.transpose_history.clone(), stats.elapsed_ms())
+                        };
+                        draw_transpose_graph(ui, &history, elapsed);
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Export Stats").clicked() {
+                                self.export_stats();
+                            }
+                            let mut on_disconnect = self.engine.export_stats_on_disconnect.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut on_disconnect, "Export on Disconnect").changed() {
+                                self.engine.export_stats_on_disconnect.store(on_disconnect, Ordering::Relaxed);
+                            }
+                            // Takes effect immediately; turning it on mid-session starts a new file
+                            let mut record_keys = self.engine.record_keys_enabled.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut record_keys, "Record Key Events").changed() {
+                                self.engine.record_keys_enabled.store(record_keys, Ordering::Relaxed);
+                                if record_keys {
+                                    self.status_message = self.engine.start_key_recording();
+                                } else {
+                                    self.engine.router.lock().unwrap().recorder = None;
+                                }
+                            }
+                        });
+                    });
+
+                    // Event Inspector
+                    egui::CollapsingHeader::new("Event Inspector").show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            let mut capture = self.engine.inspector_enabled.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut capture, "Capture").changed() {
+                                self.engine.inspector_enabled.store(capture, Ordering::Relaxed);
+                            }
+                            if ui.button("Clear").clicked() {
+                                self.engine.inspector.lock().unwrap().clear();
+                            }
+                            ui.label("Note:");
+                            ui.add(egui::TextEdit::singleline(&mut self.inspector_filter).hint_text("e.g. C4 or 60").desired_width(80.0));
+                        });
+
+                        let filter = midifile::parse_note(&self.inspector_filter);
+                        let log = self.engine.inspector.lock().unwrap();
+                        let rows: Vec<&inspector::InspectorEntry> = log
+                            .entries
+                            .iter()
+                            .filter(|e| filter.is_none() || e.note() == filter)
+                            .collect();
+                        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                        egui::ScrollArea::vertical()
+                            .id_salt("inspector_rows")
+                            .max_height(200.0)
+                            .stick_to_bottom(true)
+                            .show_rows(ui, row_height, rows.len(), |ui, range| {
+                                for entry in &rows[range] {
+                                    let decision = entry.decision.as_ref().map(|d| d.describe()).unwrap_or_else(|| "-".to_string());
+                                    let line = format!(
+                                        "{:>9.3}s  {:<22} {:<36} {}",
+                                        entry.at_ms as f64 / 1000.0,
+                                        entry.describe_input(),
+                                        decision,
+                                        entry.describe_output()
+                                    );
+                                    let color = match entry.decision {
+                                        Some(Decision::Dropped(_)) => egui::Color32::from_rgb(255, 140, 0),
+                                        _ => egui::Color32::LIGHT_GRAY,
+                                    };
+                                    ui.label(egui::RichText::new(line).monospace().color(color));
+                                }
+                            });
+                    });
+
+                    // Solver Decisions
+                    egui::CollapsingHeader::new("Solver Decisions").show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            let mut capture = self.engine.solver_log_enabled.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut capture, "Capture").changed() {
+                                self.engine.solver_log_enabled.store(capture, Ordering::Relaxed);
+                            }
+                            if ui.button("Clear").clicked() {
+                                self.engine.solver_log.lock().unwrap().clear();
+                            }
+                        });
+
+                        let log = self.engine.solver_log.lock().unwrap();
+                        egui::ScrollArea::vertical()
+                            .id_salt("solver_decisions")
+                            .max_height(250.0)
+                            .stick_to_bottom(true)
+                            .show(ui, |ui| {
+                                for (i, decision) in log.entries.iter().enumerate() {
+                                    let title = format!("{:>9.3}s  {}", decision.at_us as f64 / 1_000_000.0, decision.describe());
+                                    let color = if decision.chosen.is_some() { egui::Color32::LIGHT_GRAY } else { egui::Color32::from_rgb(255, 140, 0) };
+                                    egui::CollapsingHeader::new(egui::RichText::new(title).monospace().color(color))
+                                        .id_salt(("solver_decision", i, decision.at_us))
+                                        .show(ui, |ui| {
+                                            // Keys that would need more than the whole range are only counted
+                                            let out_of_range = decision.candidates.iter().filter(|c| c.outcome == Err(solver::Rejection::OutOfRange)).count();
+                                            for candidate in decision.candidates.iter().filter(|c| c.outcome != Err(solver::Rejection::OutOfRange)) {
+                                                let modifiers = format!("{}{}", if candidate.ctrl { "Ctrl+" } else { "" }, if candidate.shift { "Shift+" } else { "" });
+                                                let outcome = match candidate.outcome {
+                                                    Ok(cost) => format!("cost {}", cost),
+                                                    Err(rejection) => rejection.label().to_string(),
+                                                };
+                                                let picked = decision.chosen == Some((candidate.transpose, candidate.key));
+                                                let line = format!("{} {:+3}  {}{:?}  {}", if picked { ">" } else { " " }, candidate.transpose, modifiers, candidate.key, outcome);
+                                                ui.label(egui::RichText::new(line).monospace());
+                                            }
+                                            if out_of_range > 0 {
+                                                ui.label(egui::RichText::new(format!("  ({} more keys out of the transpose range)", out_of_range)).monospace());
+                                            }
+                                        });
+                                }
+                            });
+                    });
+
+                    ui.separator();
+
+                    // MIDI File (shared by practice and the playability report)
+                    ui.horizontal(|ui| {
+                        ui.label("MIDI File:");
+                        ui.add(egui::TextEdit::singleline(&mut self.midi_file_path).hint_text("/path/to/song.mid"));
+                        if ui.button("Load").clicked() {
+                            self.load_midi_file();
+                        }
+                        if ui.add_enabled(self.midi_file.is_some(), egui::Button::new("Export Letter Sheet")).clicked()
+                            && let Some(file) = &self.midi_file
+                        {
+                            let text = sheet::export(file, &self.engine.settings());
+                            self.status_message = match sheet::save(&file.name, &text) {
+                                Ok(path) => format!("Letter sheet written to {}", path.display()),
+                                Err(e) => format!("Failed to write letter sheet: {}", e),
+                            };
+                        }
+                    });
+
+                    // Practice Scoring
+                    egui::CollapsingHeader::new("Practice").show(ui, |ui| {
+                        let mut practice = self.engine.practice.lock().unwrap();
+                        if let Some(file) = &self.midi_file
+                            && ui.button("Use Loaded File as Reference").clicked()
+                        {
+                            practice.load_reference(file);
+                        }
+
+                        if practice.has_reference() {
+                            ui.label(format!("Reference: {} ({} notes)", practice.reference_name, practice.reference_len()));
+                            ui.horizontal(|ui| {
+                                if practice.is_armed() {
+                                    ui.label(if practice.is_running() { "Recording..." } else { "Waiting for first note..." });
+                                    if ui.button("Stop Run").clicked()
+                                        && let Some(summary) = practice.finish()
+                                    {
+                                        self.status_message = format!("Practice run finished: {:.0}%", summary.score_percent);
+                                    }
+                                } else if ui.button("Start Run").clicked() {
+                                    practice.arm();
+                                }
+                            });
+
+                            for (i, run) in practice.history.iter().enumerate().rev() {
+                                ui.label(format!(
+                                    "Run {}: {:.0}%  correct {}, missed {}, wrong {}, timing {:+.0} ms (avg dev {:.0} ms)",
+                                    i + 1, run.score_percent, run.correct, run.missed, run.extra, run.mean_offset_ms, run.mean_deviation_ms
+                                ));
+                            }
+                        }
+                    });
+
+                    // Unplayable Notes Report
+                    egui::CollapsingHeader::new("Playability Report").show(ui, |ui| {
+                        if ui.add_enabled(self.midi_file.is_some(), egui::Button::new("Analyze with Current Settings")).clicked()
+                            && let Some(file) = &self.midi_file
+                        {
+                            self.playability = Some(playability::analyze(file, &self.engine.settings()));
+                        }
+
+                        if let Some(report) = &self.playability {
+                            ui.label(format!(
+                                "{}: {} of {} notes would be dropped",
+                                report.file_name, report.unplayable.len(), report.total_notes
+                            ));
+                            for (reason, count) in report.counts_by_reason() {
+                                ui.label(format!("    {}: {}", reason.label(), count));
+                            }
+
+                            // Timeline with a marker per dropped note
+                            let (timeline, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 24.0), egui::Sense::hover());
+                            painter.rect_filled(timeline.rect, 2.0, egui::Color32::from_gray(40));
+                            let duration = report.duration_us.max(1) as f32;
+                            for n in &report.unplayable {
+                                let x = timeline.rect.min.x + timeline.rect.width() * (n.at_us as f32 / duration);
+                                let color = match n.reason {
+                                    DropReason::DrumChannel => egui::Color32::GRAY,
+                                    DropReason::ChannelFiltered => egui::Color32::DARK_GRAY,
+                                    DropReason::OutOfRange => egui::Color32::RED,
+                                    DropReason::NoMapping => egui::Color32::from_rgb(255, 140, 0),
+                                    DropReason::NoSolverMatch => egui::Color32::YELLOW,
+                                    DropReason::KeyBusy => egui::Color32::KHAKI,
+                                    DropReason::TooSoft => egui::Color32::LIGHT_GRAY,
+                                    DropReason::Polyphony => egui::Color32::from_rgb(180, 120, 255),
+                                };
+                                painter.line_segment([egui::pos2(x, timeline.rect.min.y), egui::pos2(x, timeline.rect.max.y)], egui::Stroke::new(1.0, color));
+                            }
+
+                            if report.transpose_path.len() > 1 {
+                                ui.label(format!("Solver Transpose Path ({} changes):", report.transpose_path.len() - 1));
+                                draw_transpose_graph(ui, &report.transpose_path, report.duration_us / 1000);
+                            }
+
+                            egui::ScrollArea::vertical().id_salt("playability_list").max_height(120.0).show(ui, |ui| {
+                                for n in &report.unplayable {
+                                    let ms = n.at_us / 1000;
+                                    ui.label(format!(
+                                        "{}:{:02}.{:03}  ch {:>2}  {:<4} {}",
+                                        ms / 60_000, (ms / 1000) % 60, ms % 1000,
+                                        n.channel + 1, midifile::note_name(n.note), n.reason.label()
+                                    ));
+                                }
+                            });
+                        }
+                    });
+
+                    // A/B comparison of two saved profiles on the loaded file
+                    egui::CollapsingHeader::new("Compare Profiles").show(ui, |ui| {
+                        let profiles = config::list_profiles();
+                        ui.horizontal(|ui| {
+                            for (label, selected) in [("A:", &mut self.compare_profiles.0), ("B:", &mut self.compare_profiles.1)] {
+                                ui.label(label);
+                                egui::ComboBox::from_id_salt(format!("compare_{}", label))
+                                    .selected_text(if selected.is_empty() { "Select Profile" } else { selected.as_str() })
+                                    .show_ui(ui, |ui| {
+                                        for name in &profiles {
+                                            ui.selectable_value(selected, name.clone(), name);
+                                        }
+                                    });
+                            }
+                            let ready = self.midi_file.is_some() && !self.compare_profiles.0.is_empty() && !self.compare_profiles.1.is_empty();
+                            if ui.add_enabled(ready, egui::Button::new("Compare")).clicked()
+                                && let Some(file) = &self.midi_file
+                            {
+                                let (a, b) = &self.compare_profiles;
+                                match playability::compare_profiles(file, a, b) {
+                                    Ok(text) => self.comparison = Some(text),
+                                    Err(e) => self.status_message = e.to_string(),
+                                }
+                            }
+                        });
+                        if let Some(text) = &self.comparison {
+                            egui::ScrollArea::vertical().id_salt("comparison").max_height(200.0).show(ui, |ui| {
+                                ui.label(egui::RichText::new(text).monospace());
+                            });
+                        }
+                    });
+
+
+                    // Duet: a second controller playing through an engine of its own
+                    egui::CollapsingHeader::new("Duet").show(ui, |ui| {
+                        let Some(partner) = &mut self.partner else {
+                            if ui.button("Add Second Player").clicked() {
+                                match output::create_partner_device() {
+                                    Ok(device) => {
+                                        self.partner = Some(Partner::new(
+                                            KeyOutput::Uinput(device),
+                                            &self.engine.settings(),
+                                            self.engine.mappings.lock().unwrap().clone(),
+                                        ));
+                                        self.status_message = "Second player keyboard created".to_string();
+                                    }
+                                    Err(e) => self.status_message = format!("Failed to create the second keyboard: {}", e),
+                                }
+                            }
+                            return;
+                        };
+                        let profiles = config::list_profiles();
+                        ui.horizontal(|ui| {
+                            ui.label("Profile:");
+                            egui::ComboBox::from_id_salt("partner_profile")
+                                .selected_text(partner.profile.as_deref().unwrap_or("Same as Player 1"))
+                                .show_ui(ui, |ui| {
+                                    for name in &profiles {
+                                        if ui.selectable_label(partner.profile.as_ref() == Some(name), name).clicked() {
+                                            match config::load_profile(name) {
+                                                Ok(settings) => {
+                                                    partner.engine.apply_settings(&settings);
+                                                    partner.profile = Some(name.clone());
+                                                }
+                                                Err(e) => self.status_message = format!("Failed to load profile: {}", e),
+                                            }
+                                        }
+                                    }
+                                });
+                        });
+                        if partner.connection.is_some() {
+                            ui.horizontal(|ui| {
+                                ui.label(egui::RichText::new("Player 2: Connected").color(egui::Color32::GREEN));
+                                if ui.button("Disconnect").clicked() {
+                                    partner.disconnect();
+                                }
+                            });
+                        } else {
+                            ui.horizontal(|ui| {
+                                ui.label("Player 2 Device:");
+                                egui::ComboBox::from_id_salt("partner_port")
+                                    .selected_text(partner.selected_port_name.as_deref().unwrap_or("Select MIDI Device"))
+                                    .show_ui(ui, |ui| {
+                                        for (name, _) in &partner.ports {
+                                            ui.selectable_value(&mut partner.selected_port_name, Some(name.clone()), name);
+                                        }
+                                    });
+                                if ui.button("Refresh").clicked() {
+                                    partner.refresh_ports();
+                                }
+                                if ui.add_enabled(partner.selected_port_name.is_some(), egui::Button::new("Connect")).clicked()
+                                    && let Some(port_name) = partner.selected_port_name.clone()
+                                    && let Some((_, port)) = partner.ports.iter().find(|(n, _)| *n == port_name)
+                                    && let Some(midi_in) = partner.midi_input.take()
+                                {
+                                    match midi_in.connect(port, "miditoroblox-player-2", handle_midi, partner.queue.clone()) {
+                                        Ok(conn) => {
+                                            partner.connection = Some(conn);
+                                            self.status_message = format!("Player 2 connected to {}", port_name);
+                                        }
+                                        Err(e) => {
+                                            self.status_message = format!("Error connecting player 2: {}", e);
+                                            partner.midi_input = Some(e.into_inner());
+                                        }
+                                    }
+                                }
+                            });
+                        }
+                        if ui.button("Remove Second Player").clicked()
+                            && let Some(partner) = self.partner.take()
+                        {
+                            partner.engine.release_on_exit();
+                        }
+                    });
+                    ui.separator();
+
+                    // Game Auto-Detection
+                    let mut auto_profile = self.engine.auto_profile_enabled.load(Ordering::Relaxed);
+                    if ui.checkbox(&mut auto_profile, "Switch Profile by Window Title").changed() {
+                        self.engine.auto_profile_enabled.store(auto_profile, Ordering::Relaxed);
+                    }
+                    if auto_profile {
+                        ui.indent("window_rules", |ui| {
+                            let title = self.engine.active_window_title.lock().unwrap().clone();
+                            ui.label(format!("Focused Window: {}", title));
+                            if let Some(profile) = self.engine.active_profile.lock().unwrap().as_ref() {
+                                ui.label(format!("Active Profile: {}", profile));
+                            }
+
+                            let mut rules = self.engine.window_rules.lock().unwrap();
+                            let mut changed = false;
+                            let mut remove = None;
+                            for (i, rule) in rules.iter_mut().enumerate() {
+                                ui.horizontal(|ui| {
+                                    ui.label("Title contains");
+                                    changed |= ui.add(egui::TextEdit::singleline(&mut rule.title_contains).desired_width(120.0)).changed();
+                                    ui.label("Profile");
+                                    changed |= ui.add(egui::TextEdit::singleline(&mut rule.profile).desired_width(120.0)).changed();
+                                    if ui.button("Save Current").on_hover_text("Store the current settings as this rule's profile").clicked() {
+                                        self.status_message = match config::save_profile(&rule.profile, &self.engine.settings()) {
+                                            Ok(()) => format!("Saved profile '{}'", rule.profile),
+                                            Err(e) => format!("Failed to save profile: {}", e),
+                                        };
+                                    }
+                                    if ui.button("Remove").clicked() {
+                                        remove = Some(i);
+                                    }
+                                });
+                            }
+                            if let Some(i) = remove {
+                                rules.remove(i);
+                                changed = true;
+                            }
+                            if ui.button("Add Rule").clicked() {
+                                rules.push(WindowRule::default());
+                                changed = true;
+                            }
+                            if changed && let Err(e) = config::save_window_rules(&rules) {
+                                self.status_message = format!("Failed to save window rules: {}", e);
+                            }
+                        });
+                    }
+                });
+            } else {
+                 ui.label("Status: Not Connected");
+                 for (name, _) in &self.lost_ports {
+                     ui.label(format!("Waiting for {} to come back...", name));
+                 }
+                 let connect_enabled = self.selected_port_name.is_some();
+                 if ui.add_enabled(connect_enabled, egui::Button::new("Connect")).clicked()
+                     && let Some(port_name) = self.selected_port_name.clone()
+                 {
+                     self.start_session(&port_name);
+                 }
+            }
+
+            
+            ui.add_space(10.0);
+            ui.label(format!("Log: {}", self.status_message));
+
+            // BLE-MIDI keyboards, played alongside the ports above
+            egui::CollapsingHeader::new("Bluetooth MIDI").show(ui, |ui| {
+                let mut ble = self.ble.lock().unwrap();
+                let (mut scan, mut wanted) = (false, None);
+                ui.horizontal(|ui| {
+                    scan = ui.add_enabled(!ble.busy, egui::Button::new("Scan")).on_hover_text("Look for Bluetooth MIDI devices for a few seconds").clicked();
+                    if ble.busy {
+                        ui.spinner();
+                    }
+                });
+                let mut disconnect = None;
+                for (i, connection) in ble.connections.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(format!("Playing from {}", connection.name)).color(egui::Color32::GREEN));
+                        if ui.button("Disconnect").clicked() {
+                            disconnect = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = disconnect {
+                    let connection = ble.connections.remove(i);
+                    self.status_message = format!("Disconnected {}", connection.name);
+                }
+                for device in &ble.devices {
+                    if ble.connections.iter().any(|c| c.name == device.name) {
+                        continue;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label(&device.name);
+                        if !device.paired {
+                            ui.weak("(not paired)");
+                        }
+                        if ui.add_enabled(!ble.busy, egui::Button::new("Connect")).on_hover_text("Pairs first if needed").clicked() {
+                            wanted = Some(device.name.clone());
+                        }
+                    });
+                }
+                if ble.devices.is_empty() && !ble.busy {
+                    ui.weak("No devices yet; turn the keyboard's Bluetooth on and scan.");
+                }
+                drop(ble);
+                if scan {
+                    ble::scan_in_background(&self.ble, self.engine.clone());
+                }
+                if let Some(name) = wanted {
+                    self.status_message = format!("Connecting to {}", name);
+                    ble::connect_in_background(&self.ble, name, self.engine.clone(), self.queue.clone());
+                }
+            });
+
+            // MIDI thru: hear on a synth exactly the notes the game gets
+            egui::CollapsingHeader::new("MIDI Thru").show(ui, |ui| {
+                // The port goes away on its own if the synth does
+                if self.engine.router.lock().unwrap().thru.is_none() {
+                    self.thru_connected = None;
+                }
+                ui.horizontal(|ui| {
+                    if let Some(name) = self.thru_connected.clone() {
+                        ui.label(egui::RichText::new(format!("Sending to {}", name)).color(egui::Color32::GREEN));
+                        if ui.button("Disconnect").clicked() {
+                            let mut state = self.engine.router.lock().unwrap();
+                            state.thru_all_off();
+                            state.thru = None;
+                            self.thru_connected = None;
+                        }
+                        return;
+                    }
+                    ui.label("Output:");
+                    egui::ComboBox::from_id_salt("thru_port")
+                        .selected_text(self.thru_port_name.as_deref().unwrap_or("Select MIDI Output"))
+                        .show_ui(ui, |ui| {
+                            for name in &self.thru_ports {
+                                ui.selectable_value(&mut self.thru_port_name, Some(name.clone()), name);
+                            }
+                        });
+                    if ui.button("Refresh").clicked() {
+                        self.thru_ports = thru::port_names();
+                    }
+                    if ui.add_enabled(self.thru_port_name.is_some(), egui::Button::new("Connect")).clicked()
+                        && let Some(name) = self.thru_port_name.clone()
+                    {
+                        self.status_message = match thru::connect(&name) {
+                            Ok(connection) => {
+                                self.engine.router.lock().unwrap().thru = Some(Box::new(connection));
+                                self.thru_connected = Some(name.clone());
+                                format!("MIDI thru to {}", name)
+                            }
+                            Err(e) => format!("MIDI thru: {}", e),
+                        };
+                    }
+                });
+            });
+
+            // Replay a key-event recording (works without a MIDI connection)
+            egui::CollapsingHeader::new("Replay Key Recording").show(ui, |ui| {
+                let running = self.engine.replay_running.load(Ordering::Relaxed);
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.replay_path).hint_text("/path/to/keys-123.jsonl"));
+                    if ui.add_enabled(!running, egui::Button::new("Load")).clicked() {
+                        let path = std::path::Path::new(self.replay_path.trim());
+                        match recording::load(path) {
+                            Ok(events) => {
+                                let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                                self.status_message = format!("Loaded '{}' ({} key events)", name, events.len());
+                                self.replay = Some((name, events));
+                            }
+                            Err(e) => self.status_message = format!("Failed to load {}: {}", path.display(), e),
+                        }
+                    }
+                });
+                if let Some((name, events)) = &self.replay {
+                    ui.horizontal(|ui| {
+                        let duration_s = events.last().zip(events.first()).map(|(l, f)| (l.at_us - f.at_us) as f64 / 1_000_000.0).unwrap_or(0.0);
+                        ui.label(format!("{}: {} events, {:.1}s", name, events.len(), duration_s));
+                        if running {
+                            if ui.button("Stop").clicked() {
+                                self.engine.replay_stop.store(true, Ordering::Relaxed);
+                            }
+                            ui.label(egui::RichText::new("Replaying...").color(egui::Color32::YELLOW));
+                        } else if ui.button("Play").clicked() {
+                            recording::spawn_replay(self.engine.clone(), events.clone());
+                            self.status_message = format!("Replay starts in {} seconds, focus the game window", recording::REPLAY_LEAD_IN.as_secs());
+                        }
+                    });
+                }
+            });
+            
+
+            // Play a MIDI file into the game through the same pipeline as a controller
+            egui::CollapsingHeader::new("Play MIDI File").show(ui, |ui| {
+                let running = self.engine.playback_running.load(Ordering::Relaxed);
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.midi_file_path).hint_text("/path/to/song.mid"));
+                    if ui.add_enabled(!running, egui::Button::new("Load")).clicked() {
+                        self.load_midi_file();
+                    }
+                });
+                // Dense orchestral chords thinned out before playing
+                ui.horizontal(|ui| {
+                    let mut max_chord_notes = self.engine.max_chord_notes.load(Ordering::Relaxed);
+                    let mut enabled = max_chord_notes > 0;
+                    if ui
+                        .checkbox(&mut enabled, "Simplify Chords")
+                        .on_hover_text("Chords with more notes lose octave doublings and inner voices, keeping the top and bottom; from the next Play")
+                        .changed()
+                    {
+                        max_chord_notes = if enabled { 4 } else { 0 };
+                    }
+                    if enabled {
+                        ui.add(egui::Slider::new(&mut max_chord_notes, simplify::MIN_CHORD_NOTES..=12).text("Most Notes"));
+                    }
+                    self.engine.max_chord_notes.store(max_chord_notes, Ordering::Relaxed);
+                });
+                if let Some(file) = &self.midi_file {
+                    let duration_us = file.events.last().map(|e| e.at_us).unwrap_or(0);
+                    ui.label(format!("{}: {} notes, {:.1}s", file.name, file.note_ons().count(), duration_us as f64 / 1_000_000.0));
+                    // Transport bar
+                    ui.horizontal(|ui| {
+                        if !running {
+                            if ui.button("Play into Game").clicked() {
+                                player::spawn(self.engine.clone(), file);
+                                self.status_message = format!("Playback starts in {} seconds, focus the game window", recording::REPLAY_LEAD_IN.as_secs());
+                            }
+                        } else {
+                            let paused = self.engine.playback_paused.load(Ordering::Relaxed);
+                            if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                                self.engine.playback_paused.store(!paused, Ordering::Relaxed);
+                            }
+                            if ui.button("Stop").clicked() {
+                                self.engine.playback_stop.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        let mut rate = self.engine.playback_rate_percent.load(Ordering::Relaxed);
+                        if ui.add(egui::Slider::new(&mut rate, 25..=200).text("Speed %")).changed() {
+                            self.engine.playback_rate_percent.store(rate, Ordering::Relaxed);
+                        }
+                    });
+                    if running {
+                        let mut position_s = self.engine.playback_position_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+                        let playing_us = self.engine.playback_duration_us.load(Ordering::Relaxed);
+                        let seek = ui.add(
+                            egui::Slider::new(&mut position_s, 0.0..=playing_us as f64 / 1_000_000.0)
+                                .text("Position (s)")
+                                .fixed_decimals(1),
+                        );
+                        if seek.changed() {
+                            *self.engine.playback_seek_us.lock().unwrap() = Some((position_s * 1_000_000.0) as u64);
+                        }
+                        ctx.request_repaint_after(time::Duration::from_millis(100));
+                    }
+                }
+            });
+
+            // Songs played back to back, with a pause between them
+            egui::CollapsingHeader::new("Playlist").show(ui, |ui| {
+                let running = self.engine.playback_running.load(Ordering::Relaxed);
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.midi_file_path).hint_text("/path/to/song.mid"));
+                    if ui.add_enabled(!running, egui::Button::new("Add")).clicked() {
+                        match midifile::MidiFile::load(std::path::Path::new(self.midi_file_path.trim())) {
+                            Ok(file) => {
+                                self.status_message = format!("Queued '{}'", file.name);
+                                self.playlist.push(file);
+                            }
+                            Err(e) => self.status_message = e.to_string(),
+                        }
+                    }
+                });
+                let playing = self.engine.playback_song.load(Ordering::Relaxed) as usize;
+                let mut move_up = None;
+                let mut remove = None;
+                for (i, file) in self.playlist.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let duration_s = file.events.last().map(|e| e.at_us).unwrap_or(0) / 1_000_000;
+                        let text = format!("{}. {} ({}:{:02})", i + 1, file.name, duration_s / 60, duration_s % 60);
+                        if running && i == playing {
+                            ui.label(egui::RichText::new(text).color(egui::Color32::GREEN));
+                        } else {
+                            ui.label(text);
+                        }
+                        ui.add_enabled_ui(!running, |ui| {
+                            if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                                move_up = Some(i);
+                            }
+                            if ui.add_enabled(i + 1 < self.playlist.len(), egui::Button::new("Down")).clicked() {
+                                move_up = Some(i + 1);
+                            }
+                            if ui.button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    });
+                }
+                if let Some(i) = move_up {
+                    self.playlist.swap(i - 1, i);
+                }
+                if let Some(i) = remove {
+                    self.playlist.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    ui.add_enabled(!running, egui::Slider::new(&mut self.playlist_gap_s, 0..=60).text("Gap (s)"));
+                    if !running {
+                        if ui.add_enabled(!self.playlist.is_empty(), egui::Button::new("Play Playlist")).clicked() {
+                            player::spawn_playlist(self.engine.clone(), &self.playlist, time::Duration::from_secs(self.playlist_gap_s));
+                            self.status_message = format!("Playlist starts in {} seconds, focus the game window", recording::REPLAY_LEAD_IN.as_secs());
+                        }
+                    } else {
+                        if ui.button("Next Song").clicked() {
+                            self.engine.playback_skip.store(true, Ordering::Relaxed);
+                        }
+                        if ui.button("Stop").clicked() {
+                            self.engine.playback_stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                });
+            });
+            ui.add_space(10.0);
+            ui.separator();
+            
+            let mut vis_enabled = self.engine.visualizer_enabled.load(Ordering::Relaxed);
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut vis_enabled, "Show Visualizer").changed() {
+                     self.engine.visualizer_enabled.store(vis_enabled, Ordering::Relaxed);
+                }
+                ui.checkbox(&mut self.viewer_open, "Viewer Window");
+                ui.checkbox(&mut self.overlay_open, "Stream Overlay");
+                if self.overlay_open {
+                    let mut background = *self.engine.overlay_background.lock().unwrap();
+                    egui::ComboBox::from_id_salt("overlay_background")
+                        .selected_text(background.label())
+                        .show_ui(ui, |ui| {
+                            for option in OverlayBackground::ALL {
+                                ui.selectable_value(&mut background, option, option.label());
+                            }
+                        });
+                    *self.engine.overlay_background.lock().unwrap() = background;
+                }
+                
+                if vis_enabled {
+                    ui.separator();
+                    ui.label("Show Mode:");
+                    egui::ComboBox::from_id_salt("vis_mode")
+                        .selected_text("Select Modes...")
+                        .show_ui(ui, |ui| {
+                             let mut show_midi = self.engine.visualizer_show_midi.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_midi, "Midi Inputs").changed() {
+                                 self.engine.visualizer_show_midi.store(show_midi, Ordering::Relaxed);
+                             }
+                             let mut show_roblox = self.engine.visualizer_show_roblox.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_roblox, "Roblox Played").changed() {
+                                 self.engine.visualizer_show_roblox.store(show_roblox, Ordering::Relaxed);
+                             }
+                             let mut show_roll = self.engine.visualizer_show_roll.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_roll, "Piano Roll").changed() {
+                                 self.engine.visualizer_show_roll.store(show_roll, Ordering::Relaxed);
+                             }
+                             let mut show_heatmap = self.engine.visualizer_show_heatmap.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_heatmap, "Usage Heatmap").changed() {
+                                 self.engine.visualizer_show_heatmap.store(show_heatmap, Ordering::Relaxed);
+                             }
+                             let mut show_keyboard = self.engine.visualizer_show_keyboard.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_keyboard, "Computer Keyboard").changed() {
+                                 self.engine.visualizer_show_keyboard.store(show_keyboard, Ordering::Relaxed);
+                             }
+                             let mut show_names = self.engine.visualizer_show_names.load(Ordering::Relaxed);
+                             if ui.checkbox(&mut show_names, "Note Names").changed() {
+                                 self.engine.visualizer_show_names.store(show_names, Ordering::Relaxed);
+                             }
+                        });
+                }
+            });
+            
+            if vis_enabled {
+                egui::ScrollArea::horizontal().enable_scrolling(false).show(ui, |ui| {
+                    // Only the keys the game's piano has
+                    let game_mode = *self.engine.game_mode.lock().unwrap();
+                    let layout = PianoLayout::new(game_mode.key_range());
+                    if self.engine.visualizer_show_roll.load(Ordering::Relaxed) {
+                        draw_roll(ui, &self.engine, &layout, 160.0);
+                    }
+                    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 100.0), egui::Sense::click_and_drag());
+                    let rect = response.rect;
+                    self.follow_piano_clicks(&response, &layout);
+                    
+                    draw_keys(&painter, rect, &self.engine, &layout);
+                    let transpose = self.engine.game_transpose();
+                    let reachable = game_mode.reachable_range(transpose);
+                    draw_key_marks(&painter, rect, &layout, reachable.clone(), self.engine.visualizer_show_names.load(Ordering::Relaxed));
+                    let reachable_text = match &reachable {
+                        Some(notes) => format!("{} to {}", midifile::note_name(*notes.start()), midifile::note_name(*notes.end())),
+                        None => "nothing".to_string(),
+                    };
+                    ui.label(egui::RichText::new(format!("Transpose {:+}: the game plays {} right now", transpose, reachable_text)).weak());
+
+                    if self.engine.visualizer_show_keyboard.load(Ordering::Relaxed) {
+                        self.draw_computer_keyboard(ui);
+                    }
+
+                    // Physical key usage strip, one cell per mapped key
+                    if self.engine.visualizer_show_heatmap.load(Ordering::Relaxed) {
+                        let keys = self.engine.mappings.lock().unwrap().keys();
+                        let (key_usage, max_key_usage) = {
+                            let stats = self.engine.stats.lock().unwrap();
+                            (stats.key_usage.clone(), stats.max_key_usage())
+                        };
+                        let (strip, strip_painter) = ui.allocate_painter(egui::vec2(rect.width(), 22.0), egui::Sense::hover());
+                        let cell_width = strip.rect.width() / keys.len().max(1) as f32;
+                        for (i, key) in keys.iter().enumerate() {
+                            let cell = egui::Rect::from_min_size(
+                                egui::pos2(strip.rect.min.x + i as f32 * cell_width, strip.rect.min.y),
+                                egui::vec2(cell_width, strip.rect.height()),
+                            );
+                            let count = key_usage.get(key).copied().unwrap_or(0);
+                            let fill = if count > 0 { heat_color(count, max_key_usage) } else { egui::Color32::from_gray(40) };
+                            strip_painter.rect(cell, 1.0, fill, egui::Stroke::new(1.0, egui::Color32::GRAY), egui::StrokeKind::Inside);
+                            let label = format!("{:?}", key);
+                            strip_painter.text(
+                                cell.center(),
+                                egui::Align2::CENTER_CENTER,
+                                label.trim_start_matches("KEY_"),
+                                egui::FontId::monospace(10.0),
+                                egui::Color32::WHITE,
+                            );
+                        }
+                    }
+                });
+            }
+        });
+
+        if self.viewer_open {
+            self.viewer_open = show_viewer(ctx, &self.engine);
+        }
+        if self.overlay_open {
+            self.overlay_open = show_overlay(ctx, &self.engine);
+        }
+    }
+}
+
+// How much time the piano roll shows: notes played, and during file playback notes coming
+const ROLL_PAST_US: u64 = 4_000_000;
+const ROLL_AHEAD_US: u64 = 4_000_000;
+
+// A color per MIDI channel, around the color wheel
+fn channel_color(channel: u8) -> egui::Color32 {
+    egui::ecolor::Hsva::new(channel as f32 / 16.0, 0.7, 0.9, 1.0).into()
+}
+
+// Falling notes above the visualizer's keys, lined up with them. Time runs down the roll:
+// what was played falls toward the keys and out of sight, and during playback the song's
+// next notes come down from the top to the "now" line, dimmer until they're played.
+fn draw_roll(ui: &mut egui::Ui, engine: &Engine, layout: &PianoLayout, height: f32) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), height), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+    let now_us = engine.clock.now_us() as i64;
+    let playing = engine.playback_running.load(Ordering::Relaxed);
+    let ahead_us = if playing { ROLL_AHEAD_US as i64 } else { 0 };
+    let shown_us = (ahead_us + ROLL_PAST_US as i64) as f32;
+    let y_of = |at_us: i64| rect.min.y + (now_us + ahead_us - at_us) as f32 / shown_us * rect.height();
+    let bar = |span: &NoteSpan, start_us: i64, end_us: i64, color: egui::Color32| {
+        let Some((left, right)) = layout.key_span(span.note) else { return };
+        let top = y_of(end_us).max(rect.min.y);
+        let bottom = y_of(start_us).min(rect.max.y);
+        if top < bottom {
+            let bar = egui::Rect::from_min_max(egui::pos2(rect.min.x + left * rect.width(), top), egui::pos2(rect.min.x + right * rect.width(), bottom));
+            painter.rect_filled(bar.shrink2(egui::vec2(1.0, 0.0)), 2.0, color);
+        }
+    };
+
+    let history = engine.note_history.lock().unwrap().since((now_us - ROLL_PAST_US as i64).max(0) as u64);
+    for span in &history {
+        bar(span, span.start_us as i64, span.end_us.map_or(now_us, |end| end as i64), channel_color(span.channel));
+    }
+    if playing {
+        // File time to clock time, at the playback speed
+        let position_us = engine.playback_position_us.load(Ordering::Relaxed) as i64;
+        let rate = engine.playback_rate_percent.load(Ordering::Relaxed).max(1) as i64;
+        let to_clock = |file_us: i64| now_us + (file_us - position_us) * 100 / rate;
+        let events = engine.playback_events.lock().unwrap().clone();
+        let until_us = position_us + ahead_us * rate / 100;
+        for span in roll::upcoming(&events, position_us as u64, until_us as u64) {
+            let end_us = span.end_us.map_or(i64::MAX / 2, |end| to_clock(end as i64));
+            bar(&span, to_clock(span.start_us as i64), end_us, channel_color(span.channel).gamma_multiply(0.5));
+        }
+        painter.hline(rect.x_range(), y_of(now_us), egui::Stroke::new(1.0, egui::Color32::WHITE));
+    }
+    if playing || !history.is_empty() {
+        ui.ctx().request_repaint();
+    }
+}
+
+// A "#rrggbb" color setting; grey if it isn't one (`--check-config` says so)
+fn setting_color(setting: &Mutex<String>) -> egui::Color32 {
+    let [r, g, b] = config::parse_color(&setting.lock().unwrap()).unwrap_or([128, 128, 128]);
+    egui::Color32::from_rgb(r, g, b)
+}
+
+// egui's dark or light look with the theme's accent, and panels see-through by `opacity`
+fn theme_visuals(engine: &Engine, opacity: f32) -> egui::Visuals {
+    let mut visuals = if engine.light_mode.load(Ordering::Relaxed) { egui::Visuals::light() } else { egui::Visuals::dark() };
+    let accent = setting_color(&engine.accent_color);
+    visuals.selection.bg_fill = accent;
+    visuals.hyperlink_color = accent;
+    visuals.window_fill = visuals.window_fill.gamma_multiply(opacity);
+    visuals.panel_fill = visuals.panel_fill.gamma_multiply(opacity);
+    visuals
+}
+
+// Usage relative to the most used note or key: faint yellow to solid red
+fn heat_color(count: u64, max: u64) -> egui::Color32 {
+    let t = if max == 0 { 0.0 } else { count as f32 / max as f32 };
+    egui::Color32::from_rgba_unmultiplied(255, (160.0 * (1.0 - t)) as u8, 0, (40.0 + 180.0 * t) as u8)
+}
+
+// The visualizer's piano keys in `rect`: input notes green, notes the game plays blue (half
+// and half when both), the usage heatmap over them when it's on.
+fn draw_keys(painter: &egui::Painter, rect: egui::Rect, engine: &Engine, layout: &PianoLayout) {
+    let input_set = if let Ok(n) = engine.active_notes.lock() { n.clone() } else { std::collections::HashSet::new() };
+    let output_set = if let Ok(n) = engine.active_output_notes.lock() { n.clone() } else { std::collections::HashSet::new() };
+    
+    let show_input = engine.visualizer_show_midi.load(Ordering::Relaxed);
+    let show_output = engine.visualizer_show_roblox.load(Ordering::Relaxed);
+
+    // Heatmap snapshot (usage relative to the most used note)
+    let show_heatmap = engine.visualizer_show_heatmap.load(Ordering::Relaxed);
+    let (note_usage, max_note_usage) = {
+        let stats = engine.stats.lock().unwrap();
+        (stats.note_usage, stats.max_note_usage())
+    };
+
+    let input_color = setting_color(&engine.input_key_color);
+    let output_color = setting_color(&engine.output_key_color);
+    let white_color = setting_color(&engine.white_key_color);
+    let black_color = setting_color(&engine.black_key_color);
+    let draw_key = |key_rect: egui::Rect, note: u8, is_black: bool| {
+        let inp = show_input && input_set.contains(&note);
+        let outp = show_output && output_set.contains(&note);
+        
+        let base_color = if is_black { black_color } else { white_color };
+
+        if inp && outp && show_input && show_output {
+            let half_h = key_rect.height() / 2.0;
+            painter.rect_filled(egui::Rect::from_min_size(key_rect.min, egui::vec2(key_rect.width(), half_h)), if is_black {1.0} else {2.0}, input_color);
+            painter.rect_filled(egui::Rect::from_min_size(egui::pos2(key_rect.min.x, key_rect.min.y + half_h), egui::vec2(key_rect.width(), half_h)), if is_black {1.0} else {2.0}, output_color);
+        } else if inp {
+             painter.rect_filled(key_rect, if is_black {1.0} else {2.0}, input_color);
+        } else if outp {
+             painter.rect_filled(key_rect, if is_black {1.0} else {2.0}, output_color);
+        } else {
+             painter.rect_filled(key_rect, if is_black {1.0} else {2.0}, base_color);
+        }
+        let count = note_usage[note as usize];
+        if show_heatmap && count > 0 {
+            painter.rect_filled(key_rect, if is_black {1.0} else {2.0}, heat_color(count, max_note_usage));
+        }
+        painter.rect(key_rect, 1.0, egui::Color32::TRANSPARENT, egui::Stroke::new(1.0, egui::Color32::GRAY), egui::StrokeKind::Inside);
+    };
+
+    // White keys first, black ones over them
+    for black in [false, true] {
+        for note in layout.notes().filter(|&n| piano::is_black(n) == black) {
+            let Some((left, right)) = layout.key_span(note) else { continue };
+            let key_rect = egui::Rect::from_min_max(
+                egui::pos2(rect.min.x + left * rect.width(), rect.min.y),
+                egui::pos2(rect.min.x + right * rect.width(), rect.min.y + PianoLayout::key_height(note) * rect.height()),
+            );
+            draw_key(key_rect, note, black);
+        }
+    }
+}
+
+// What goes over the visualizer's keys: note names (every C's octave, and every key when
+// there's room), a dot on middle C, and the keys the game can't sound at its current
+// transpose greyed out with a line where what it can starts and ends.
+fn draw_key_marks(painter: &egui::Painter, rect: egui::Rect, layout: &PianoLayout, reachable: Option<std::ops::RangeInclusive<u8>>, show_names: bool) {
+    const MIDDLE_C: u8 = 60;
+    const LABEL_WIDTH: f32 = 22.0; // Room a name like "F#5" needs
+    let boundary = egui::Color32::from_rgb(255, 140, 0);
+    let key_rect = |note: u8| {
+        let (left, right) = layout.key_span(note)?;
+        Some(egui::Rect::from_min_max(
+            egui::pos2(rect.min.x + left * rect.width(), rect.min.y),
+            egui::pos2(rect.min.x + right * rect.width(), rect.min.y + PianoLayout::key_height(note) * rect.height()),
+        ))
+    };
+    let white_width = layout.white_width() * rect.width();
+    for note in layout.notes() {
+        let Some(key) = key_rect(note) else { continue };
+        let black = piano::is_black(note);
+        if !reachable.as_ref().is_some_and(|r| r.contains(&note)) {
+            let shade = if black { egui::Color32::from_white_alpha(50) } else { egui::Color32::from_black_alpha(110) };
+            painter.rect_filled(key, 1.0, shade);
+        }
+        if note == MIDDLE_C {
+            painter.circle_filled(egui::pos2(key.center().x, key.max.y - 26.0), 3.5, boundary);
+        }
+        let room = if black { white_width * piano::BLACK_WIDTH } else { white_width };
+        if show_names && (room >= LABEL_WIDTH || (note % 12 == 0 && white_width >= 14.0)) {
+            let color = if black { egui::Color32::WHITE } else { egui::Color32::DARK_GRAY };
+            painter.text(egui::pos2(key.center().x, key.max.y - 3.0), egui::Align2::CENTER_BOTTOM, midifile::note_name(note), egui::FontId::proportional(9.0), color);
+        }
+    }
+    // The reachable range's ends, where they fall on this keyboard
+    if let Some(reachable) = reachable {
+        let edges = [(*reachable.start(), true), (*reachable.end(), false)];
+        for (note, is_start) in edges {
+            if note == *layout.notes().start() && is_start || note == *layout.notes().end() && !is_start {
+                continue;
+            }
+            let Some(key) = key_rect(note) else { continue };
+            let x = if is_start { key.min.x } else { key.max.x };
+            painter.vline(x, rect.y_range(), egui::Stroke::new(2.0, boundary));
+        }
+    }
+}
+
+// The audience window: big note names, the chord they make and song progress, from the same
+// note state the visualizer draws. Borderless for capturing; drag it anywhere to move it.
+// Returns false once it's been closed.
+fn show_viewer(ctx: &egui::Context, engine: &Engine) -> bool {
+    let mut open = true;
+    ctx.show_viewport_immediate(
+        egui::ViewportId::from_hash_of("viewer"),
+        egui::ViewportBuilder::default()
+            .with_title("Miditoroblox Viewer")
+            .with_decorations(false)
+            .with_inner_size([480.0, 270.0]),
+        |ctx, _class| {
+            egui::CentralPanel::default()
+                .frame(egui::Frame::new().fill(egui::Color32::BLACK).inner_margin(16.0))
+                .show(ctx, |ui| {
+                    let drag = ui.interact(ui.max_rect(), egui::Id::new("viewer_drag"), egui::Sense::drag());
+                    if drag.drag_started() {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag);
+                    }
+                    let mut notes: Vec<u8> = engine.active_notes.lock().unwrap().iter().copied().collect();
+                    notes.sort_unstable();
+                    ui.vertical_centered(|ui| {
+                        let chord = midifile::chord_name(&notes).unwrap_or_default();
+                        ui.label(egui::RichText::new(chord).size(72.0).strong().color(egui::Color32::WHITE));
+                        let names: Vec<String> = notes.iter().map(|n| midifile::note_name(*n)).collect();
+                        ui.label(egui::RichText::new(names.join(" ")).size(32.0).color(egui::Color32::LIGHT_GREEN));
+                    });
+                    let duration_us = engine.playback_duration_us.load(Ordering::Relaxed);
+                    if engine.playback_running.load(Ordering::Relaxed) && duration_us > 0 {
+                        let position_us = engine.playback_position_us.load(Ordering::Relaxed);
+                        let clock = |us: u64| format!("{}:{:02}", us / 60_000_000, (us / 1_000_000) % 60);
+                        ui.with_layout(egui::Layout::bottom_up(egui::Align::Center), |ui| {
+                            ui.label(egui::RichText::new(format!("{} / {}", clock(position_us), clock(duration_us))).size(20.0));
+                            ui.add(egui::ProgressBar::new(position_us as f32 / duration_us as f32));
+                        });
+                    }
+                });
+            if ctx.input(|i| i.viewport().close_requested()) {
+                open = false;
+            }
+        },
+    );
+    open
+}
+
+// The stream overlay: the visualizer alone (piano roll too when it's on) on magenta or on
+// nothing at all, borderless, for OBS and the like to capture without the settings.
+// Drag it to move it, as the viewer. Returns false once it's been closed.
+fn show_overlay(ctx: &egui::Context, engine: &Engine) -> bool {
+    let mut open = true;
+    let fill = match *engine.overlay_background.lock().unwrap() {
+        OverlayBackground::Magenta => egui::Color32::from_rgb(255, 0, 255),
+        OverlayBackground::Transparent => egui::Color32::TRANSPARENT,
+    };
+    ctx.show_viewport_immediate(
+        egui::ViewportId::from_hash_of("overlay"),
+        egui::ViewportBuilder::default()
+            .with_title("Miditoroblox Overlay")
+            .with_decorations(false)
+            .with_transparent(true)
+            .with_inner_size([1000.0, 260.0]),
+        |ctx, _class| {
+            egui::CentralPanel::default().frame(egui::Frame::new().fill(fill)).show(ctx, |ui| {
+                let drag = ui.interact(ui.max_rect(), egui::Id::new("overlay_drag"), egui::Sense::drag());
+                if drag.drag_started() {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag);
+                }
+                ui.spacing_mut().item_spacing = egui::Vec2::ZERO;
+                let layout = PianoLayout::new(engine.game_mode.lock().unwrap().key_range());
+                let keys_height = if engine.visualizer_show_roll.load(Ordering::Relaxed) {
+                    let keys_height = (ui.available_height() * 0.4).max(60.0);
+                    draw_roll(ui, engine, &layout, ui.available_height() - keys_height);
+                    keys_height
+                } else {
+                    ui.available_height()
+                };
+                let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), keys_height), egui::Sense::hover());
+                draw_keys(&painter, response.rect, engine, &layout);
+                // The names, but none of the transpose marks; those are for the player
+                draw_key_marks(&painter, response.rect, &layout, Some(layout.notes()), engine.visualizer_show_names.load(Ordering::Relaxed));
+            });
+            if ctx.input(|i| i.viewport().close_requested()) {
+                open = false;
+            }
+        },
+    );
+    open
+}
+
+// Bars per latency bucket, red past the outlier threshold
+fn draw_latency_histogram(ui: &mut egui::Ui, histogram: &stats::LatencyHistogram) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(30));
+
+    let max_count = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+    let bar_width = rect.width() / histogram.counts.len() as f32;
+    for (i, &count) in histogram.counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let height = (rect.height() - 12.0) * count as f32 / max_count as f32;
+        let x = rect.min.x + i as f32 * bar_width;
+        let bar = egui::Rect::from_min_max(egui::pos2(x + 1.0, rect.max.y - height), egui::pos2(x + bar_width - 1.0, rect.max.y));
+        let bucket_start = i as u64 * histogram.bucket_us;
+        let color = if bucket_start >= histogram.outlier_threshold_us {
+            egui::Color32::from_rgb(220, 60, 60)
+        } else {
+            egui::Color32::from_rgb(0, 180, 255)
+        };
+        painter.rect_filled(bar, 0.0, color);
+    }
+
+    // Threshold marker
+    let range_us = (histogram.bucket_us * histogram.counts.len() as u64) as f32;
+    let x = rect.min.x + rect.width() * (histogram.outlier_threshold_us as f32 / range_us).min(1.0);
+    painter.line_segment([egui::pos2(x, rect.min.y), egui::pos2(x, rect.max.y)], egui::Stroke::new(1.0, egui::Color32::YELLOW));
+
+    painter.text(rect.left_top() + egui::vec2(4.0, 2.0), egui::Align2::LEFT_TOP, "0 ms", egui::FontId::monospace(10.0), egui::Color32::GRAY);
+    painter.text(
+        rect.right_top() + egui::vec2(-4.0, 2.0),
+        egui::Align2::RIGHT_TOP,
+        format!("{:.1} ms+", range_us / 1000.0),
+        egui::FontId::monospace(10.0),
+        egui::Color32::GRAY,
+    );
+}
+
+// Step plot of the transpose offset over time, scaled to the largest offset reached
+fn draw_transpose_graph(ui: &mut egui::Ui, samples: &[(u64, i32)], end_ms: u64) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(30));
+
+    let max_abs = samples.iter().map(|(_, o)| o.abs()).max().unwrap_or(0).max(1);
+    let end = end_ms.max(1) as f32;
+    let x_for = |ms: u64| rect.min.x + rect.width() * (ms as f32 / end).min(1.0);
+    let y_for = |offset: i32| rect.center().y - (offset as f32 / max_abs as f32) * (rect.height() / 2.0 - 4.0);
+
+    painter.line_segment(
+        [egui::pos2(rect.min.x, rect.center().y), egui::pos2(rect.max.x, rect.center().y)],
+        egui::Stroke::new(1.0, egui::Color32::from_gray(70)),
+    );
+
+    let mut points = Vec::new();
+    for (i, &(ms, offset)) in samples.iter().enumerate() {
+        let x = x_for(ms);
+        if i > 0 {
+            points.push(egui::pos2(x, y_for(samples[i - 1].1)));
+        }
+        points.push(egui::pos2(x, y_for(offset)));
+    }
+    if let Some(&(_, last)) = samples.last() {
+        points.push(egui::pos2(rect.max.x, y_for(last)));
+    }
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, egui::Color32::from_rgb(0, 180, 255))));
+
+    painter.text(rect.left_top() + egui::vec2(4.0, 2.0), egui::Align2::LEFT_TOP, format!("+{}", max_abs), egui::FontId::monospace(10.0), egui::Color32::GRAY);
+    painter.text(rect.left_bottom() + egui::vec2(4.0, -2.0), egui::Align2::LEFT_BOTTOM, format!("-{}", max_abs), egui::FontId::monospace(10.0), egui::Color32::GRAY);
+}
+
+const WINDOW_TITLE: &str = "Miditoroblox";
+
+pub fn run(output: KeyOutput, drum_output: Option<KeyOutput>, startup: Startup) -> eframe::Result {
+    // Where and how big it was last time; MidiApp::new restores the rest
+    let state = config::load_window_state();
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_transparent(true)
+        .with_inner_size([state.width, state.height]);
+    if let (Some(x), Some(y)) = (state.x, state.y) {
+        viewport = viewport.with_position([x, y]);
+    }
+    let options = eframe::NativeOptions { viewport, ..Default::default() };
+    eframe::run_native(
+        WINDOW_TITLE,
+        options,
+        Box::new(|cc| Ok(Box::new(MidiApp::new(cc, output, drum_output, startup)))),
+    )
+}
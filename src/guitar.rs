@@ -0,0 +1,85 @@
+use evdev::KeyCode;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::GameMode;
+use crate::error::{Error, Result};
+use crate::mappings::parse_key_str;
+use crate::midifile;
+use crate::Engine;
+
+// Guitar/bass games: held notes select fret keys (one per pitch class, any octave) and a
+// separate strum key sounds them. Notes arriving within the strum window count as one
+// chord, so the strum is tapped once, after all of its frets are down. The built-in layout
+// comes from guitar.json.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuitarMap {
+    pub strum: KeyCode,
+    pub frets: [Option<KeyCode>; 12], // By pitch class, C = 0
+}
+
+#[derive(Deserialize)]
+struct JsonGuitarMap {
+    strum: String,
+    frets: BTreeMap<String, String>, // "C#" -> "KEY_2"
+}
+
+impl GuitarMap {
+    /// The layout shipped in guitar.json.
+    pub fn builtin() -> Self {
+        Self::from_json(include_str!("../guitar.json")).expect("Failed to parse guitar.json")
+    }
+
+    /// `{"strum": "KEY_SPACE", "frets": {"C": "KEY_1", "C#": "KEY_2", ...}}`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let map: JsonGuitarMap =
+            serde_json::from_str(json).map_err(|e| Error::Parse(format!("Invalid guitar map: {}", e)))?;
+        let strum = parse_key_str(&map.strum);
+        if strum == KeyCode::KEY_RESERVED {
+            return Err(Error::Parse(format!("Unknown strum key {}", map.strum)));
+        }
+        let mut frets = [None; 12];
+        for (name, key) in &map.frets {
+            // Pitch class names reuse the note parser with a dummy octave
+            let Some(note) = midifile::parse_note(&format!("{}4", name)) else {
+                return Err(Error::Parse(format!("Unknown pitch class {}", name)));
+            };
+            let key_code = parse_key_str(key);
+            if key_code == KeyCode::KEY_RESERVED {
+                return Err(Error::Parse(format!("Unknown key {} for {}", key, name)));
+            }
+            frets[(note % 12) as usize] = Some(key_code);
+        }
+        Ok(Self { strum, frets })
+    }
+
+    pub fn fret(&self, note: u8) -> Option<KeyCode> {
+        self.frets[(note % 12) as usize]
+    }
+
+    /// Strum plus every fret key, for registering with the virtual device.
+    pub fn keys(&self) -> Vec<KeyCode> {
+        std::iter::once(self.strum).chain(self.frets.iter().flatten().copied()).collect()
+    }
+}
+
+// How often the strummer looks for a chord whose window has closed
+const STRUM_POLL: Duration = Duration::from_millis(2);
+
+/// Taps the strum key for chords as their window closes. Only needed on the real clock;
+/// offline runs strum from `Engine::advance_to`.
+pub fn spawn_strummer(shared: Arc<Engine>) {
+    thread::spawn(move || loop {
+        thread::sleep(STRUM_POLL);
+        if *shared.game_mode.lock().unwrap() != GameMode::Guitar {
+            continue;
+        }
+        if let Err(e) = shared.flush_strum() {
+            eprintln!("Strum failed: {}", e);
+        }
+    });
+}
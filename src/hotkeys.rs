@@ -0,0 +1,266 @@
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, Device, EventSummary, InputEvent, KeyCode};
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use crate::config::{Hotkeys, TypingSource};
+use crate::typing::{self, TypedNotes};
+use crate::{player, Engine};
+
+// Hotkeys read straight from the physical keyboards through evdev, so they work while the
+// game has focus. Needs read access to /dev/input (the `input` group on most distros). Our
+// own virtual keyboards are skipped, or the keys we press could trigger them. Keys aren't
+// grabbed: the game sees them too, so bind ones it doesn't use.
+//
+// With a keyboard picked in the hotkeys, only that one's keys are hotkeys; a numpad or macro
+// pad can then carry them while the main keyboard types as usual. The same listeners catch
+// the key for MIDI-learn on every keyboard, so it can be taught from any of them, and play
+// typed notes (typing.rs) when that reads the hotkeys' keyboard.
+//
+// Grab mode keeps the hotkeys out of the game: the keyboards they're read from are grabbed
+// (nothing else sees their keys) and every key that isn't a hotkey is typed again on a
+// virtual keyboard. A grab belongs to the open device, so the kernel lets go of it however
+// the program ends, crashes and kill -9 included; the virtual keyboard goes with it.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HotkeyAction {
+    OctaveUp,
+    OctaveDown,
+    TransposeUp,
+    TransposeDown,
+    TransposeOctaveUp,
+    TransposeOctaveDown,
+    ToggleSolver,
+    PausePlayback,
+    Panic,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 9] = [
+        HotkeyAction::OctaveUp,
+        HotkeyAction::OctaveDown,
+        HotkeyAction::TransposeUp,
+        HotkeyAction::TransposeDown,
+        HotkeyAction::TransposeOctaveUp,
+        HotkeyAction::TransposeOctaveDown,
+        HotkeyAction::ToggleSolver,
+        HotkeyAction::PausePlayback,
+        HotkeyAction::Panic,
+    ];
+
+    /// Its name in hotkeys.json.
+    pub fn name(self) -> &'static str {
+        match self {
+            HotkeyAction::OctaveUp => "octave_up",
+            HotkeyAction::OctaveDown => "octave_down",
+            HotkeyAction::TransposeUp => "transpose_up",
+            HotkeyAction::TransposeDown => "transpose_down",
+            HotkeyAction::TransposeOctaveUp => "transpose_octave_up",
+            HotkeyAction::TransposeOctaveDown => "transpose_octave_down",
+            HotkeyAction::ToggleSolver => "toggle_solver",
+            HotkeyAction::PausePlayback => "pause_playback",
+            HotkeyAction::Panic => "panic",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HotkeyAction::OctaveUp => "Octave Up",
+            HotkeyAction::OctaveDown => "Octave Down",
+            HotkeyAction::TransposeUp => "Transpose +1",
+            HotkeyAction::TransposeDown => "Transpose -1",
+            HotkeyAction::TransposeOctaveUp => "Transpose +12",
+            HotkeyAction::TransposeOctaveDown => "Transpose -12",
+            HotkeyAction::ToggleSolver => "Toggle Solver",
+            HotkeyAction::PausePlayback => "Pause Playback",
+            HotkeyAction::Panic => "Panic",
+        }
+    }
+
+    /// The key name bound to it; empty for none.
+    pub fn key(self, hotkeys: &Hotkeys) -> &String {
+        match self {
+            HotkeyAction::OctaveUp => &hotkeys.octave_up,
+            HotkeyAction::OctaveDown => &hotkeys.octave_down,
+            HotkeyAction::TransposeUp => &hotkeys.transpose_up,
+            HotkeyAction::TransposeDown => &hotkeys.transpose_down,
+            HotkeyAction::TransposeOctaveUp => &hotkeys.transpose_octave_up,
+            HotkeyAction::TransposeOctaveDown => &hotkeys.transpose_octave_down,
+            HotkeyAction::ToggleSolver => &hotkeys.toggle_solver,
+            HotkeyAction::PausePlayback => &hotkeys.pause_playback,
+            HotkeyAction::Panic => &hotkeys.panic,
+        }
+    }
+
+    pub fn key_mut(self, hotkeys: &mut Hotkeys) -> &mut String {
+        match self {
+            HotkeyAction::OctaveUp => &mut hotkeys.octave_up,
+            HotkeyAction::OctaveDown => &mut hotkeys.octave_down,
+            HotkeyAction::TransposeUp => &mut hotkeys.transpose_up,
+            HotkeyAction::TransposeDown => &mut hotkeys.transpose_down,
+            HotkeyAction::TransposeOctaveUp => &mut hotkeys.transpose_octave_up,
+            HotkeyAction::TransposeOctaveDown => &mut hotkeys.transpose_octave_down,
+            HotkeyAction::ToggleSolver => &mut hotkeys.toggle_solver,
+            HotkeyAction::PausePlayback => &mut hotkeys.pause_playback,
+            HotkeyAction::Panic => &mut hotkeys.panic,
+        }
+    }
+}
+
+/// The action bound to `key`, if any. Unknown key names bind nothing.
+pub fn action_for(hotkeys: &Hotkeys, key: KeyCode) -> Option<HotkeyAction> {
+    HotkeyAction::ALL.into_iter().find(|action| KeyCode::from_str(action.key(hotkeys)).is_ok_and(|k| k == key))
+}
+
+/// Whether hotkeys are read from the keyboard called `device`: the picked one (by any part
+/// of its name, any case), or every one when none is picked.
+pub fn listens_on(hotkeys: &Hotkeys, device: &str) -> bool {
+    hotkeys.keyboard.is_empty() || device.to_lowercase().contains(&hotkeys.keyboard.to_lowercase())
+}
+
+pub fn is_known_key(name: &str) -> bool {
+    KeyCode::from_str(name).is_ok()
+}
+
+fn run(engine: &Engine, action: HotkeyAction) {
+    match action {
+        HotkeyAction::OctaveUp => engine.shift_octaves(1),
+        HotkeyAction::OctaveDown => engine.shift_octaves(-1),
+        HotkeyAction::TransposeUp | HotkeyAction::TransposeDown | HotkeyAction::TransposeOctaveUp | HotkeyAction::TransposeOctaveDown => {
+            let semitones = match action {
+                HotkeyAction::TransposeUp => 1,
+                HotkeyAction::TransposeDown => -1,
+                HotkeyAction::TransposeOctaveUp => 12,
+                _ => -12,
+            };
+            if let Err(e) = engine.nudge_transpose(semitones) {
+                eprintln!("Transpose failed: {}", e);
+            }
+        }
+        HotkeyAction::ToggleSolver => {
+            engine.solver_enabled.fetch_xor(true, Ordering::Relaxed);
+            engine.request_repaint();
+        }
+        HotkeyAction::PausePlayback => {
+            player::toggle_pause(engine);
+        }
+        HotkeyAction::Panic => {
+            if let Err(e) = engine.panic() {
+                eprintln!("Panic failed: {}", e);
+            }
+        }
+    }
+}
+
+// Keyboards: anything with letter keys, not made by us
+fn is_keyboard(device: &Device) -> bool {
+    let ours = device.name().is_some_and(|n| n.starts_with("Miditoroblox"));
+    let has_letters = device.supported_keys().is_some_and(|keys| keys.contains(KeyCode::KEY_A));
+    has_letters && !ours
+}
+
+// A keyboard's events while it's grabbed: everything but its hotkeys, retyped on a virtual
+// keyboard so the rest of it keeps working
+fn create_passthrough(device: &Device, name: &str) -> io::Result<VirtualDevice> {
+    let keys = device.supported_keys().map(|keys| keys.iter().collect::<AttributeSet<KeyCode>>()).unwrap_or_default();
+    VirtualDevice::builder()?.name(&format!("Miditoroblox Passthrough {}", name)).with_keys(&keys)?.build()
+}
+
+// Reads one keyboard until it's unplugged, running its hotkeys and grabbing it when asked.
+// The grab only changes while no key on it is down: a key pressed on one side of the change
+// and released on the other would stay down for whoever missed the release.
+fn listen(shared: &Engine, mut device: Device, name: &str) {
+    // Modifiers held on this keyboard, for MIDI-learn
+    let (mut shift, mut ctrl) = (false, false);
+    let mut passthrough: Option<VirtualDevice> = None;
+    let mut grab_failed = false;
+    let mut typed = TypedNotes::default();
+    loop {
+        let events: Vec<InputEvent> = match device.fetch_events() {
+            Ok(events) => events.collect(),
+            Err(e) => {
+                // Unplugged
+                eprintln!("Hotkeys: stopped reading {}: {}", name, e);
+                return;
+            }
+        };
+        let (listening, want_grab) = {
+            let hotkeys = shared.hotkeys.lock().unwrap();
+            let listening = listens_on(&hotkeys, name);
+            (listening, listening && hotkeys.grab)
+        };
+        let mut forward = Vec::new();
+        for event in events {
+            let EventSummary::Key(_, key, value) = event.destructure() else { continue };
+            match key {
+                KeyCode::KEY_LEFTSHIFT | KeyCode::KEY_RIGHTSHIFT => shift = value != 0,
+                KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL => ctrl = value != 0,
+                _ => {}
+            }
+            let action = if listening { action_for(&shared.hotkeys.lock().unwrap(), key) } else { None };
+            let typed_note = match typing::char_for_key(key) {
+                Some(c) if listening && action.is_none() && value != 2 => typed.key(shared, TypingSource::Keyboard, c, value == 1),
+                _ => None,
+            };
+            if let Some(message) = typed_note
+                && let Err(e) = shared.handle_message(&message)
+                && !e.is_dropped_note()
+            {
+                eprintln!("Typed note: {}", e);
+            }
+            // Grabbed hotkeys and typed notes stay ours, repeats and releases included
+            let typing_key = typing::char_for_key(key).is_some() && *shared.typing_source.lock().unwrap() == TypingSource::Keyboard;
+            if action.is_none() && !(listening && typing_key) {
+                forward.push(event);
+            }
+            // Presses only; auto-repeat (2) and releases (0) do nothing
+            if value != 1 || shared.learn_key(key, shift, ctrl) {
+                continue;
+            }
+            if let Some(action) = action {
+                run(shared, action);
+            }
+        }
+        if let Some(passthrough) = &mut passthrough
+            && !forward.is_empty()
+            && let Err(e) = passthrough.emit(&forward)
+        {
+            eprintln!("Hotkeys: couldn't pass {}'s keys on: {}", name, e);
+        }
+
+        grab_failed &= want_grab;
+        let idle = device.get_key_state().is_ok_and(|keys| keys.iter().next().is_none());
+        if want_grab == passthrough.is_some() || grab_failed || !idle {
+            continue;
+        }
+        if want_grab {
+            match create_passthrough(&device, name).and_then(|created| device.grab().map(|()| created)) {
+                Ok(created) => passthrough = Some(created),
+                Err(e) => {
+                    eprintln!("Hotkeys: couldn't grab {}: {}", name, e);
+                    grab_failed = true;
+                }
+            }
+        } else {
+            if let Err(e) = device.ungrab() {
+                eprintln!("Hotkeys: couldn't let go of {}: {}", name, e);
+            }
+            passthrough = None;
+        }
+    }
+}
+
+/// Listens on every keyboard present now, one thread each. Returns their names.
+pub fn spawn(shared: Arc<Engine>) -> Vec<String> {
+    let keyboards: Vec<Device> = evdev::enumerate().map(|(_, d)| d).filter(is_keyboard).collect();
+    let names = keyboards.iter().map(|d| d.name().unwrap_or("keyboard").to_string()).collect();
+    for device in keyboards {
+        let shared = shared.clone();
+        let name = device.name().unwrap_or("keyboard").to_string();
+        thread::spawn(move || listen(&shared, device, &name));
+    }
+    names
+}
@@ -0,0 +1,47 @@
+use midir::MidiInput;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::Engine;
+
+// Keeps `Engine::midi_ports` current, so a frontend can notice a device being unplugged and
+// reconnect to it when it comes back.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Polls the MIDI input ports on a client of its own.
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || {
+        let midi_in = match MidiInput::new("Miditoroblox Port Watch") {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Port watch unavailable: {}", e);
+                return;
+            }
+        };
+        loop {
+            let names: Vec<String> = midi_in.ports().iter().filter_map(|port| midi_in.port_name(port).ok()).collect();
+            let changed = {
+                let mut ports = shared.midi_ports.lock().unwrap();
+                let changed = *ports != names;
+                *ports = names;
+                changed
+            };
+            if changed {
+                shared.request_repaint();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+/// The device part of a port name. ALSA ends names with the client:port address, e.g.
+/// "KeyStation:KeyStation MIDI 1 24:0", and a replugged device may come back on another client.
+pub fn device_name(port_name: &str) -> &str {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match port_name.rsplit_once(' ') {
+        Some((device, address)) if address.split_once(':').is_some_and(|(c, p)| is_number(c) && is_number(p)) => device,
+        _ => port_name,
+    }
+}
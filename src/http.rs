@@ -0,0 +1,230 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::net::{digests_match, is_local_origin, localhost_if_bare_port};
+use crate::remote::{self, RemoteState};
+use crate::Engine;
+
+// Small HTTP server for status and control (`--http`), for OBS browser overlays and scripts
+// that would rather poll than keep a WebSocket open. Every answer is JSON. The status and
+// the notes any origin may read, so a browser source loaded from a file can fetch them. With
+// a token set, requests bring it as `Authorization: Bearer <token>` or `?token=<token>`.
+//
+// The settings name files on this machine, and the POSTs change them (the mappings and
+// script files among them), so any page open in the user's browser mustn't be able to read
+// or send them: only pages on this machine may, and the POSTs also need the token (and so a
+// token set) and a JSON Content-Type, which a cross-site form can't send without asking first.
+//
+//   GET  /status          solver, transpose, active_notes, playing
+//   GET  /notes           the notes held on the input and played, and the keys held
+//   GET  /settings        every setting
+//   POST /settings        a JSON object of settings to change
+//   POST /toggle/<name>   flips an on/off setting
+//   POST /command         a remote command (remote.rs), as sent over the WebSocket
+//
+// One request per connection; nothing here is worth keeping a connection open for.
+
+// A request that takes longer than this to arrive isn't coming
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY: usize = 64 * 1024;
+// The request line and headers together
+const MAX_HEAD: u64 = 16 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    body: String,
+}
+
+// The request, or the status and message to turn it away with
+fn read_request(stream: &TcpStream) -> std::result::Result<Request, (u16, String)> {
+    let invalid = |what: &str| (400, what.to_string());
+    let mut reader = BufReader::new(stream);
+    let mut head = reader.by_ref().take(MAX_HEAD);
+    let mut line = String::new();
+    read_head_line(&mut head, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else { return Err(invalid("bad request line")) };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization: None,
+        origin: None,
+        content_type: None,
+        body: String::new(),
+    };
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if read_head_line(&mut head, &mut line)? == 0 {
+            return Err(invalid("headers cut short"));
+        }
+        let Some((name, value)) = line.trim_end().split_once(':') else { break };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse().map_err(|_| invalid("bad Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = value.strip_prefix("Bearer ").map(str::to_string);
+        } else if name.eq_ignore_ascii_case("origin") {
+            request.origin = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-type") {
+            request.content_type = Some(value.to_string());
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid("body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| (400, e.to_string()))?;
+    request.body = String::from_utf8(body).map_err(|_| invalid("body isn't UTF-8"))?;
+    Ok(request)
+}
+
+// One line of the request head, out of what's left of `MAX_HEAD`
+fn read_head_line(head: &mut io::Take<&mut BufReader<&TcpStream>>, line: &mut String) -> std::result::Result<usize, (u16, String)> {
+    let read = head.read_line(line).map_err(|e| (400, e.to_string()))?;
+    if read > 0 && !line.ends_with('\n') && head.limit() == 0 {
+        return Err((431, "request headers too large".to_string()));
+    }
+    Ok(read)
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else { return true };
+    let from_query = request.query.split('&').find_map(|pair| pair.strip_prefix("token="));
+    request.authorization.as_deref().or(from_query).is_some_and(|given| digests_match(given, token))
+}
+
+fn error(status: u16, message: &str) -> (u16, String) {
+    (status, serde_json::json!({ "ok": false, "error": message }).to_string())
+}
+
+// The overlays' GETs, which any page may read
+fn is_public(request: &Request) -> bool {
+    request.method == "GET" && matches!(request.path.as_str(), "/status" | "/notes")
+}
+
+// Whether a browser sent the request from a page on some other site
+fn cross_site(request: &Request) -> bool {
+    request.origin.as_deref().is_some_and(|origin| !is_local_origin(origin))
+}
+
+fn is_json(request: &Request) -> bool {
+    request.content_type.as_deref().is_some_and(|value| {
+        value.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json")
+    })
+}
+
+// The status and JSON body for a request
+fn route(engine: &Arc<Engine>, request: &Request) -> (u16, String) {
+    let json = |value: serde_json::Value| (200, value.to_string());
+    let answer = |result: Result<serde_json::Value>| (if result.is_ok() { 200 } else { 400 }, remote::reply(result));
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => json(serde_json::to_value(RemoteState::of(engine)).unwrap_or_default()),
+        ("GET", "/notes") => {
+            let snapshot = engine.snapshot();
+            let keys: Vec<String> = snapshot.held_keys.iter().map(|k| format!("{:?}", k)).collect();
+            json(serde_json::json!({ "active_notes": snapshot.active_notes, "output_notes": snapshot.output_notes, "held_keys": keys }))
+        }
+        ("GET", "/settings") => json(serde_json::to_value(engine.settings()).unwrap_or_default()),
+        ("POST", "/settings") => answer(
+            serde_json::from_str(&request.body)
+                .map_err(|e| Error::Parse(format!("bad JSON: {}", e)))
+                .and_then(|patch| remote::patch_settings(engine, &patch))
+                .map(|settings| serde_json::json!({ "settings": settings })),
+        ),
+        ("POST", "/command") => answer(remote::parse(&request.body).and_then(|command| remote::execute(engine, command))),
+        ("POST", path) if path.starts_with("/toggle/") => {
+            let name = &path["/toggle/".len()..];
+            answer(remote::toggle_setting(engine, name).map(|on| serde_json::json!({ name: on })))
+        }
+        (_, "/status" | "/notes" | "/settings" | "/command") => error(405, "method not allowed"),
+        _ => error(404, "no such endpoint"),
+    }
+}
+
+fn serve_client(engine: &Arc<Engine>, mut stream: TcpStream, token: Option<&str>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = read_request(&stream);
+    let (status, body) = match &request {
+        Ok(request) if !is_public(request) && cross_site(request) => error(403, "requests from other sites aren't allowed"),
+        // Browsers ask before a POST from another local page
+        Ok(request) if request.method == "OPTIONS" => (204, String::new()),
+        Ok(request) if token.is_none() && request.method != "GET" => error(403, "set a token to control over HTTP"),
+        Ok(request) if !authorized(request, token) => error(401, "bad or missing token"),
+        Ok(request) if request.method == "POST" && !is_json(request) => error(415, "the body must be application/json"),
+        Ok(request) => route(engine, request),
+        Err((status, message)) => error(*status, message),
+    };
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        _ => "Method Not Allowed",
+    };
+    // Any page may read the status and notes; only local pages get to see the rest
+    let allow_origin = match &request {
+        Ok(request) if is_public(request) => Some("*"),
+        Ok(request) if !cross_site(request) => request.origin.as_deref(),
+        _ => None,
+    };
+    let cors = match allow_origin {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\nAccess-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+             Access-Control-Allow-Headers: Authorization, Content-Type\r\n",
+            origin
+        ),
+        None => String::new(),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        cors,
+        body
+    )?;
+    stream.flush()?;
+    // Closing on a request we didn't read to the end would reset the connection, and the
+    // client might lose the answer with it; let it finish sending first
+    stream.shutdown(Shutdown::Write)?;
+    let _ = io::copy(&mut (&stream).take(MAX_BODY as u64), &mut io::sink());
+    Ok(())
+}
+
+/// Listens on `addr` on a background thread, a thread per request. A bare port listens on
+/// localhost only. Returns the address it's listening on.
+pub fn spawn(shared: Arc<Engine>, addr: &str, token: Option<String>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(localhost_if_bare_port(addr))?;
+    let local = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (engine, token) = (shared.clone(), token.clone());
+            thread::spawn(move || {
+                if let Err(e) = serve_client(&engine, stream, token.as_deref()) {
+                    eprintln!("HTTP: {}", e);
+                }
+            });
+        }
+    });
+    Ok(local)
+}
@@ -0,0 +1,110 @@
+use evdev::KeyCode;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::midifile;
+use crate::stats::DropReason;
+
+// Event timeline inspector: what came in, what was decided, and which key events went out,
+// for the most recent MAX_ENTRIES MIDI messages.
+
+const MAX_ENTRIES: usize = 5000;
+
+#[derive(Clone, Debug)]
+pub enum Decision {
+    Played { note: u8, key: KeyCode, transpose: i32 }, // `note` is the note that was looked up
+    Released { key: KeyCode },
+    Dropped(DropReason),
+}
+
+impl Decision {
+    pub fn describe(&self) -> String {
+        match self {
+            Decision::Played { note, key, transpose } if *transpose != 0 => {
+                format!("{} -> {:?} (transpose {:+})", midifile::note_name(*note), key, transpose)
+            }
+            Decision::Played { note, key, .. } => format!("{} -> {:?}", midifile::note_name(*note), key),
+            Decision::Released { key } => format!("release {:?}", key),
+            Decision::Dropped(reason) => format!("dropped: {}", reason.label()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InspectorEntry {
+    pub at_ms: u64,
+    pub message: [u8; 3],
+    pub decision: Option<Decision>,
+    pub output: Vec<(u16, i32)>, // (key code, value) as emitted
+}
+
+impl InspectorEntry {
+    pub fn note(&self) -> Option<u8> {
+        matches!(self.message[0] & 0xF0, 0x80 | 0x90).then_some(self.message[1])
+    }
+
+    pub fn describe_input(&self) -> String {
+        let [status, data1, data2] = self.message;
+        let channel = (status & 0x0F) + 1;
+        match status & 0xF0 {
+            0x90 if data2 > 0 => format!("ch {:>2} on  {:<4} v{}", channel, midifile::note_name(data1), data2),
+            0x80 | 0x90 => format!("ch {:>2} off {:<4}", channel, midifile::note_name(data1)),
+            0xB0 => format!("ch {:>2} cc{} = {}", channel, data1, data2),
+            _ => format!("ch {:>2} {:02X} {:02X} {:02X}", channel, status, data1, data2),
+        }
+    }
+
+    pub fn describe_output(&self) -> String {
+        self.output
+            .iter()
+            .map(|&(code, value)| format!("{:?}{}", KeyCode::new(code), if value == 0 { "^" } else { "v" }))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub struct EventLog {
+    started: Instant,
+    pub entries: VecDeque<InspectorEntry>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), entries: VecDeque::new() }
+    }
+
+    pub fn begin(&mut self, message: &[u8]) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        let mut raw = [0u8; 3];
+        for (dst, src) in raw.iter_mut().zip(message) {
+            *dst = *src;
+        }
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.entries.push_back(InspectorEntry { at_ms, message: raw, decision: None, output: Vec::new() });
+    }
+
+    /// Sets the decision of the entry currently being handled.
+    pub fn decide(&mut self, decision: Decision) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.decision = Some(decision);
+        }
+    }
+
+    pub fn finish(&mut self, output: Vec<(u16, i32)>) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.output = output;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
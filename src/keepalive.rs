@@ -0,0 +1,21 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::Engine;
+
+// Anti-AFK: Roblox disconnects players after about 20 minutes without input, which ends a
+// session between sets. When enabled, a harmless key is tapped after a stretch with no notes.
+
+// Idle times are in minutes, so there's no need to look often
+const POLL: Duration = Duration::from_secs(1);
+
+/// Runs the keep-alive on the real clock. Offline runs call `Engine::keep_alive` directly.
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || loop {
+        thread::sleep(POLL);
+        if let Err(e) = shared.keep_alive() {
+            eprintln!("Keep-alive failed: {}", e);
+        }
+    });
+}
@@ -0,0 +1,66 @@
+//! MIDI to Roblox key presses.
+//!
+//! [`Engine`] holds the settings and session state and turns raw MIDI messages into key
+//! events on its [`Router`], which owns the key output (uinput, desktop portal or a remote
+//! receiver). The binary's GUI (gui.rs) and terminal UI (tui.rs) are two frontends; anything
+//! that can produce MIDI bytes can drive an engine the same way, directly or through a
+//! [`worker::MidiQueue`] when the bytes come from a callback that shouldn't wait on key output.
+
+pub mod arpeggiator;
+pub mod ble;
+pub mod check;
+pub mod clock;
+pub mod config;
+pub mod drums;
+pub mod engine;
+pub mod error;
+pub mod guitar;
+pub mod hotkeys;
+pub mod hotplug;
+pub mod http;
+pub mod inspector;
+pub mod keepalive;
+pub mod mappings;
+pub mod metronome;
+pub mod midiclock;
+pub mod midifile;
+pub mod mpris;
+pub mod net;
+pub mod osc;
+pub mod output;
+pub mod piano;
+pub mod pipeline;
+pub mod planner;
+pub mod playability;
+pub mod player;
+pub mod polyphony;
+pub mod portal;
+pub mod practice;
+pub mod processor;
+pub mod quantize;
+pub mod qwerty;
+pub mod recording;
+pub mod remote;
+pub mod roll;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sheet;
+pub mod shutdown;
+pub mod simplify;
+pub mod simulate;
+pub mod solver;
+pub mod startup;
+pub mod stats;
+pub mod stress;
+pub mod thru;
+pub mod typing;
+pub mod volume;
+pub mod wayland;
+#[cfg(feature = "remote")]
+pub mod websocket;
+pub mod window_watch;
+pub mod worker;
+
+pub use engine::{Engine, EngineSnapshot, Router};
+pub use error::{Error, Result};
@@ -0,0 +1,364 @@
+use evdev::KeyCode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::{self, Settings};
+use crate::error::{Error, Result};
+
+// Note -> key mappings. `MappingSet` is the one place lookups, merging and validation live;
+// the built-in set comes from mappings.json, unless the mapping editor has saved one of its
+// own to the config dir or settings name a mapping file. Named sets (one per game layout,
+// say) are mapping files kept in the config dir's `mappings` folder.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyMapping {
+    pub midi_note: u8,
+    pub key_code: KeyCode,
+    pub shift: bool,
+    pub ctrl: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonKeyMapping {
+    midi_note: u8,
+    key: String,
+    shift: bool,
+    ctrl: bool,
+}
+
+// Key names accepted in the JSON files and settings
+pub const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("KEY_1", KeyCode::KEY_1),
+    ("KEY_2", KeyCode::KEY_2),
+    ("KEY_3", KeyCode::KEY_3),
+    ("KEY_4", KeyCode::KEY_4),
+    ("KEY_5", KeyCode::KEY_5),
+    ("KEY_6", KeyCode::KEY_6),
+    ("KEY_7", KeyCode::KEY_7),
+    ("KEY_8", KeyCode::KEY_8),
+    ("KEY_9", KeyCode::KEY_9),
+    ("KEY_0", KeyCode::KEY_0),
+    ("KEY_Q", KeyCode::KEY_Q),
+    ("KEY_W", KeyCode::KEY_W),
+    ("KEY_E", KeyCode::KEY_E),
+    ("KEY_R", KeyCode::KEY_R),
+    ("KEY_T", KeyCode::KEY_T),
+    ("KEY_Y", KeyCode::KEY_Y),
+    ("KEY_U", KeyCode::KEY_U),
+    ("KEY_I", KeyCode::KEY_I),
+    ("KEY_O", KeyCode::KEY_O),
+    ("KEY_P", KeyCode::KEY_P),
+    ("KEY_A", KeyCode::KEY_A),
+    ("KEY_S", KeyCode::KEY_S),
+    ("KEY_D", KeyCode::KEY_D),
+    ("KEY_F", KeyCode::KEY_F),
+    ("KEY_G", KeyCode::KEY_G),
+    ("KEY_H", KeyCode::KEY_H),
+    ("KEY_J", KeyCode::KEY_J),
+    ("KEY_K", KeyCode::KEY_K),
+    ("KEY_L", KeyCode::KEY_L),
+    ("KEY_Z", KeyCode::KEY_Z),
+    ("KEY_X", KeyCode::KEY_X),
+    ("KEY_C", KeyCode::KEY_C),
+    ("KEY_V", KeyCode::KEY_V),
+    ("KEY_B", KeyCode::KEY_B),
+    ("KEY_N", KeyCode::KEY_N),
+    ("KEY_M", KeyCode::KEY_M),
+    ("KEY_SPACE", KeyCode::KEY_SPACE),
+    ("KEY_MINUS", KeyCode::KEY_MINUS),
+    ("KEY_EQUAL", KeyCode::KEY_EQUAL),
+    ("KEY_LEFTBRACE", KeyCode::KEY_LEFTBRACE),
+    ("KEY_RIGHTBRACE", KeyCode::KEY_RIGHTBRACE),
+    ("KEY_LEFT", KeyCode::KEY_LEFT),
+    ("KEY_RIGHT", KeyCode::KEY_RIGHT),
+    ("KEY_UP", KeyCode::KEY_UP),
+    ("KEY_DOWN", KeyCode::KEY_DOWN),
+    ("KEY_PAGEUP", KeyCode::KEY_PAGEUP),
+    ("KEY_PAGEDOWN", KeyCode::KEY_PAGEDOWN),
+];
+
+pub(crate) fn parse_key_str(k: &str) -> KeyCode {
+    KEY_NAMES.iter().find(|(name, _)| *name == k).map(|(_, key)| *key).unwrap_or(KeyCode::KEY_RESERVED)
+}
+
+/// The name `key` goes by in the JSON files, if it has one.
+pub fn key_name(key: KeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|(_, k)| *k == key).map(|(name, _)| *name)
+}
+
+fn user_path() -> PathBuf {
+    config::config_dir().join("mappings.json")
+}
+
+fn sets_dir() -> PathBuf {
+    config::config_dir().join("mappings")
+}
+
+/// Where the named set `name` is stored; settings refer to a set by this path.
+pub fn set_path(name: &str) -> PathBuf {
+    config::named_file(&sets_dir(), name)
+}
+
+/// Names of all saved sets, sorted.
+pub fn list_sets() -> Vec<String> {
+    config::named_files(&sets_dir())
+}
+
+/// The set a mapping file path belongs to, if it's one of the named sets.
+pub fn set_name(path: &str) -> Option<String> {
+    list_sets().into_iter().find(|name| set_path(name) == Path::new(path))
+}
+
+pub fn delete_set(name: &str) -> Result<()> {
+    Ok(fs::remove_file(set_path(name))?)
+}
+
+// MIDI-learn in the mapping editor: play a note, then press the key that should play it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LearnStep {
+    #[default]
+    Off,
+    WaitingForNote,
+    WaitingForKey(u8),      // The note played; playing another replaces it
+    Learned(KeyMapping),    // Ready for the editor to add
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MappingIssue {
+    DuplicateNote(u8),     // More than one mapping for the note; the first one wins
+    UnknownKey(u8),        // Key name not recognized
+    ShiftAndCtrl(u8),      // The game has no Shift+Ctrl layer
+    SameKeystroke(u8, u8), // Two notes produce the exact same key + modifiers
+}
+
+impl MappingIssue {
+    pub fn describe(&self) -> String {
+        match self {
+            MappingIssue::DuplicateNote(n) => format!("note {} is mapped more than once", n),
+            MappingIssue::UnknownKey(n) => format!("note {} uses an unknown key", n),
+            MappingIssue::ShiftAndCtrl(n) => format!("note {} needs both Shift and Ctrl", n),
+            MappingIssue::SameKeystroke(a, b) => format!("notes {} and {} use the same keystroke", a, b),
+        }
+    }
+
+    /// Whether the set can't be played as intended. The rest only waste a key or a note.
+    pub fn is_error(&self) -> bool {
+        matches!(self, MappingIssue::UnknownKey(_) | MappingIssue::ShiftAndCtrl(_))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MappingSet {
+    // Kept in file order; the solver's tie-breaking depends on it
+    mappings: Vec<KeyMapping>,
+    // Where in `mappings` each note's (first) mapping is, by note, for the lookup every
+    // played note does. Empty for an empty set.
+    by_note: Vec<Option<usize>>,
+}
+
+// Two sets are the same if they map the same way; the index follows from that
+impl PartialEq for MappingSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.mappings == other.mappings
+    }
+}
+
+impl MappingSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The mappings shipped in mappings.json, parsed the first time they're asked for.
+    pub fn builtin() -> Self {
+        static BUILTIN: OnceLock<MappingSet> = OnceLock::new();
+        BUILTIN
+            .get_or_init(|| Self::from_json(include_str!("../mappings.json")).expect("Failed to parse mappings.json"))
+            .clone()
+    }
+
+    /// The set saved by the mapping editor, or the built-in one if there is none (or it no
+    /// longer parses).
+    pub fn load() -> Self {
+        let Ok(json) = fs::read_to_string(user_path()) else { return Self::builtin() };
+        Self::from_json(&json).unwrap_or_else(|e| {
+            eprintln!("{}: {}; using the built-in mappings", user_path().display(), e);
+            Self::builtin()
+        })
+    }
+
+    /// Reads a mapping file, refusing one with mappings that can't be played as written.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)?;
+        let set = Self::from_json(&json)?;
+        let errors: Vec<String> = set.validate().iter().filter(|i| i.is_error()).map(|i| i.describe()).collect();
+        if !errors.is_empty() {
+            return Err(Error::Parse(format!("{}: {}", path.display(), errors.join(", "))));
+        }
+        Ok(set)
+    }
+
+    /// The set `settings` play with: their mapping file if they name one, otherwise `load`'s.
+    /// A file that can't be used falls back the same way, with a line on stderr.
+    pub fn for_settings(settings: &Settings) -> Self {
+        if settings.mappings_file.is_empty() {
+            return Self::load();
+        }
+        Self::from_file(Path::new(&settings.mappings_file)).unwrap_or_else(|e| {
+            eprintln!("Mappings: {}; using the default ones", e);
+            Self::load()
+        })
+    }
+
+    /// Writes the set to mappings.json in the config dir, where `load` finds it.
+    pub fn save(&self) -> Result<PathBuf> {
+        fs::create_dir_all(config::config_dir())?;
+        let path = user_path();
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    /// Stores the set as the named set `name`, returning its path.
+    pub fn save_set(&self, name: &str) -> Result<PathBuf> {
+        fs::create_dir_all(sets_dir())?;
+        let path = set_path(name);
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        Ok(fs::write(path, self.to_json())?)
+    }
+
+    /// Forgets the saved set, so `load` goes back to the built-in one.
+    pub fn reset_saved() -> Result<()> {
+        match fs::remove_file(user_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The mappings.json format, one mapping per line like the shipped file.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .mappings
+            .iter()
+            .map(|m| {
+                let row = JsonKeyMapping {
+                    midi_note: m.midi_note,
+                    key: key_name(m.key_code).unwrap_or("KEY_RESERVED").to_string(),
+                    shift: m.shift,
+                    ctrl: m.ctrl,
+                };
+                // Serializing plain fields can't fail
+                serde_json::to_string(&row).unwrap().replace(",\"", ", \"").replace("\":", "\": ")
+            })
+            .collect();
+        format!("[\n    {}\n]\n", rows.join(",\n    "))
+    }
+
+    /// Parses the mappings.json format: `[{"midi_note": 60, "key": "KEY_T", "shift": false, "ctrl": false}, ...]`.
+    /// Unknown key names become KEY_RESERVED and show up in `validate`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let json_mappings: Vec<JsonKeyMapping> = serde_json::from_str(json).map_err(|e| Error::Parse(format!("Invalid mappings: {}", e)))?;
+        Ok(Self::from_mappings(json_mappings.into_iter().map(|m| KeyMapping {
+            midi_note: m.midi_note,
+            key_code: parse_key_str(&m.key),
+            shift: m.shift,
+            ctrl: m.ctrl,
+        })))
+    }
+
+    pub fn from_mappings(mappings: impl IntoIterator<Item = KeyMapping>) -> Self {
+        let mut set = Self { mappings: mappings.into_iter().collect(), by_note: Vec::new() };
+        set.reindex();
+        set
+    }
+
+    fn reindex(&mut self) {
+        self.by_note = vec![None; 256];
+        // Backwards, so a duplicated note ends up at its first mapping
+        for (i, m) in self.mappings.iter().enumerate().rev() {
+            self.by_note[m.midi_note as usize] = Some(i);
+        }
+    }
+
+    pub fn get(&self, note: u8) -> Option<&KeyMapping> {
+        let i = self.by_note.get(note as usize).copied().flatten()?;
+        self.mappings.get(i)
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        self.get(note).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &KeyMapping> {
+        self.mappings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Each physical key once, in mapping order.
+    pub fn keys(&self) -> Vec<KeyCode> {
+        let mut keys = Vec::new();
+        for m in &self.mappings {
+            if !keys.contains(&m.key_code) {
+                keys.push(m.key_code);
+            }
+        }
+        keys
+    }
+
+    /// Adds a mapping, replacing any existing one for the same note.
+    pub fn insert(&mut self, mapping: KeyMapping) {
+        match self.by_note.get(mapping.midi_note as usize).copied().flatten() {
+            Some(i) => self.mappings[i] = mapping,
+            None => {
+                self.mappings.push(mapping);
+                self.reindex();
+            }
+        }
+    }
+
+    pub fn remove(&mut self, note: u8) -> Option<KeyMapping> {
+        let idx = self.by_note.get(note as usize).copied().flatten()?;
+        let removed = self.mappings.remove(idx);
+        self.reindex();
+        Some(removed)
+    }
+
+    /// Overlays `other` on top of this set: its mappings replace ours note by note.
+    pub fn merge(&mut self, other: &MappingSet) {
+        for mapping in &other.mappings {
+            self.insert(*mapping);
+        }
+    }
+
+    pub fn validate(&self) -> Vec<MappingIssue> {
+        let mut issues = Vec::new();
+        for (i, m) in self.mappings.iter().enumerate() {
+            let earlier = &self.mappings[..i];
+            if earlier.iter().any(|e| e.midi_note == m.midi_note) {
+                issues.push(MappingIssue::DuplicateNote(m.midi_note));
+                continue;
+            }
+            if m.key_code == KeyCode::KEY_RESERVED {
+                issues.push(MappingIssue::UnknownKey(m.midi_note));
+            }
+            if m.shift && m.ctrl {
+                issues.push(MappingIssue::ShiftAndCtrl(m.midi_note));
+            }
+            if let Some(e) = earlier.iter().find(|e| e.key_code == m.key_code && e.shift == m.shift && e.ctrl == m.ctrl) {
+                issues.push(MappingIssue::SameKeystroke(e.midi_note, m.midi_note));
+            }
+        }
+        issues
+    }
+}
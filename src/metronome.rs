@@ -0,0 +1,201 @@
+use alsa::pcm::{Access, Format, HwParams, PCM};
+use alsa::{Direction, ValueOr};
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::QuantizeMode;
+use crate::midifile::MidiFile;
+use crate::quantize::Grid;
+use crate::Engine;
+
+// Metronome for playing along: clicks on the quantize grid (so live notes land where the
+// quantizer puts them anyway) or on the beats of a loaded file's tempo map. Each beat can
+// click through the local sound card and/or flash the beat indicator.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BeatSource {
+    #[default]
+    Quantize, // Every `grid_steps` quantize lines, or every beat of a tempo grid, as the quantizer has it
+    File,     // The beats of a loaded file, counted from `start_file`
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metronome {
+    pub enabled: bool,
+    pub audible: bool,
+    pub source: BeatSource,
+    pub grid_steps: u64,    // Quantize lines per beat
+    pub beats_per_bar: u32, // The first beat of each bar is accented
+    file_beats: Vec<u64>,
+    started_us: u64,
+    next_beat: usize,
+    last_grid_beat: Option<u64>,
+    pub last_beat: Option<(u64, bool)>, // (clock time, downbeat) of the latest beat, for the indicator
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            audible: true,
+            source: BeatSource::Quantize,
+            grid_steps: 4,
+            beats_per_bar: 4,
+            file_beats: Vec::new(),
+            started_us: 0,
+            next_beat: 0,
+            last_grid_beat: None,
+            last_beat: None,
+        }
+    }
+}
+
+impl Metronome {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follows `file`'s tempo map from `now_us` on, as if the song started then.
+    pub fn start_file(&mut self, file: &MidiFile, now_us: u64) {
+        self.source = BeatSource::File;
+        self.file_beats = file.beats_us.clone();
+        self.beats_per_bar = file.beats_per_bar;
+        self.started_us = now_us;
+        self.next_beat = 0;
+    }
+
+    /// Whether a beat fell due by `now_us`; `Some(true)` for a downbeat.
+    pub fn poll(&mut self, now_us: u64, quantize_ms: u64) -> Option<bool> {
+        self.poll_on(now_us, Grid { step_us: (quantize_ms * self.grid_steps).max(1) * 1000, anchor_us: 0, swing_us: 0 })
+    }
+
+    /// `poll` with a beat on every line of `beats`, e.g. a tempo grid's beats.
+    pub fn poll_on(&mut self, now_us: u64, beats: Grid) -> Option<bool> {
+        self.poll_counted(now_us, Some(now_us.saturating_sub(beats.anchor_us) / beats.step_us.max(1)))
+    }
+
+    /// `poll` with the beats counted elsewhere, e.g. by a sequencer's clock: a beat whenever
+    /// `index` moves on, none while it's None.
+    pub fn poll_counted(&mut self, now_us: u64, index: Option<u64>) -> Option<bool> {
+        if !self.enabled {
+            self.last_grid_beat = None;
+            return None;
+        }
+        let downbeat = |index: u64, per_bar: u32| index.is_multiple_of(per_bar.max(1) as u64);
+        let beat = match self.source {
+            BeatSource::Quantize => {
+                let Some(index) = index else {
+                    self.last_grid_beat = None;
+                    return None;
+                };
+                // Starting up mid-beat waits for the next one
+                let last = self.last_grid_beat.replace(index);
+                (last.is_some_and(|last| last != index)).then(|| downbeat(index, self.beats_per_bar))
+            }
+            BeatSource::File => {
+                let elapsed = now_us.checked_sub(self.started_us)?;
+                let mut due = None;
+                // A late poll skips the beats it missed rather than clicking them all at once
+                while self.file_beats.get(self.next_beat).is_some_and(|at| *at <= elapsed) {
+                    due = Some(downbeat(self.next_beat as u64, self.beats_per_bar));
+                    self.next_beat += 1;
+                }
+                due
+            }
+        };
+        if let Some(downbeat) = beat {
+            self.last_beat = Some((now_us, downbeat));
+        }
+        beat
+    }
+}
+
+const SAMPLE_RATE: u32 = 44_100;
+const CLICK_MS: u32 = 25;
+// How often the metronome thread checks for a beat
+const POLL: Duration = Duration::from_millis(2);
+
+// The sound card, opened on the first audible beat
+struct Clicker {
+    pcm: PCM,
+}
+
+impl Clicker {
+    fn open() -> alsa::Result<Self> {
+        let pcm = PCM::new("default", Direction::Playback, false)?;
+        {
+            let hw = HwParams::any(&pcm)?;
+            hw.set_channels(1)?;
+            hw.set_rate(SAMPLE_RATE, ValueOr::Nearest)?;
+            hw.set_format(Format::s16())?;
+            hw.set_access(Access::RWInterleaved)?;
+            pcm.hw_params(&hw)?;
+        }
+        Ok(Self { pcm })
+    }
+
+    // A short decaying tone, higher on the downbeat
+    fn click(&self, downbeat: bool) -> alsa::Result<()> {
+        let pitch = if downbeat { 1500.0 } else { 1000.0 };
+        let len = SAMPLE_RATE * CLICK_MS / 1000;
+        let samples: Vec<i16> = (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let envelope = 1.0 - i as f32 / len as f32;
+                ((TAU * pitch * t).sin() * envelope * 12_000.0) as i16
+            })
+            .collect();
+        self.pcm.prepare()?;
+        self.pcm.io_i16()?.writei(&samples)?;
+        self.pcm.drain()
+    }
+}
+
+/// Runs the metronome: polls for beats, clicks and asks the frontend to repaint the indicator.
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || {
+        let mut clicker: Option<Clicker> = None;
+        let mut audio_failed = false;
+        loop {
+            thread::sleep(POLL);
+            let quantize_ms = shared.quantize_ms.load(Ordering::Relaxed);
+            let tempo_beats = shared.tempo_beats();
+            let clock_mode = *shared.quantize_mode.lock().unwrap() == QuantizeMode::MidiClock;
+            let (beat, audible) = {
+                let now_us = shared.clock.now_us();
+                let clock_beat = if clock_mode { shared.midi_clock.lock().unwrap().beat(now_us) } else { None };
+                let mut metronome = shared.metronome.lock().unwrap();
+                let beat = match tempo_beats {
+                    _ if clock_mode => metronome.poll_counted(now_us, clock_beat),
+                    Some(beats) => metronome.poll_on(now_us, beats),
+                    None => metronome.poll(now_us, quantize_ms),
+                };
+                (beat, metronome.audible)
+            };
+            let Some(downbeat) = beat else { continue };
+            shared.request_repaint();
+            if !audible || audio_failed {
+                continue;
+            }
+            if clicker.is_none() {
+                match Clicker::open() {
+                    Ok(c) => clicker = Some(c),
+                    Err(e) => {
+                        // No sound card (or no permission); the indicator still works
+                        eprintln!("Metronome audio unavailable: {}", e);
+                        audio_failed = true;
+                        continue;
+                    }
+                }
+            }
+            if let Some(c) = &clicker
+                && let Err(e) = c.click(downbeat)
+            {
+                eprintln!("Metronome click failed: {}", e);
+            }
+        }
+    });
+}
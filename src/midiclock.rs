@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use crate::quantize::Grid;
+
+// Following an external sequencer's MIDI clock, so the quantize grid can be slaved to a DAW
+// playing backing tracks. Clock ticks (0xF8) come 24 to a beat; the tempo is the average gap
+// between the last beat's worth of them. Start (0xFA) counts beats from the next tick,
+// Continue (0xFB) carries on from the Song Position Pointer (0xF2) and Stop (0xFC) stops.
+// The grid is there only while the sequencer plays, and lines up with its beats.
+
+pub const TICKS_PER_BEAT: u64 = 24;
+// Ticks further apart than this mean the clock stopped coming
+const STALE_US: u64 = 500_000;
+
+#[derive(Default)]
+pub struct MidiClock {
+    running: bool,
+    ticks: u64, // Since the start of the song
+    last_tick_us: Option<u64>,
+    gaps: VecDeque<u64>,
+    beat_us: Option<u64>, // When the latest beat's tick came
+}
+
+impl MidiClock {
+    /// Follows `message` if it's a clock, transport or song position message; false for
+    /// anything else, which is left to play.
+    pub fn message(&mut self, message: &[u8], at_us: u64) -> bool {
+        match message.first() {
+            Some(0xF8) => self.tick(at_us),
+            Some(0xFA) => {
+                self.running = true;
+                self.ticks = 0;
+                self.beat_us = None;
+            }
+            Some(0xFB) => self.running = true,
+            Some(0xFC) => self.running = false,
+            Some(0xF2) => {
+                // In 16th notes, 6 ticks each
+                let sixteenths = message.get(1).copied().unwrap_or(0) as u64 | (message.get(2).copied().unwrap_or(0) as u64) << 7;
+                self.ticks = sixteenths * 6;
+                self.beat_us = None;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn tick(&mut self, at_us: u64) {
+        if let Some(last_us) = self.last_tick_us {
+            let gap = at_us.saturating_sub(last_us);
+            if gap > STALE_US {
+                self.gaps.clear();
+            } else {
+                self.gaps.push_back(gap);
+                if self.gaps.len() > TICKS_PER_BEAT as usize {
+                    self.gaps.pop_front();
+                }
+            }
+        }
+        self.last_tick_us = Some(at_us);
+        if self.running {
+            if self.ticks.is_multiple_of(TICKS_PER_BEAT) {
+                self.beat_us = Some(at_us);
+            }
+            self.ticks += 1;
+        }
+    }
+
+    /// Whether the sequencer is playing and its clock still coming at `now_us`.
+    pub fn is_running(&self, now_us: u64) -> bool {
+        self.running && self.last_tick_us.is_some_and(|last| now_us.saturating_sub(last) <= STALE_US)
+    }
+
+    /// Which beat of the song the sequencer is on, counting from 0; None while it isn't
+    /// playing or hasn't reached a beat since it started or moved.
+    pub fn beat(&self, now_us: u64) -> Option<u64> {
+        (self.is_running(now_us) && self.beat_us.is_some()).then(|| (self.ticks - 1) / TICKS_PER_BEAT)
+    }
+
+    fn tick_us(&self) -> Option<u64> {
+        (!self.gaps.is_empty()).then(|| self.gaps.iter().sum::<u64>() / self.gaps.len() as u64)
+    }
+
+    /// The sequencer's tempo, once there have been a couple of ticks.
+    pub fn bpm(&self) -> Option<f64> {
+        self.tick_us().filter(|us| *us > 0).map(|us| 60_000_000.0 / (us * TICKS_PER_BEAT) as f64)
+    }
+
+    /// `subdivision` lines per beat of the sequencer, a line on each of its beats; None while
+    /// it isn't playing.
+    pub fn grid(&self, subdivision: u64, now_us: u64) -> Option<Grid> {
+        if !self.is_running(now_us) {
+            return None;
+        }
+        let beat_us = self.tick_us()? * TICKS_PER_BEAT;
+        Some(Grid { step_us: beat_us / subdivision.max(1), anchor_us: self.beat_us?, swing_us: 0 })
+    }
+}
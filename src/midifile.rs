@@ -0,0 +1,231 @@
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+// Standard MIDI File loading.
+//
+// All tracks are merged and flattened into raw 3-byte channel messages with absolute
+// timestamps, so anything that consumes live MIDI can consume a file the same way.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileEvent {
+    pub at_us: u64,
+    pub message: [u8; 3],
+}
+
+impl FileEvent {
+    pub fn is_note_on(&self) -> bool {
+        self.message[0] & 0xF0 == 0x90 && self.message[2] > 0
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.message[0] & 0x0F
+    }
+
+    pub fn note(&self) -> u8 {
+        self.message[1]
+    }
+}
+
+/// Pitch class names, C = 0.
+pub const PITCH_CLASSES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Scientific pitch name, e.g. 60 -> "C4".
+pub fn note_name(note: u8) -> String {
+    format!("{}{}", PITCH_CLASSES[(note % 12) as usize], note as i32 / 12 - 1)
+}
+
+// Chord shapes as intervals above the root, most specific first
+const CHORD_SHAPES: [(&[u8], &str); 12] = [
+    (&[0, 4, 7, 11], "maj7"),
+    (&[0, 4, 7, 10], "7"),
+    (&[0, 3, 7, 10], "m7"),
+    (&[0, 3, 6, 10], "m7b5"),
+    (&[0, 3, 6, 9], "dim7"),
+    (&[0, 4, 7], ""),
+    (&[0, 3, 7], "m"),
+    (&[0, 3, 6], "dim"),
+    (&[0, 4, 8], "aug"),
+    (&[0, 2, 7], "sus2"),
+    (&[0, 5, 7], "sus4"),
+    (&[0, 7], "5"),
+];
+
+/// Chord symbol for the notes held, e.g. [64, 67, 72] -> "C/E". Octave doublings don't
+/// matter; `None` for a single pitch or a shape that isn't a common chord.
+pub fn chord_name(notes: &[u8]) -> Option<String> {
+    let bass = *notes.iter().min()? % 12;
+    let mut classes: Vec<u8> = notes.iter().map(|n| n % 12).collect();
+    classes.sort_unstable();
+    classes.dedup();
+    // The bass note is the likeliest root, so it's tried first
+    let mut roots = classes.clone();
+    roots.sort_by_key(|r| (*r != bass, *r));
+    for (shape, suffix) in CHORD_SHAPES {
+        for &root in &roots {
+            let mut intervals: Vec<u8> = classes.iter().map(|c| (c + 12 - root) % 12).collect();
+            intervals.sort_unstable();
+            if intervals == shape {
+                let name = format!("{}{}", PITCH_CLASSES[root as usize], suffix);
+                return Some(if root == bass { name } else { format!("{}/{}", name, PITCH_CLASSES[bass as usize]) });
+            }
+        }
+    }
+    None
+}
+
+/// Inverse of `note_name`. Also accepts flats ("Db3") and plain note numbers ("60").
+pub fn parse_note(text: &str) -> Option<u8> {
+    let text = text.trim();
+    if let Ok(n) = text.parse::<u8>() {
+        return (n < 128).then_some(n);
+    }
+    let mut chars = text.chars();
+    let base = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let note = (octave.parse::<i32>().ok()? + 1) * 12 + base + accidental;
+    u8::try_from(note).ok().filter(|n| *n < 128)
+}
+
+pub struct MidiFile {
+    pub name: String,
+    pub events: Vec<FileEvent>,
+    pub beats_us: Vec<u64>, // Start of every beat up to the last event, from the tempo map
+    pub beats_per_bar: u32, // From the first time signature, 4 without one
+}
+
+// Raw bytes for the channel messages the pipeline understands. Two-byte messages
+// (program change, channel pressure) have no effect on key output and are skipped.
+fn encode(channel: u8, message: MidiMessage) -> Option<[u8; 3]> {
+    match message {
+        MidiMessage::NoteOff { key, vel } => Some([0x80 | channel, key.as_int(), vel.as_int()]),
+        MidiMessage::NoteOn { key, vel } => Some([0x90 | channel, key.as_int(), vel.as_int()]),
+        MidiMessage::Aftertouch { key, vel } => Some([0xA0 | channel, key.as_int(), vel.as_int()]),
+        MidiMessage::Controller { controller, value } => Some([0xB0 | channel, controller.as_int(), value.as_int()]),
+        MidiMessage::PitchBend { bend } => {
+            let raw = bend.0.as_int();
+            Some([0xE0 | channel, (raw & 0x7F) as u8, (raw >> 7) as u8])
+        }
+        MidiMessage::ProgramChange { .. } | MidiMessage::ChannelAftertouch { .. } => None,
+    }
+}
+
+impl MidiFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|e| Error::Midi(format!("Failed to read {}: {}", path.display(), e)))?;
+        let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        Self::parse(&bytes, name)
+    }
+
+    pub fn parse(bytes: &[u8], name: String) -> Result<Self> {
+        let smf = Smf::parse(bytes).map_err(|e| Error::Midi(format!("Invalid MIDI file: {}", e)))?;
+
+        // Merge tracks on absolute ticks. Tempo changes are kept alongside so the
+        // tick -> time conversion below can walk them in order.
+        let mut tempo_changes: Vec<(u64, u32)> = Vec::new();
+        let mut time_signature: Option<u32> = None;
+        let mut ticked: Vec<(u64, usize, [u8; 3])> = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                match event.kind {
+                    TrackEventKind::Midi { channel, message } => {
+                        if let Some(raw) = encode(channel.as_int(), message) {
+                            // Sequence number keeps same-tick events in file order
+                            ticked.push((tick, ticked.len(), raw));
+                        }
+                    }
+                    TrackEventKind::Meta(MetaMessage::Tempo(us_per_beat)) => {
+                        tempo_changes.push((tick, us_per_beat.as_int()));
+                    }
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, ..)) => {
+                        time_signature.get_or_insert(numerator.max(1) as u32);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        ticked.sort_by_key(|(tick, seq, _)| (*tick, *seq));
+        tempo_changes.sort_by_key(|(tick, _)| *tick);
+
+        // Beats only mean something with metrical timing
+        let last_tick = ticked.last().map(|(tick, _, _)| *tick).unwrap_or(0);
+        let beats_ticks: Vec<u64> = match smf.header.timing {
+            Timing::Metrical(ppq) => (0..=last_tick).step_by(ppq.as_int().max(1) as usize).collect(),
+            Timing::Timecode(..) => Vec::new(),
+        };
+
+        let to_us = TickClock::new(smf.header.timing, tempo_changes);
+        let events: Vec<FileEvent> = ticked
+            .into_iter()
+            .map(|(tick, _, message)| FileEvent { at_us: to_us.micros(tick), message })
+            .collect();
+        let beats_us = beats_ticks.into_iter().map(|tick| to_us.micros(tick)).collect();
+
+        Ok(Self { name, events, beats_us, beats_per_bar: time_signature.unwrap_or(4) })
+    }
+
+    /// Note-on events only.
+    pub fn note_ons(&self) -> impl Iterator<Item = &FileEvent> {
+        self.events.iter().filter(|e| e.is_note_on())
+    }
+}
+
+// Converts absolute ticks to microseconds, honoring the tempo map.
+struct TickClock {
+    timing: Timing,
+    // (tick, microseconds at that tick, microseconds per beat from there on)
+    segments: Vec<(u64, u64, u32)>,
+}
+
+const DEFAULT_TEMPO: u32 = 500_000; // 120 BPM
+
+impl TickClock {
+    fn new(timing: Timing, tempo_changes: Vec<(u64, u32)>) -> Self {
+        let ppq = match timing {
+            Timing::Metrical(ppq) => ppq.as_int().max(1) as u64,
+            Timing::Timecode(..) => 1,
+        };
+        let mut segments = vec![(0u64, 0u64, DEFAULT_TEMPO)];
+        for (tick, tempo) in tempo_changes {
+            let (last_tick, last_us, last_tempo) = *segments.last().unwrap();
+            let us = last_us + (tick - last_tick) * last_tempo as u64 / ppq;
+            if tick == last_tick {
+                segments.pop();
+            }
+            segments.push((tick, us, tempo));
+        }
+        Self { timing, segments }
+    }
+
+    fn micros(&self, tick: u64) -> u64 {
+        match self.timing {
+            Timing::Metrical(ppq) => {
+                let ppq = ppq.as_int().max(1) as u64;
+                let idx = self.segments.partition_point(|(t, _, _)| *t <= tick).saturating_sub(1);
+                let (seg_tick, seg_us, tempo) = self.segments[idx];
+                seg_us + (tick - seg_tick) * tempo as u64 / ppq
+            }
+            Timing::Timecode(fps, subframe) => {
+                let ticks_per_second = fps.as_f32() as f64 * subframe.max(1) as f64;
+                (tick as f64 * 1_000_000.0 / ticks_per_second) as u64
+            }
+        }
+    }
+}
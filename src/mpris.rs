@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use zbus::blocking::connection;
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{interface, SignalContext};
+
+use crate::{player, Engine};
+
+// MPRIS (org.mpris.MediaPlayer2) for MIDI file playback, so desktop media keys, playerctl
+// and panel widgets can pause, skip and seek the playlist without leaving the game window.
+// Everything goes through the same transport fields the GUI's buttons use (player.rs).
+//
+// Previous goes back to the start of the song; there's no going back a song. Play only
+// resumes: starting a file or playlist takes the GUI or a remote, which know what to play.
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.miditoroblox";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+// How often property changes are looked for, to tell listeners about them
+const POLL: Duration = Duration::from_millis(250);
+const MIN_RATE: f64 = 0.25;
+const MAX_RATE: f64 = 2.0;
+
+/// "Playing", "Paused" or "Stopped", as MPRIS spells them.
+pub fn status(engine: &Engine) -> &'static str {
+    if !engine.playback_running.load(Ordering::Relaxed) {
+        "Stopped"
+    } else if engine.playback_paused.load(Ordering::Relaxed) {
+        "Paused"
+    } else {
+        "Playing"
+    }
+}
+
+/// The name of the song playing, if any.
+pub fn title(engine: &Engine) -> Option<String> {
+    if !engine.playback_running.load(Ordering::Relaxed) {
+        return None;
+    }
+    let song = engine.playback_song.load(Ordering::Relaxed) as usize;
+    engine.playback_titles.lock().unwrap().get(song).cloned()
+}
+
+fn track_id(engine: &Engine) -> ObjectPath<'static> {
+    ObjectPath::try_from(format!("/org/miditoroblox/track/{}", engine.playback_song.load(Ordering::Relaxed))).unwrap()
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "MIDI to Roblox"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Player {
+    engine: Arc<Engine>,
+}
+
+impl Player {
+    fn running(&self) -> bool {
+        self.engine.playback_running.load(Ordering::Relaxed)
+    }
+
+    // Jumps to `to_us` of the song, within it
+    fn seek_to(&self, to_us: i64) -> i64 {
+        let duration_us = self.engine.playback_duration_us.load(Ordering::Relaxed) as i64;
+        let to_us = to_us.clamp(0, duration_us);
+        *self.engine.playback_seek_us.lock().unwrap() = Some(to_us as u64);
+        self.engine.request_repaint();
+        to_us
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        self.engine.playback_skip.store(true, Ordering::Relaxed);
+    }
+
+    async fn previous(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>) -> zbus::fdo::Result<()> {
+        if self.running() {
+            Self::seeked(&ctxt, self.seek_to(0)).await?;
+        }
+        Ok(())
+    }
+
+    fn pause(&self) {
+        if self.running() && !self.engine.playback_paused.load(Ordering::Relaxed) {
+            player::toggle_pause(&self.engine);
+        }
+    }
+
+    fn play_pause(&self) {
+        player::toggle_pause(&self.engine);
+    }
+
+    fn stop(&self) {
+        self.engine.playback_stop.store(true, Ordering::Relaxed);
+    }
+
+    fn play(&self) {
+        if self.engine.playback_paused.load(Ordering::Relaxed) {
+            player::toggle_pause(&self.engine);
+        }
+    }
+
+    async fn seek(&self, offset: i64, #[zbus(signal_context)] ctxt: SignalContext<'_>) -> zbus::fdo::Result<()> {
+        if self.running() {
+            let position_us = self.engine.playback_position_us.load(Ordering::Relaxed) as i64;
+            Self::seeked(&ctxt, self.seek_to(position_us + offset)).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64, #[zbus(signal_context)] ctxt: SignalContext<'_>) -> zbus::fdo::Result<()> {
+        // A position meant for another song is stale, and the spec says to ignore it
+        if self.running() && track_id == crate::mpris::track_id(&self.engine) {
+            Self::seeked(&ctxt, self.seek_to(position)).await?;
+        }
+        Ok(())
+    }
+
+    fn open_uri(&self, _uri: &str) -> zbus::fdo::Result<()> {
+        Err(zbus::fdo::Error::NotSupported("open files from the app".to_string()))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        status(&self.engine)
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        self.engine.playback_rate_percent.load(Ordering::Relaxed) as f64 / 100.0
+    }
+
+    #[zbus(property)]
+    fn set_rate(&self, rate: f64) {
+        // 0 means pause, by the spec
+        if rate == 0.0 {
+            self.pause();
+            return;
+        }
+        let percent = (rate.clamp(MIN_RATE, MAX_RATE) * 100.0).round() as u64;
+        self.engine.playback_rate_percent.store(percent, Ordering::Relaxed);
+        self.engine.request_repaint();
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        MIN_RATE
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        MAX_RATE
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        let mut metadata = HashMap::new();
+        let Some(title) = title(&self.engine) else { return metadata };
+        metadata.insert("mpris:trackid".to_string(), Value::from(track_id(&self.engine)));
+        metadata.insert("mpris:length".to_string(), Value::from(self.engine.playback_duration_us.load(Ordering::Relaxed) as i64));
+        metadata.insert("xesam:title".to_string(), Value::from(title));
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.engine.playback_position_us.load(Ordering::Relaxed) as i64
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.running()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.running()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        self.running()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        self.running()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.running()
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Offers playback over MPRIS on the session bus, with a background thread telling listeners
+/// when it starts, stops, pauses, changes song or speed.
+pub fn spawn(engine: Arc<Engine>) -> zbus::Result<()> {
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(OBJECT_PATH, Player { engine: engine.clone() })?
+        .build()?;
+    let player = connection.object_server().interface::<_, Player>(OBJECT_PATH)?;
+    thread::spawn(move || {
+        // Kept here so the name stays ours as long as the app runs
+        let _connection = connection;
+        let mut last = None;
+        loop {
+            thread::sleep(POLL);
+            let now = (status(&engine), title(&engine), engine.playback_rate_percent.load(Ordering::Relaxed));
+            if last.as_ref() == Some(&now) {
+                continue;
+            }
+            let (ctxt, player) = (player.signal_context(), player.get());
+            let changed = zbus::block_on(async {
+                player.playback_status_changed(ctxt).await?;
+                player.metadata_changed(ctxt).await?;
+                player.rate_changed(ctxt).await?;
+                player.can_play_changed(ctxt).await?;
+                player.can_pause_changed(ctxt).await?;
+                player.can_seek_changed(ctxt).await?;
+                player.can_go_next_changed(ctxt).await?;
+                player.can_go_previous_changed(ctxt).await
+            });
+            if let Err(e) = changed {
+                eprintln!("MPRIS: {}", e);
+            }
+            last = Some(now);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use evdev::{EventType, InputEvent};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::output;
+
+// Network key emitter.
+//
+// The GUI side (`--remote`) sends key events over TCP to a receiver (`--serve`) that owns the
+// uinput device. Handshake is a challenge-response on a shared token so the token itself never
+// crosses the wire:
+//   server -> client: "MTRB1 <nonce hex>\n"
+//   client -> server: "<sha256(nonce || token) hex>\n"
+//   server -> client: "OK\n"
+// After that the client sends frames: u16 event count, then (u16 type, u16 code, i32 value) per
+// event, all little endian.
+
+const PROTOCOL_HEADER: &str = "MTRB1";
+const MAX_EVENTS_PER_FRAME: usize = 64;
+// Handshake lines are a nonce or a digest; anything longer isn't a key emitter
+const MAX_HANDSHAKE_LINE: u64 = 128;
+// The receiver serves one client at a time, so a silent one can't be waited on forever
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_PORT: u16 = 7645;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn auth_digest(nonce: &[u8], token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(nonce);
+    hasher.update(token.as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+// Compare without bailing out on the first differing byte
+pub(crate) fn digests_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// A bare port for the control servers means that port on localhost only
+pub(crate) fn localhost_if_bare_port(addr: &str) -> String {
+    if addr.parse::<u16>().is_ok() { format!("127.0.0.1:{}", addr) } else { addr.to_string() }
+}
+
+// Whether a browser's `Origin` header names a page served from this machine. Any other site
+// the user has open could otherwise drive the control servers from their browser.
+pub(crate) fn is_local_origin(origin: &str) -> bool {
+    let Some((_, rest)) = origin.split_once("://") else { return false };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => authority.rsplit_once(':').map_or(authority, |(host, _)| host),
+    };
+    host.eq_ignore_ascii_case("localhost") || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn with_default_port(addr: &str) -> String {
+    if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    }
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = String::new();
+    let read = reader.by_ref().take(MAX_HANDSHAKE_LINE).read_line(&mut line)?;
+    if read == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during handshake"));
+    }
+    if !line.ends_with('\n') && read as u64 == MAX_HANDSHAKE_LINE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake line too long"));
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Client half: forwards emitted events to a remote receiver.
+pub struct RemoteEmitter {
+    stream: TcpStream,
+}
+
+impl RemoteEmitter {
+    pub fn connect(addr: &str, token: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(with_default_port(addr))?;
+        stream.set_nodelay(true)?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let hello = read_line(&mut reader)?;
+        let nonce_hex = hello
+            .strip_prefix(PROTOCOL_HEADER)
+            .map(str::trim)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected greeting: {}", hello)))?;
+        let nonce = parse_hex(nonce_hex)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed nonce"))?;
+
+        let mut writer = stream.try_clone()?;
+        writeln!(writer, "{}", auth_digest(&nonce, token))?;
+
+        if read_line(&mut reader)? != "OK" {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "receiver rejected the token"));
+        }
+
+        Ok(Self { stream })
+    }
+
+    pub fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        for chunk in events.chunks(MAX_EVENTS_PER_FRAME) {
+            let mut frame = Vec::with_capacity(2 + chunk.len() * 8);
+            frame.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            for ev in chunk {
+                frame.extend_from_slice(&ev.event_type().0.to_le_bytes());
+                frame.extend_from_slice(&ev.code().to_le_bytes());
+                frame.extend_from_slice(&ev.value().to_le_bytes());
+            }
+            self.stream.write_all(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    // Byte offsets below; a multibyte character would split
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn random_nonce() -> io::Result<[u8; 16]> {
+    let mut nonce = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Receiver half: owns the uinput device and replays events from one authenticated client at a time.
+pub fn serve(addr: &str, token: &str) -> Result<()> {
+    println!("Initializing virtual keyboard (requires permissions to write to /dev/uinput)...");
+    let mut device = output::create_virtual_device().map_err(Error::Device)?;
+
+    let addr = if addr.is_empty() { format!("0.0.0.0:{}", DEFAULT_PORT) } else { with_default_port(addr) };
+    let listener = TcpListener::bind(&addr)?;
+    println!("Listening for key emitters on {}", addr);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+
+        match handle_client(stream, token, &mut device) {
+            Ok(()) => println!("{} disconnected", peer),
+            Err(e) => println!("{} dropped: {}", peer, e),
+        }
+    }
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, token: &str, device: &mut evdev::uinput::VirtualDevice) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let nonce = random_nonce()?;
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{} {}", PROTOCOL_HEADER, to_hex(&nonce))?;
+
+    let mut reader = BufReader::new(stream);
+    let answer = read_line(&mut reader)?;
+    if !digests_match(&answer, &auth_digest(&nonce, token)) {
+        let _ = writeln!(writer, "DENIED");
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "bad token"));
+    }
+    writeln!(writer, "OK")?;
+    println!("Emitter authenticated");
+    // Keys are held for as long as the player likes, so frames can be far apart
+    reader.get_ref().set_read_timeout(None)?;
+
+    // Keys the client currently holds, so a dropped connection can't leave anything stuck
+    let mut held: HashSet<u16> = HashSet::new();
+    let result = pump_frames(&mut reader, device, &mut held);
+
+    for code in held {
+        let _ = device.emit(&[InputEvent::new(EventType::KEY.0, code, 0)]);
+    }
+    result
+}
+
+fn pump_frames(
+    reader: &mut BufReader<TcpStream>,
+    device: &mut evdev::uinput::VirtualDevice,
+    held: &mut HashSet<u16>,
+) -> io::Result<()> {
+    loop {
+        let mut count_buf = [0u8; 2];
+        match reader.read_exact(&mut count_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let count = u16::from_le_bytes(count_buf) as usize;
+        if count > MAX_EVENTS_PER_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "oversized frame"));
+        }
+
+        let mut events = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            let ev_type = u16::from_le_bytes([buf[0], buf[1]]);
+            let code = u16::from_le_bytes([buf[2], buf[3]]);
+            let value = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+
+            // Only key events and the frame ends between them are forwarded; the device has no
+            // other capabilities anyway
+            if ev_type == EventType::SYNCHRONIZATION.0 {
+                events.push(InputEvent::new(ev_type, code, value));
+                continue;
+            }
+            if ev_type != EventType::KEY.0 {
+                continue;
+            }
+            if value == 0 {
+                held.remove(&code);
+            } else {
+                held.insert(code);
+            }
+            events.push(InputEvent::new(ev_type, code, value));
+        }
+
+        if !events.is_empty() {
+            device.emit(&events)?;
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+
+use crate::error::{Error, Result};
+use crate::net::localhost_if_bare_port;
+use crate::worker::MidiQueue;
+
+// Open Sound Control input over UDP, for TouchOSC layouts and custom controllers. Messages
+// are turned into MIDI and queued like a MIDI port's, so everything downstream is the same:
+//
+//   /note/on <note> [velocity] [channel]   velocity 0 (or none given: 100) as usual
+//   /note/off <note> [channel]
+//   /cc <controller> <value> [channel]
+//
+// Numbers can be ints or floats. Channels are 1-16 and default to 1. Velocities and CC
+// values given as floats from 0 to 1 (TouchOSC's faders and buttons) are scaled to 0-127.
+// Bundles are unpacked and their messages played at once; their time tags are ignored.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+// Reads OSC's 4-byte-aligned pieces off the front of a packet
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(Error::Parse("OSC packet cut short".to_string()));
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn word(&mut self) -> Result<[u8; 4]> {
+        Ok(self.take(4)?.try_into().unwrap())
+    }
+
+    // A NUL-terminated string padded to a multiple of 4 bytes
+    fn string(&mut self) -> Result<String> {
+        let end = self.data.iter().position(|b| *b == 0).ok_or_else(|| Error::Parse("OSC string without an end".to_string()))?;
+        let text = std::str::from_utf8(&self.data[..end]).map_err(|_| Error::Parse("OSC string isn't UTF-8".to_string()))?.to_string();
+        self.take((end + 4) & !3)?;
+        Ok(text)
+    }
+}
+
+fn decode_message(packet: &[u8]) -> Result<OscMessage> {
+    let mut reader = Reader { data: packet };
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(Error::Parse(format!("OSC address '{}' doesn't start with /", address)));
+    }
+    // Very old senders leave the type tags out; nothing useful comes without them
+    let tags = if reader.data.is_empty() { ",".to_string() } else { reader.string()? };
+    let Some(tags) = tags.strip_prefix(',') else { return Err(Error::Parse("OSC type tags missing".to_string())) };
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(reader.word()?)),
+            'f' => OscArg::Float(f32::from_be_bytes(reader.word()?)),
+            's' => OscArg::Str(reader.string()?),
+            // Arguments without data: true/false become 1/0, nil and impulse are skipped
+            'T' => OscArg::Int(1),
+            'F' => OscArg::Int(0),
+            'N' | 'I' => continue,
+            other => return Err(Error::Parse(format!("OSC argument type '{}' isn't supported", other))),
+        });
+    }
+    Ok(OscMessage { address, args })
+}
+
+/// The messages in one UDP packet: a message, or a bundle of them (nested or not).
+pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>> {
+    let Some(mut elements) = packet.strip_prefix(b"#bundle\0") else { return Ok(vec![decode_message(packet)?]) };
+    let mut reader = Reader { data: elements };
+    reader.take(8)?; // Time tag
+    elements = reader.data;
+    let mut messages = Vec::new();
+    while !elements.is_empty() {
+        let mut reader = Reader { data: elements };
+        let size = u32::from_be_bytes(reader.word()?) as usize;
+        messages.extend(decode(reader.take(size)?)?);
+        elements = reader.data;
+    }
+    Ok(messages)
+}
+
+// A MIDI data byte from a number; floats from 0 to 1 are scaled when `unit` allows it
+fn data_byte(arg: Option<&OscArg>, unit: bool) -> Option<u8> {
+    let value = match arg? {
+        OscArg::Int(i) => *i as f32,
+        OscArg::Float(f) if unit && (0.0..=1.0).contains(f) => f * 127.0,
+        OscArg::Float(f) => *f,
+        OscArg::Str(_) => return None,
+    };
+    (0.0..=127.0).contains(&value.round()).then_some(value.round() as u8)
+}
+
+fn channel(arg: Option<&OscArg>) -> Option<u8> {
+    match arg {
+        None => Some(0),
+        Some(arg) => data_byte(Some(arg), false).filter(|ch| (1..=16).contains(ch)).map(|ch| ch - 1),
+    }
+}
+
+/// The MIDI message for `message`, if it's one of ours and its numbers are in range.
+pub fn to_midi(message: &OscMessage) -> Option<[u8; 3]> {
+    let args = &message.args;
+    match message.address.as_str() {
+        "/note/on" => {
+            let velocity = if args.len() < 2 { Some(100) } else { data_byte(args.get(1), true) };
+            Some([0x90 | channel(args.get(2))?, data_byte(args.first(), false)?, velocity?])
+        }
+        "/note/off" => Some([0x80 | channel(args.get(1))?, data_byte(args.first(), false)?, 0]),
+        "/cc" => Some([0xB0 | channel(args.get(2))?, data_byte(args.first(), false)?, data_byte(args.get(1), true)?]),
+        _ => None,
+    }
+}
+
+/// Listens for OSC on `addr` on a background thread, queueing what it plays. A bare port
+/// listens on localhost only. Returns the address it's listening on.
+pub fn spawn(addr: &str, queue: MidiQueue) -> io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(localhost_if_bare_port(addr))?;
+    let local = socket.local_addr()?;
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let Ok((size, from)) = socket.recv_from(&mut buffer) else { continue };
+            match decode(&buffer[..size]) {
+                Ok(messages) => {
+                    for message in messages.iter().filter_map(to_midi) {
+                        queue.push(&message);
+                    }
+                }
+                Err(e) => eprintln!("OSC from {}: {}", from, e),
+            }
+        }
+    });
+    Ok(local)
+}
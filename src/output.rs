@@ -0,0 +1,196 @@
+use evdev::{uinput::VirtualDevice, AttributeSet, EventType, InputEvent, KeyCode, SynchronizationCode};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::clock::Clock;
+use crate::drums::DrumMap;
+use crate::mappings::KEY_NAMES;
+use crate::net::RemoteEmitter;
+use crate::portal::PortalKeyboard;
+
+// Where key events end up. Every backend implements `OutputBackend`; `KeyOutput` picks one
+// of the built-in ones (a local uinput keyboard, the desktop portal for sandboxed/Wayland
+// setups, a receiver running on another machine, a spy that just keeps them for tests) or
+// carries any other implementation, so new backends need no changes to the routing code.
+
+/// A key event for `key`: 1 = press, 0 = release.
+pub fn key_event(key: KeyCode, value: i32) -> InputEvent {
+    InputEvent::new(EventType::KEY.0, key.code(), value)
+}
+
+/// The end of a frame: what comes before it is one change of the keyboard's state.
+pub fn syn_report() -> InputEvent {
+    InputEvent::new(EventType::SYNCHRONIZATION.0, SynchronizationCode::SYN_REPORT.0, 0)
+}
+
+/// Something that can type. Only `emit` is required; the rest are built on it and can be
+/// overridden by backends with a better way to do them.
+pub trait OutputBackend: Send {
+    /// Sends raw evdev events, in order, as one write where the backend can. Batched key
+    /// sequences come with a SYN_REPORT between their events; the backend ends the last frame.
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()>;
+
+    fn press(&mut self, key: KeyCode) -> io::Result<()> {
+        self.emit(&[key_event(key, 1)])
+    }
+
+    fn release(&mut self, key: KeyCode) -> io::Result<()> {
+        self.emit(&[key_event(key, 0)])
+    }
+
+    fn tap(&mut self, key: KeyCode) -> io::Result<()> {
+        self.press(key)?;
+        self.release(key)
+    }
+
+    /// Taps `key` with `modifiers` held around it, e.g. Shift+T.
+    fn tap_with(&mut self, key: KeyCode, modifiers: &[KeyCode]) -> io::Result<()> {
+        for modifier in modifiers {
+            self.press(*modifier)?;
+        }
+        self.tap(key)?;
+        for modifier in modifiers.iter().rev() {
+            self.release(*modifier)?;
+        }
+        Ok(())
+    }
+}
+
+pub enum KeyOutput {
+    Uinput(VirtualDevice),
+    Portal(PortalKeyboard),
+    Remote(RemoteEmitter),
+    Spy(SpyOutput),
+    Custom(Box<dyn OutputBackend>), // Anything else: XTest, a ydotool socket, a dry run...
+}
+
+impl OutputBackend for KeyOutput {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        match self {
+            KeyOutput::Uinput(device) => device.emit(events),
+            KeyOutput::Portal(portal) => portal.emit(events),
+            KeyOutput::Remote(remote) => remote.emit(events),
+            KeyOutput::Spy(spy) => spy.emit(events),
+            KeyOutput::Custom(backend) => backend.emit(events),
+        }
+    }
+}
+
+impl OutputBackend for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
+
+impl OutputBackend for PortalKeyboard {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        PortalKeyboard::emit(self, events)
+    }
+}
+
+impl OutputBackend for RemoteEmitter {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        RemoteEmitter::emit(self, events)
+    }
+}
+
+impl OutputBackend for SpyOutput {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        SpyOutput::emit(self, events)
+    }
+}
+
+/// Prints key presses instead of sending them, for trying settings without a game (`--dry-run`).
+pub struct DryRunOutput;
+
+impl OutputBackend for DryRunOutput {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        for event in events.iter().filter(|e| e.event_type() == EventType::KEY) {
+            println!("{:?} {}", KeyCode::new(event.code()), if event.value() == 0 { "up" } else { "down" });
+        }
+        Ok(())
+    }
+}
+
+/// Keeps every key event instead of sending it anywhere. Clones share the same log, so one
+/// can be handed to the engine while another is used to inspect what it emitted.
+#[derive(Clone, Default)]
+pub struct SpyOutput {
+    clock: Option<Clock>, // Timestamps events when set
+    events: Arc<Mutex<Vec<(u64, KeyCode, i32)>>>,
+}
+
+impl SpyOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        Self { clock: Some(clock), ..Self::default() }
+    }
+
+    pub fn emit(&self, events: &[InputEvent]) -> io::Result<()> {
+        let at_us = self.clock.as_ref().map(|c| c.now_us()).unwrap_or(0);
+        let mut log = self.events.lock().unwrap();
+        log.extend(
+            events
+                .iter()
+                .filter(|e| e.event_type() == EventType::KEY)
+                .map(|e| (at_us, KeyCode::new(e.code()), e.value())),
+        );
+        Ok(())
+    }
+
+    /// Everything emitted since the last call, oldest first.
+    pub fn take(&self) -> Vec<(KeyCode, i32)> {
+        self.take_timed().into_iter().map(|(_, key, value)| (key, value)).collect()
+    }
+
+    /// Like `take`, with the clock time (microseconds) of each event.
+    pub fn take_timed(&self) -> Vec<(u64, KeyCode, i32)> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+/// Creates the uinput keyboard with every key the program may press.
+pub fn create_virtual_device() -> io::Result<VirtualDevice> {
+    create_keyboard("Miditoroblox Rust Presser")
+}
+
+/// The second player's keyboard in duet mode, with the same keys as the first.
+pub fn create_partner_device() -> io::Result<VirtualDevice> {
+    create_keyboard("Miditoroblox Rust Player 2")
+}
+
+fn create_keyboard(name: &str) -> io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<KeyCode>::new();
+    keys.insert(KeyCode::KEY_E);
+    keys.insert(KeyCode::KEY_LEFTSHIFT);
+    keys.insert(KeyCode::KEY_LEFTCTRL);
+    keys.insert(KeyCode::KEY_UP);
+    keys.insert(KeyCode::KEY_DOWN);
+
+    // Register every key a mapping, drum kit, guitar layout or setting can name
+    for (_, key) in KEY_NAMES {
+        keys.insert(*key);
+    }
+
+    // Create the virtual device using the builder
+    VirtualDevice::builder()?
+        .name(name)
+        .with_keys(&keys)?
+        .build()
+}
+
+/// Second uinput keyboard with just the drum keys, for dual-instrument setups where the drum
+/// part has to come from its own device (e.g. a second game client bound to it).
+pub fn create_drum_device() -> io::Result<VirtualDevice> {
+    let mut keys = AttributeSet::<KeyCode>::new();
+    for key in DrumMap::builtin().keys() {
+        keys.insert(key);
+    }
+    VirtualDevice::builder()?
+        .name("Miditoroblox Rust Drums")
+        .with_keys(&keys)?
+        .build()
+}
@@ -0,0 +1,62 @@
+use std::ops::RangeInclusive;
+
+// Where each key of an on-screen piano keyboard is, for drawing it and for finding the key
+// under the pointer. Positions are fractions of the keyboard's width and height, so the
+// frontend only scales them: white keys share the width evenly and run the full height,
+// black keys sit across the line between two white keys, narrower and shorter.
+
+pub const BLACK_WIDTH: f32 = 0.6; // Of a white key's width
+pub const BLACK_HEIGHT: f32 = 0.6; // Of the keyboard's height
+
+pub fn is_black(note: u8) -> bool {
+    matches!(note % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+pub struct PianoLayout {
+    notes: RangeInclusive<u8>,
+    white_keys: usize,
+}
+
+impl PianoLayout {
+    pub fn new(notes: RangeInclusive<u8>) -> Self {
+        let white_keys = notes.clone().filter(|&n| !is_black(n)).count().max(1);
+        Self { notes, white_keys }
+    }
+
+    pub fn notes(&self) -> RangeInclusive<u8> {
+        self.notes.clone()
+    }
+
+    pub fn white_width(&self) -> f32 {
+        1.0 / self.white_keys as f32
+    }
+
+    /// Left and right edges of `note`'s key, if it's on this keyboard.
+    pub fn key_span(&self, note: u8) -> Option<(f32, f32)> {
+        if !self.notes.contains(&note) {
+            return None;
+        }
+        let whites_before = (*self.notes.start()..note).filter(|&n| !is_black(n)).count() as f32;
+        let width = self.white_width();
+        if is_black(note) {
+            let center = whites_before * width;
+            Some((center - width * BLACK_WIDTH / 2.0, center + width * BLACK_WIDTH / 2.0))
+        } else {
+            Some((whites_before * width, (whites_before + 1.0) * width))
+        }
+    }
+
+    /// How far down the key goes.
+    pub fn key_height(note: u8) -> f32 {
+        if is_black(note) { BLACK_HEIGHT } else { 1.0 }
+    }
+
+    /// The key at (`x`, `y`). Black keys are on top, so they win where they overlap a white one.
+    pub fn note_at(&self, x: f32, y: f32) -> Option<u8> {
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return None;
+        }
+        let hit = |note: &u8| self.key_span(*note).is_some_and(|(left, right)| (left..right).contains(&x)) && y <= Self::key_height(*note);
+        self.notes().filter(|&n| is_black(n)).find(hit).or_else(|| self.notes().filter(|&n| !is_black(n)).find(hit))
+    }
+}
@@ -0,0 +1,191 @@
+use crate::config::{BlackKeys, GameMode, ModifierRole, ScaleMode, Settings};
+use crate::mappings::KeyMapping;
+use crate::piano;
+use crate::stats::DropReason;
+
+// Note routing decisions shared by the live callback and offline analysis.
+
+/// The settings a note's routing reads. The live path copies these for every note, where the
+/// whole `Settings` would clone its key names, paths and colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Routing {
+    pub game_mode: GameMode,
+    pub base_mapping_enabled: bool,
+    pub low_mapping_enabled: bool,
+    pub high_mapping_enabled: bool,
+    pub auto_transpose_enabled: bool,
+    pub fold_low_notes: bool,
+    pub fold_high_notes: bool,
+    pub snap_key: Option<u8>,
+    pub snap_scale: ScaleMode,
+    pub channel_transpose: [i32; 16],
+    pub black_keys: BlackKeys,
+    pub shift_role: ModifierRole,
+    pub ctrl_role: ModifierRole,
+    pub experimental_transpose_enabled: bool,
+}
+
+impl From<&Settings> for Routing {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            game_mode: settings.game_mode,
+            base_mapping_enabled: settings.base_mapping_enabled,
+            low_mapping_enabled: settings.low_mapping_enabled,
+            high_mapping_enabled: settings.high_mapping_enabled,
+            auto_transpose_enabled: settings.auto_transpose_enabled,
+            fold_low_notes: settings.fold_low_notes,
+            fold_high_notes: settings.fold_high_notes,
+            snap_key: settings.snap_key,
+            snap_scale: settings.snap_scale,
+            channel_transpose: settings.channel_transpose,
+            black_keys: settings.black_keys,
+            shift_role: settings.shift_role,
+            ctrl_role: settings.ctrl_role,
+            experimental_transpose_enabled: settings.experimental_transpose_enabled,
+        }
+    }
+}
+
+/// Whether `note` falls in one of the currently enabled ranges.
+pub fn is_note_in_range(routing: &Routing, note: u8) -> bool {
+    if routing.game_mode == GameMode::Keys61 {
+        // The whole 61-key board is the base range; the range toggles don't apply
+        return routing.game_mode.key_range().contains(&note);
+    }
+    if note < 36 {
+        routing.low_mapping_enabled
+    } else if note > 96 {
+        routing.high_mapping_enabled
+    } else {
+        routing.base_mapping_enabled
+    }
+}
+
+pub const MAX_CHANNEL_TRANSPOSE: i32 = 48;
+
+/// `note` on `channel` moved by that channel's transpose; past either end of the MIDI range
+/// it stays where it was.
+pub fn transpose_channel(routing: &Routing, channel: u8, note: u8) -> u8 {
+    let offset = routing.channel_transpose[channel as usize & 0x0F].clamp(-MAX_CHANNEL_TRANSPOSE, MAX_CHANNEL_TRANSPOSE);
+    u8::try_from(note as i32 + offset).ok().filter(|n| *n < 128).unwrap_or(note)
+}
+
+/// Range check plus octave auto-transpose and folding, used when the solver is off.
+/// Returns the note to look up in the mappings.
+pub fn resolve_note(routing: &Routing, note: u8) -> Result<u8, DropReason> {
+    let is_note_valid = |n: u8| is_note_in_range(routing, n);
+    let note = fold_black_key(routing, note);
+    if is_note_valid(note) {
+        return Ok(note);
+    }
+    // A 61-key game always folds the extreme octaves in rather than dropping them
+    if !routing.auto_transpose_enabled && routing.game_mode != GameMode::Keys61 {
+        return fold_note(routing, note).ok_or(DropReason::OutOfRange);
+    }
+
+    // Auto-transpose up
+    let mut test_note = note;
+    while test_note <= 108 && !is_note_valid(test_note) {
+        if let Some(next) = test_note.checked_add(12) { test_note = next; } else { break; }
+    }
+    if is_note_valid(test_note) {
+        return Ok(test_note);
+    }
+
+    // Auto-transpose down
+    let mut test_note = note;
+    while test_note >= 21 && !is_note_valid(test_note) {
+        if let Some(prev) = test_note.checked_sub(12) { test_note = prev; } else { break; }
+    }
+    if is_note_valid(test_note) {
+        return Ok(test_note);
+    }
+
+    fold_note(routing, note).ok_or(DropReason::OutOfRange)
+}
+
+/// `note` wrapped by octaves into the playable window, if it's past the end of the window
+/// whose folding is on: notes below come up from the bottom, notes above come down from the
+/// top, each to the first octave that's in an enabled range.
+fn fold_note(routing: &Routing, note: u8) -> Option<u8> {
+    let is_note_valid = |n: u8| is_note_in_range(routing, n);
+    let lowest = (0..=127).find(|n| is_note_valid(*n))?;
+    let highest = (0..=127).rev().find(|n| is_note_valid(*n))?;
+    if note < lowest && routing.fold_low_notes {
+        (note..=highest).step_by(12).find(|n| is_note_valid(*n))
+    } else if note > highest && routing.fold_high_notes {
+        (lowest..=note).rev().step_by(12).find(|n| is_note_valid(*n))
+    } else {
+        None
+    }
+}
+
+/// `note` as the white key it plays as when black keys are folded, or as itself.
+pub fn fold_black_key(routing: &Routing, note: u8) -> u8 {
+    if !piano::is_black(note) {
+        return note;
+    }
+    // Black keys are never at either end of the MIDI range, so both neighbours exist
+    match routing.black_keys {
+        BlackKeys::Mapped => note,
+        BlackKeys::FoldDown => note - 1,
+        BlackKeys::FoldUp => note + 1,
+    }
+}
+
+/// Whether the solver may pick Shift mappings. A game without sharps has no use for them, even
+/// when a transpose would land a folded note on one.
+pub fn solver_allows_shift(routing: &Routing) -> bool {
+    routing.shift_role == ModifierRole::Range && routing.black_keys == BlackKeys::Mapped
+}
+
+/// `note` moved to the nearest tone of `scale` on `tonic` (a pitch class, C = 0), the lower
+/// one when two are as near; scale tones stay put.
+pub fn snap_to_scale(note: u8, tonic: u8, scale: ScaleMode) -> u8 {
+    let in_scale = |n: u8| scale.intervals().contains(&((n + 12 - tonic % 12) % 12));
+    for distance in 0..12 {
+        if let Some(lower) = note.checked_sub(distance)
+            && in_scale(lower)
+        {
+            return lower;
+        }
+        if let Some(upper) = note.checked_add(distance).filter(|n| *n < 128)
+            && in_scale(upper)
+        {
+            return upper;
+        }
+    }
+    note
+}
+
+/// `note` snapped to the routing's scale, if snapping is on.
+pub fn snap_note(routing: &Routing, note: u8) -> u8 {
+    match routing.snap_key {
+        Some(tonic) => snap_to_scale(note, tonic, routing.snap_scale),
+        None => note,
+    }
+}
+
+/// `note` on `channel` with the channel's transpose and then the scale snap, as the live path
+/// pitches it before routing (less the live octave shift).
+pub fn pitched_note(routing: &Routing, channel: u8, note: u8) -> u8 {
+    snap_note(routing, transpose_channel(routing, channel, note))
+}
+
+/// The note the piano routing works with for `note` on `channel`: pitched, then folded onto a
+/// white key if black keys are folded.
+pub fn analysis_note(routing: &Routing, channel: u8, note: u8) -> u8 {
+    fold_black_key(routing, pitched_note(routing, channel, note))
+}
+
+/// Whether the legacy path can play `mapping` given what the game does with the modifiers.
+/// Shift for black keys can be replaced by an experimental transpose; Ctrl can't.
+pub fn is_mapping_usable(routing: &Routing, mapping: &KeyMapping) -> bool {
+    if mapping.ctrl {
+        routing.ctrl_role == ModifierRole::Range
+    } else if mapping.shift {
+        routing.shift_role == ModifierRole::Range || routing.experimental_transpose_enabled
+    } else {
+        true
+    }
+}
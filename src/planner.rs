@@ -0,0 +1,122 @@
+use crate::config::{ChannelRoute, GameMode, ModifierRole, Settings};
+use crate::engine;
+use crate::mappings::MappingSet;
+use crate::midifile::FileEvent;
+use crate::pipeline::{self, Routing};
+
+// Whole-song transpose planning for file playback. The live solver only sees one note at a
+// time, so it moves the transpose to wherever the next note is closest and may have to move
+// straight back. With the whole file known up front, a dynamic program over its note-ons
+// finds the transpose path with the fewest dropped notes and, among those, the fewest
+// Up/Down taps. Playback hands the planned transpose to the solver before each note-on.
+//
+// The plan only looks at notes and keys, not at which keys are still held, so the solver
+// keeps the last word: a planned key that's busy or needs the wrong modifiers falls back to
+// its usual choice.
+
+// A dropped note costs more than any number of taps could add up to in a song
+const DROP_COST: u64 = 1 << 32;
+
+pub struct TransposePlan {
+    // Planned transpose per file event; None for events the plan doesn't cover or drops
+    steps: Vec<Option<i32>>,
+    pub transpose_taps: u64,
+    pub dropped: u64,
+}
+
+impl TransposePlan {
+    /// The transpose to play event `index` at, if it's a planned note-on.
+    pub fn at(&self, index: usize) -> Option<i32> {
+        self.steps.get(index).copied().flatten()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    Played { from: i32 },
+    Dropped,
+}
+
+/// Plans the solver's transposes for `events` under `settings`, starting from `start`.
+pub fn plan(events: &[FileEvent], settings: &Settings, mappings: &MappingSet, start: i32) -> TransposePlan {
+    let routing = Routing::from(settings);
+    let range = settings.transpose_range as i32;
+    let max_jump = if settings.solver_mode_efficiency { settings.solver_max_jump as i32 } else { i32::MAX };
+    let key_range = settings.game_mode.key_range();
+    let allow_shift = pipeline::solver_allows_shift(&routing);
+    let allow_ctrl = settings.ctrl_role == ModifierRole::Range;
+    let playable = |note: u8, transpose: i32| {
+        let played = note as i32 - transpose;
+        (0..=127).contains(&played)
+            && mappings.get(played as u8).is_some_and(|m| {
+                key_range.contains(&m.midi_note) && (allow_shift || !m.shift) && (allow_ctrl || !m.ctrl)
+            })
+    };
+
+    // The notes the solver would see, with their index in `events`
+    let notes: Vec<(usize, u8)> = events
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| {
+            e.is_note_on()
+                && settings.channel_routes[e.channel() as usize] == ChannelRoute::Play
+                && (e.message[2] as u64) >= settings.min_velocity
+                && settings.sostenuto_trigger.matches(&e.message).is_none()
+                && settings.panic_trigger.matches(&e.message).is_none()
+                && engine::transpose_triggers(settings).iter().all(|(trigger, _)| trigger.matches(&e.message).is_none())
+        })
+        .map(|(i, e)| (i, pipeline::analysis_note(&routing, e.channel(), e.note())))
+        .collect();
+
+    let mut steps = vec![None; events.len()];
+    let plays_notes = settings.solver_enabled && matches!(settings.game_mode, GameMode::Full88 | GameMode::Keys61);
+    if !plays_notes || notes.is_empty() {
+        return TransposePlan { steps, transpose_taps: 0, dropped: 0 };
+    }
+
+    // cost[t] is the cheapest way to be at transpose t - range after the notes so far
+    let width = (2 * range + 1) as usize;
+    let index = |transpose: i32| (transpose + range) as usize;
+    let mut cost = vec![u64::MAX; width];
+    cost[index(start.clamp(-range, range))] = 0;
+    let mut choices: Vec<Vec<Step>> = Vec::with_capacity(notes.len());
+    for &(_, note) in &notes {
+        let mut next = vec![u64::MAX; width];
+        let mut choice = vec![Step::Dropped; width];
+        for to in -range..=range {
+            // Dropping the note leaves the transpose where it was
+            let mut best = cost[index(to)].saturating_add(DROP_COST);
+            if playable(note, to) {
+                for from in -range..=range {
+                    let jump = (to - from).abs();
+                    if jump > max_jump || cost[index(from)] == u64::MAX {
+                        continue;
+                    }
+                    let total = cost[index(from)] + jump as u64;
+                    if total < best {
+                        best = total;
+                        choice[index(to)] = Step::Played { from };
+                    }
+                }
+            }
+            next[index(to)] = best;
+        }
+        cost = next;
+        choices.push(choice);
+    }
+
+    // Walk back from the cheapest end, preferring to finish nearest no transpose
+    let mut at = (-range..=range).min_by_key(|&t| (cost[index(t)], t.abs())).unwrap_or(0);
+    let (mut transpose_taps, mut dropped) = (0, 0);
+    for (&(event, _), choice) in notes.iter().zip(&choices).rev() {
+        match choice[index(at)] {
+            Step::Played { from } => {
+                steps[event] = Some(at);
+                transpose_taps += (at - from).unsigned_abs() as u64;
+                at = from;
+            }
+            Step::Dropped => dropped += 1,
+        }
+    }
+    TransposePlan { steps, transpose_taps, dropped }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use crate::config::{self, ChannelRoute, GameMode, ModifierRole, Settings};
+use crate::drums::DrumMap;
+use crate::error::{Error, Result};
+use crate::guitar::GuitarMap;
+use crate::midifile::{self, MidiFile};
+use crate::mappings::MappingSet;
+use crate::pipeline::{self, Routing};
+use crate::polyphony::{Admit, Polyphony};
+use crate::simplify;
+use crate::solver::{Solver, SolverMode};
+use crate::stats::DropReason;
+
+// Dry run of a MIDI file through the routing logic with a given set of settings,
+// listing every note that would not be played and why.
+
+#[derive(Clone, Debug)]
+pub struct UnplayableNote {
+    pub at_us: u64,
+    pub channel: u8,
+    pub note: u8,
+    pub reason: DropReason,
+}
+
+pub struct PlayabilityReport {
+    pub file_name: String,
+    pub total_notes: usize,
+    pub duration_us: u64,
+    pub unplayable: Vec<UnplayableNote>,
+    // (ms into the file, transpose offset) whenever the solver would change it
+    pub transpose_path: Vec<(u64, i32)>,
+    pub transposes: u64,
+    pub transpose_taps: u64,
+    pub steals: u64,
+}
+
+impl PlayabilityReport {
+    pub fn counts_by_reason(&self) -> BTreeMap<DropReason, usize> {
+        let mut counts = BTreeMap::new();
+        for n in &self.unplayable {
+            *counts.entry(n.reason).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+pub fn analyze(file: &MidiFile, settings: &Settings) -> PlayabilityReport {
+    let routing = Routing::from(settings);
+    let mappings = MappingSet::for_settings(settings);
+    let drums = DrumMap::builtin();
+    let guitar = GuitarMap::builtin();
+    let mode = if settings.solver_mode_efficiency { SolverMode::Efficiency } else { SolverMode::Accuracy };
+    let mut solver = Solver::new();
+    solver.mappings = mappings.clone();
+    solver.key_range = settings.game_mode.key_range();
+    solver.allow_shift = pipeline::solver_allows_shift(&routing);
+    solver.allow_ctrl = settings.ctrl_role == ModifierRole::Range;
+    solver.allow_steal = !settings.solver_drop_steals;
+    solver.hold_transpose = settings.solver_hold_transpose;
+    let mut polyphony = Polyphony::default();
+    let mut unplayable = Vec::new();
+    let mut total_notes = 0;
+    let mut transpose_path = vec![(0, 0)];
+    let (mut transposes, mut transpose_taps, mut steals) = (0u64, 0u64, 0u64);
+    // Legacy (non-solver) experimental transpose offset
+    let mut legacy_offset = 0;
+
+    for event in simplify::simplify(&file.events, settings).iter() {
+        let status = event.message[0] & 0xF0;
+        let velocity = event.message[2];
+        let is_note_on = status == 0x90 && velocity > 0;
+        let is_note_off = status == 0x80 || (status == 0x90 && velocity == 0);
+        // A sostenuto trigger note is a control, not part of the music
+        if !is_note_on && !is_note_off || settings.sostenuto_trigger.matches(&event.message).is_some() {
+            continue;
+        }
+
+        let note = event.note();
+        let route = settings.channel_routes[event.channel() as usize];
+        let is_drum_hit = settings.game_mode == GameMode::Drums || (route == ChannelRoute::Drums && settings.dual_drums_enabled);
+        // Pitched parts are transposed and snapped as they are live; the piano paths below fold
+        let note = if is_drum_hit || route == ChannelRoute::Drums { note } else { pipeline::pitched_note(&routing, event.channel(), note) };
+        let reason = if matches!(route, ChannelRoute::Visualize | ChannelRoute::Ignore) {
+            Some(DropReason::ChannelFiltered)
+        } else if is_note_on && (velocity as u64) < settings.min_velocity {
+            Some(DropReason::TooSoft)
+        } else if is_drum_hit {
+            (drums.get(note).is_none()).then_some(DropReason::NoMapping)
+        } else if route != ChannelRoute::Drums && over_polyphony(&mut polyphony, event.channel(), note, is_note_on, settings) {
+            Some(DropReason::Polyphony)
+        } else if settings.game_mode == GameMode::Guitar && route != ChannelRoute::Drums {
+            (guitar.fret(note).is_none()).then_some(DropReason::NoMapping)
+        } else if route == ChannelRoute::Drums {
+            Some(DropReason::DrumChannel)
+        } else if settings.solver_enabled {
+            // The solver is stateful (held keys, current transpose), so note-offs matter too
+            if is_note_off {
+                solver.register_note_off(note);
+                None
+            } else {
+                let (max_jump, range) = (settings.solver_max_jump as i32, settings.transpose_range as i32);
+                let solved_note = pipeline::fold_black_key(&routing, note);
+                match solver.solve(solved_note, event.at_us, mode, max_jump, range) {
+                    Some((delta, mapping)) => {
+                        if delta != solver.current_transpose {
+                            transpose_path.push((event.at_us / 1000, delta));
+                            transposes += 1;
+                            transpose_taps += (delta - solver.current_transpose).unsigned_abs() as u64;
+                        }
+                        if solver.active_keys.get(&mapping.key_code).is_some_and(|n| !n.is_empty()) {
+                            steals += 1;
+                        }
+                        solver.register_note_on(mapping.key_code, note, event.at_us, delta, mapping.shift, mapping.ctrl);
+                        None
+                    }
+                    None if solver.needs_steal(solved_note, event.at_us, mode, max_jump, range) => Some(DropReason::KeyBusy),
+                    None => Some(DropReason::NoSolverMatch),
+                }
+            }
+        } else {
+            match pipeline::resolve_note(&routing, note) {
+                Ok(final_note) => match mappings.get(final_note) {
+                    Some(mapping) if !pipeline::is_mapping_usable(&routing, mapping) => Some(DropReason::NoMapping),
+                    Some(mapping) => {
+                        // Mirrors the experimental Up/Down handling of the live callback
+                        if is_note_on && settings.experimental_transpose_enabled {
+                            if settings.lazy_transpose_enabled {
+                                let target = if mapping.shift && !mapping.ctrl { 1 } else { 0 };
+                                if target != legacy_offset {
+                                    legacy_offset = target;
+                                    transposes += 1;
+                                    transpose_taps += 1;
+                                }
+                            } else if mapping.shift && !mapping.ctrl {
+                                // Up for the note, then back down
+                                transposes += 2;
+                                transpose_taps += 2;
+                            }
+                        }
+                        None
+                    }
+                    None => Some(DropReason::NoMapping),
+                },
+                Err(reason) => Some(reason),
+            }
+        };
+
+        if is_note_on {
+            total_notes += 1;
+            if let Some(reason) = reason {
+                unplayable.push(UnplayableNote { at_us: event.at_us, channel: event.channel(), note, reason });
+            }
+        }
+    }
+
+    PlayabilityReport {
+        file_name: file.name.clone(),
+        total_notes,
+        duration_us: file.events.last().map(|e| e.at_us).unwrap_or(0),
+        unplayable,
+        transpose_path,
+        transposes,
+        transpose_taps,
+        steals,
+    }
+}
+
+/// Plain-text side-by-side comparison of two analyses of the same file, followed by the
+/// notes only one of them drops.
+pub fn format_comparison(label_a: &str, a: &PlayabilityReport, label_b: &str, b: &PlayabilityReport) -> String {
+    let mut out = format!("'{}': A = {}, B = {}\n\n", a.file_name, label_a, label_b);
+    out.push_str(&format!("{:<20}{:>10}{:>10}{:>10}\n", "", "A", "B", "B - A"));
+    let mut row = |name: &str, va: u64, vb: u64| {
+        let diff = vb as i64 - va as i64;
+        let diff = if diff == 0 { String::new() } else { format!("{:+}", diff) };
+        out.push_str(&format!("{:<20}{:>10}{:>10}{:>10}\n", name, va, vb, diff));
+    };
+    row("Notes", a.total_notes as u64, b.total_notes as u64);
+    row("Dropped", a.unplayable.len() as u64, b.unplayable.len() as u64);
+    let (counts_a, counts_b) = (a.counts_by_reason(), b.counts_by_reason());
+    let mut reasons: Vec<DropReason> = counts_a.keys().chain(counts_b.keys()).copied().collect();
+    reasons.sort();
+    reasons.dedup();
+    for reason in reasons {
+        let count = |c: &BTreeMap<DropReason, usize>| c.get(&reason).copied().unwrap_or(0) as u64;
+        row(&format!("  {}", reason.label()), count(&counts_a), count(&counts_b));
+    }
+    row("Transposes", a.transposes, b.transposes);
+    row("Transpose taps", a.transpose_taps, b.transpose_taps);
+    row("Steals", a.steals, b.steals);
+
+    let key = |n: &UnplayableNote| (n.at_us, n.channel, n.note);
+    for (label, this, other) in [("A", a, b), ("B", b, a)] {
+        let only: Vec<&UnplayableNote> = this
+            .unplayable
+            .iter()
+            .filter(|n| !other.unplayable.iter().any(|o| key(o) == key(n)))
+            .collect();
+        out.push_str(&format!("\nDropped only in {} ({}):\n", label, only.len()));
+        for n in only {
+            let ms = n.at_us / 1000;
+            out.push_str(&format!(
+                "  {}:{:02}.{:03}  ch {:>2}  {:<4} {}\n",
+                ms / 60_000, (ms / 1000) % 60, ms % 1000,
+                n.channel + 1, midifile::note_name(n.note), n.reason.label()
+            ));
+        }
+    }
+    out
+}
+
+/// Analyzes `file` under two saved profiles and formats the differences.
+pub fn compare_profiles(file: &MidiFile, a: &str, b: &str) -> Result<String> {
+    let load = |name: &str| {
+        config::load_profile(name).map_err(|e| Error::Parse(format!("Failed to load profile '{}': {}", name, e)))
+    };
+    let report_a = analyze(file, &load(a)?);
+    let report_b = analyze(file, &load(b)?);
+    Ok(format_comparison(a, &report_a, b, &report_b))
+}
+
+// Follows the polyphony limit like the live callback; true for a note-on it drops. Held notes
+// it would let go early still count as played.
+fn over_polyphony(polyphony: &mut Polyphony, channel: u8, note: u8, is_note_on: bool, settings: &Settings) -> bool {
+    if is_note_on {
+        return polyphony.on(channel, note, settings.max_polyphony as usize, settings.polyphony_priority) == Admit::Drop;
+    }
+    polyphony.off(channel, note);
+    false
+}
@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use crate::midifile::{FileEvent, MidiFile};
+use crate::planner::{self, TransposePlan};
+use crate::recording::REPLAY_LEAD_IN;
+use crate::simplify;
+use crate::Engine;
+
+// Plays a loaded MIDI file into the game. Its events go through `Engine::handle_message`
+// like a controller's would, so every setting (solver, processors, game mode) applies. The
+// tempo map is already resolved in the file's timestamps.
+//
+// The transport (pause, seek, speed) is read from the engine on every step. Playback keeps
+// its own position in file time and moves it by however much the clock moved times the
+// speed, so changing speed or jumping around never replays or skips what's next.
+//
+// A playlist is the same thing song after song, with the gap between songs as the lead-in.
+//
+// With the solver on, each song's transposes are planned before it starts (planner.rs) and
+// every note-on is sent with its planned transpose set on the solver.
+//
+// Dense chords are simplified (simplify.rs) before anything else sees the song.
+
+// Longest sleep between transport checks
+const PLAYBACK_POLL: Duration = Duration::from_millis(20);
+
+/// Plays `events` on the engine's clock, starting `lead_in` from now. Returns early when
+/// `playback_stop` or `playback_skip` is set; notes still sounding at the end are released
+/// either way.
+pub fn play(shared: &Engine, events: &[FileEvent], lead_in: Duration) {
+    let events = &*simplify::simplify(events, &shared.settings());
+    *shared.playback_events.lock().unwrap() = Arc::new(events.to_vec());
+    shared.playback_position_us.store(0, Ordering::Relaxed);
+    shared.playback_duration_us.store(events.last().map(|e| e.at_us).unwrap_or(0), Ordering::Relaxed);
+    let lead_in_end = shared.clock.now_us() + lead_in.as_micros() as u64;
+    while shared.clock.now_us() < lead_in_end {
+        if shared.playback_stop.load(Ordering::Relaxed) || shared.playback_skip.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        shared.clock.sleep(Duration::from_micros(lead_in_end - shared.clock.now_us()).min(PLAYBACK_POLL));
+    }
+    // The song's first beat is a line of the tempo grid
+    shared.anchor_quantize(Some(shared.clock.now_us()));
+
+    let plan = song_plan(shared, events);
+    let mut sounding: HashSet<(u8, u8)> = HashSet::new();
+    let mut next = 0;
+    let mut position_us = 0u64;
+    let mut last_clock_us = shared.clock.now_us();
+    while !shared.playback_stop.load(Ordering::Relaxed) && !shared.playback_skip.swap(false, Ordering::Relaxed) {
+        if let Some(to_us) = shared.playback_seek_us.lock().unwrap().take() {
+            // Whatever was sounding belongs to the old position
+            release(shared, &mut sounding);
+            position_us = to_us;
+            next = events.partition_point(|e| e.at_us < to_us);
+        }
+        let now_us = shared.clock.now_us();
+        let elapsed_us = now_us - last_clock_us;
+        last_clock_us = now_us;
+        if shared.playback_paused.load(Ordering::Relaxed) {
+            release(shared, &mut sounding);
+            shared.clock.sleep(PLAYBACK_POLL);
+            continue;
+        }
+        let rate = shared.playback_rate_percent.load(Ordering::Relaxed).max(1);
+        position_us += elapsed_us * rate / 100;
+
+        while let Some(event) = events.get(next).filter(|e| e.at_us <= position_us) {
+            let key = (event.channel(), event.note());
+            let status = event.message[0] & 0xF0;
+            if event.is_note_on() {
+                sounding.insert(key);
+            } else if status == 0x80 || status == 0x90 {
+                sounding.remove(&key);
+            }
+            if let Some(plan) = &plan
+                && event.is_note_on()
+            {
+                shared.router.lock().unwrap().solver.planned = plan.at(next);
+            }
+            send(shared, now_us, &event.message);
+            next += 1;
+        }
+        shared.playback_position_us.store(position_us, Ordering::Relaxed);
+        let Some(event) = events.get(next) else { break };
+        // Clock time until the next event at this speed, rounded up so it's due on arrival
+        let wait_us = ((event.at_us - position_us) * 100).div_ceil(rate);
+        shared.clock.sleep(Duration::from_micros(wait_us).min(PLAYBACK_POLL));
+    }
+    release(shared, &mut sounding);
+    // Live input goes back to the solver's own choices
+    shared.router.lock().unwrap().solver.planned = None;
+}
+
+// Plans the song's transposes from where the solver is now, when the settings ask for it
+fn song_plan(shared: &Engine, events: &[FileEvent]) -> Option<TransposePlan> {
+    let settings = shared.settings();
+    if !settings.solver_enabled || !settings.solver_plan_files {
+        return None;
+    }
+    let (mappings, start) = {
+        let router = shared.router.lock().unwrap();
+        (router.solver.mappings.clone(), router.solver.current_transpose)
+    };
+    Some(planner::plan(events, &settings, &mappings, start))
+}
+
+// Note-offs for everything still sounding, through the pipeline so its state stays right
+fn release(shared: &Engine, sounding: &mut HashSet<(u8, u8)>) {
+    let now_us = shared.clock.now_us();
+    for (channel, note) in sounding.drain() {
+        send(shared, now_us, &[0x80 | channel, note, 0]);
+    }
+}
+
+fn send(shared: &Engine, at_us: u64, message: &[u8]) {
+    // Dropped notes are already in the stats; anything else is worth a line
+    if let Err(e) = shared.inject(at_us, message)
+        && !e.is_dropped_note()
+    {
+        eprintln!("Playback: {}", e);
+    }
+}
+
+/// Plays the songs in order, waiting `gap` between them, until the last ends or
+/// `playback_stop` is set. `playback_song` says which one is playing.
+pub fn play_all(shared: &Engine, songs: &[Vec<FileEvent>], lead_in: Duration, gap: Duration) {
+    for (i, events) in songs.iter().enumerate() {
+        if shared.playback_stop.load(Ordering::Relaxed) {
+            break;
+        }
+        shared.playback_song.store(i as u64, Ordering::Relaxed);
+        shared.request_repaint();
+        play(shared, events, if i == 0 { lead_in } else { gap });
+    }
+}
+
+/// Pauses playing, or resumes paused, playback; does nothing when nothing plays. Returns
+/// whether it's paused now.
+pub fn toggle_pause(shared: &Engine) -> bool {
+    let paused = shared.playback_running.load(Ordering::Relaxed) && !shared.playback_paused.fetch_xor(true, Ordering::Relaxed);
+    shared.request_repaint();
+    paused
+}
+
+/// Plays `file` on a background thread after the replay lead-in, to give time to focus the game.
+pub fn spawn(shared: Arc<Engine>, file: &MidiFile) {
+    spawn_playlist(shared, std::slice::from_ref(file), Duration::ZERO);
+}
+
+/// Plays a playlist on a background thread, starting after the replay lead-in.
+pub fn spawn_playlist(shared: Arc<Engine>, files: &[MidiFile], gap: Duration) {
+    let songs: Vec<Vec<FileEvent>> = files.iter().map(|f| f.events.clone()).collect();
+    *shared.playback_titles.lock().unwrap() = files.iter().map(|f| f.name.clone()).collect();
+    shared.playback_stop.store(false, Ordering::Relaxed);
+    shared.playback_skip.store(false, Ordering::Relaxed);
+    shared.playback_paused.store(false, Ordering::Relaxed);
+    *shared.playback_seek_us.lock().unwrap() = None;
+    shared.playback_running.store(true, Ordering::Relaxed);
+
+    thread::spawn(move || {
+        play_all(&shared, &songs, REPLAY_LEAD_IN, gap);
+        shared.playback_running.store(false, Ordering::Relaxed);
+        shared.request_repaint();
+    });
+}
@@ -0,0 +1,67 @@
+// The polyphony limiter: Roblox and the keyboard's rollover both choke on huge chords, so
+// at most so many notes are held at once. Past the limit the priority decides which note
+// goes: the new one is dropped, or a held one is let go early ("stolen") to make room.
+
+use crate::config::NotePriority;
+
+/// What to do with a note-on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admit {
+    Play,
+    Drop,
+    Steal { channel: u8, note: u8 }, // Let go of this one first, then play
+}
+
+#[derive(Default)]
+pub struct Polyphony {
+    sounding: Vec<(u8, u8)>, // Channel and note, in the order they started
+    stolen: Vec<(u8, u8)>,   // Let go early; their own note-off has nothing left to do
+}
+
+impl Polyphony {
+    /// A note-on, with at most `max` notes held (0 is no limit).
+    pub fn on(&mut self, channel: u8, note: u8, max: usize, priority: NotePriority) -> Admit {
+        let key = (channel, note);
+        self.sounding.retain(|held| *held != key);
+        self.stolen.retain(|held| *held != key);
+        if max == 0 || self.sounding.len() < max {
+            self.sounding.push(key);
+            return Admit::Play;
+        }
+        let by_note = |held: &&(u8, u8)| held.1;
+        let victim = match priority {
+            NotePriority::Highest => self.sounding.iter().min_by_key(by_note).filter(|low| low.1 < note),
+            NotePriority::Lowest => self.sounding.iter().max_by_key(by_note).filter(|high| high.1 > note),
+            NotePriority::MostRecent => self.sounding.first(),
+            NotePriority::MelodyOnTop => {
+                // The top note is the melody; of the rest, the oldest goes
+                let top = self.sounding.iter().max_by_key(by_note).copied().filter(|top| top.1 > note);
+                self.sounding.iter().find(|held| Some(**held) != top)
+            }
+        };
+        let Some(&victim) = victim else { return Admit::Drop };
+        self.sounding.retain(|held| *held != victim);
+        self.sounding.push(key);
+        Admit::Steal { channel: victim.0, note: victim.1 }
+    }
+
+    /// The note from `Admit::Steal` was let go; its own note-off is swallowed when it comes.
+    pub fn stole(&mut self, channel: u8, note: u8) {
+        self.stolen.push((channel, note));
+    }
+
+    /// A note-off; false when the note was stolen and is already let go.
+    pub fn off(&mut self, channel: u8, note: u8) -> bool {
+        let key = (channel, note);
+        self.sounding.retain(|held| *held != key);
+        let stolen = self.stolen.len();
+        self.stolen.retain(|held| *held != key);
+        self.stolen.len() == stolen
+    }
+
+    /// Forgets every note, e.g. on panic.
+    pub fn clear(&mut self) {
+        self.sounding.clear();
+        self.stolen.clear();
+    }
+}
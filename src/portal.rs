@@ -0,0 +1,108 @@
+use evdev::{EventType, InputEvent};
+use std::collections::HashMap;
+use std::io;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+// Key injection through the xdg-desktop-portal RemoteDesktop interface.
+//
+// Works inside Flatpak/sandboxes and on Wayland compositors where /dev/uinput isn't reachable.
+// The portal shows a permission dialog when the session starts; after that keycodes are plain
+// evdev codes, so the rest of the program doesn't need to know which backend it's talking to.
+
+const PORTAL_DEST: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const REMOTE_DESKTOP_IFACE: &str = "org.freedesktop.portal.RemoteDesktop";
+const REQUEST_IFACE: &str = "org.freedesktop.portal.Request";
+
+// RemoteDesktop device type bitmask
+const DEVICE_KEYBOARD: u32 = 1;
+
+pub struct PortalKeyboard {
+    proxy: Proxy<'static>, // The RemoteDesktop interface; holds the connection the session lives on
+    session: OwnedObjectPath,
+}
+
+impl PortalKeyboard {
+    /// Creates a RemoteDesktop session with keyboard access. Blocks until the user answers the
+    /// portal's permission dialog.
+    pub fn open() -> zbus::Result<Self> {
+        let connection = Connection::session()?;
+        let proxy = Proxy::new(&connection, PORTAL_DEST, PORTAL_PATH, REMOTE_DESKTOP_IFACE)?;
+
+        let results = portal_request(&connection, "create", |token| {
+            let mut options: HashMap<&str, Value> = HashMap::new();
+            options.insert("handle_token", Value::from(token));
+            options.insert("session_handle_token", Value::from("miditoroblox"));
+            proxy.call("CreateSession", &(options,))
+        })?;
+        let session_str: &str = results
+            .get("session_handle")
+            .ok_or_else(|| zbus::Error::Failure("portal returned no session handle".to_string()))?
+            .downcast_ref()?;
+        let session = OwnedObjectPath::from(ObjectPath::try_from(session_str.to_string())?);
+
+        portal_request(&connection, "select", |token| {
+            let mut options: HashMap<&str, Value> = HashMap::new();
+            options.insert("handle_token", Value::from(token));
+            options.insert("types", Value::from(DEVICE_KEYBOARD));
+            proxy.call("SelectDevices", &(&session, options))
+        })?;
+
+        portal_request(&connection, "start", |token| {
+            let mut options: HashMap<&str, Value> = HashMap::new();
+            options.insert("handle_token", Value::from(token));
+            proxy.call("Start", &(&session, "", options))
+        })?;
+
+        Ok(Self { proxy, session })
+    }
+
+    pub fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        for ev in events {
+            // The portal synthesizes its own repeats, so only presses and releases are forwarded
+            if ev.event_type() != EventType::KEY || ev.value() > 1 {
+                continue;
+            }
+            let options: HashMap<&str, Value> = HashMap::new();
+            self.proxy
+                .call::<_, _, ()>(
+                    "NotifyKeyboardKeycode",
+                    &(&self.session, options, ev.code() as i32, ev.value() as u32),
+                )
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+// Portal calls answer asynchronously through a Request object's Response signal. The request
+// path is predictable from our unique name and the handle token, so we subscribe before calling
+// to avoid missing a fast reply.
+fn portal_request(
+    connection: &Connection,
+    token: &str,
+    call: impl FnOnce(&str) -> zbus::Result<OwnedObjectPath>,
+) -> zbus::Result<HashMap<String, OwnedValue>> {
+    let sender = connection
+        .unique_name()
+        .map(|n| n.trim_start_matches(':').replace('.', "_"))
+        .ok_or_else(|| zbus::Error::Failure("no unique bus name".to_string()))?;
+    let token = format!("miditoroblox_{}", token);
+    let request_path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+
+    let request = Proxy::new(connection, PORTAL_DEST, request_path, REQUEST_IFACE)?;
+    let mut responses = request.receive_signal("Response")?;
+
+    call(&token)?;
+
+    let message = responses
+        .next()
+        .ok_or_else(|| zbus::Error::Failure("portal closed the request".to_string()))?;
+    let (response, results): (u32, HashMap<String, OwnedValue>) = message.body().deserialize()?;
+    match response {
+        0 => Ok(results),
+        1 => Err(zbus::Error::Failure("remote desktop request was cancelled".to_string())),
+        _ => Err(zbus::Error::Failure("remote desktop request failed".to_string())),
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Instant;
+
+use crate::midifile::MidiFile;
+
+// Practice mode: score live playing against a reference MIDI file.
+//
+// A run is armed from the UI and starts on the first played note, which is lined up with the
+// first reference note, so there's no count-in to hit. Each reference note is matched to the
+// closest unmatched live note of the same pitch within MATCH_WINDOW_MS.
+
+const MATCH_WINDOW_MS: f64 = 250.0;
+// A run ends on its own this long after the last reference note
+const RUN_TAIL_MS: f64 = 3000.0;
+
+#[derive(Clone, Debug)]
+pub struct RunSummary {
+    pub correct: usize,
+    pub missed: usize,
+    pub extra: usize,
+    pub mean_offset_ms: f64,    // Signed, positive = late
+    pub mean_deviation_ms: f64, // Absolute
+    pub score_percent: f64,
+}
+
+#[derive(Default)]
+pub struct PracticeSession {
+    pub reference_name: String,
+    reference: Vec<(f64, u8)>,
+    live: Vec<(f64, u8)>,
+    armed: bool,
+    started: Option<Instant>,
+    pub history: Vec<RunSummary>,
+}
+
+impl PracticeSession {
+    pub fn load_reference(&mut self, file: &MidiFile) {
+        self.reference = file
+            .note_ons()
+            .filter(|e| e.channel() != 9)
+            .map(|e| (e.at_us as f64 / 1000.0, e.note()))
+            .collect();
+        self.reference_name = file.name.clone();
+        self.armed = false;
+        self.started = None;
+        self.live.clear();
+        self.history.clear();
+    }
+
+    pub fn has_reference(&self) -> bool {
+        !self.reference.is_empty()
+    }
+
+    pub fn reference_len(&self) -> usize {
+        self.reference.len()
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn arm(&mut self) {
+        self.live.clear();
+        self.started = None;
+        self.armed = self.has_reference();
+    }
+
+    pub fn record(&mut self, note: u8) {
+        if !self.armed {
+            return;
+        }
+        let first_ref = self.reference.first().map(|r| r.0).unwrap_or(0.0);
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let at_ms = first_ref + started.elapsed().as_secs_f64() * 1000.0;
+        self.live.push((at_ms, note));
+    }
+
+    /// True once the run has gone past the end of the reference.
+    pub fn should_finish(&self) -> bool {
+        match (self.started, self.reference.first(), self.reference.last()) {
+            (Some(started), Some(first), Some(last)) => {
+                started.elapsed().as_secs_f64() * 1000.0 > (last.0 - first.0) + RUN_TAIL_MS
+            }
+            _ => false,
+        }
+    }
+
+    pub fn finish(&mut self) -> Option<RunSummary> {
+        if !self.armed {
+            return None;
+        }
+        self.armed = false;
+        self.started = None;
+        let summary = score(&self.reference, &self.live);
+        self.history.push(summary.clone());
+        Some(summary)
+    }
+}
+
+pub fn score(reference: &[(f64, u8)], live: &[(f64, u8)]) -> RunSummary {
+    let mut used = vec![false; live.len()];
+    let mut offsets = Vec::new();
+
+    for &(ref_ms, ref_note) in reference {
+        let best = live
+            .iter()
+            .enumerate()
+            .filter(|(i, (ms, note))| !used[*i] && *note == ref_note && (ms - ref_ms).abs() <= MATCH_WINDOW_MS)
+            .min_by(|a, b| (a.1.0 - ref_ms).abs().total_cmp(&(b.1.0 - ref_ms).abs()));
+        if let Some((i, (ms, _))) = best {
+            used[i] = true;
+            offsets.push(ms - ref_ms);
+        }
+    }
+
+    let correct = offsets.len();
+    let missed = reference.len() - correct;
+    let extra = used.iter().filter(|u| !**u).count();
+    let mean = |values: &mut dyn Iterator<Item = f64>| -> f64 {
+        if correct == 0 { 0.0 } else { values.sum::<f64>() / correct as f64 }
+    };
+    let mean_offset_ms = mean(&mut offsets.iter().copied());
+    let mean_deviation_ms = mean(&mut offsets.iter().map(|o| o.abs()));
+
+    // Wrong notes count against the score just like missed ones
+    let denominator = (reference.len() + extra).max(1);
+    let score_percent = correct as f64 * 100.0 / denominator as f64;
+
+    RunSummary { correct, missed, extra, mean_offset_ms, mean_deviation_ms, score_percent }
+}
@@ -0,0 +1,121 @@
+// Note processors: pluggable effects and game adapters that sit in front of routing. A
+// processor sees each note on/off and writes the notes to play instead: nothing to swallow
+// it, several to add notes. Enabled processors run in the order they were added, each one
+// fed the previous one's output. Register them with `Engine::add_processor`.
+//
+// This trait is the stable surface for third-party code: new methods only ever get added
+// with a default body.
+
+/// One note on or off. A note-off is velocity 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteEvent {
+    pub channel: u8, // 0-15
+    pub note: u8,
+    pub velocity: u8,
+}
+
+impl NoteEvent {
+    pub fn on(channel: u8, note: u8, velocity: u8) -> Self {
+        Self { channel, note, velocity }
+    }
+
+    pub fn off(channel: u8, note: u8) -> Self {
+        Self { channel, note, velocity: 0 }
+    }
+
+    /// Note on/off messages only (note-on with velocity 0 counts as off).
+    pub fn from_message(message: &[u8]) -> Option<Self> {
+        let &[status, note, velocity, ..] = message else { return None };
+        match status & 0xF0 {
+            0x90 => Some(Self { channel: status & 0x0F, note, velocity }),
+            0x80 => Some(Self::off(status & 0x0F, note)),
+            _ => None,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.velocity > 0
+    }
+
+    pub fn to_message(&self) -> [u8; 3] {
+        if self.is_on() {
+            [0x90 | self.channel, self.note, self.velocity]
+        } else {
+            [0x80 | self.channel, self.note, 0]
+        }
+    }
+}
+
+/// A user-facing setting; the GUI draws a checkbox or slider for each.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    Bool(bool),
+    Int { value: i64, min: i64, max: i64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub value: ParamValue,
+}
+
+pub trait Processor: Send {
+    /// Shown in the UI.
+    fn name(&self) -> &str;
+
+    /// Handles one note. `at_us` is the engine clock; push whatever should be played to `out`.
+    fn process(&mut self, at_us: u64, event: NoteEvent, out: &mut Vec<NoteEvent>);
+
+    /// Drops held notes and other state, e.g. on disconnect.
+    fn reset(&mut self) {}
+
+    /// Current settings, for the UI.
+    fn params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+
+    /// Called with one of the names from `params` when the user changes it.
+    fn set_param(&mut self, _name: &str, _value: ParamValue) {}
+}
+
+pub struct ProcessorSlot {
+    pub processor: Box<dyn Processor>,
+    pub enabled: bool,
+}
+
+#[derive(Default)]
+pub struct ProcessorChain {
+    pub slots: Vec<ProcessorSlot>,
+}
+
+impl ProcessorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, processor: Box<dyn Processor>) {
+        self.slots.push(ProcessorSlot { processor, enabled: true });
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.slots.iter().any(|s| s.enabled)
+    }
+
+    pub fn process(&mut self, at_us: u64, event: NoteEvent) -> Vec<NoteEvent> {
+        let mut events = vec![event];
+        for slot in self.slots.iter_mut().filter(|s| s.enabled) {
+            let mut out = Vec::new();
+            for event in events {
+                slot.processor.process(at_us, event, &mut out);
+            }
+            events = out;
+        }
+        events
+    }
+
+    pub fn reset(&mut self) {
+        for slot in &mut self.slots {
+            slot.processor.reset();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// The quantizer's grid: notes wait for its next line before they play. In milliseconds
+// mode the lines are every so many milliseconds of the clock; in tempo mode they're
+// 8ths, 16ths or triplets of a tempo, counted from where the grid was anchored (playback
+// starting, the first note, or the last tap of the tempo). Swing delays every other line
+// (the off-beats) for a shuffle feel.
+
+pub const MIN_BPM: u64 = 20;
+pub const MAX_BPM: u64 = 300;
+/// Most grid lines per beat: 8 is 32nd notes.
+pub const MAX_SUBDIVISION: u64 = 8;
+/// Swing is the share of each pair of lines the first one gets: 50% is straight, 66% a
+/// triplet shuffle.
+pub const MIN_SWING: u64 = 50;
+pub const MAX_SWING: u64 = 75;
+
+// Taps further apart than this start a new tempo
+const TAP_TIMEOUT_US: u64 = 2_000_000;
+// Taps averaged for the tempo
+const TAP_WINDOW: usize = 8;
+
+/// A note value for `subdivision` lines per beat, for the UI.
+pub fn subdivision_label(subdivision: u64) -> &'static str {
+    match subdivision {
+        1 => "Quarters",
+        2 => "8ths",
+        3 => "Triplet 8ths",
+        4 => "16ths",
+        6 => "Triplet 16ths",
+        8 => "32nds",
+        _ => "Lines",
+    }
+}
+
+/// Grid lines every `step_us`, one of them at `anchor_us`; every other line from there is
+/// `swing_us` late.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Grid {
+    pub step_us: u64,
+    pub anchor_us: u64,
+    pub swing_us: u64,
+}
+
+impl Grid {
+    pub fn millis(ms: u64) -> Self {
+        Self { step_us: ms * 1000, anchor_us: 0, swing_us: 0 }
+    }
+
+    pub fn tempo(bpm: u64, subdivision: u64, anchor_us: u64) -> Self {
+        Self { step_us: 60_000_000 / (bpm.clamp(MIN_BPM, MAX_BPM) * subdivision.clamp(1, MAX_SUBDIVISION)), anchor_us, swing_us: 0 }
+    }
+
+    /// The grid with `percent` swing, from `MIN_SWING` (straight) to `MAX_SWING`.
+    pub fn swung(self, percent: u64) -> Self {
+        Self { swing_us: self.step_us * 2 * (percent.clamp(MIN_SWING, MAX_SWING) - MIN_SWING) / 100, ..self }
+    }
+
+    /// How long from `now_us` to the next line; 0 on a line.
+    pub fn wait_us(&self, now_us: u64) -> u64 {
+        if now_us < self.anchor_us {
+            return self.anchor_us - now_us;
+        }
+        let step_us = self.step_us.max(1);
+        let offbeat_us = step_us + self.swing_us;
+        match (now_us - self.anchor_us) % (step_us * 2) {
+            0 => 0,
+            into if into <= offbeat_us => offbeat_us - into,
+            into => step_us * 2 - into,
+        }
+    }
+
+    /// The first line after `now_us`, even when `now_us` is on one.
+    pub fn next_after(&self, now_us: u64) -> u64 {
+        now_us + 1 + self.wait_us(now_us + 1)
+    }
+}
+
+/// Tempo from taps of a button: the average gap between the last few.
+#[derive(Default)]
+pub struct TapTempo {
+    taps: Vec<u64>,
+}
+
+impl TapTempo {
+    /// A tap at `at_us`, and the tempo so far once there are two.
+    pub fn tap(&mut self, at_us: u64) -> Option<u64> {
+        if self.taps.last().is_some_and(|last| at_us.saturating_sub(*last) > TAP_TIMEOUT_US) {
+            self.taps.clear();
+        }
+        self.taps.push(at_us);
+        if self.taps.len() > TAP_WINDOW {
+            self.taps.remove(0);
+        }
+        let (first, last) = (*self.taps.first()?, *self.taps.last()?);
+        let gaps = self.taps.len() as u64 - 1;
+        if gaps == 0 || last == first {
+            return None;
+        }
+        Some((60_000_000 * gaps / (last - first)).clamp(MIN_BPM, MAX_BPM))
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use evdev::KeyCode;
+
+// The computer keyboard as the game sees it, for the visualizer's keyboard view: every key
+// a mapping or the engine can press, laid out like a QWERTY board with the arrows and page
+// keys off to the right. Positions are in key widths (x) and rows (y).
+
+pub const WIDTH: f32 = 16.0;
+pub const ROWS: u8 = 5;
+
+// How long a tap stays lit; taps are far too short to see otherwise
+pub const FLASH_US: u64 = 150_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutKey {
+    pub key: KeyCode,
+    pub label: &'static str,
+    pub row: u8,
+    pub x: f32,
+    pub width: f32,
+}
+
+const NUMBERS: [(KeyCode, &str); 12] = [
+    (KeyCode::KEY_1, "1"),
+    (KeyCode::KEY_2, "2"),
+    (KeyCode::KEY_3, "3"),
+    (KeyCode::KEY_4, "4"),
+    (KeyCode::KEY_5, "5"),
+    (KeyCode::KEY_6, "6"),
+    (KeyCode::KEY_7, "7"),
+    (KeyCode::KEY_8, "8"),
+    (KeyCode::KEY_9, "9"),
+    (KeyCode::KEY_0, "0"),
+    (KeyCode::KEY_MINUS, "-"),
+    (KeyCode::KEY_EQUAL, "="),
+];
+const TOP: [(KeyCode, &str); 12] = [
+    (KeyCode::KEY_Q, "Q"),
+    (KeyCode::KEY_W, "W"),
+    (KeyCode::KEY_E, "E"),
+    (KeyCode::KEY_R, "R"),
+    (KeyCode::KEY_T, "T"),
+    (KeyCode::KEY_Y, "Y"),
+    (KeyCode::KEY_U, "U"),
+    (KeyCode::KEY_I, "I"),
+    (KeyCode::KEY_O, "O"),
+    (KeyCode::KEY_P, "P"),
+    (KeyCode::KEY_LEFTBRACE, "["),
+    (KeyCode::KEY_RIGHTBRACE, "]"),
+];
+const HOME: [(KeyCode, &str); 11] = [
+    (KeyCode::KEY_A, "A"),
+    (KeyCode::KEY_S, "S"),
+    (KeyCode::KEY_D, "D"),
+    (KeyCode::KEY_F, "F"),
+    (KeyCode::KEY_G, "G"),
+    (KeyCode::KEY_H, "H"),
+    (KeyCode::KEY_J, "J"),
+    (KeyCode::KEY_K, "K"),
+    (KeyCode::KEY_L, "L"),
+    (KeyCode::KEY_SEMICOLON, ";"),
+    (KeyCode::KEY_APOSTROPHE, "'"),
+];
+const BOTTOM: [(KeyCode, &str); 10] = [
+    (KeyCode::KEY_Z, "Z"),
+    (KeyCode::KEY_X, "X"),
+    (KeyCode::KEY_C, "C"),
+    (KeyCode::KEY_V, "V"),
+    (KeyCode::KEY_B, "B"),
+    (KeyCode::KEY_N, "N"),
+    (KeyCode::KEY_M, "M"),
+    (KeyCode::KEY_COMMA, ","),
+    (KeyCode::KEY_DOT, "."),
+    (KeyCode::KEY_SLASH, "/"),
+];
+
+/// Every key of the view, row by row.
+pub fn layout() -> Vec<LayoutKey> {
+    let mut keys = Vec::new();
+    let mut row = |row: u8, start: f32, letters: &[(KeyCode, &'static str)]| {
+        for (i, (key, label)) in letters.iter().enumerate() {
+            keys.push(LayoutKey { key: *key, label, row, x: start + i as f32, width: 1.0 });
+        }
+    };
+    row(0, 0.0, &NUMBERS);
+    row(1, 0.5, &TOP);
+    row(2, 0.75, &HOME);
+    row(3, 2.25, &BOTTOM);
+    let mut other = |key, label, row, x, width| keys.push(LayoutKey { key, label, row, x, width });
+    other(KeyCode::KEY_LEFTSHIFT, "Shift", 3, 0.0, 2.25);
+    other(KeyCode::KEY_RIGHTSHIFT, "Shift", 3, 12.25, 1.75);
+    other(KeyCode::KEY_LEFTCTRL, "Ctrl", 4, 0.0, 1.5);
+    other(KeyCode::KEY_LEFTALT, "Alt", 4, 1.5, 1.25);
+    other(KeyCode::KEY_SPACE, "Space", 4, 2.75, 6.25);
+    other(KeyCode::KEY_RIGHTALT, "Alt", 4, 9.0, 1.25);
+    other(KeyCode::KEY_RIGHTCTRL, "Ctrl", 4, 10.25, 1.5);
+    other(KeyCode::KEY_PAGEUP, "PgUp", 0, 14.5, 1.5);
+    other(KeyCode::KEY_PAGEDOWN, "PgDn", 1, 14.5, 1.5);
+    other(KeyCode::KEY_UP, "↑", 3, 14.0, 1.0);
+    other(KeyCode::KEY_LEFT, "←", 4, 13.0, 1.0);
+    other(KeyCode::KEY_DOWN, "↓", 4, 14.0, 1.0);
+    other(KeyCode::KEY_RIGHT, "→", 4, 15.0, 1.0);
+    keys
+}
+
+/// Which keys were pressed lately, from the router's count of key-downs per key: a key
+/// lights when its count moves and stays lit for `FLASH_US`, so taps show up too.
+#[derive(Default)]
+pub struct KeyLights {
+    seen: HashMap<KeyCode, (u64, u64)>, // Key-downs as last seen, and when they last moved
+}
+
+impl KeyLights {
+    pub fn update(&mut self, key_downs: &HashMap<KeyCode, u64>, now_us: u64) {
+        for (key, &count) in key_downs {
+            let seen = self.seen.entry(*key).or_insert((count, now_us));
+            if seen.0 != count {
+                *seen = (count, now_us);
+            }
+        }
+    }
+
+    pub fn is_lit(&self, key: KeyCode, now_us: u64) -> bool {
+        self.seen.get(&key).is_some_and(|(_, at_us)| now_us < at_us + FLASH_US)
+    }
+
+    /// Whether anything is still lit, i.e. the view needs redrawing to let it go dark.
+    pub fn any_lit(&self, now_us: u64) -> bool {
+        self.seen.keys().any(|key| self.is_lit(*key, now_us))
+    }
+}
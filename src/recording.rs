@@ -0,0 +1,131 @@
+use evdev::{EventType, InputEvent, KeyCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::error::{Error, Result};
+use crate::Engine;
+
+// Key-event session recording: every key event sent to the output, one JSON object per line.
+// `at_us` is relative to the start of the recording.
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub at_us: u64,
+    pub code: u16,
+    pub key: String, // Human readable, e.g. "KEY_A". Informational only.
+    pub value: i32,  // 1 = press, 0 = release
+}
+
+pub struct KeyRecorder {
+    started: Instant,
+    writer: LineWriter<File>,
+    path: PathBuf,
+}
+
+impl KeyRecorder {
+    /// Starts `recordings/keys-<unix>.jsonl` in the config dir.
+    pub fn start() -> Result<Self> {
+        let dir = config::config_dir().join("recordings");
+        fs::create_dir_all(&dir)?;
+        let unix = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = dir.join(format!("keys-{}.jsonl", unix));
+        // LineWriter flushes per event, so a crash still leaves a usable file
+        let writer = LineWriter::new(File::create(&path)?);
+        Ok(Self { started: Instant::now(), writer, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, events: &[InputEvent]) {
+        let at_us = self.started.elapsed().as_micros() as u64;
+        for event in events.iter().filter(|e| e.event_type() == EventType::KEY) {
+            let entry = RecordedEvent {
+                at_us,
+                code: event.code(),
+                key: format!("{:?}", KeyCode::new(event.code())),
+                value: event.value(),
+            };
+            if let Ok(line) = serde_json::to_string(&entry) {
+                let _ = writeln!(self.writer, "{}", line);
+            }
+        }
+    }
+}
+
+/// Reads a recording back. Blank lines are ignored; anything else malformed is an error.
+pub fn load(path: &Path) -> Result<Vec<RecordedEvent>> {
+    let data = fs::read_to_string(path)?;
+    let mut events = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: RecordedEvent = serde_json::from_str(line).map_err(|e| Error::Parse(format!("Line {}: {}", i + 1, e)))?;
+        events.push(event);
+    }
+    events.sort_by_key(|e| e.at_us);
+    Ok(events)
+}
+
+// Time to switch focus to the game after pressing Play
+pub const REPLAY_LEAD_IN: Duration = Duration::from_secs(3);
+// Longest sleep between stop checks
+const REPLAY_POLL: Duration = Duration::from_millis(20);
+
+/// Plays a recording through the output with its original timing, starting from the first
+/// event. Keys still held when it ends or is stopped get released.
+pub fn spawn_replay(shared: Arc<Engine>, events: Vec<RecordedEvent>) {
+    shared.replay_stop.store(false, Ordering::Relaxed);
+    shared.replay_running.store(true, Ordering::Relaxed);
+
+    thread::spawn(move || {
+        let first_us = events.first().map(|e| e.at_us).unwrap_or(0);
+        let start = Instant::now() + REPLAY_LEAD_IN;
+        let mut held: HashSet<u16> = HashSet::new();
+
+        'events: for event in &events {
+            let due = start + Duration::from_micros(event.at_us - first_us);
+            loop {
+                if shared.replay_stop.load(Ordering::Relaxed) {
+                    break 'events;
+                }
+                let now = Instant::now();
+                if now >= due {
+                    break;
+                }
+                thread::sleep((due - now).min(REPLAY_POLL));
+            }
+
+            if event.value == 0 {
+                held.remove(&event.code);
+            } else {
+                held.insert(event.code);
+            }
+            if let Err(e) = shared.router.lock().unwrap().emit(&[InputEvent::new(EventType::KEY.0, event.code, event.value)]) {
+                eprintln!("Replay stopped: {}", e);
+                break;
+            }
+        }
+
+        let mut state = shared.router.lock().unwrap();
+        for code in held {
+            if let Err(e) = state.key_up(KeyCode::new(code)) {
+                eprintln!("Failed to release key {}: {}", code, e);
+            }
+        }
+        drop(state);
+
+        shared.replay_running.store(false, Ordering::Relaxed);
+        shared.request_repaint();
+    });
+}
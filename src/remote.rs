@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use crate::config::Settings;
+use crate::error::{Error, Result};
+use crate::midifile::MidiFile;
+use crate::{player, Engine};
+
+// Remote control: commands from outside the frontends (companion apps, stream deck plugins,
+// phone remotes) and the state they follow. Commands arrive as JSON, `{"cmd": "panic"}` or
+// `{"cmd": "transpose", "semitones": 2}`, and get `{"ok": true, ...}` or `{"ok": false,
+// "error": ...}` back. The frontend owns the MIDI connections, so connecting and
+// disconnecting are queued on the engine as `RemoteRequest`s for it to carry out.
+
+/// One command, as sent by a remote.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    Connect {
+        #[serde(default)]
+        port: Option<String>, // Part of the input's name, any case; else the selected one
+    },
+    Disconnect,
+    Solver {
+        #[serde(default)]
+        enabled: Option<bool>, // Without it the solver is toggled
+    },
+    Transpose { semitones: i32 },
+    PlayFile { path: String },
+    Stop,
+    Panic,
+    State,
+}
+
+/// What only the frontend can do, waiting in `Engine::remote_requests`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoteRequest {
+    Connect(Option<String>),
+    Disconnect,
+}
+
+/// What a remote sees of the engine.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RemoteState {
+    pub solver: bool,
+    pub transpose: i32, // The game's, from every source
+    pub active_notes: Vec<u8>,
+    pub playing: bool,
+}
+
+impl RemoteState {
+    pub fn of(engine: &Engine) -> Self {
+        Self {
+            solver: engine.solver_enabled.load(Ordering::Relaxed),
+            transpose: engine.game_transpose(),
+            active_notes: engine.snapshot().active_notes,
+            playing: engine.playback_running.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A change a connected remote is told about without asking.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Notes { active_notes: Vec<u8> },
+    Transpose { transpose: i32 },
+    Solver { enabled: bool },
+    Playing { playing: bool },
+}
+
+/// Turns successive states into the events between them; the first poll reports everything.
+#[derive(Default)]
+pub struct Watcher {
+    last: Option<RemoteState>,
+}
+
+impl Watcher {
+    pub fn poll(&mut self, engine: &Engine) -> Vec<Event> {
+        let now = RemoteState::of(engine);
+        let last = self.last.as_ref();
+        let mut events = Vec::new();
+        if last.is_none_or(|l| l.active_notes != now.active_notes) {
+            events.push(Event::Notes { active_notes: now.active_notes.clone() });
+        }
+        if last.is_none_or(|l| l.transpose != now.transpose) {
+            events.push(Event::Transpose { transpose: now.transpose });
+        }
+        if last.is_none_or(|l| l.solver != now.solver) {
+            events.push(Event::Solver { enabled: now.solver });
+        }
+        if last.is_none_or(|l| l.playing != now.playing) {
+            events.push(Event::Playing { playing: now.playing });
+        }
+        self.last = Some(now);
+        events
+    }
+}
+
+fn request(engine: &Engine, request: RemoteRequest) {
+    engine.remote_requests.lock().unwrap().push(request);
+    // The frontend looks when it's woken
+    engine.request_repaint();
+}
+
+/// Carries out `command`, returning what to answer with besides `"ok": true`.
+pub fn execute(engine: &Arc<Engine>, command: Command) -> Result<serde_json::Value> {
+    match command {
+        Command::Connect { port } => request(engine, RemoteRequest::Connect(port)),
+        Command::Disconnect => request(engine, RemoteRequest::Disconnect),
+        Command::Solver { enabled } => {
+            let enabled = enabled.unwrap_or(!engine.solver_enabled.load(Ordering::Relaxed));
+            engine.solver_enabled.store(enabled, Ordering::Relaxed);
+            engine.request_repaint();
+            return Ok(serde_json::json!({ "solver": enabled }));
+        }
+        Command::Transpose { semitones } => engine.set_transpose(semitones)?,
+        Command::PlayFile { path } => {
+            if engine.playback_running.load(Ordering::Relaxed) {
+                return Err(Error::Parse("a file is already playing; stop it first".to_string()));
+            }
+            let file = MidiFile::load(Path::new(&path))?;
+            player::spawn(engine.clone(), &file);
+            return Ok(serde_json::json!({ "file": file.name }));
+        }
+        Command::Stop => engine.playback_stop.store(true, Ordering::Relaxed),
+        Command::Panic => engine.panic()?,
+        Command::State => return Ok(serde_json::to_value(RemoteState::of(engine)).unwrap_or_default()),
+    }
+    Ok(serde_json::json!({}))
+}
+
+/// Parses one JSON command.
+pub fn parse(text: &str) -> Result<Command> {
+    serde_json::from_str(text).map_err(|e| Error::Parse(format!("bad command: {}", e)))
+}
+
+/// The JSON answer for how a command went.
+pub fn reply(result: Result<serde_json::Value>) -> String {
+    let reply = match result {
+        Ok(mut value) => {
+            if let Some(fields) = value.as_object_mut() {
+                fields.insert("ok".to_string(), true.into());
+            }
+            value
+        }
+        Err(e) => serde_json::json!({ "ok": false, "error": e.to_string() }),
+    };
+    reply.to_string()
+}
+
+/// Parses and carries out one JSON command, answering in JSON.
+pub fn handle(engine: &Arc<Engine>, text: &str) -> String {
+    reply(parse(text).and_then(|command| execute(engine, command)))
+}
+
+/// Changes the settings named in `patch`, a JSON object of setting names and values, and
+/// keeps the rest. Returns the settings now in effect.
+pub fn patch_settings(engine: &Engine, patch: &serde_json::Value) -> Result<Settings> {
+    let mut settings = serde_json::to_value(engine.settings()).map_err(|e| Error::Parse(e.to_string()))?;
+    let (Some(fields), Some(patch)) = (settings.as_object_mut(), patch.as_object()) else {
+        return Err(Error::Parse("settings to change go in a JSON object".to_string()));
+    };
+    for (name, value) in patch {
+        let Some(field) = fields.get_mut(name) else { return Err(Error::Parse(format!("there's no setting '{}'", name))) };
+        *field = value.clone();
+    }
+    let settings: Settings = serde_json::from_value(settings).map_err(|e| Error::Parse(format!("bad setting: {}", e)))?;
+    engine.apply_settings(&settings);
+    engine.request_repaint();
+    Ok(settings)
+}
+
+/// Flips the on/off setting `name`. Returns its new value.
+pub fn toggle_setting(engine: &Engine, name: &str) -> Result<bool> {
+    let settings = serde_json::to_value(engine.settings()).map_err(|e| Error::Parse(e.to_string()))?;
+    let on = settings.get(name).and_then(|v| v.as_bool()).ok_or_else(|| Error::Parse(format!("'{}' isn't an on/off setting", name)))?;
+    patch_settings(engine, &serde_json::json!({ name: !on }))?;
+    Ok(!on)
+}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+use crate::midifile::FileEvent;
+
+// Note history for the piano-roll visualizer: when each input note started and ended, on
+// the engine's clock, for the last few seconds. During file playback the roll also shows
+// what's coming, straight from the song's events (`upcoming`).
+
+// How far back the history goes, and a cap for very busy input
+const KEEP_US: u64 = 10_000_000;
+const MAX_SPANS: usize = 4096;
+
+/// One note from its note-on to its note-off; `end_us` is None while it's still held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteSpan {
+    pub note: u8,
+    pub channel: u8,
+    pub start_us: u64,
+    pub end_us: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct NoteHistory {
+    spans: VecDeque<NoteSpan>,
+}
+
+impl NoteHistory {
+    pub fn note_on(&mut self, at_us: u64, channel: u8, note: u8) {
+        // A note struck again without a note-off ends where the new one starts
+        self.note_off(at_us, channel, note);
+        self.spans.push_back(NoteSpan { note, channel, start_us: at_us, end_us: None });
+        while self.spans.len() > MAX_SPANS
+            || self.spans.front().is_some_and(|s| s.end_us.is_some_and(|end| end + KEEP_US < at_us))
+        {
+            self.spans.pop_front();
+        }
+    }
+
+    pub fn note_off(&mut self, at_us: u64, channel: u8, note: u8) {
+        if let Some(span) = self.spans.iter_mut().rev().find(|s| s.note == note && s.channel == channel && s.end_us.is_none()) {
+            span.end_us = Some(at_us);
+        }
+    }
+
+    /// Every span still sounding at or after `from_us`, oldest first.
+    pub fn since(&self, from_us: u64) -> Vec<NoteSpan> {
+        self.spans.iter().filter(|s| s.end_us.is_none_or(|end| end >= from_us)).copied().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.clear();
+    }
+}
+
+/// The notes of a song starting between `from_us` and `to_us` (file time), each ending at
+/// its note-off, or None if the song never lets go of it.
+pub fn upcoming(events: &[FileEvent], from_us: u64, to_us: u64) -> Vec<NoteSpan> {
+    let first = events.partition_point(|e| e.at_us < from_us);
+    let mut spans = Vec::new();
+    for (i, event) in events.iter().enumerate().skip(first) {
+        if event.at_us > to_us {
+            break;
+        }
+        if !event.is_note_on() {
+            continue;
+        }
+        let ends = |e: &&FileEvent| e.channel() == event.channel() && e.note() == event.note() && matches!(e.message[0] & 0xF0, 0x80 | 0x90);
+        let end_us = events[i + 1..].iter().find(ends).map(|e| e.at_us);
+        spans.push(NoteSpan { note: event.note(), channel: event.channel(), start_us: event.at_us, end_us });
+    }
+    spans
+}
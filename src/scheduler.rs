@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::RollDirection;
+use crate::processor::NoteEvent;
+use crate::stress::Rng;
+use crate::Engine;
+
+// The output scheduler: notes held back to play a little later, without sleeping in the
+// MIDI callback or the worker. Three things hold notes:
+//
+// - The quantizer: a note-on off the grid waits for its next line.
+// - The humanizer: each note-on waits a random few milliseconds, so playing doesn't come out
+//   machine-perfect and a chord's notes come out in a shuffled order. The random sequence
+//   is the same every run, so offline simulations stay reproducible.
+// - The chord roll: note-ons struck together are collected for `CHORD_WINDOW_US`, then
+//   spread over the roll time from the lowest note up (or the highest down), like a harp.
+//
+// A note-off waits for its note-on, so a short note never comes out backwards: while it's
+// held back, and while it's being played. The scheduler thread, the worker and the player
+// can all be playing due notes at once, so a note-on handed out stays in flight until it's
+// been played, and its note-off isn't handed out before then.
+
+/// Most delay the humanize setting allows.
+pub const MAX_HUMANIZE_MS: u64 = 50;
+/// Longest chord roll the setting allows.
+pub const MAX_CHORD_ROLL_MS: u64 = 80;
+/// Note-ons this close together are one chord for the roll.
+pub const CHORD_WINDOW_US: u64 = 20_000;
+
+// How often the player looks for held notes that are due
+const SCHEDULER_POLL: Duration = Duration::from_millis(1);
+
+/// How a held note-on is spread out.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spread {
+    pub humanize_us: u64, // Most random delay
+    pub roll_us: u64,     // A chord's first note to its last; 0 is no roll
+    pub roll: RollDirection,
+}
+
+// Note-ons still coming in for a roll
+struct Chord {
+    start_us: u64,
+    spread: Spread,
+    notes: Vec<(NoteEvent, Instant)>,
+    offs: Vec<(NoteEvent, Instant)>, // Let go before the roll even started
+}
+
+pub struct Scheduler {
+    rng: Rng,
+    held: Vec<(u64, NoteEvent, Instant)>, // Due time, the note, and when it was received; by due time
+    chord: Option<Chord>,
+    in_flight: Vec<NoteEvent>, // Note-ons handed out by take_due and not played yet
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self { rng: Rng::new(1), held: Vec::new(), chord: None, in_flight: Vec::new() }
+    }
+}
+
+impl Scheduler {
+    /// Holds `event`, which would have played at `at_us`: a note-on as `spread` says, a
+    /// note-off until its note-on is out.
+    pub fn hold(&mut self, event: NoteEvent, at_us: u64, received_at: Instant, spread: Spread) {
+        if !event.is_on() {
+            if let Some(chord) = &mut self.chord
+                && chord.notes.iter().any(|(on, _)| same_note(*on, event))
+            {
+                chord.offs.push((event, received_at));
+                return;
+            }
+            let due_us = self.due_of_on(event).map_or(at_us, |on_us| at_us.max(on_us));
+            self.insert(due_us, event, received_at);
+            return;
+        }
+        if spread.roll_us == 0 {
+            let due_us = at_us + self.rng.below(spread.humanize_us + 1);
+            self.insert(due_us, event, received_at);
+            return;
+        }
+        if let Some(chord) = &mut self.chord
+            && at_us < chord.start_us + CHORD_WINDOW_US
+        {
+            chord.notes.push((event, received_at));
+            return;
+        }
+        self.close_chord();
+        self.chord = Some(Chord { start_us: at_us, spread, notes: vec![(event, received_at)], offs: Vec::new() });
+    }
+
+    /// The held notes due by `now_us`, in order. The note-ons are in flight until `played`;
+    /// a note-off whose note-on is still in flight from an earlier call stays held.
+    pub fn take_due(&mut self, now_us: u64) -> Vec<(NoteEvent, Instant)> {
+        if self.chord.as_ref().is_some_and(|chord| chord.start_us + CHORD_WINDOW_US <= now_us) {
+            self.close_chord();
+        }
+        let due = self.held.partition_point(|(due_us, _, _)| *due_us <= now_us);
+        let earlier = self.in_flight.len();
+        let mut taken = Vec::new();
+        let mut kept = Vec::new();
+        for (due_us, event, received_at) in self.held.drain(..due) {
+            if !event.is_on() && self.in_flight[..earlier].iter().any(|on| same_note(*on, event)) {
+                kept.push((due_us, event, received_at));
+                continue;
+            }
+            if event.is_on() {
+                self.in_flight.push(event);
+            }
+            taken.push((event, received_at));
+        }
+        self.held.splice(..0, kept);
+        taken
+    }
+
+    /// Marks a note handed out by `take_due` as played.
+    pub fn played(&mut self, event: NoteEvent) {
+        if let Some(index) = self.in_flight.iter().position(|on| *on == event) {
+            self.in_flight.remove(index);
+        }
+    }
+
+    /// Whether the note-on `event` is for (or lets go of) is held back, waiting for a roll or
+    /// still being played.
+    pub fn holds_note(&self, event: NoteEvent) -> bool {
+        let in_chord = self.chord.as_ref().is_some_and(|chord| chord.notes.iter().any(|(on, _)| same_note(*on, event)));
+        in_chord || self.due_of_on(event).is_some() || self.in_flight.iter().any(|on| same_note(*on, event))
+    }
+
+    /// When the next held note is due, or the chord being collected is rolled out.
+    pub fn next_due(&self) -> Option<u64> {
+        let held = self.held.first().map(|(due_us, _, _)| *due_us);
+        let chord = self.chord.as_ref().map(|chord| chord.start_us + CHORD_WINDOW_US);
+        held.into_iter().chain(chord).min()
+    }
+
+    /// Drops every held note, e.g. on panic.
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.chord = None;
+        self.in_flight.clear();
+    }
+
+    // Lays the collected chord out over the roll time, in pitch order
+    fn close_chord(&mut self) {
+        let Some(mut chord) = self.chord.take() else { return };
+        chord.notes.sort_by_key(|(event, _)| event.note);
+        if chord.spread.roll == RollDirection::Down {
+            chord.notes.reverse();
+        }
+        let start_us = chord.start_us + CHORD_WINDOW_US;
+        let gaps = (chord.notes.len() as u64 - 1).max(1);
+        for (i, (event, received_at)) in chord.notes.into_iter().enumerate() {
+            let due_us = start_us + chord.spread.roll_us * i as u64 / gaps + self.rng.below(chord.spread.humanize_us + 1);
+            self.insert(due_us, event, received_at);
+        }
+        for (event, received_at) in chord.offs {
+            let due_us = self.due_of_on(event).unwrap_or(start_us);
+            self.insert(due_us, event, received_at);
+        }
+    }
+
+    // When the held note-on for the same note as `event` is due
+    fn due_of_on(&self, event: NoteEvent) -> Option<u64> {
+        let held_on = self.held.iter().rev().find(|(_, held, _)| held.is_on() && held.channel == event.channel && held.note == event.note);
+        held_on.map(|(due_us, _, _)| *due_us)
+    }
+
+    fn insert(&mut self, due_us: u64, event: NoteEvent, received_at: Instant) {
+        // After everything due at the same time, so a note-off stays behind its note-on
+        let index = self.held.partition_point(|(held_us, _, _)| *held_us <= due_us);
+        self.held.insert(index, (due_us, event, received_at));
+    }
+}
+
+fn same_note(a: NoteEvent, b: NoteEvent) -> bool {
+    a.channel == b.channel && a.note == b.note
+}
+
+/// Plays held notes as they fall due. Only needed on the real clock; offline runs play them
+/// from `Engine::advance_to`.
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || loop {
+        thread::sleep(SCHEDULER_POLL);
+        if let Err(e) = shared.flush_scheduled()
+            && !e.is_dropped_note()
+        {
+            eprintln!("Scheduler: {}", e);
+        }
+    });
+}
@@ -0,0 +1,190 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use evdev::KeyCode;
+use rhai::{AST, Array, CallFnOptions, Dynamic, Engine as Rhai, EvalAltResult, Map, Scope};
+
+use crate::error::{Error, Result};
+
+// User scripts (Rhai) that see every MIDI message before the engine does. The script defines
+// `on_midi(event, ctx)`; `event` is a map describing the message and `ctx` a copy of what's
+// playing (`active_notes`, `transpose`, `at_us`). What it returns decides what's played:
+//
+//   nothing or `true`   the message as it came
+//   `false`             nothing
+//   a map               that message instead (change `event` and hand it back)
+//   an array of maps    each of them, in order
+//
+// Event maps have a `kind`: "note_on"/"note_off" with `channel` (0-15), `note` and
+// `velocity`, "cc" with `channel`, `controller` and `value`, or "other" with the raw `bytes`.
+// `note_on(ch, note, vel)`, `note_off(ch, note)` and `cc(ch, controller, value)` build them,
+// and `tap("KEY_F")` a key tap for macros. `this` is a map kept between calls, for scripts
+// that need to remember something (an arpeggiator's step, say).
+
+// A script that loops forever shouldn't hang playing; this is far more than any sane hook needs
+const MAX_OPERATIONS: u64 = 200_000;
+
+/// What a script asked for in answer to one message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptAction {
+    Midi(Vec<u8>),
+    Tap(KeyCode),
+}
+
+/// The read-only context a script sees next to each event.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptContext {
+    pub at_us: u64,
+    pub active_notes: Vec<u8>,
+    pub transpose: i32,
+}
+
+pub struct Script {
+    rhai: Rhai,
+    ast: AST,
+    takes_context: bool,
+    state: Dynamic, // `this` inside the script
+    pub last_error: Option<String>, // The latest runtime error; the message played unchanged
+}
+
+fn message_map(message: &[u8]) -> Map {
+    let mut map = Map::new();
+    let mut set = |name: &str, value: Dynamic| {
+        map.insert(name.into(), value);
+    };
+    match *message {
+        [status, note, velocity, ..] if status & 0xF0 == 0x90 && velocity > 0 => {
+            set("kind", "note_on".into());
+            set("channel", Dynamic::from_int((status & 0x0F).into()));
+            set("note", Dynamic::from_int(note.into()));
+            set("velocity", Dynamic::from_int(velocity.into()));
+        }
+        [status, note, ..] if matches!(status & 0xF0, 0x80 | 0x90) => {
+            set("kind", "note_off".into());
+            set("channel", Dynamic::from_int((status & 0x0F).into()));
+            set("note", Dynamic::from_int(note.into()));
+            set("velocity", Dynamic::from_int(0));
+        }
+        [status, controller, value, ..] if status & 0xF0 == 0xB0 => {
+            set("kind", "cc".into());
+            set("channel", Dynamic::from_int((status & 0x0F).into()));
+            set("controller", Dynamic::from_int(controller.into()));
+            set("value", Dynamic::from_int(value.into()));
+        }
+        _ => {
+            set("kind", "other".into());
+            set("bytes", message.iter().map(|b| Dynamic::from_int((*b).into())).collect::<Array>().into());
+        }
+    }
+    map
+}
+
+fn field(map: &Map, name: &str, max: i64) -> Result<u8> {
+    let value = map.get(name).and_then(|v| v.as_int().ok()).ok_or_else(|| Error::Parse(format!("script event without a number for '{}'", name)))?;
+    if !(0..=max).contains(&value) {
+        return Err(Error::Parse(format!("script event {} {} is out of range 0-{}", name, value, max)));
+    }
+    Ok(value as u8)
+}
+
+fn action(value: Dynamic) -> Result<ScriptAction> {
+    let map = value.try_cast::<Map>().ok_or_else(|| Error::Parse("script returned something that isn't an event".to_string()))?;
+    let kind = map.get("kind").map(|k| k.to_string()).unwrap_or_default();
+    let channel = || field(&map, "channel", 15);
+    Ok(match kind.as_str() {
+        "note_on" => ScriptAction::Midi(vec![0x90 | channel()?, field(&map, "note", 127)?, field(&map, "velocity", 127)?.max(1)]),
+        "note_off" => ScriptAction::Midi(vec![0x80 | channel()?, field(&map, "note", 127)?, 0]),
+        "cc" => ScriptAction::Midi(vec![0xB0 | channel()?, field(&map, "controller", 127)?, field(&map, "value", 127)?]),
+        "tap" => {
+            let name = map.get("key").map(|k| k.to_string()).unwrap_or_default();
+            ScriptAction::Tap(KeyCode::from_str(&name).map_err(|_| Error::Parse(format!("script tapped unknown key '{}'", name)))?)
+        }
+        "other" => {
+            let bytes = map.get("bytes").and_then(|b| b.clone().try_cast::<Array>()).unwrap_or_default();
+            let bytes = bytes.into_iter().map(|b| b.as_int().ok().filter(|b| (0..=255).contains(b)).map(|b| b as u8));
+            ScriptAction::Midi(bytes.collect::<Option<Vec<u8>>>().ok_or_else(|| Error::Parse("script event bytes aren't all 0-255".to_string()))?)
+        }
+        _ => return Err(Error::Parse(format!("script event of unknown kind '{}'", kind))),
+    })
+}
+
+fn event(kind: &str, fields: &[(&str, i64)]) -> Map {
+    let mut map = Map::new();
+    map.insert("kind".into(), kind.into());
+    for (name, value) in fields {
+        map.insert((*name).into(), Dynamic::from_int(*value));
+    }
+    map
+}
+
+impl Script {
+    /// Compiles `source`; it has to define `on_midi(event)` or `on_midi(event, ctx)`.
+    pub fn compile(source: &str) -> Result<Self> {
+        let mut rhai = Rhai::new();
+        rhai.set_max_operations(MAX_OPERATIONS);
+        rhai.register_fn("note_on", |channel: i64, note: i64, velocity: i64| {
+            event("note_on", &[("channel", channel), ("note", note), ("velocity", velocity)])
+        });
+        rhai.register_fn("note_off", |channel: i64, note: i64| event("note_off", &[("channel", channel), ("note", note), ("velocity", 0)]));
+        rhai.register_fn("cc", |channel: i64, controller: i64, value: i64| {
+            event("cc", &[("channel", channel), ("controller", controller), ("value", value)])
+        });
+        rhai.register_fn("tap", |key: &str| {
+            let mut map = event("tap", &[]);
+            map.insert("key".into(), key.into());
+            map
+        });
+        let ast = rhai.compile(source).map_err(|e| Error::Parse(format!("script: {}", e)))?;
+        let arity = ast.iter_functions().find(|f| f.name == "on_midi").map(|f| f.params.len());
+        let takes_context = match arity {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(Error::Parse("script needs a function on_midi(event) or on_midi(event, ctx)".to_string())),
+        };
+        Ok(Self { rhai, ast, takes_context, state: Map::new().into(), last_error: None })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::compile(&fs::read_to_string(path)?)
+    }
+
+    /// What to play for `message`. A script error plays the message unchanged and is kept in
+    /// `last_error`.
+    pub fn run(&mut self, message: &[u8], context: &ScriptContext) -> Vec<ScriptAction> {
+        match self.call(message, context) {
+            Ok(actions) => actions,
+            Err(e) => {
+                self.last_error = Some(e.to_string());
+                vec![ScriptAction::Midi(message.to_vec())]
+            }
+        }
+    }
+
+    fn call(&mut self, message: &[u8], context: &ScriptContext) -> Result<Vec<ScriptAction>> {
+        let event: Dynamic = message_map(message).into();
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        let mut scope = Scope::new();
+        let returned: std::result::Result<Dynamic, Box<EvalAltResult>> = if self.takes_context {
+            let mut ctx = Map::new();
+            ctx.insert("at_us".into(), Dynamic::from_int(context.at_us as i64));
+            ctx.insert("transpose".into(), Dynamic::from_int(context.transpose.into()));
+            let notes: Array = context.active_notes.iter().map(|n| Dynamic::from_int((*n).into())).collect();
+            ctx.insert("active_notes".into(), notes.into());
+            self.rhai.call_fn_with_options(options, &mut scope, &self.ast, "on_midi", (event, Dynamic::from_map(ctx)))
+        } else {
+            self.rhai.call_fn_with_options(options, &mut scope, &self.ast, "on_midi", (event,))
+        };
+        let returned = returned.map_err(|e| Error::Parse(format!("script: {}", e)))?;
+        if returned.is_unit() || returned.as_bool() == Ok(true) {
+            return Ok(vec![ScriptAction::Midi(message.to_vec())]);
+        }
+        if returned.as_bool() == Ok(false) {
+            return Ok(Vec::new());
+        }
+        if returned.is_array() {
+            return returned.cast::<Array>().into_iter().map(action).collect();
+        }
+        Ok(vec![action(returned)?])
+    }
+}
@@ -0,0 +1,152 @@
+use evdev::KeyCode;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{self, ChannelRoute, ModifierRole, Settings};
+use crate::error::Result;
+use crate::mappings::MappingSet;
+use crate::midifile::MidiFile;
+use crate::pipeline::{self, Routing};
+use crate::solver::{Solver, SolverMode};
+
+// Letter sheets: the "virtual piano" notation Roblox piano communities share songs in. Each
+// note is the key that plays it, written as the character it types, so a sharp played with
+// Shift is the shifted character ("T" for Shift+T, "!" for Shift+1). Notes struck together
+// go in brackets ("[tuo]"), a space is a short rest and "|" a long one. Notes that need Ctrl
+// have no standard spelling and are written "^t"; solver transposes show up inline as "(+2)".
+//
+// Notes are placed the way the current settings would play them (range folding, or the
+// solver's plan); notes that would be dropped are left out.
+
+// Notes starting this close together are one chord
+const CHORD_US: u64 = 30_000;
+// Gaps between chords from which a space, a bar and a new line are written
+const REST_US: u64 = 150_000;
+const LONG_REST_US: u64 = 600_000;
+const LINE_BREAK_US: u64 = 2_000_000;
+
+// The character a key types, plain and with Shift (US layout)
+fn key_chars(key: KeyCode) -> Option<(char, char)> {
+    const DIGITS: [(KeyCode, char, char); 10] = [
+        (KeyCode::KEY_1, '1', '!'),
+        (KeyCode::KEY_2, '2', '@'),
+        (KeyCode::KEY_3, '3', '#'),
+        (KeyCode::KEY_4, '4', '$'),
+        (KeyCode::KEY_5, '5', '%'),
+        (KeyCode::KEY_6, '6', '^'),
+        (KeyCode::KEY_7, '7', '&'),
+        (KeyCode::KEY_8, '8', '*'),
+        (KeyCode::KEY_9, '9', '('),
+        (KeyCode::KEY_0, '0', ')'),
+    ];
+    if let Some((_, plain, shifted)) = DIGITS.iter().find(|(k, _, _)| *k == key) {
+        return Some((*plain, *shifted));
+    }
+    // Letter keys: the key name ends in the letter
+    let name = format!("{:?}", key);
+    let letter = name.strip_prefix("KEY_").filter(|l| l.len() == 1)?.chars().next()?;
+    letter.is_ascii_alphabetic().then(|| (letter.to_ascii_lowercase(), letter))
+}
+
+fn spell(key: KeyCode, shift: bool, ctrl: bool) -> Option<String> {
+    let (plain, shifted) = key_chars(key)?;
+    let c = if shift { shifted } else { plain };
+    Some(if ctrl { format!("^{}", c) } else { c.to_string() })
+}
+
+/// The letter sheet for `file` under `settings`.
+pub fn export(file: &MidiFile, settings: &Settings) -> String {
+    let routing = Routing::from(settings);
+    let mappings = MappingSet::for_settings(settings);
+    let mode = if settings.solver_mode_efficiency { SolverMode::Efficiency } else { SolverMode::Accuracy };
+    let mut solver = Solver::new();
+    solver.mappings = mappings.clone();
+    solver.key_range = settings.game_mode.key_range();
+    solver.allow_shift = pipeline::solver_allows_shift(&routing);
+    solver.allow_ctrl = settings.ctrl_role == ModifierRole::Range;
+    solver.allow_steal = !settings.solver_drop_steals;
+    solver.hold_transpose = settings.solver_hold_transpose;
+
+    // (start, what to write) per played note
+    let mut notes: Vec<(u64, String)> = Vec::new();
+    for event in &file.events {
+        let status = event.message[0] & 0xF0;
+        let is_note_on = event.is_note_on();
+        let is_note_off = status == 0x80 || (status == 0x90 && event.message[2] == 0);
+        let too_soft = is_note_on && (event.message[2] as u64) < settings.min_velocity;
+        if settings.channel_routes[event.channel() as usize] != ChannelRoute::Play || too_soft || settings.sostenuto_trigger.matches(&event.message).is_some() {
+            continue;
+        }
+        let note = pipeline::analysis_note(&routing, event.channel(), event.note());
+        if settings.solver_enabled {
+            if is_note_off {
+                solver.register_note_off(note);
+                continue;
+            }
+            if !is_note_on {
+                continue;
+            }
+            let Some((delta, mapping)) =
+                solver.solve(note, event.at_us, mode, settings.solver_max_jump as i32, settings.transpose_range as i32)
+            else {
+                continue;
+            };
+            if delta != solver.current_transpose {
+                notes.push((event.at_us, format!("({:+})", delta)));
+            }
+            solver.register_note_on(mapping.key_code, note, event.at_us, delta, mapping.shift, mapping.ctrl);
+            if let Some(text) = spell(mapping.key_code, mapping.shift, mapping.ctrl) {
+                notes.push((event.at_us, text));
+            }
+        } else if is_note_on
+            && let Ok(final_note) = pipeline::resolve_note(&routing, note)
+            && let Some(mapping) = mappings.get(final_note).filter(|m| pipeline::is_mapping_usable(&routing, m))
+            && let Some(text) = spell(mapping.key_code, mapping.shift, mapping.ctrl)
+        {
+            notes.push((event.at_us, text));
+        }
+    }
+
+    let mut out = String::new();
+    let mut chord: Vec<String> = Vec::new();
+    let mut chord_start = 0;
+    let flush = |out: &mut String, chord: &mut Vec<String>| {
+        // A transpose marker isn't part of the chord it precedes
+        let (marks, keys): (Vec<String>, Vec<String>) = chord.drain(..).partition(|t| t.starts_with('('));
+        out.push_str(&marks.concat());
+        match keys.len() {
+            0 => {}
+            1 => out.push_str(&keys[0]),
+            _ => out.push_str(&format!("[{}]", keys.concat())),
+        }
+    };
+    for (at_us, text) in notes {
+        if !chord.is_empty() && at_us - chord_start > CHORD_US {
+            flush(&mut out, &mut chord);
+            let gap = at_us - chord_start;
+            if gap >= LINE_BREAK_US {
+                out.push('\n');
+            } else if gap >= LONG_REST_US {
+                out.push_str(" | ");
+            } else if gap >= REST_US {
+                out.push(' ');
+            }
+        }
+        if chord.is_empty() {
+            chord_start = at_us;
+        }
+        chord.push(text);
+    }
+    flush(&mut out, &mut chord);
+    out.push('\n');
+    out
+}
+
+/// Writes a sheet to `<name>.txt` in the config dir's `sheets` folder.
+pub fn save(name: &str, sheet: &str) -> Result<PathBuf> {
+    let dir = config::config_dir().join("sheets");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.txt", name));
+    fs::write(&path, sheet)?;
+    Ok(path)
+}
@@ -0,0 +1,52 @@
+use nix::sys::signal::{SigSet, Signal};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::thread;
+
+use crate::Engine;
+
+// A key held down on a virtual keyboard stays down system-wide if the program goes away
+// without releasing it. Registered engines let go of their keys on Ctrl+C, SIGTERM and
+// panics; a normal exit is up to the frontend (the GUI does it when its window closes).
+
+static ENGINES: Mutex<Vec<Weak<Engine>>> = Mutex::new(Vec::new());
+
+/// Has `engine` release its keys if the program is interrupted or panics.
+pub fn register(engine: &Arc<Engine>) {
+    static PANIC_HOOK: Once = Once::new();
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            release_all();
+            previous(info);
+        }));
+    });
+    let mut engines = ENGINES.lock().unwrap_or_else(|e| e.into_inner());
+    engines.retain(|e| e.strong_count() > 0);
+    engines.push(Arc::downgrade(engine));
+}
+
+/// Takes SIGINT and SIGTERM over from the default handler, releasing every registered
+/// engine's keys before exiting. Call before starting any other thread: the signals are
+/// blocked and waited for on a thread of their own, and threads inherit that from here.
+pub fn catch_signals() {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    if signals.thread_block().is_err() {
+        return;
+    }
+    thread::spawn(move || {
+        if let Ok(signal) = signals.wait() {
+            release_all();
+            std::process::exit(128 + signal as i32);
+        }
+    });
+}
+
+/// Releases every registered engine's keys.
+pub fn release_all() {
+    let engines = ENGINES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    for engine in engines.iter().filter_map(Weak::upgrade) {
+        engine.release_on_exit();
+    }
+}
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::config::{ChannelRoute, GameMode, Settings};
+use crate::midifile::FileEvent;
+use crate::scheduler::CHORD_WINDOW_US;
+
+// Chord simplification for dense files: an orchestral score stacks the same chord across
+// a dozen instruments, far more notes than the game (or the keyboard output) can take. A
+// chord with more notes than `max_chord_notes` keeps its top and bottom notes, loses its
+// octave doublings, then loses inner voices from the bottom up until it fits, so the
+// melody and bass stay and the harmony thins out. Note-ons within `CHORD_WINDOW_US` of
+// each other on the played channels are a chord; a dropped note's note-off goes with it.
+
+/// Fewest notes a simplified chord keeps: its top and bottom.
+pub const MIN_CHORD_NOTES: u64 = 2;
+
+/// `events` with dense chords simplified as the settings ask; untouched when they don't.
+pub fn simplify<'a>(events: &'a [FileEvent], settings: &Settings) -> Cow<'a, [FileEvent]> {
+    if settings.max_chord_notes == 0 || settings.game_mode == GameMode::Drums {
+        return Cow::Borrowed(events);
+    }
+    let max = settings.max_chord_notes.max(MIN_CHORD_NOTES) as usize;
+    let played = |event: &FileEvent| event.is_note_on() && settings.channel_routes[event.channel() as usize] == ChannelRoute::Play;
+
+    let mut dropped = vec![false; events.len()];
+    let mut start = 0;
+    while start < events.len() {
+        if !played(&events[start]) {
+            start += 1;
+            continue;
+        }
+        let chord_end_us = events[start].at_us + CHORD_WINDOW_US;
+        let end = start + events[start..].partition_point(|event| event.at_us < chord_end_us);
+        let chord: Vec<usize> = (start..end).filter(|&i| played(&events[i])).collect();
+        for i in thin(&chord, events, max) {
+            dropped[i] = true;
+        }
+        start = end;
+    }
+
+    // Each dropped note-on takes the next note-off of its note with it
+    let mut offs_to_drop: HashMap<(u8, u8), usize> = HashMap::new();
+    let mut kept = Vec::with_capacity(events.len());
+    for (event, dropped) in events.iter().zip(dropped) {
+        let key = (event.channel(), event.note());
+        let status = event.message[0] & 0xF0;
+        if dropped {
+            *offs_to_drop.entry(key).or_default() += 1;
+            continue;
+        }
+        if !event.is_note_on()
+            && (status == 0x80 || status == 0x90)
+            && let Some(count) = offs_to_drop.get_mut(&key).filter(|count| **count > 0)
+        {
+            *count -= 1;
+            continue;
+        }
+        kept.push(*event);
+    }
+    Cow::Owned(kept)
+}
+
+// The note-ons of `chord` (indices into `events`) to drop to get it down to `max` notes
+fn thin(chord: &[usize], events: &[FileEvent], max: usize) -> Vec<usize> {
+    if chord.len() <= max {
+        return Vec::new();
+    }
+    let mut by_pitch = chord.to_vec();
+    by_pitch.sort_by_key(|&i| events[i].note());
+    let (bottom, top) = (by_pitch[0], by_pitch[by_pitch.len() - 1]);
+    let mut classes = vec![events[bottom].note() % 12, events[top].note() % 12];
+    let mut dropped = Vec::new();
+    // Inner voices from the top down, so the ones nearest the melody are the ones kept
+    let mut inner = Vec::new();
+    for &i in by_pitch[1..by_pitch.len() - 1].iter().rev() {
+        let class = events[i].note() % 12;
+        if classes.contains(&class) {
+            dropped.push(i);
+        } else {
+            classes.push(class);
+            inner.push(i);
+        }
+    }
+    while inner.len() + 2 > max {
+        dropped.extend(inner.pop());
+    }
+    dropped
+}
@@ -0,0 +1,126 @@
+use evdev::KeyCode;
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use crate::clock::Clock;
+use crate::config::Settings;
+use crate::error::{Error, Result};
+use crate::inspector::Decision;
+use crate::midifile::{self, FileEvent, MidiFile};
+use crate::output::{KeyOutput, SpyOutput};
+use crate::simplify;
+use crate::stats::DropReason;
+use crate::Engine;
+
+// Deterministic offline run of a MIDI file through the full engine: virtual clock (delays
+// cost nothing), spy output instead of a device. The same file and settings always give
+// the same log, so it can be diffed or snapshot-tested.
+//
+// Event streams can also be written by hand, one message per line (see `parse_events`),
+// which is what the fixtures in tests/fixtures use.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimEntry {
+    Key { at_us: u64, key: KeyCode, pressed: bool },
+    Dropped { at_us: u64, note: u8, reason: DropReason },
+}
+
+// One line per entry: "<microseconds> key KEY_T down" / "<microseconds> drop C4 OutOfRange"
+impl fmt::Display for SimEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimEntry::Key { at_us, key, pressed } => {
+                write!(f, "{} key {:?} {}", at_us, key, if *pressed { "down" } else { "up" })
+            }
+            SimEntry::Dropped { at_us, note, reason } => {
+                write!(f, "{} drop {} {:?}", at_us, midifile::note_name(*note), reason)
+            }
+        }
+    }
+}
+
+pub fn simulate(file: &MidiFile, settings: &Settings) -> Vec<SimEntry> {
+    simulate_events(&file.events, settings)
+}
+
+/// Runs timestamped MIDI messages (in time order) through a fresh engine.
+pub fn simulate_events(events: &[FileEvent], settings: &Settings) -> Vec<SimEntry> {
+    let events = simplify::simplify(events, settings);
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(settings);
+    // The inspector tells us which messages were dropped and why
+    engine.inspector_enabled.store(true, Ordering::Relaxed);
+
+    let mut log = Vec::new();
+    for event in events.iter() {
+        // Delays from earlier messages may already have pushed the clock past this event
+        let _ = engine.advance_to(event.at_us);
+        let at_us = clock.now_us();
+        // Drops show up in the inspector below, and the spy output can't fail
+        let _ = engine.handle_message(&event.message);
+
+        log.extend(
+            spy.take_timed()
+                .into_iter()
+                .map(|(at_us, key, value)| SimEntry::Key { at_us, key, pressed: value != 0 }),
+        );
+        if event.is_note_on()
+            && let Some(Decision::Dropped(reason)) = engine.inspector.lock().unwrap().entries.back().and_then(|e| e.decision.clone())
+        {
+            log.push(SimEntry::Dropped { at_us, note: event.note(), reason });
+        }
+    }
+    // Anything still scheduled (a guitar strum) goes out after the last event
+    let _ = engine.advance_to(clock.now_us() + 1_000_000);
+    log.extend(
+        spy.take_timed()
+            .into_iter()
+            .map(|(at_us, key, value)| SimEntry::Key { at_us, key, pressed: value != 0 }),
+    );
+    log
+}
+
+/// Parses a hand-written event stream: one message per line as `<microseconds> on <note>
+/// [velocity]`, `<microseconds> off <note>` or `<microseconds> cc <number> <value>`, each
+/// optionally followed by `ch <1-16>` (channel 1 otherwise). Notes are names (`C#4`) or
+/// numbers; velocity defaults to 100. Blank lines and lines starting with `#` are skipped.
+pub fn parse_events(text: &str) -> Result<Vec<FileEvent>> {
+    let mut events = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |what: &str| Error::Parse(format!("line {}: {} in '{}'", i + 1, what, line));
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let mut channel = 0u8;
+        if let [.., "ch", n] = words[..] {
+            channel = n.parse::<u8>().ok().filter(|c| (1..=16).contains(c)).ok_or_else(|| bad("bad channel"))? - 1;
+            words.truncate(words.len() - 2);
+        }
+        let at_us = words[0].parse::<u64>().map_err(|_| bad("bad time"))?;
+        let byte = |word: Option<&&str>, default: Option<u8>| match word {
+            Some(w) => w.parse::<u8>().ok().filter(|v| *v < 128).ok_or_else(|| bad("bad value")),
+            None => default.ok_or_else(|| bad("missing value")),
+        };
+        let note = |word: Option<&&str>| word.and_then(|w| midifile::parse_note(w)).ok_or_else(|| bad("bad note"));
+        let message = match words.get(1).copied() {
+            Some("on") => [0x90 | channel, note(words.get(2))?, byte(words.get(3), Some(100))?],
+            Some("off") => [0x80 | channel, note(words.get(2))?, 0],
+            Some("cc") => [0xB0 | channel, byte(words.get(2), None)?, byte(words.get(3), None)?],
+            _ => return Err(bad("expected on, off or cc")),
+        };
+        if events.last().is_some_and(|e: &FileEvent| e.at_us > at_us) {
+            return Err(bad("time goes backwards"));
+        }
+        events.push(FileEvent { at_us, message });
+    }
+    Ok(events)
+}
+
+/// The whole log as text, one entry per line.
+pub fn format_log(entries: &[SimEntry]) -> String {
+    entries.iter().map(|e| format!("{}\n", e)).collect()
+}
@@ -1,6 +1,20 @@
 use evdev::KeyCode;
-use std::collections::{HashMap, HashSet};
-use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::ops::RangeInclusive;
+
+use crate::mappings::{KeyMapping, MappingSet};
+use crate::midifile;
+
+// Each semitone of transpose is an Up/Down tap plus a short pause before the note
+pub const TRANSPOSE_TAP_US: u64 = 5_000;
+// Taps delay the note they're for. A delay up to this fraction of the gap since the last
+// note hides in the rhythm; every tap past that costs this many semitones extra, so fast
+// runs stay near the current transpose and slow passages can still jump.
+const HIDDEN_DELAY_DIVISOR: u64 = 4;
+const LATE_TAP_COST: i32 = 3;
+
+// Most recent decisions kept in the log
+const MAX_DECISIONS: usize = 500;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SolverMode {
@@ -8,168 +22,270 @@ pub enum SolverMode {
     Accuracy,   // Best accuracy
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct KeyMapping {
-    pub midi_note: u8,
-    pub key_code: KeyCode,
+/// Why a key couldn't play the note.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    OutOfRange,       // Needs more transpose than the range allows
+    TransposeHeld,    // Needs a transpose change while keys are held
+    KeyBusy,          // Held, and stealing is off
+    ModifierConflict, // Needs Shift/Ctrl the other way from the held keys
+    TooFar,           // Efficiency mode: costs more than the max jump
+}
+
+impl Rejection {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Rejection::OutOfRange => "out of range",
+            Rejection::TransposeHeld => "transpose held",
+            Rejection::KeyBusy => "key busy",
+            Rejection::ModifierConflict => "modifier conflict",
+            Rejection::TooFar => "too far",
+        }
+    }
+}
+
+/// One key the solver could have played the note on.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub transpose: i32,
+    pub key: KeyCode,
     pub shift: bool,
     pub ctrl: bool,
+    pub outcome: std::result::Result<i32, Rejection>, // Its cost, or why it was ruled out
 }
 
-// Standard key mappings
-
-#[derive(Deserialize)]
-struct JsonKeyMapping {
-    midi_note: u8,
-    key: String,
-    shift: bool,
-    ctrl: bool,
+/// A note the solver was asked to play, what it weighed and what it picked.
+#[derive(Clone, Debug)]
+pub struct SolverDecision {
+    pub at_us: u64,
+    pub note: u8,
+    pub current_transpose: i32,
+    pub planned: Option<i32>,
+    pub candidates: Vec<Candidate>,
+    pub chosen: Option<(i32, KeyCode)>,
 }
 
-fn parse_key_str(k: &str) -> KeyCode {
-    match k {
-        "KEY_1" => KeyCode::KEY_1,
-        "KEY_2" => KeyCode::KEY_2,
-        "KEY_3" => KeyCode::KEY_3,
-        "KEY_4" => KeyCode::KEY_4,
-        "KEY_5" => KeyCode::KEY_5,
-        "KEY_6" => KeyCode::KEY_6,
-        "KEY_7" => KeyCode::KEY_7,
-        "KEY_8" => KeyCode::KEY_8,
-        "KEY_9" => KeyCode::KEY_9,
-        "KEY_0" => KeyCode::KEY_0,
-        "KEY_Q" => KeyCode::KEY_Q,
-        "KEY_W" => KeyCode::KEY_W,
-        "KEY_E" => KeyCode::KEY_E,
-        "KEY_R" => KeyCode::KEY_R,
-        "KEY_T" => KeyCode::KEY_T,
-        "KEY_Y" => KeyCode::KEY_Y,
-        "KEY_U" => KeyCode::KEY_U,
-        "KEY_I" => KeyCode::KEY_I,
-        "KEY_O" => KeyCode::KEY_O,
-        "KEY_P" => KeyCode::KEY_P,
-        "KEY_A" => KeyCode::KEY_A,
-        "KEY_S" => KeyCode::KEY_S,
-        "KEY_D" => KeyCode::KEY_D,
-        "KEY_F" => KeyCode::KEY_F,
-        "KEY_G" => KeyCode::KEY_G,
-        "KEY_H" => KeyCode::KEY_H,
-        "KEY_J" => KeyCode::KEY_J,
-        "KEY_K" => KeyCode::KEY_K,
-        "KEY_L" => KeyCode::KEY_L,
-        "KEY_Z" => KeyCode::KEY_Z,
-        "KEY_X" => KeyCode::KEY_X,
-        "KEY_C" => KeyCode::KEY_C,
-        "KEY_V" => KeyCode::KEY_V,
-        "KEY_B" => KeyCode::KEY_B,
-        "KEY_N" => KeyCode::KEY_N,
-        "KEY_M" => KeyCode::KEY_M,
-        _ => KeyCode::KEY_RESERVED,
+impl SolverDecision {
+    pub fn describe(&self) -> String {
+        let chosen = match self.chosen {
+            Some((transpose, key)) => format!("{:?} at {:+}", key, transpose),
+            None => "dropped".to_string(),
+        };
+        let planned = self.planned.map(|p| format!(", plan {:+}", p)).unwrap_or_default();
+        format!("{} (at {:+}{}) -> {}", midifile::note_name(self.note), self.current_transpose, planned, chosen)
     }
 }
 
-pub fn get_available_mappings() -> Vec<KeyMapping> {
-    let json_data = include_str!("../mappings.json");
-    let json_mappings: Vec<JsonKeyMapping> = serde_json::from_str(json_data)
-        .expect("Failed to parse mappings.json");
-
-    json_mappings.into_iter().map(|m| KeyMapping {
-        midi_note: m.midi_note,
-        key_code: parse_key_str(&m.key),
-        shift: m.shift,
-        ctrl: m.ctrl,
-    }).collect()
+/// The most recent solver decisions, oldest first.
+#[derive(Default)]
+pub struct DecisionLog {
+    pub entries: VecDeque<SolverDecision>,
+}
+
+impl DecisionLog {
+    pub fn push(&mut self, decision: SolverDecision) {
+        if self.entries.len() == MAX_DECISIONS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(decision);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
 }
 
 pub struct Solver {
     // Tracks which physical keys are currently occupied by which MIDI note
     // KeyCode -> List of Active Midi Notes (implied, though really we only care if it's pressed)
     // Holding a key holds the note.
-    pub active_keys: HashMap<KeyCode, HashSet<u8>>, 
+    pub active_keys: BTreeMap<KeyCode, HashSet<u8>>, 
     
     pub shift_active: bool,
     pub ctrl_active: bool,
     
     // The current global transposition offset
     pub current_transpose: i32,
+    // When the last note was played, for the time the next transpose has to fit in
+    pub last_note_us: Option<u64>,
+
+    // Only mappings for notes in here are used (the game's keyboard size)
+    pub key_range: RangeInclusive<u8>,
+    // Whether mappings may use Shift/Ctrl; off when the game gives them another meaning
+    pub allow_shift: bool,
+    pub allow_ctrl: bool,
+    // Whether a held key may be cut off to play a new note; off drops the note instead
+    pub allow_steal: bool,
+    // Whether the transpose stays put while keys are held (the game retunes held notes)
+    pub hold_transpose: bool,
+    // The keys there are to play notes on
+    pub mappings: MappingSet,
+    // Transpose the file playback plan wants for the next note (see planner.rs)
+    pub planned: Option<i32>,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Solver {
     pub fn new() -> Self {
         Self {
-            active_keys: HashMap::new(),
+            active_keys: BTreeMap::new(),
             shift_active: false,
             ctrl_active: false,
             current_transpose: 0,
+            last_note_us: None,
+            key_range: 0..=127,
+            allow_shift: true,
+            allow_ctrl: true,
+            allow_steal: true,
+            hold_transpose: false,
+            mappings: MappingSet::builtin(),
+            planned: None,
         }
     }
 
-    /// Try to find a solution to play `target_note`.
+    /// Try to find a solution to play `target_note`, arriving at `at_us`.
     /// Returns: Option<(new_transpose_offset, key_mapping_to_use)>
     pub fn solve(
         &self,
         target_note: u8,
+        at_us: u64,
         mode: SolverMode,
         max_jump: i32,
         transpose_range: i32 // 24 means -24 to +24
     ) -> Option<(i32, KeyMapping)> {
-        let mappings = get_available_mappings();
+        self.find(target_note, at_us, mode, max_jump, transpose_range, self.allow_steal)
+    }
+
+    /// Whether `solve` came up empty only because stealing is off: the note could have been
+    /// played by cutting off a held one.
+    pub fn needs_steal(&self, target_note: u8, at_us: u64, mode: SolverMode, max_jump: i32, transpose_range: i32) -> bool {
+        !self.allow_steal && self.find(target_note, at_us, mode, max_jump, transpose_range, true).is_some()
+    }
 
+    fn find(&self, target_note: u8, at_us: u64, mode: SolverMode, max_jump: i32, transpose_range: i32, steal: bool) -> Option<(i32, KeyMapping)> {
         // Potential solution candidates
         let mut best_candidate: Option<(i32, KeyMapping)> = None;
         let mut min_distance = i32::MAX;
 
-        // Find required transposition T = target_note - map.midi_note
-        for map in &mappings {
-            let required_transpose = target_note as i32 - map.midi_note as i32;
-            
-            // Check if required transpose is within global range limits
-            if required_transpose.abs() > transpose_range {
-                continue;
-            }
+        let usable = |m: &&KeyMapping| {
+            self.key_range.contains(&m.midi_note) && (self.allow_shift || !m.shift) && (self.allow_ctrl || !m.ctrl)
+        };
 
-            // Check if this physical key is currently pressed
-            let key_busy = self.active_keys.contains_key(&map.key_code) && !self.active_keys[&map.key_code].is_empty();
-            
-            // Check modifiers conflict
-            if !self.is_modifier_safe(map) {
-                continue;
-            }
+        // A planned transpose wins when its key is free to play now; the plan already
+        // weighed the jump against the rest of the song
+        if let Some(planned) = self.planned
+            && planned.abs() <= transpose_range
+            && self.may_transpose_to(planned)
+            && let Ok(played) = u8::try_from(target_note as i32 - planned)
+            && let Some(map) = self.mappings.get(played).filter(usable)
+            && self.is_modifier_safe(map)
+            && self.active_keys.get(&map.key_code).is_none_or(|notes| notes.is_empty())
+        {
+            return Some((planned, *map));
+        }
 
-            let mut distance = (required_transpose - self.current_transpose).abs();
-            
-            // Penalty for stealing a busy key (we prefer free keys via transposition)
-            if key_busy {
-                distance += 100; // Equivalent to 100 semitones jump, so we only do it if necessary
+        for map in self.mappings.iter().filter(usable) {
+            // Efficiency must stay within max_jump; Accuracy takes the closest of any
+            if let Ok((required_transpose, distance)) = self.evaluate(map, target_note, at_us, transpose_range, steal)
+                && (mode == SolverMode::Accuracy || distance <= max_jump)
+                && distance < min_distance
+            {
+                min_distance = distance;
+                best_candidate = Some((required_transpose, *map));
             }
+        }
+
+        best_candidate
+    }
+
+    // The transpose `map` needs for `target_note` and what it costs, or why it can't be used
+    fn evaluate(&self, map: &KeyMapping, target_note: u8, at_us: u64, transpose_range: i32, steal: bool) -> Result<(i32, i32), Rejection> {
+        // Find required transposition T = target_note - map.midi_note
+        let required_transpose = target_note as i32 - map.midi_note as i32;
+
+        // Check if required transpose is within global range limits
+        if required_transpose.abs() > transpose_range {
+            return Err(Rejection::OutOfRange);
+        }
+        if !self.may_transpose_to(required_transpose) {
+            return Err(Rejection::TransposeHeld);
+        }
+
+        // Check if this physical key is currently pressed
+        let key_busy = self.active_keys.contains_key(&map.key_code) && !self.active_keys[&map.key_code].is_empty();
+        if key_busy && !steal {
+            return Err(Rejection::KeyBusy);
+        }
 
-            match mode {
-                SolverMode::Efficiency => {
-                    // Must be within max_jump
-                    if distance <= max_jump {
-                        if distance < min_distance {
-                            min_distance = distance;
-                            best_candidate = Some((required_transpose, *map));
-                        }
-                    }
-                },
-                SolverMode::Accuracy => {
-                    // Just find any valid one. Preference for closer distance?
-                    if distance < min_distance {
-                        min_distance = distance;
-                        best_candidate = Some((required_transpose, *map));
-                    }
+        // Check modifiers conflict
+        if !self.is_modifier_safe(map) {
+            return Err(Rejection::ModifierConflict);
+        }
+
+        let mut distance = self.jump_cost((required_transpose - self.current_transpose).abs(), at_us);
+
+        // Penalty for stealing a busy key (we prefer free keys via transposition)
+        if key_busy {
+            distance += 100; // Equivalent to 100 semitones jump, so we only do it if necessary
+        }
+        Ok((required_transpose, distance))
+    }
+
+    /// Everything the solver weighed for `target_note`, for the decision log. Keys outside
+    /// the game's range or needing a modifier the game uses for something else aren't listed.
+    pub fn explain(&self, target_note: u8, at_us: u64, mode: SolverMode, max_jump: i32, transpose_range: i32) -> SolverDecision {
+        let usable = |m: &&KeyMapping| {
+            self.key_range.contains(&m.midi_note) && (self.allow_shift || !m.shift) && (self.allow_ctrl || !m.ctrl)
+        };
+        let candidates = self
+            .mappings
+            .iter()
+            .filter(usable)
+            .map(|map| {
+                let outcome = self
+                    .evaluate(map, target_note, at_us, transpose_range, self.allow_steal)
+                    .and_then(|(_, cost)| if mode == SolverMode::Efficiency && cost > max_jump { Err(Rejection::TooFar) } else { Ok(cost) });
+                Candidate {
+                    transpose: target_note as i32 - map.midi_note as i32,
+                    key: map.key_code,
+                    shift: map.shift,
+                    ctrl: map.ctrl,
+                    outcome,
                 }
-            }
+            })
+            .collect();
+        SolverDecision {
+            at_us,
+            note: target_note,
+            current_transpose: self.current_transpose,
+            planned: self.planned,
+            candidates,
+            chosen: self.solve(target_note, at_us, mode, max_jump, transpose_range).map(|(t, m)| (t, m.key_code)),
         }
+    }
 
-        best_candidate
+    // Semitones of transpose, plus the taps that won't hide in the gap since the last note
+    fn jump_cost(&self, semitones: i32, at_us: u64) -> i32 {
+        let Some(last_us) = self.last_note_us else { return semitones };
+        let hidden = at_us.saturating_sub(last_us) / HIDDEN_DELAY_DIVISOR / TRANSPOSE_TAP_US;
+        let late = (semitones as i64 - hidden.min(i32::MAX as u64) as i64).max(0) as i32;
+        semitones + late * LATE_TAP_COST
+    }
+
+    // With the transpose held, only the current one is open while any key is down
+    fn may_transpose_to(&self, transpose: i32) -> bool {
+        !self.hold_transpose || transpose == self.current_transpose || self.active_keys.values().all(|notes| notes.is_empty())
     }
 
     // Check if activating modifiers for 'new_map' would disrupt currently held notes
     fn is_modifier_safe(&self, new_map: &KeyMapping) -> bool {
         // Iterate over all active keys
-        for (_code, notes) in &self.active_keys {
+        for notes in self.active_keys.values() {
             if notes.is_empty() { continue; }
             
             // Ensure modifier compatibility.
@@ -190,24 +306,22 @@ impl Solver {
         true
     }
 
-    pub fn register_note_on(&mut self, key: KeyCode, note: u8, transpose: i32, shift: bool, ctrl: bool) {
-        self.active_keys.entry(key).or_insert_with(HashSet::new).insert(note);
+    pub fn register_note_on(&mut self, key: KeyCode, note: u8, at_us: u64, transpose: i32, shift: bool, ctrl: bool) {
+        self.active_keys.entry(key).or_default().insert(note);
+        self.last_note_us = Some(at_us);
         self.current_transpose = transpose;
         self.shift_active = shift;
         self.ctrl_active = ctrl;
     }
 
-    pub fn register_note_off(&mut self, note: u8) -> Option<KeyCode> {
-        // Find the physical key mapped to this MIDI note.
-        let mut key_to_release = None;
-        
+    /// Releases `note` everywhere it is held (a repeated note-on can put it on more than one
+    /// key) and returns the keys that are now free.
+    pub fn register_note_off(&mut self, note: u8) -> Vec<KeyCode> {
+        let mut keys_to_release = Vec::new();
+
         for (code, notes) in self.active_keys.iter_mut() {
-            if notes.contains(&note) {
-                notes.remove(&note);
-                if notes.is_empty() {
-                    key_to_release = Some(*code);
-                }
-                break;
+            if notes.remove(&note) && notes.is_empty() {
+                keys_to_release.push(*code);
             }
         }
         
@@ -219,7 +333,7 @@ impl Solver {
             self.ctrl_active = false;
         }
 
-        key_to_release
+        keys_to_release
     }
 
     pub fn reset_keys(&mut self) -> Vec<KeyCode> {
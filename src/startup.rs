@@ -0,0 +1,91 @@
+use crate::config::{self, Settings};
+use crate::error::{Error, Result};
+
+// Startup flags for the frontends, so a desktop shortcut or script can launch ready to play:
+// a profile, settings overriding it, and which MIDI input to pick (and connect to).
+
+#[derive(Clone, Debug, Default)]
+pub struct Startup {
+    pub profile: Option<String>,
+    pub settings: Settings,
+    pub port: Option<String>, // Part of the input's name, any case
+    pub connect: bool,
+    pub ble: Option<String>, // Part of a Bluetooth MIDI device's name, connected to at startup
+    pub websocket: Option<String>, // Address for the remote control server
+    pub http: Option<String>,      // Address (or just a port, on localhost) for the HTTP server
+    pub osc: Option<String>,       // Address (or just a port, on localhost) to take OSC on
+    pub token: Option<String>,     // Remotes have to bring it, when set
+}
+
+// The value after `flag`, if it's there at all
+fn value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>> {
+    let Some(i) = args.iter().position(|a| a == flag) else { return Ok(None) };
+    match args.get(i + 1) {
+        Some(value) if !value.starts_with("--") => Ok(Some(value)),
+        _ => Err(Error::Parse(format!("{} expects a value", flag))),
+    }
+}
+
+impl Startup {
+    /// Reads the startup flags from the command line: `--profile <name>` (else `profile`, else
+    /// the defaults), then `--mappings <file>`, `--script <file>`, `--solver`/`--no-solver` and
+    /// `--quantize <ms>` (0 turns quantizing off) on top of it, `--port <name>` and `--connect`,
+    /// `--ble <name>` to connect a Bluetooth MIDI device too, `--osc <addr>` to play from OSC,
+    /// and `--ws <addr>` and `--http <addr>` for remote control. The token comes from wherever
+    /// the caller's does.
+    pub fn from_args(args: &[String], profile: Option<&str>) -> Result<Self> {
+        let profile = value(args, "--profile")?.or(profile).map(str::to_string);
+        let mut settings = match &profile {
+            Some(name) => config::load_profile(name).map_err(|e| Error::Parse(format!("Failed to load profile '{}': {}", name, e)))?,
+            None => Settings::default(),
+        };
+        if let Some(path) = value(args, "--mappings")? {
+            settings.mappings_file = path.to_string();
+        }
+        if let Some(path) = value(args, "--script")? {
+            settings.script_file = path.to_string();
+        }
+        if args.iter().any(|a| a == "--solver") {
+            settings.solver_enabled = true;
+        }
+        if args.iter().any(|a| a == "--no-solver") {
+            settings.solver_enabled = false;
+        }
+        if let Some(ms) = value(args, "--quantize")? {
+            let ms: u64 = ms.parse().map_err(|_| Error::Parse("--quantize expects milliseconds".to_string()))?;
+            settings.quantize_enabled = ms > 0;
+            if ms > 0 {
+                settings.quantize_ms = ms;
+            }
+        }
+        Ok(Self {
+            profile,
+            settings,
+            port: value(args, "--port")?.map(str::to_string),
+            connect: args.iter().any(|a| a == "--connect"),
+            ble: value(args, "--ble")?.map(str::to_string),
+            websocket: value(args, "--ws")?.map(str::to_string),
+            http: value(args, "--http")?.map(str::to_string),
+            osc: value(args, "--osc")?.map(str::to_string),
+            token: None,
+        })
+    }
+
+    /// The first of `ports` that `--port` names, or the first one at all without `--port`.
+    pub fn find_port<'a>(&self, ports: impl IntoIterator<Item = &'a String>) -> Option<&'a String> {
+        find_port(self.port.as_deref(), ports)
+    }
+}
+
+/// The first of `ports` whose name contains `wanted` (any case), or the first one at all
+/// without `wanted`.
+pub fn find_port<'a>(wanted: Option<&str>, ports: impl IntoIterator<Item = &'a String>) -> Option<&'a String> {
+    let mut ports = ports.into_iter();
+    match wanted {
+        Some(wanted) => {
+            let wanted = wanted.to_lowercase();
+            ports.find(|name| name.to_lowercase().contains(&wanted))
+        }
+        None => ports.next(),
+    }
+}
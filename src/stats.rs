@@ -0,0 +1,284 @@
+use evdev::KeyCode;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::error::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum DropReason {
+    DrumChannel,     // Drum channel outside drums mode and dual drums
+    ChannelFiltered, // Channel set to ignore or visualize only
+    OutOfRange,      // Range disabled and auto-transpose couldn't help
+    NoMapping,       // No key for the (transposed) note
+    NoSolverMatch,   // Solver found no candidate within its limits
+    KeyBusy,         // Only a held key could play it, and stealing is off
+    TooSoft,         // Below the velocity gate; usually a ghost note
+    Polyphony,       // Past the most notes held at once, and lower priority than those
+}
+
+impl DropReason {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DropReason::DrumChannel => "Drum channel",
+            DropReason::ChannelFiltered => "Channel filtered",
+            DropReason::OutOfRange => "Out of range",
+            DropReason::NoMapping => "No mapping",
+            DropReason::NoSolverMatch => "No solver match",
+            DropReason::KeyBusy => "Key busy",
+            DropReason::TooSoft => "Below velocity gate",
+            DropReason::Polyphony => "Polyphony limit",
+        }
+    }
+}
+
+// Nearest-rank percentile of an already sorted slice
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[idx]
+}
+
+const HISTOGRAM_BUCKETS: usize = 30;
+// Latency samples kept for the percentiles and the histogram. The GUI sorts them every frame,
+// so a long session must not make that slower; older samples only count toward the max.
+const LATENCY_WINDOW: usize = 4096;
+// Jitter below this is never worth flagging
+const MIN_OUTLIER_US: u64 = 1000;
+
+pub struct LatencyHistogram {
+    pub bucket_us: u64,
+    pub counts: Vec<u64>, // The last bucket also holds everything past the range
+    pub outlier_threshold_us: u64,
+    pub outliers: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DroppedNote {
+    pub at_ms: u64,
+    pub note: u8,
+    pub reason: DropReason,
+}
+
+// Counters for the current connection. Reset on connect.
+pub struct SessionStats {
+    started: Instant,
+    started_unix: u64,
+    pub notes_in: u64,
+    pub notes_played: u64,
+    pub dropped: Vec<DroppedNote>,
+    pub transposes: u64,
+    pub transpose_taps: u64,
+    pub steals: u64,
+    latencies_us: VecDeque<u64>,
+    latency_max_us: u64,
+    // Heatmap data: how often each MIDI note / physical key got played
+    pub note_usage: [u64; 128],
+    pub key_usage: HashMap<KeyCode, u64>,
+    // (elapsed ms, transpose offset) every time the offset changes
+    pub transpose_history: Vec<(u64, i32)>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_unix: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            notes_in: 0,
+            notes_played: 0,
+            dropped: Vec::new(),
+            transposes: 0,
+            transpose_taps: 0,
+            steals: 0,
+            latencies_us: VecDeque::with_capacity(LATENCY_WINDOW),
+            latency_max_us: 0,
+            note_usage: [0; 128],
+            key_usage: HashMap::new(),
+            transpose_history: vec![(0, 0)],
+        }
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn note_in(&mut self) {
+        self.notes_in += 1;
+    }
+
+    pub fn dropped(&mut self, note: u8, reason: DropReason) {
+        let at_ms = self.elapsed_ms();
+        self.dropped.push(DroppedNote { at_ms, note, reason });
+    }
+
+    /// `latency` is from the MIDI callback firing to the key press being emitted.
+    pub fn played(&mut self, note: u8, key: KeyCode, latency: Duration) {
+        self.notes_played += 1;
+        let latency_us = latency.as_micros() as u64;
+        if self.latencies_us.len() == LATENCY_WINDOW {
+            self.latencies_us.pop_front();
+        }
+        self.latencies_us.push_back(latency_us);
+        self.latency_max_us = self.latency_max_us.max(latency_us);
+        self.note_usage[(note & 0x7F) as usize] += 1;
+        *self.key_usage.entry(key).or_default() += 1;
+    }
+
+    pub fn max_note_usage(&self) -> u64 {
+        self.note_usage.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn max_key_usage(&self) -> u64 {
+        self.key_usage.values().copied().max().unwrap_or(0)
+    }
+
+    pub fn transposed(&mut self, offset: i32, taps: u64) {
+        self.transposes += 1;
+        self.transpose_taps += taps;
+        let at_ms = self.elapsed_ms();
+        self.transpose_history.push((at_ms, offset));
+    }
+
+    // The recent latency samples, sorted
+    fn sorted_latencies(&self) -> Vec<u64> {
+        let mut sorted: Vec<u64> = self.latencies_us.iter().copied().collect();
+        sorted.sort_unstable();
+        sorted
+    }
+
+    /// Input->emit latency distribution of the recent notes. Outliers are samples beyond
+    /// Q3 + 3 * IQR.
+    pub fn latency_histogram(&self) -> LatencyHistogram {
+        let sorted = self.sorted_latencies();
+        let q1 = percentile(&sorted, 0.25);
+        let q3 = percentile(&sorted, 0.75);
+        let outlier_threshold_us = (q3 + 3 * (q3 - q1)).max(MIN_OUTLIER_US);
+
+        // Range covers the threshold with some headroom so outliers show up on the right
+        let range_us = (outlier_threshold_us * 3 / 2).max(percentile(&sorted, 0.99));
+        let bucket_us = range_us.div_ceil(HISTOGRAM_BUCKETS as u64).max(1);
+        let mut counts = vec![0u64; HISTOGRAM_BUCKETS];
+        for &us in &sorted {
+            counts[((us / bucket_us) as usize).min(HISTOGRAM_BUCKETS - 1)] += 1;
+        }
+        let outliers = sorted.iter().filter(|&&us| us > outlier_threshold_us).count() as u64;
+
+        LatencyHistogram { bucket_us, counts, outlier_threshold_us, outliers }
+    }
+
+    pub fn stolen(&mut self) {
+        self.steals += 1;
+    }
+
+    /// The session so far; latency percentiles are of the recent notes, the max of them all.
+    pub fn report(&self) -> StatsReport {
+        let sorted = self.sorted_latencies();
+
+        let mut drops_by_reason = BTreeMap::new();
+        for d in &self.dropped {
+            *drops_by_reason.entry(d.reason).or_insert(0u64) += 1;
+        }
+
+        StatsReport {
+            started_unix: self.started_unix,
+            duration_s: self.started.elapsed().as_secs(),
+            notes_in: self.notes_in,
+            notes_played: self.notes_played,
+            notes_dropped: self.dropped.len() as u64,
+            drops_by_reason,
+            transposes: self.transposes,
+            transpose_taps: self.transpose_taps,
+            steals: self.steals,
+            latency_p50_us: percentile(&sorted, 0.50),
+            latency_p90_us: percentile(&sorted, 0.90),
+            latency_p99_us: percentile(&sorted, 0.99),
+            latency_max_us: self.latency_max_us,
+            note_usage: (0..128u8)
+                .filter(|&n| self.note_usage[n as usize] > 0)
+                .map(|n| (n, self.note_usage[n as usize]))
+                .collect(),
+            key_usage: self.key_usage.iter().map(|(k, c)| (format!("{:?}", k), *c)).collect(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StatsReport {
+    pub started_unix: u64,
+    pub duration_s: u64,
+    pub notes_in: u64,
+    pub notes_played: u64,
+    pub notes_dropped: u64,
+    pub drops_by_reason: BTreeMap<DropReason, u64>,
+    pub transposes: u64,
+    pub transpose_taps: u64,
+    pub steals: u64,
+    pub latency_p50_us: u64,
+    pub latency_p90_us: u64,
+    pub latency_p99_us: u64,
+    pub latency_max_us: u64,
+    pub note_usage: BTreeMap<u8, u64>,
+    pub key_usage: BTreeMap<String, u64>,
+    pub dropped: Vec<DroppedNote>,
+}
+
+impl StatsReport {
+    // Summary rows first, then one row per dropped note
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("metric,value\n");
+        let rows: [(&str, u64); 12] = [
+            ("started_unix", self.started_unix),
+            ("duration_s", self.duration_s),
+            ("notes_in", self.notes_in),
+            ("notes_played", self.notes_played),
+            ("notes_dropped", self.notes_dropped),
+            ("transposes", self.transposes),
+            ("transpose_taps", self.transpose_taps),
+            ("steals", self.steals),
+            ("latency_p50_us", self.latency_p50_us),
+            ("latency_p90_us", self.latency_p90_us),
+            ("latency_p99_us", self.latency_p99_us),
+            ("latency_max_us", self.latency_max_us),
+        ];
+        for (name, value) in rows {
+            out.push_str(&format!("{},{}\n", name, value));
+        }
+        for (reason, count) in &self.drops_by_reason {
+            out.push_str(&format!("dropped_{:?},{}\n", reason, count));
+        }
+        for (note, count) in &self.note_usage {
+            out.push_str(&format!("note_{},{}\n", note, count));
+        }
+        for (key, count) in &self.key_usage {
+            out.push_str(&format!("{},{}\n", key, count));
+        }
+
+        out.push_str("\nat_ms,note,reason\n");
+        for d in &self.dropped {
+            out.push_str(&format!("{},{},{:?}\n", d.at_ms, d.note, d.reason));
+        }
+        out
+    }
+
+    /// Writes `session-<start>.json` and `.csv` into the config dir's `sessions` folder.
+    pub fn export(&self) -> Result<PathBuf> {
+        let dir = config::config_dir().join("sessions");
+        fs::create_dir_all(&dir)?;
+        let base = dir.join(format!("session-{}", self.started_unix));
+        fs::write(base.with_extension("json"), serde_json::to_string_pretty(self)?)?;
+        fs::write(base.with_extension("csv"), self.to_csv())?;
+        Ok(base.with_extension("json"))
+    }
+}
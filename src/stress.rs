@@ -0,0 +1,152 @@
+use evdev::KeyCode;
+
+use crate::clock::Clock;
+use crate::config::Settings;
+use crate::midifile::{FileEvent, MidiFile};
+use crate::output::{KeyOutput, SpyOutput};
+use crate::Engine;
+
+// Synthetic stress run: a randomized note stream (overlapping notes, missing note-offs,
+// duplicated messages) pushed through the engine on a virtual clock. Afterwards every note
+// gets released and whatever is still held on the output, or still tracked by the engine,
+// is reported. Same seed, same stream, so anything it finds can be reproduced.
+
+#[derive(Clone, Debug)]
+pub struct StressConfig {
+    pub seed: u64,
+    pub notes: usize,         // Note-ons to generate
+    pub notes_per_sec: u32,   // Average density
+    pub missing_off_pct: u32, // Chance a note never gets its note-off
+    pub duplicate_pct: u32,   // Chance a message is sent twice
+    pub drum_pct: u32,        // Chance a note goes to channel 10
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self { seed: 1, notes: 2000, notes_per_sec: 20, missing_off_pct: 5, duplicate_pct: 5, drum_pct: 5 }
+    }
+}
+
+// xorshift64*: plenty for test streams (and the humanizer) and keeps the crate free of an
+// RNG dependency
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    fn chance(&mut self, pct: u32) -> bool {
+        self.below(100) < pct as u64
+    }
+}
+
+/// Builds the stream as an in-memory MIDI file, events sorted by time.
+pub fn generate(config: &StressConfig) -> MidiFile {
+    let mut rng = Rng::new(config.seed);
+    let mean_gap_us = 1_000_000 / config.notes_per_sec.max(1) as u64;
+    let mut events = Vec::new();
+    let mut at_us = 0u64;
+
+    for _ in 0..config.notes {
+        at_us += rng.below(mean_gap_us * 2);
+        let channel = if rng.chance(config.drum_pct) { 9 } else { rng.below(2) as u8 };
+        let note = rng.below(128) as u8;
+        let velocity = 1 + rng.below(127) as u8;
+
+        let mut push = |at_us: u64, message: [u8; 3], rng: &mut Rng| {
+            events.push(FileEvent { at_us, message });
+            if rng.chance(config.duplicate_pct) {
+                events.push(FileEvent { at_us, message });
+            }
+        };
+        push(at_us, [0x90 | channel, note, velocity], &mut rng);
+        if !rng.chance(config.missing_off_pct) {
+            let held_us = 10_000 + rng.below(1_000_000);
+            // Half the note-offs arrive as note-on with velocity 0, like many keyboards send them
+            let off = if rng.chance(50) { [0x80 | channel, note, 0] } else { [0x90 | channel, note, 0] };
+            push(at_us + held_us, off, &mut rng);
+        }
+    }
+
+    events.sort_by_key(|e| e.at_us);
+    MidiFile { name: format!("stress-{}", config.seed), events, beats_us: Vec::new(), beats_per_bar: 4 }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StressReport {
+    pub messages: usize,
+    pub key_events: usize,
+    pub stuck_keys: Vec<KeyCode>, // Still pressed after every note was released
+    pub desync: Vec<String>,      // Engine bookkeeping that disagrees with the output
+}
+
+impl StressReport {
+    pub fn is_clean(&self) -> bool {
+        self.stuck_keys.is_empty() && self.desync.is_empty()
+    }
+
+    pub fn format(&self) -> String {
+        let mut out = format!("{} MIDI messages, {} key events\n", self.messages, self.key_events);
+        if self.is_clean() {
+            out.push_str("No stuck keys, engine state consistent\n");
+        }
+        for key in &self.stuck_keys {
+            out.push_str(&format!("stuck: {:?}\n", key));
+        }
+        for problem in &self.desync {
+            out.push_str(&format!("desync: {}\n", problem));
+        }
+        out
+    }
+}
+
+/// Runs the generated stream through a fresh engine, then sends a note-off for every note on
+/// every channel and checks that nothing is left held.
+pub fn run(config: &StressConfig, settings: &Settings) -> StressReport {
+    let file = generate(config);
+    let spy = SpyOutput::new();
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), Clock::new_virtual());
+    engine.apply_settings(settings);
+
+    for event in &file.events {
+        // Drops are expected here; the spy output can't fail
+        let _ = engine.inject(event.at_us, &event.message);
+    }
+    let end_us = file.events.last().map(|e| e.at_us).unwrap_or(0) + 1_000_000;
+    for channel in 0..16u8 {
+        for note in 0..128u8 {
+            let _ = engine.inject(end_us, &[0x80 | channel, note, 0]);
+        }
+    }
+
+    let state = engine.snapshot();
+    let mut desync = Vec::new();
+    if !state.active_notes.is_empty() {
+        desync.push(format!("{} input notes still marked active", state.active_notes.len()));
+    }
+    if !state.output_notes.is_empty() {
+        desync.push(format!("{} output notes still marked active", state.output_notes.len()));
+    }
+    if !state.solver_keys.is_empty() {
+        desync.push(format!("solver still tracks {} held keys", state.solver_keys.len()));
+    }
+
+    StressReport {
+        messages: file.events.len() + 16 * 128,
+        key_events: spy.take().len(),
+        stuck_keys: state.held_keys,
+        desync,
+    }
+}
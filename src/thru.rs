@@ -0,0 +1,35 @@
+use midir::{MidiOutput, MidiOutputConnection};
+use std::io;
+
+use crate::error::{Error, Result};
+
+// MIDI thru: the notes the game is about to sound, after transpose, range folding and the
+// channel filter, sent on to a synth so what's heard is what Roblox gets.
+
+/// Somewhere thru notes go. `MidiOutputConnection` is the real one; tests keep them instead.
+pub trait ThruBackend: Send {
+    fn send(&mut self, message: &[u8]) -> io::Result<()>;
+}
+
+impl ThruBackend for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        MidiOutputConnection::send(self, message).map_err(io::Error::other)
+    }
+}
+
+/// Names of the MIDI output ports a thru connection can go to.
+pub fn port_names() -> Vec<String> {
+    let Ok(midi_out) = MidiOutput::new("Miditoroblox Thru") else { return Vec::new() };
+    midi_out.ports().iter().filter_map(|port| midi_out.port_name(port).ok()).collect()
+}
+
+/// Opens the output port called `name`.
+pub fn connect(name: &str) -> Result<MidiOutputConnection> {
+    let midi_out = MidiOutput::new("Miditoroblox Thru").map_err(|e| Error::Midi(e.to_string()))?;
+    let port = midi_out
+        .ports()
+        .into_iter()
+        .find(|port| midi_out.port_name(port).is_ok_and(|n| n == name))
+        .ok_or_else(|| Error::Midi(format!("no output port '{}'", name)))?;
+    midi_out.connect(&port, "miditoroblox-thru").map_err(|e| Error::Midi(e.to_string()))
+}
@@ -0,0 +1,314 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use midir::{MidiInput, MidiInputConnection};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use miditoroblox_rs::mappings::MappingSet;
+use miditoroblox_rs::output::KeyOutput;
+use miditoroblox_rs::remote::RemoteRequest;
+use miditoroblox_rs::startup::{self, Startup};
+use miditoroblox_rs::worker::MidiQueue;
+use miditoroblox_rs::{arpeggiator, ble, guitar, hotkeys, hotplug, http, keepalive, metronome, midifile, mpris, osc, scheduler, shutdown, worker, Engine};
+
+// Terminal frontend for playing over SSH or without room for another window. It drives the
+// same engine as the GUI: one MIDI input at a time, the main switches, and a live view of
+// what's held and the transpose. Everything else comes from the profile it starts with.
+
+// How often the screen is redrawn when nothing is pressed
+const TICK: Duration = Duration::from_millis(100);
+
+// MIDI input callback: queued for the engine's worker, nothing more
+fn handle_midi(_stamp: u64, message: &[u8], queue: &mut MidiQueue) {
+    queue.push(message);
+}
+
+struct TuiApp {
+    engine: Arc<Engine>,
+    queue: MidiQueue,
+    ports: Vec<String>,
+    selected: ListState,
+    connection: Option<(String, MidiInputConnection<MidiQueue>)>,
+    status: String,
+    quit: bool,
+    ble: ble::SharedBle, // A Bluetooth MIDI device from `--ble`, playing into `queue` too
+}
+
+impl TuiApp {
+    // (key, label, switch): the GUI's main checkboxes
+    fn toggles(&self) -> [(char, &'static str, &AtomicBool); 8] {
+        let engine = &self.engine;
+        [
+            ('s', "Smart Solver", &engine.solver_enabled),
+            ('a', "Auto-Octave Transposition", &engine.auto_transpose_enabled),
+            ('b', "Black Keys using Transpose", &engine.experimental_transpose_enabled),
+            ('o', "Optimized Transpose", &engine.lazy_transpose_enabled),
+            ('z', "Note Quantization", &engine.quantize_enabled),
+            ('l', "Low Range", &engine.low_mapping_enabled),
+            ('h', "High Range", &engine.high_mapping_enabled),
+            ('d', "Play Drum Channels", &engine.dual_drums_enabled),
+        ]
+    }
+
+    // Follows the port watch; a connected device that went away is let go of
+    fn follow_ports(&mut self) {
+        let ports = self.engine.midi_ports.lock().unwrap().clone();
+        if ports == self.ports {
+            return;
+        }
+        self.ports = ports;
+        if let Some((name, _)) = &self.connection
+            && !self.ports.contains(name)
+        {
+            self.status = format!("{} went away", name);
+            self.disconnect();
+        }
+        let last = self.ports.len().saturating_sub(1);
+        self.selected.select(if self.ports.is_empty() { None } else { Some(self.selected.selected().unwrap_or(0).min(last)) });
+    }
+
+    fn connect(&mut self, port_name: &str) {
+        let midi_in = match MidiInput::new("Miditoroblox Input") {
+            Ok(m) => m,
+            Err(e) => {
+                self.status = format!("Failed to create MidiInput: {}", e);
+                return;
+            }
+        };
+        let Some(port) = midi_in.ports().into_iter().find(|p| midi_in.port_name(p).is_ok_and(|n| n == port_name)) else {
+            self.status = format!("{} isn't there any more", port_name);
+            return;
+        };
+        match midi_in.connect(&port, "miditoroblox-in", handle_midi, self.queue.clone()) {
+            Ok(connection) => {
+                self.connection = Some((port_name.to_string(), connection));
+                self.status = format!("Connected to {}", port_name);
+            }
+            Err(e) => self.status = format!("Error connecting: {}", e),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.connection.take().is_some() {
+            let _ = self.engine.router.lock().unwrap().release_held();
+        }
+    }
+
+    // Connects and disconnects asked for by remote control (remote.rs)
+    fn follow_remote(&mut self) {
+        let requests = std::mem::take(&mut *self.engine.remote_requests.lock().unwrap());
+        for request in requests {
+            match request {
+                RemoteRequest::Connect(wanted) => {
+                    let port = match &wanted {
+                        Some(wanted) => startup::find_port(Some(wanted), &self.ports).cloned(),
+                        None => self.selected.selected().and_then(|i| self.ports.get(i)).cloned(),
+                    };
+                    let Some(port) = port else {
+                        self.status = format!("No MIDI input matching '{}'", wanted.unwrap_or_default());
+                        continue;
+                    };
+                    self.selected.select(self.ports.iter().position(|p| *p == port));
+                    if self.connection.as_ref().is_none_or(|(name, _)| *name != port) {
+                        self.disconnect();
+                        self.connect(&port);
+                    }
+                }
+                RemoteRequest::Disconnect => {
+                    if let Some((name, _)) = &self.connection {
+                        self.status = format!("Disconnected from {}", name);
+                    }
+                    self.disconnect();
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            // Raw mode swallows the signal, so Ctrl+C arrives as a key
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Up => self.selected.select_previous(),
+            KeyCode::Down => self.selected.select_next(),
+            KeyCode::Enter => {
+                let Some(port) = self.selected.selected().and_then(|i| self.ports.get(i)).cloned() else { return };
+                let same = self.connection.as_ref().is_some_and(|(name, _)| *name == port);
+                self.disconnect();
+                if same {
+                    self.status = format!("Disconnected from {}", port);
+                } else {
+                    self.connect(&port);
+                }
+            }
+            KeyCode::Char('p') => {
+                self.status = match self.engine.panic() {
+                    Ok(()) => "Panic: every key released, transpose reset".to_string(),
+                    Err(e) => format!("Panic failed: {}", e),
+                };
+            }
+            KeyCode::Char(c) => {
+                let toggled = self.toggles().into_iter().find(|(key, _, _)| *key == c).map(|(_, label, switch)| {
+                    let on = !switch.load(Ordering::Relaxed);
+                    switch.store(on, Ordering::Relaxed);
+                    format!("{} {}", label, if on { "on" } else { "off" })
+                });
+                if let Some(status) = toggled {
+                    self.status = status;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, notes, help] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(6), Constraint::Length(5), Constraint::Length(1)]).areas(frame.area());
+        let [ports_area, toggles_area] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+
+        let snapshot = self.engine.snapshot();
+        let connection = match &self.connection {
+            Some((name, _)) => format!("Connected: {}", name),
+            None => "Not connected".to_string(),
+        };
+        let header_line = format!("{}  |  Transpose {:+}  |  {}", connection, snapshot.transpose, self.status);
+        frame.render_widget(Paragraph::new(header_line).block(Block::bordered().title(" Miditoroblox ")), header);
+
+        let items: Vec<ListItem> = self
+            .ports
+            .iter()
+            .map(|port| {
+                let connected = self.connection.as_ref().is_some_and(|(name, _)| name == port);
+                let item = ListItem::new(format!("{} {}", if connected { "*" } else { " " }, port));
+                if connected { item.style(Style::new().fg(Color::Green)) } else { item }
+            })
+            .collect();
+        let ports = List::new(items)
+            .block(Block::bordered().title(" MIDI Inputs "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(ports, ports_area, &mut self.selected);
+
+        let toggles: Vec<Line> = self
+            .toggles()
+            .iter()
+            .map(|(key, label, switch)| Line::from(format!("[{}] {}  {}", if switch.load(Ordering::Relaxed) { "x" } else { " " }, key, label)))
+            .collect();
+        frame.render_widget(Paragraph::new(toggles).block(Block::bordered().title(" Settings ")), toggles_area);
+
+        let names = |notes: &[u8]| notes.iter().map(|n| midifile::note_name(*n)).collect::<Vec<_>>().join(" ");
+        let keys = snapshot.held_keys.iter().map(|k| format!("{:?}", k).trim_start_matches("KEY_").to_string()).collect::<Vec<_>>().join(" ");
+        let lines = vec![
+            Line::from(format!("In:   {}", names(&snapshot.active_notes))),
+            Line::from(format!("Out:  {}", names(&snapshot.output_notes))),
+            Line::from(format!("Keys: {}", keys)),
+        ];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Notes ")), notes);
+
+        let help_line = "Up/Down pick input  Enter connect/disconnect  letters toggle  p panic  q quit";
+        frame.render_widget(Paragraph::new(help_line).style(Style::new().fg(Color::DarkGray)), help);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            self.follow_ports();
+            self.follow_remote();
+            if let Some(news) = self.ble.lock().unwrap().news.take() {
+                self.status = news;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.handle_key(key.code, key.modifiers);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the terminal frontend until it's quit, starting the way `startup` says.
+pub fn run(output: KeyOutput, drum_output: Option<KeyOutput>, startup: Startup) -> io::Result<()> {
+    let engine = Arc::new(Engine::new(output));
+    shutdown::register(&engine);
+    engine.router.lock().unwrap().drum_output = drum_output;
+    engine.set_mappings(MappingSet::load());
+    engine.apply_settings(&startup.settings);
+    *engine.active_profile.lock().unwrap() = startup.profile.clone();
+
+    guitar::spawn_strummer(engine.clone());
+    scheduler::spawn(engine.clone());
+    arpeggiator::spawn(engine.clone());
+    metronome::spawn(engine.clone());
+    keepalive::spawn(engine.clone());
+    hotplug::spawn(engine.clone());
+    hotkeys::spawn(engine.clone());
+    if let Err(e) = mpris::spawn(engine.clone()) {
+        eprintln!("MPRIS: {}", e);
+    }
+
+    let mut app = TuiApp {
+        queue: worker::spawn(engine.clone()),
+        engine: engine.clone(),
+        ports: Vec::new(),
+        selected: ListState::default(),
+        connection: None,
+        status: "Pick a MIDI input and press Enter".to_string(),
+        quit: false,
+        ble: ble::SharedBle::default(),
+    };
+    // The port watch hasn't looked yet; a flag-picked input can't wait for it
+    if let Ok(midi_in) = MidiInput::new("Miditoroblox Input") {
+        app.ports = midi_in.ports().iter().filter_map(|p| midi_in.port_name(p).ok()).collect();
+        *engine.midi_ports.lock().unwrap() = app.ports.clone();
+    }
+    if let Some(addr) = &startup.osc {
+        app.status = match osc::spawn(addr, app.queue.clone()) {
+            Ok(local) => format!("Playing OSC sent to {}", local),
+            Err(e) => format!("Can't take OSC on {}: {}", addr, e),
+        };
+    }
+    if let Some(addr) = &startup.http {
+        app.status = match http::spawn(engine.clone(), addr, startup.token.clone()) {
+            Ok(local) => format!("Status and control on http://{}", local),
+            Err(e) => format!("HTTP server couldn't listen on {}: {}", addr, e),
+        };
+    }
+    #[cfg(feature = "remote")]
+    if let Some(addr) = &startup.websocket {
+        app.status = match miditoroblox_rs::websocket::spawn(engine.clone(), addr, startup.token.clone()) {
+            Ok(local) => format!("Remote control on ws://{}", local),
+            Err(e) => format!("Remote control couldn't listen on {}: {}", addr, e),
+        };
+    }
+    if let Some(wanted) = &startup.ble {
+        ble::connect_in_background(&app.ble, wanted.clone(), engine.clone(), app.queue.clone());
+        app.status = format!("Looking for Bluetooth MIDI device '{}'", wanted);
+    }
+    let port = startup.find_port(&app.ports).cloned();
+    match port {
+        Some(port) => {
+            app.selected.select(app.ports.iter().position(|p| *p == port));
+            if startup.connect {
+                app.connect(&port);
+            }
+        }
+        None if startup.port.is_some() || startup.connect => {
+            app.status = format!("No MIDI input matching '{}'", startup.port.as_deref().unwrap_or_default());
+        }
+        None => app.selected.select(if app.ports.is_empty() { None } else { Some(0) }),
+    }
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    app.disconnect();
+    engine.release_on_exit();
+    result
+}
@@ -0,0 +1,145 @@
+use std::sync::atomic::Ordering;
+
+use evdev::KeyCode;
+
+use crate::config::TypingSource;
+use crate::Engine;
+
+// The computer keyboard as a MIDI keyboard, for playing without a controller. Typed keys
+// become note-ons and note-offs on channel 1 and go through the pipeline like a port's, so
+// the solver, quantizer, processors and visualizer all work on them.
+//
+// The layout is a tracker's: the bottom row's letters are white keys from the base note
+// (Z = C by default) with the black keys on the row above (S, D, G, H, J), and the top row
+// carries on an octave up from Q with the number row as its black keys.
+
+/// Highest base note: the top of the layout still has to be a MIDI note.
+pub const MAX_BASE_NOTE: u64 = 127 - 31;
+
+// (key, semitones above the base note)
+const LAYOUT: [(char, u8); 37] = [
+    ('z', 0),
+    ('s', 1),
+    ('x', 2),
+    ('d', 3),
+    ('c', 4),
+    ('v', 5),
+    ('g', 6),
+    ('b', 7),
+    ('h', 8),
+    ('n', 9),
+    ('j', 10),
+    ('m', 11),
+    (',', 12),
+    ('l', 13),
+    ('.', 14),
+    (';', 15),
+    ('/', 16),
+    ('q', 12),
+    ('2', 13),
+    ('w', 14),
+    ('3', 15),
+    ('e', 16),
+    ('r', 17),
+    ('5', 18),
+    ('t', 19),
+    ('6', 20),
+    ('y', 21),
+    ('7', 22),
+    ('u', 23),
+    ('i', 24),
+    ('9', 25),
+    ('o', 26),
+    ('0', 27),
+    ('p', 28),
+    ('[', 29),
+    ('=', 30),
+    (']', 31),
+];
+
+/// Semitones above the base note that `key` plays, if it's in the layout.
+pub fn offset(key: char) -> Option<u8> {
+    let key = key.to_ascii_lowercase();
+    LAYOUT.iter().find(|(k, _)| *k == key).map(|(_, offset)| *offset)
+}
+
+/// The layout's character for an evdev key, for keys read off a keyboard device.
+pub fn char_for_key(key: KeyCode) -> Option<char> {
+    Some(match key {
+        KeyCode::KEY_Z => 'z',
+        KeyCode::KEY_S => 's',
+        KeyCode::KEY_X => 'x',
+        KeyCode::KEY_D => 'd',
+        KeyCode::KEY_C => 'c',
+        KeyCode::KEY_V => 'v',
+        KeyCode::KEY_G => 'g',
+        KeyCode::KEY_B => 'b',
+        KeyCode::KEY_H => 'h',
+        KeyCode::KEY_N => 'n',
+        KeyCode::KEY_J => 'j',
+        KeyCode::KEY_M => 'm',
+        KeyCode::KEY_COMMA => ',',
+        KeyCode::KEY_L => 'l',
+        KeyCode::KEY_DOT => '.',
+        KeyCode::KEY_SEMICOLON => ';',
+        KeyCode::KEY_SLASH => '/',
+        KeyCode::KEY_Q => 'q',
+        KeyCode::KEY_2 => '2',
+        KeyCode::KEY_W => 'w',
+        KeyCode::KEY_3 => '3',
+        KeyCode::KEY_E => 'e',
+        KeyCode::KEY_R => 'r',
+        KeyCode::KEY_5 => '5',
+        KeyCode::KEY_T => 't',
+        KeyCode::KEY_6 => '6',
+        KeyCode::KEY_Y => 'y',
+        KeyCode::KEY_7 => '7',
+        KeyCode::KEY_U => 'u',
+        KeyCode::KEY_I => 'i',
+        KeyCode::KEY_9 => '9',
+        KeyCode::KEY_O => 'o',
+        KeyCode::KEY_0 => '0',
+        KeyCode::KEY_P => 'p',
+        KeyCode::KEY_LEFTBRACE => '[',
+        KeyCode::KEY_EQUAL => '=',
+        KeyCode::KEY_RIGHTBRACE => ']',
+        _ => return None,
+    })
+}
+
+/// The notes one typing source is holding, so each key's note-off matches its note-on even
+/// if the base note or the source changed in between.
+#[derive(Default)]
+pub struct TypedNotes {
+    held: Vec<(char, u8)>,
+}
+
+impl TypedNotes {
+    /// The MIDI message for a key pressed or released on `source`, if typing is on from
+    /// there and the key plays a note. Repeated presses of a held key play nothing.
+    pub fn key(&mut self, engine: &Engine, source: TypingSource, key: char, pressed: bool) -> Option<[u8; 3]> {
+        let key = key.to_ascii_lowercase();
+        if !pressed {
+            let i = self.held.iter().position(|(k, _)| *k == key)?;
+            let (_, note) = self.held.remove(i);
+            return Some([0x80, note, 0]);
+        }
+        if *engine.typing_source.lock().unwrap() != source || self.held.iter().any(|(k, _)| *k == key) {
+            return None;
+        }
+        let base = engine.typing_base_note.load(Ordering::Relaxed).min(MAX_BASE_NOTE) as u8;
+        let note = base + offset(key)?;
+        self.held.push((key, note));
+        Some([0x90, note, engine.typing_velocity.load(Ordering::Relaxed).clamp(1, 127) as u8])
+    }
+
+    /// Note-offs for everything held, e.g. when the window loses focus and the releases
+    /// would go elsewhere.
+    pub fn release_all(&mut self) -> Vec<[u8; 3]> {
+        self.held.drain(..).map(|(_, note)| [0x80, note, 0]).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
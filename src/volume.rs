@@ -0,0 +1,58 @@
+// Follows the player's dynamics with the game's volume-up/volume-down keys. The game only
+// has a handful of levels and never tells us which one it's on, so the first change walks
+// all the way down to the bottom and counts up from there.
+
+// How much each new note-on velocity moves the running average
+const SMOOTHING: f32 = 0.2;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VolumeFollower {
+    level: Option<u32>,     // In-game level as far as we know, 0 = quietest
+    average: Option<f32>,   // Running average of note-on velocities
+}
+
+impl VolumeFollower {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Taps (true = up) to follow one more note-on at `velocity`.
+    pub fn on_velocity(&mut self, velocity: u8, steps: u32) -> Vec<bool> {
+        let average = match self.average {
+            Some(average) => average + (velocity as f32 - average) * SMOOTHING,
+            None => velocity as f32,
+        };
+        self.average = Some(average);
+        self.move_to(average, steps)
+    }
+
+    /// Taps to follow a controller value (CC 7 volume, CC 11 expression and the like).
+    pub fn on_cc(&mut self, value: u8, steps: u32) -> Vec<bool> {
+        self.move_to(value as f32, steps)
+    }
+
+    /// Forgets the in-game level, e.g. after reconnecting or switching games.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn move_to(&mut self, value: f32, steps: u32) -> Vec<bool> {
+        let top = steps.max(2) - 1;
+        let target = ((value / 127.0) * top as f32).round() as u32;
+        let (from, mut taps) = match self.level {
+            Some(level) => (level, Vec::new()),
+            None => (0, vec![false; top as usize]),
+        };
+        self.level = Some(target);
+        if target > from {
+            taps.extend(std::iter::repeat_n(true, (target - from) as usize));
+        } else {
+            taps.extend(std::iter::repeat_n(false, (from - target) as usize));
+        }
+        taps
+    }
+
+    pub fn level(&self) -> Option<u32> {
+        self.level
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs;
+use zbus::blocking::{Connection, Proxy};
+
+// Native Wayland sessions. Key injection there goes through the RemoteDesktop portal (see
+// portal.rs), which GNOME and KDE both implement. Wayland has no way for a client to put
+// itself on top, so "Always On Top" asks the compositor instead: KWin takes a script over
+// D-Bus; GNOME's Mutter has no such interface, so there it's left to the user (Super+right
+// click on the title bar, "Always on Top").
+
+const KWIN_DEST: &str = "org.kde.KWin";
+const KWIN_SCRIPTING_PATH: &str = "/Scripting";
+const KWIN_SCRIPTING_IFACE: &str = "org.kde.kwin.Scripting";
+const KWIN_SCRIPT_IFACE: &str = "org.kde.kwin.Script";
+const SCRIPT_NAME: &str = "miditoroblox-keep-above";
+
+/// Whether we're a Wayland client. main.rs hides WAYLAND_DISPLAY unless started with
+/// `--wayland`, so anything else runs under XWayland.
+pub fn is_native() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some_and(|d| !d.is_empty())
+}
+
+/// Keeps the window titled `title` above others (or stops), where the compositor allows it.
+pub fn set_keep_above(title: &str, above: bool) -> Result<(), String> {
+    let connection = Connection::session().map_err(|e| e.to_string())?;
+    let scripting = Proxy::new(&connection, KWIN_DEST, KWIN_SCRIPTING_PATH, KWIN_SCRIPTING_IFACE)
+        .map_err(|e| e.to_string())?;
+    // Only KWin has the scripting interface; anything else fails the first call
+    let unload = scripting.call::<_, _, bool>("unloadScript", &(SCRIPT_NAME,));
+    if unload.is_err() {
+        return Err("this compositor doesn't let apps keep themselves on top; use its window menu".to_string());
+    }
+
+    // KWin 6 has windowList, KWin 5 clientList
+    let title = serde_json::to_string(title).map_err(|e| e.to_string())?;
+    let script = format!(
+        "const windows = workspace.windowList ? workspace.windowList() : workspace.clientList();\n\
+         for (const w of windows) {{ if (w.caption === {}) {{ w.keepAbove = {}; }} }}\n",
+        title, above
+    );
+    let path = std::env::temp_dir().join(format!("{}.js", SCRIPT_NAME));
+    fs::write(&path, script).map_err(|e| e.to_string())?;
+    let id: i32 = scripting
+        .call("loadScript", &(path.to_string_lossy().as_ref(), SCRIPT_NAME))
+        .map_err(|e| e.to_string())?;
+    if id < 0 {
+        return Err("KWin refused the script".to_string());
+    }
+    // The script object's path changed between KWin 5 and 6
+    let run = |path: String| -> zbus::Result<()> {
+        Proxy::new(&connection, KWIN_DEST, path, KWIN_SCRIPT_IFACE)?.call("run", &())
+    };
+    run(format!("/Scripting/Script{}", id))
+        .or_else(|_| run(format!("/{}", id)))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,85 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::net::{digests_match, is_local_origin, localhost_if_bare_port};
+use crate::remote::{self, Watcher};
+use crate::Engine;
+
+// WebSocket server for remote control (remote.rs has the commands). Each client gets a
+// thread that answers its commands and pushes state changes (notes, transpose, solver,
+// playback) as they happen, starting with the whole state right after connecting. With a
+// token set, clients connect to `ws://host:port/?token=<token>`; without one, anyone who can
+// reach the address is in control, so keep it on localhost. Browsers are only let in from
+// pages on this machine, so a site open in another tab can't take over.
+
+// How often a client's thread looks for state changes when it isn't sent anything
+const POLL: Duration = Duration::from_millis(20);
+
+// Lets a client in when it brings the token, or when there's none to bring, unless it's a
+// browser on some other site
+struct TokenCheck(Option<String>);
+
+impl Callback for TokenCheck {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let origin = request.headers().get("Origin").map(|origin| origin.to_str().unwrap_or_default());
+        if origin.is_some_and(|origin| !is_local_origin(origin)) {
+            let mut refused = ErrorResponse::new(Some("connections from other sites aren't allowed".to_string()));
+            *refused.status_mut() = StatusCode::FORBIDDEN;
+            return Err(refused);
+        }
+        let Some(token) = self.0 else { return Ok(response) };
+        let given = request.uri().query().and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+        if given.is_some_and(|given| digests_match(given, &token)) {
+            return Ok(response);
+        }
+        let mut refused = ErrorResponse::new(Some("bad or missing token".to_string()));
+        *refused.status_mut() = StatusCode::UNAUTHORIZED;
+        Err(refused)
+    }
+}
+
+fn serve_client(engine: Arc<Engine>, stream: TcpStream, token: Option<String>) -> io::Result<()> {
+    let mut socket: WebSocket<TcpStream> = tungstenite::accept_hdr(stream, TokenCheck(token)).map_err(io::Error::other)?;
+    socket.get_ref().set_read_timeout(Some(POLL))?;
+    let mut watcher = Watcher::default();
+    loop {
+        let reply = match socket.read() {
+            Ok(Message::Text(text)) => Some(remote::handle(&engine, &text)),
+            Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Ok(_) => None,
+            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => None,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let events = watcher.poll(&engine).into_iter().map(|event| serde_json::to_string(&event).unwrap_or_default());
+        for text in reply.into_iter().chain(events) {
+            socket.send(Message::text(text)).map_err(io::Error::other)?;
+        }
+    }
+}
+
+/// Listens on `addr` (port 0 picks a free one) on a background thread, a thread per client.
+/// A bare port listens on localhost only. Returns the address it's listening on.
+pub fn spawn(shared: Arc<Engine>, addr: &str, token: Option<String>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(localhost_if_bare_port(addr))?;
+    let local = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (engine, token) = (shared.clone(), token.clone());
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                if let Err(e) = serve_client(engine, stream, token) {
+                    eprintln!("Remote {}: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(local)
+}
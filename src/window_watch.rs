@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::config;
+use crate::Engine;
+
+// Polls the focused X11 window's title and switches to the profile of the first matching rule.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+struct TitleReader {
+    conn: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    net_wm_name: Atom,
+    utf8_string: Atom,
+}
+
+impl TitleReader {
+    fn connect() -> Option<Self> {
+        let (conn, screen_num) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots.get(screen_num)?.root;
+        let intern = |name: &[u8]| -> Option<Atom> {
+            Some(conn.intern_atom(false, name).ok()?.reply().ok()?.atom)
+        };
+        let net_active_window = intern(b"_NET_ACTIVE_WINDOW")?;
+        let net_wm_name = intern(b"_NET_WM_NAME")?;
+        let utf8_string = intern(b"UTF8_STRING")?;
+        Some(Self { conn, root, net_active_window, net_wm_name, utf8_string })
+    }
+
+    fn active_title(&self) -> Option<String> {
+        let active = self
+            .conn
+            .get_property(false, self.root, self.net_active_window, AtomEnum::WINDOW, 0, 1)
+            .ok()?
+            .reply()
+            .ok()?;
+        let window = active.value32()?.next()?;
+        if window == x11rb::NONE {
+            return None;
+        }
+
+        // Prefer the EWMH UTF-8 title, fall back to the legacy WM_NAME
+        let read = |property: Atom, kind: Atom| -> Option<String> {
+            let reply = self.conn.get_property(false, window, property, kind, 0, 1024).ok()?.reply().ok()?;
+            if reply.value.is_empty() {
+                return None;
+            }
+            Some(String::from_utf8_lossy(&reply.value).into_owned())
+        };
+        read(self.net_wm_name, self.utf8_string)
+            .or_else(|| read(AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()))
+    }
+}
+
+pub fn spawn(shared: Arc<Engine>) {
+    thread::spawn(move || {
+        let Some(reader) = TitleReader::connect() else {
+            eprintln!("Window title detection unavailable: could not connect to the X server");
+            return;
+        };
+
+        // Remember a profile that failed to load so it isn't retried (and logged) every poll
+        let mut failed_profile: Option<String> = None;
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            if !shared.auto_profile_enabled.load(Ordering::Relaxed) {
+                continue;
+            }
+            let Some(title) = reader.active_title() else { continue };
+
+            let title_changed = {
+                let mut current = shared.active_window_title.lock().unwrap();
+                let changed = *current != title;
+                *current = title.clone();
+                changed
+            };
+
+            // Titles without a rule (including our own window) leave the current profile alone
+            let matched = {
+                let rules = shared.window_rules.lock().unwrap();
+                config::match_window_rule(&rules, &title).map(|r| r.profile.clone())
+            };
+            if let Some(profile) = matched {
+                let already_active = shared.active_profile.lock().unwrap().as_deref() == Some(profile.as_str());
+                if !already_active && failed_profile.as_ref() != Some(&profile) {
+                    match config::load_profile(&profile) {
+                        Ok(settings) => {
+                            shared.apply_settings(&settings);
+                            *shared.active_profile.lock().unwrap() = Some(profile);
+                            failed_profile = None;
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to load profile '{}': {}", profile, e);
+                            failed_profile = Some(profile);
+                        }
+                    }
+                    shared.request_repaint();
+                }
+            } else {
+                failed_profile = None;
+            }
+
+            if title_changed {
+                shared.request_repaint();
+            }
+        }
+    });
+}
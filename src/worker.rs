@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+use crate::Engine;
+
+// MIDI input callbacks only timestamp a message and queue it; a thread per engine plays the
+// queue in arrival order, so nothing piles up in the driver and messages from several ports
+// keep the order they arrived in. Notes that wait for the quantize grid go to the output
+// scheduler (scheduler.rs) instead of being waited on here; only the few milliseconds
+// between transpose taps hold up the queue.
+
+/// The way into an engine's worker. Clones feed the same worker; it stops once every clone
+/// is gone and the queue is played out.
+#[derive(Clone)]
+pub struct MidiQueue {
+    sender: Sender<(Instant, Vec<u8>)>,
+}
+
+impl MidiQueue {
+    /// Queues `message`, stamped with the time it arrived.
+    pub fn push(&self, message: &[u8]) {
+        // Only fails once the worker is gone, and then there's nothing left to play into
+        let _ = self.sender.send((Instant::now(), message.to_vec()));
+    }
+}
+
+/// Starts the worker for `engine`.
+pub fn spawn(engine: Arc<Engine>) -> MidiQueue {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || run(&engine, receiver));
+    MidiQueue { sender }
+}
+
+fn run(engine: &Engine, receiver: Receiver<(Instant, Vec<u8>)>) {
+    for (received_at, message) in receiver {
+        // Dropped notes are already in the stats; anything else is worth a line
+        if let Err(e) = engine.handle_received(&message, received_at)
+            && !e.is_dropped_note()
+        {
+            eprintln!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// The arpeggiator: held chords stepped through one note at a time on the tempo grid.
+
+use miditoroblox_rs::arpeggiator::Arpeggiator;
+use miditoroblox_rs::clock::Clock;
+use miditoroblox_rs::config::{ArpPattern, Settings};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::processor::NoteEvent;
+use miditoroblox_rs::quantize::Grid;
+use miditoroblox_rs::Engine;
+
+fn steps(pattern: ArpPattern, count: usize) -> Vec<u8> {
+    let mut arp = Arpeggiator::default();
+    for note in [64, 60, 67] {
+        arp.press(NoteEvent::on(0, note, 100), 0);
+    }
+    let grid = Grid::tempo(120, 4, 0);
+    let mut notes = Vec::new();
+    while notes.len() < count {
+        let due_us = arp.next_due().unwrap();
+        notes.extend(arp.tick(due_us, grid, pattern).into_iter().filter(|event| event.is_on()).map(|event| event.note));
+    }
+    notes
+}
+
+#[test]
+fn patterns_step_through_the_chord() {
+    assert_eq!(steps(ArpPattern::Up, 4), [60, 64, 67, 60]);
+    assert_eq!(steps(ArpPattern::Down, 4), [67, 64, 60, 67]);
+    assert_eq!(steps(ArpPattern::UpDown, 6), [60, 64, 67, 64, 60, 64]);
+    assert!(steps(ArpPattern::Random, 8).iter().all(|note| [60, 64, 67].contains(note)));
+}
+
+#[test]
+fn steps_let_go_of_the_last_note_and_stop_with_the_keys() {
+    let mut arp = Arpeggiator::default();
+    arp.press(NoteEvent::on(0, 60, 100), 1_000);
+    arp.press(NoteEvent::on(0, 64, 100), 1_000);
+    let grid = Grid::tempo(120, 2, 1_000);
+    assert_eq!(arp.tick(1_000, grid, ArpPattern::Up), [NoteEvent::on(0, 60, 100)]);
+    assert_eq!(arp.next_due(), Some(251_000));
+    assert!(arp.tick(250_000, grid, ArpPattern::Up).is_empty(), "not due yet");
+    assert_eq!(arp.tick(251_000, grid, ArpPattern::Up), [NoteEvent::off(0, 60), NoteEvent::on(0, 64, 100)]);
+
+    assert_eq!(arp.release(NoteEvent::off(0, 60)), None, "still holding one");
+    assert_eq!(arp.release(NoteEvent::off(0, 64)), Some(NoteEvent::off(0, 64)));
+    assert_eq!(arp.next_due(), None);
+}
+
+#[test]
+fn held_chords_play_one_key_at_a_time() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    // 16ths at 120 BPM: a step every 125 ms
+    engine.apply_settings(&Settings { base_mapping_enabled: true, arpeggiator_enabled: true, ..Settings::default() });
+
+    engine.inject(1_000_000, &[0x90, 60, 100]).unwrap();
+    engine.inject(1_000_000, &[0x90, 64, 100]).unwrap();
+    engine.inject(1_000_000, &[0x90, 67, 100]).unwrap();
+    engine.advance_to(1_300_000).unwrap();
+    let timed = spy.take_timed();
+    let downs: Vec<u64> = timed.iter().filter(|(_, _, value)| *value == 1).map(|(at_us, _, _)| *at_us).collect();
+    assert_eq!(downs, [1_000_000, 1_125_000, 1_250_000]);
+    let ups = timed.iter().filter(|(_, _, value)| *value == 0).count();
+    assert_eq!(ups, 2, "each step lets go of the one before");
+
+    for note in [60, 64, 67] {
+        engine.handle_message(&[0x80, note, 0]).unwrap();
+    }
+    let released = spy.take_timed();
+    assert_eq!(released.len(), 1);
+    assert_eq!(released[0].2, 0);
+    engine.advance_to(2_000_000).unwrap();
+    assert!(spy.take_timed().is_empty(), "stopped with the keys");
+}
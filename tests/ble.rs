@@ -0,0 +1,41 @@
+// BLE-MIDI packets: timestamps stripped, running status carried, sysex skipped.
+
+use miditoroblox_rs::ble::parse_packet;
+
+fn parse(packet: &[u8]) -> Vec<Vec<u8>> {
+    parse_packet(packet, &mut None)
+}
+
+#[test]
+fn timestamps_are_stripped() {
+    // Header, timestamp, note-on; then a second timestamp and a note-off
+    assert_eq!(parse(&[0x80, 0x81, 0x90, 60, 100, 0x82, 0x80, 60, 0]), [vec![0x90, 60, 100], vec![0x80, 60, 0]]);
+    assert_eq!(parse(&[0x80, 0x81, 0xC0, 5]), [vec![0xC0, 5]]);
+}
+
+#[test]
+fn running_status_carries_over() {
+    // Without a timestamp between them, and with a timestamp but no status
+    assert_eq!(parse(&[0x80, 0x81, 0x90, 60, 100, 64, 100, 0x82, 67, 100]), [vec![0x90, 60, 100], vec![0x90, 64, 100], vec![0x90, 67, 100]]);
+    let mut running = None;
+    parse_packet(&[0x80, 0x81, 0xB0, 64, 127], &mut running);
+    assert_eq!(running, Some(0xB0));
+    assert_eq!(parse_packet(&[0x80, 0x85, 64, 0], &mut running), [vec![0xB0, 64, 0]]);
+    // Data with no status to run on is dropped
+    assert_eq!(parse(&[0x80, 0x81, 60, 100]), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn real_time_and_sysex() {
+    // A clock tick doesn't end running status
+    assert_eq!(parse(&[0x80, 0x81, 0x90, 60, 100, 0x81, 0xF8, 0x82, 62, 100]), [vec![0x90, 60, 100], vec![0xF8], vec![0x90, 62, 100]]);
+    // Sysex, split by a timestamp, then a note
+    assert_eq!(parse(&[0x80, 0x81, 0xF0, 0x7E, 0x7F, 0x82, 0xF7, 0x83, 0x90, 60, 100]), [vec![0x90, 60, 100]]);
+}
+
+#[test]
+fn broken_packets_keep_what_came_before() {
+    assert_eq!(parse(&[0x00, 0x81, 0x90, 60, 100]), Vec::<Vec<u8>>::new(), "no header");
+    assert_eq!(parse(&[]), Vec::<Vec<u8>>::new());
+    assert_eq!(parse(&[0x80, 0x81, 0x90, 60, 100, 0x82, 0x90, 60]), [vec![0x90, 60, 100]], "cut short");
+}
@@ -0,0 +1,71 @@
+// The config directory: profiles saved and loaded by name, saved window state, and mapping
+// sets found by name and path. Where it is comes from the environment, so this binary has a
+// single test, which points it at a dir of its own before anything else runs.
+
+use std::path::PathBuf;
+
+use miditoroblox_rs::config::{self, Settings};
+use miditoroblox_rs::mappings::{self, MappingSet};
+
+// Removed when the test ends, passed or not
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn the_config_dir_keeps_what_is_saved() {
+    let dir = TempDir(std::env::temp_dir().join(format!("miditoroblox-config-{}", std::process::id())));
+    // Safety: the only test in this binary, so no other thread is reading the environment
+    unsafe { std::env::set_var("XDG_CONFIG_HOME", &dir.0) };
+    profiles_save_load_and_delete_by_name();
+    window_state_is_saved_for_the_next_start();
+    mapping_sets_are_found_by_name_and_path();
+}
+
+fn profiles_save_load_and_delete_by_name() {
+    let talent_show = Settings { solver_enabled: true, quantize_enabled: true, quantize_ms: 50, ..Settings::default() };
+    let robeats = Settings { transpose_range: 12, auto_transpose_enabled: true, ..Settings::default() };
+    config::save_profile("Talent Show 88-key", &talent_show).unwrap();
+    config::save_profile("RoBeats", &robeats).unwrap();
+
+    assert_eq!(config::list_profiles(), vec!["RoBeats".to_string(), "Talent Show 88-key".to_string()]);
+    assert_eq!(config::load_profile("Talent Show 88-key").unwrap(), talent_show);
+    assert_eq!(config::load_profile("RoBeats").unwrap(), robeats);
+
+    config::delete_profile("RoBeats").unwrap();
+    assert_eq!(config::list_profiles(), vec!["Talent Show 88-key".to_string()]);
+    assert!(config::load_profile("RoBeats").is_err());
+}
+
+fn window_state_is_saved_for_the_next_start() {
+    let state = config::WindowState { width: 1600.0, height: 900.0, x: Some(40.0), y: Some(20.0), opacity: 0.6, always_on_top: true };
+    config::save_window_state(&state).unwrap();
+    assert_eq!(config::load_window_state(), state);
+
+    // Older or hand-edited files fill in what they lack
+    let partial: config::WindowState = serde_json::from_str(r#"{"opacity": 0.5}"#).unwrap();
+    assert_eq!(partial, config::WindowState { opacity: 0.5, ..config::WindowState::default() });
+}
+
+fn mapping_sets_are_found_by_name_and_path() {
+    let mut no_shift = MappingSet::builtin();
+    let shifted: Vec<u8> = no_shift.iter().filter(|m| m.shift).map(|m| m.midi_note).collect();
+    for note in shifted {
+        no_shift.remove(note);
+    }
+    let path = no_shift.save_set("No Shift").unwrap();
+    MappingSet::builtin().save_set("88 Keys").unwrap();
+
+    assert_eq!(mappings::list_sets(), vec!["88 Keys".to_string(), "No Shift".to_string()]);
+    assert_eq!(mappings::set_name(&path.to_string_lossy()).as_deref(), Some("No Shift"));
+    assert_eq!(mappings::set_name("/somewhere/else.json"), None);
+    let settings = Settings { mappings_file: path.to_string_lossy().into_owned(), ..Settings::default() };
+    assert_eq!(MappingSet::for_settings(&settings), no_shift);
+
+    mappings::delete_set("No Shift").unwrap();
+    assert_eq!(mappings::list_sets(), vec!["88 Keys".to_string()]);
+}
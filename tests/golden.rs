@@ -0,0 +1,776 @@
+// Golden key sequences: canned MIDI in, exact key events out.
+//
+// Each case runs raw MIDI messages through a fresh engine wired to a spy output and compares
+// the emitted keys ("KEY_T+" = press, "KEY_T-" = release) with the expected sequence.
+
+use miditoroblox_rs::config::{ModifierRole, Settings};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::Engine;
+
+fn on(note: u8) -> [u8; 3] {
+    [0x90, note, 100]
+}
+
+fn off(note: u8) -> [u8; 3] {
+    [0x80, note, 0]
+}
+
+fn run(settings: Settings, messages: &[[u8; 3]]) -> Vec<String> {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&settings);
+    for message in messages {
+        // Dropped notes just produce no keys; that's what the cases check
+        let _ = engine.handle_message(message);
+    }
+    keys(&spy)
+}
+
+fn keys(spy: &SpyOutput) -> Vec<String> {
+    spy.take()
+        .into_iter()
+        .map(|(key, value)| format!("{:?}{}", key, if value == 0 { "-" } else { "+" }))
+        .collect()
+}
+
+fn base() -> Settings {
+    Settings { base_mapping_enabled: true, ..Settings::default() }
+}
+
+fn solver() -> Settings {
+    Settings { solver_enabled: true, ..Settings::default() }
+}
+
+#[test]
+fn white_key_press_and_release() {
+    assert_eq!(run(base(), &[on(60), off(60)]), ["KEY_T+", "KEY_T-"]);
+}
+
+#[test]
+fn black_key_taps_with_shift() {
+    // Shifted keys are tapped, so the note-off has nothing left to release
+    assert_eq!(
+        run(base(), &[on(61), off(61)]),
+        ["KEY_LEFTSHIFT+", "KEY_T+", "KEY_T-", "KEY_LEFTSHIFT-"]
+    );
+}
+
+#[test]
+fn black_key_experimental_transpose() {
+    let settings = Settings { experimental_transpose_enabled: true, ..base() };
+    assert_eq!(
+        run(settings, &[on(61), off(61)]),
+        ["KEY_UP+", "KEY_UP-", "KEY_T+", "KEY_DOWN+", "KEY_DOWN-", "KEY_T-"]
+    );
+}
+
+#[test]
+fn black_key_lazy_transpose_stays_up() {
+    let settings = Settings { experimental_transpose_enabled: true, lazy_transpose_enabled: true, ..base() };
+    assert_eq!(
+        run(settings, &[on(61), off(61), on(63), off(63), on(60), off(60)]),
+        [
+            "KEY_UP+", "KEY_UP-", "KEY_T+", "KEY_T-",
+            "KEY_Y+", "KEY_Y-",
+            "KEY_DOWN+", "KEY_DOWN-", "KEY_T+", "KEY_T-",
+        ]
+    );
+}
+
+#[test]
+fn low_range_uses_ctrl() {
+    let settings = Settings { low_mapping_enabled: true, ..Settings::default() };
+    assert_eq!(
+        run(settings, &[on(24), off(24)]),
+        ["KEY_LEFTCTRL+", "KEY_4+", "KEY_4-", "KEY_LEFTCTRL-"]
+    );
+}
+
+#[test]
+fn out_of_range_is_dropped() {
+    assert!(run(base(), &[on(24), off(24)]).is_empty());
+}
+
+#[test]
+fn auto_transpose_folds_into_range() {
+    let settings = Settings { auto_transpose_enabled: true, ..base() };
+    assert_eq!(run(settings, &[on(24), off(24)]), ["KEY_1+", "KEY_1-"]);
+}
+
+#[test]
+fn keys61_folds_extreme_octaves_in() {
+    use miditoroblox_rs::config::GameMode;
+    // Without auto-transpose or any range enabled, C1 and C8 still land on the 61-key board
+    let settings = Settings { game_mode: GameMode::Keys61, ..Settings::default() };
+    assert_eq!(run(settings, &[on(24), off(24), on(108), off(108)]), ["KEY_1+", "KEY_1-", "KEY_M+", "KEY_M-"]);
+}
+
+#[test]
+fn drum_rolls_alternate_keys() {
+    use miditoroblox_rs::config::GameMode;
+    let settings = Settings { game_mode: GameMode::Drums, ..Settings::default() };
+    let snare = |v| [0x99, 38, v];
+    assert_eq!(
+        run(settings, &[snare(100), snare(0), snare(100), snare(0), snare(100), [0x99, 41, 100]]),
+        ["KEY_F+", "KEY_F-", "KEY_J+", "KEY_J-", "KEY_F+", "KEY_F-", "KEY_L+", "KEY_L-"]
+    );
+}
+
+#[test]
+fn drum_channel_is_ignored() {
+    assert!(run(base(), &[[0x99, 60, 100], [0x89, 60, 0]]).is_empty());
+}
+
+#[test]
+fn solver_plays_without_transposing() {
+    assert_eq!(
+        run(solver(), &[on(60), off(60)]),
+        ["KEY_T+", "KEY_T-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-"]
+    );
+}
+
+#[test]
+fn solver_holds_shift_for_black_keys() {
+    assert_eq!(
+        run(solver(), &[on(61), off(61)]),
+        ["KEY_LEFTSHIFT+", "KEY_T+", "KEY_T-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-"]
+    );
+}
+
+#[test]
+fn solver_transposes_when_modifiers_conflict() {
+    // While C4 is held without Shift, C#4 can't use Shift. KEY_T is busy, so it comes from D4's
+    // key (KEY_Y) transposed down a semitone instead.
+    assert_eq!(
+        run(solver(), &[on(60), on(61), off(61), off(60)]),
+        [
+            "KEY_T+",
+            "KEY_DOWN+", "KEY_DOWN-", "KEY_Y+",
+            "KEY_Y-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-",
+            "KEY_T-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-",
+        ]
+    );
+}
+
+#[test]
+fn unplayable_notes_come_back_as_errors() {
+    use miditoroblox_rs::Error;
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    engine.apply_settings(&Settings { transpose_range: 0, ..solver() });
+    assert!(engine.handle_message(&on(60)).is_ok());
+    assert!(matches!(engine.handle_message(&on(10)), Err(Error::Solver { note: 10 })));
+    assert!(matches!(engine.handle_message(&[]), Err(Error::Midi(_))));
+}
+
+#[test]
+fn processors_rewrite_notes_before_routing() {
+    use miditoroblox_rs::processor::{NoteEvent, Processor};
+
+    // Doubles every note an octave up and swallows D4
+    struct Doubler;
+    impl Processor for Doubler {
+        fn name(&self) -> &str {
+            "Doubler"
+        }
+        fn process(&mut self, _at_us: u64, event: NoteEvent, out: &mut Vec<NoteEvent>) {
+            if event.note != 62 {
+                out.push(event);
+                out.push(NoteEvent { note: event.note + 12, ..event });
+            }
+        }
+    }
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&base());
+    engine.add_processor(Doubler);
+    for message in [on(60), off(60), on(62), off(62)] {
+        let _ = engine.handle_message(&message);
+    }
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_S+", "KEY_T-", "KEY_S-"]);
+}
+
+#[test]
+fn dual_drums_play_channel_10_on_the_drum_device() {
+    let piano = SpyOutput::new();
+    let drums = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(piano.clone()));
+    engine.apply_settings(&Settings { dual_drums_enabled: true, ..base() });
+    engine.router.lock().unwrap().drum_output = Some(KeyOutput::Spy(drums.clone()));
+    for message in [on(60), [0x99, 38, 100], [0x89, 38, 0], off(60)] {
+        let _ = engine.handle_message(&message);
+    }
+    assert_eq!(keys(&piano), ["KEY_T+", "KEY_T-"]);
+    assert_eq!(keys(&drums), ["KEY_F+", "KEY_F-"]);
+    assert!(engine.snapshot().held_keys.is_empty());
+}
+
+#[test]
+fn sostenuto_keeps_held_keys_down() {
+    use miditoroblox_rs::config::Trigger;
+
+    let pedal = |value: u8| [0xB0, 66, value];
+    let messages = [on(60), pedal(127), off(60), on(62), off(62), pedal(0)];
+    let keys = run(Settings { sostenuto_trigger: Trigger::Cc(66), ..base() }, &messages);
+    assert_eq!(keys, ["KEY_T+", "KEY_Y+", "KEY_Y-", "KEY_T-"]);
+}
+
+#[test]
+fn ctrl_as_sustain_follows_the_pedal() {
+    use miditoroblox_rs::config::ModifierRole;
+
+    let pedal = |value: u8| [0xB0, 64, value];
+    let settings = Settings { ctrl_role: ModifierRole::Sustain, ..solver() };
+    let keys = run(settings, &[pedal(127), pedal(100), on(60), off(60), pedal(0)]);
+    assert_eq!(keys, ["KEY_LEFTCTRL+", "KEY_LEFTCTRL-", "KEY_T+", "KEY_T-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL+", "KEY_LEFTCTRL-"]);
+}
+
+#[test]
+fn shift_as_accent_wraps_loud_notes() {
+    use miditoroblox_rs::config::ModifierRole;
+
+    let settings = Settings { shift_role: ModifierRole::Accent, ..base() };
+    let keys = run(settings, &[[0x90, 60, 120], off(60), [0x90, 62, 80], off(62), on(61)]);
+    // C#4 would need Shift for its own key, so it's dropped
+    assert_eq!(keys, ["KEY_LEFTSHIFT+", "KEY_T+", "KEY_LEFTSHIFT-", "KEY_T-", "KEY_Y+", "KEY_Y-"]);
+}
+
+#[test]
+fn volume_keys_follow_a_cc() {
+    use miditoroblox_rs::config::VolumeSource;
+
+    let settings = Settings { volume_source: VolumeSource::Cc(7), volume_steps: 3, ..base() };
+    let volume = |value: u8| [0xB0, 7, value];
+    // The first change walks down to the bottom before counting up
+    let keys = run(settings, &[volume(127), volume(60), volume(70)]);
+    let down = ["KEY_LEFT+", "KEY_LEFT-"];
+    let up = ["KEY_RIGHT+", "KEY_RIGHT-"];
+    assert_eq!(keys, [down, down, up, up, down].concat());
+}
+
+#[test]
+fn octave_shift_sticks_with_the_held_note() {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&base());
+    engine.shift_octaves(1);
+    let _ = engine.handle_message(&on(48));
+    // Shifting back mid-note must still release the key the note-on pressed
+    engine.shift_octaves(-1);
+    let _ = engine.handle_message(&off(48));
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&off(60));
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_T-", "KEY_T+", "KEY_T-"]);
+}
+
+#[test]
+fn transpose_cc_taps_to_follow_the_knob() {
+    let settings = Settings { transpose_cc: Some(1), transpose_cc_range: 2, ..base() };
+    let knob = |value: u8| [0xB0, 1, value];
+    // +2, then +1, then -2; notes play on the same keys throughout
+    let keys = run(settings, &[knob(127), knob(96), knob(0), on(60), off(60)]);
+    let up = ["KEY_UP+", "KEY_UP-"];
+    let down = ["KEY_DOWN+", "KEY_DOWN-"];
+    assert_eq!(keys, [up, up, down, down, down, down, ["KEY_T+", "KEY_T-"]].concat());
+}
+
+#[test]
+fn channel_gain_lifts_a_quiet_part_into_accents() {
+    use miditoroblox_rs::config::{ChannelDynamics, ModifierRole};
+
+    let mut settings = Settings { shift_role: ModifierRole::Accent, ..base() };
+    settings.channel_dynamics[1] = ChannelDynamics { gain: 150, compression: 0 };
+    // The same velocity on channel 1 stays unaccented, on channel 2 it's boosted past the threshold
+    let keys = run(settings, &[[0x90, 60, 80], [0x80, 60, 0], [0x91, 60, 80], [0x81, 60, 0]]);
+    assert_eq!(keys, ["KEY_T+", "KEY_T-", "KEY_LEFTSHIFT+", "KEY_T+", "KEY_LEFTSHIFT-", "KEY_T-"]);
+}
+
+#[test]
+fn keep_alive_taps_after_the_idle_time() {
+    use miditoroblox_rs::clock::Clock;
+
+    let spy = SpyOutput::new();
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), Clock::new_virtual());
+    engine.apply_settings(&Settings { keep_alive_minutes: 5, ..base() });
+    let minute = 60_000_000;
+    assert!(!engine.keep_alive().unwrap()); // Starts the idle timer
+    engine.advance_to(4 * minute).unwrap();
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&off(60));
+    // Playing restarts the timer
+    engine.advance_to(8 * minute).unwrap();
+    assert!(!engine.keep_alive().unwrap());
+    engine.advance_to(9 * minute).unwrap();
+    assert!(engine.keep_alive().unwrap());
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_T-", "KEY_PAGEDOWN+", "KEY_PAGEDOWN-"]);
+}
+
+#[test]
+fn duet_players_keep_their_own_transpose() {
+    use miditoroblox_rs::config::ModifierRole;
+
+    // Duet mode runs one engine per player; neither should see the other's transpose
+    let settings = Settings { shift_role: ModifierRole::Accent, ..solver() };
+    let (spy_1, spy_2) = (SpyOutput::new(), SpyOutput::new());
+    let player_1 = Engine::new(KeyOutput::Spy(spy_1.clone()));
+    let player_2 = Engine::new(KeyOutput::Spy(spy_2.clone()));
+    player_1.apply_settings(&settings);
+    player_2.apply_settings(&settings);
+    // C#4 has no unshifted key, so player 1's solver transposes to reach it
+    let _ = player_1.handle_message(&[0x90, 61, 80]);
+    let _ = player_2.handle_message(&[0x90, 60, 80]);
+    assert_ne!(player_1.snapshot().transpose, 0);
+    assert_eq!(player_2.snapshot().transpose, 0);
+    assert_eq!(keys(&spy_2), ["KEY_T+"]);
+}
+
+#[test]
+fn custom_backends_get_the_same_keys() {
+    use evdev::InputEvent;
+    use miditoroblox_rs::output::OutputBackend;
+    use std::sync::{Arc, Mutex};
+
+    // A backend from outside the crate, seeing raw events
+    type Writes = Vec<Vec<(u16, i32)>>;
+    struct Counter(Arc<Mutex<Writes>>);
+    impl OutputBackend for Counter {
+        fn emit(&mut self, events: &[InputEvent]) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(events.iter().map(|e| (e.code(), e.value())).collect());
+            Ok(())
+        }
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let engine = Engine::new(KeyOutput::Custom(Box::new(Counter(seen.clone()))));
+    engine.apply_settings(&base());
+    let _ = engine.handle_message(&on(61));
+    let shift = evdev::KeyCode::KEY_LEFTSHIFT.code();
+    let t = evdev::KeyCode::KEY_T.code();
+    // Shift+T is one write, a frame per key event (SYN_REPORT is code 0, value 0)
+    assert_eq!(*seen.lock().unwrap(), [vec![(shift, 1), (0, 0), (t, 1), (0, 0), (t, 0), (0, 0), (shift, 0)]]);
+}
+
+#[test]
+fn edited_mappings_play_from_the_next_note() {
+    use evdev::KeyCode;
+    use miditoroblox_rs::mappings::{KeyMapping, MappingSet};
+
+    // What the editor saves reads back as the same set
+    let builtin = MappingSet::builtin();
+    assert_eq!(MappingSet::from_json(&builtin.to_json()).unwrap(), builtin);
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&base());
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&off(60));
+    let mut edited = builtin.clone();
+    edited.insert(KeyMapping { midi_note: 60, key_code: KeyCode::KEY_SPACE, shift: false, ctrl: false });
+    engine.set_mappings(edited.clone());
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&off(60));
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_T-", "KEY_SPACE+", "KEY_SPACE-"]);
+
+    // The solver plays with them too
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&solver());
+    engine.set_mappings(edited);
+    let _ = engine.handle_message(&on(60));
+    assert_eq!(keys(&spy), ["KEY_SPACE+"]);
+}
+
+#[test]
+fn mapping_files_load_from_settings_and_bad_ones_are_refused() {
+    let dir = std::env::temp_dir().join(format!("miditoroblox-mappings-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let good = dir.join("good.json");
+    let bad = dir.join("bad.json");
+    std::fs::write(&good, r#"[{"midi_note": 60, "key": "KEY_SPACE", "shift": false, "ctrl": false}]"#).unwrap();
+    std::fs::write(&bad, r#"[{"midi_note": 60, "key": "KEY_SPACE", "shift": true, "ctrl": true}]"#).unwrap();
+
+    let settings = Settings { mappings_file: good.to_string_lossy().into_owned(), ..base() };
+    assert_eq!(run(settings.clone(), &[on(60), off(60), on(62)]), ["KEY_SPACE+", "KEY_SPACE-"]);
+
+    // A file that can't be played as written leaves the current mappings alone
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&settings);
+    assert!(engine.use_mappings_file(&bad.to_string_lossy()).is_err());
+    assert_eq!(engine.settings().mappings_file, settings.mappings_file);
+    let _ = engine.handle_message(&on(60));
+    assert_eq!(keys(&spy), ["KEY_SPACE+"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn switching_mappings_releases_what_the_old_ones_held() {
+    use evdev::KeyCode;
+    use miditoroblox_rs::mappings::{KeyMapping, MappingSet};
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&base());
+    let _ = engine.handle_message(&on(60));
+    let mut other = MappingSet::builtin();
+    other.insert(KeyMapping { midi_note: 60, key_code: KeyCode::KEY_SPACE, shift: false, ctrl: false });
+    engine.set_mappings(other);
+    // KEY_T is let go on the switch; the note-off then goes to the new key
+    let _ = engine.handle_message(&off(60));
+    let _ = engine.handle_message(&on(60));
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_T-", "KEY_SPACE-", "KEY_SPACE+"]);
+}
+
+#[test]
+fn midi_learn_takes_a_note_then_a_key() {
+    use evdev::KeyCode;
+    use miditoroblox_rs::mappings::{KeyMapping, LearnStep};
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&base());
+    assert!(!engine.learn_key(KeyCode::KEY_SPACE, false, false), "no note yet");
+
+    *engine.mapping_learn.lock().unwrap() = LearnStep::WaitingForNote;
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&on(61));
+    let _ = engine.handle_message(&off(61));
+    assert_eq!(*engine.mapping_learn.lock().unwrap(), LearnStep::WaitingForKey(61));
+    assert!(!engine.learn_key(KeyCode::KEY_LEFTSHIFT, true, false), "modifiers alone aren't keys");
+    assert!(engine.learn_key(KeyCode::KEY_SPACE, true, false));
+    assert_eq!(
+        *engine.mapping_learn.lock().unwrap(),
+        LearnStep::Learned(KeyMapping { midi_note: 61, key_code: KeyCode::KEY_SPACE, shift: true, ctrl: false })
+    );
+    // Nothing played while learning
+    assert_eq!(keys(&spy), Vec::<String>::new());
+
+    *engine.mapping_learn.lock().unwrap() = LearnStep::Off;
+    let _ = engine.handle_message(&on(60));
+    assert_eq!(keys(&spy), ["KEY_T+"]);
+}
+
+fn pedal(down: bool) -> [u8; 3] {
+    [0xB0, 64, if down { 127 } else { 0 }]
+}
+
+#[test]
+fn sustain_pedal_holds_its_key() {
+    let settings = Settings { sustain_key_enabled: true, ..base() };
+    assert_eq!(
+        run(settings, &[pedal(true), pedal(true), on(60), off(60), pedal(false)]),
+        ["KEY_SPACE+", "KEY_T+", "KEY_T-", "KEY_SPACE-"]
+    );
+}
+
+#[test]
+fn sustain_pedal_holds_notes_until_it_comes_up() {
+    let settings = Settings { sustain_hold_notes: true, sustain_key_enabled: true, ..base() };
+    assert_eq!(
+        run(settings, &[on(60), pedal(true), off(60), on(62), off(62), pedal(false), on(64), off(64)]),
+        ["KEY_T+", "KEY_SPACE+", "KEY_Y+", "KEY_SPACE-", "KEY_T-", "KEY_Y-", "KEY_U+", "KEY_U-"]
+    );
+}
+
+fn bend(value: u16) -> [u8; 3] {
+    [0xE0, (value & 0x7F) as u8, (value >> 7) as u8]
+}
+
+#[test]
+fn pitch_bend_taps_a_temporary_transpose() {
+    let settings = Settings { pitch_bend_range: 2, ..base() };
+    // Full bend up is a whole tone; halfway down a semitone; centered is back to none
+    assert_eq!(
+        run(settings, &[bend(16383), on(60), off(60), bend(4096), bend(8192)]),
+        ["KEY_UP+", "KEY_UP-", "KEY_UP+", "KEY_UP-", "KEY_T+", "KEY_T-", "KEY_DOWN+", "KEY_DOWN-", "KEY_DOWN+", "KEY_DOWN-", "KEY_DOWN+", "KEY_DOWN-", "KEY_UP+", "KEY_UP-"]
+    );
+}
+
+#[test]
+fn the_solver_counts_the_bend_in_its_transpose() {
+    let settings = Settings { pitch_bend_range: 2, ..solver() };
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&settings);
+    let _ = engine.handle_message(&bend(16383));
+    assert_eq!(engine.router.lock().unwrap().solver.current_transpose, 2);
+    // The game is two up, so C4 is played on the key for A#3 rather than bent to D4
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&off(60));
+    let _ = engine.handle_message(&bend(8192));
+    assert_eq!(engine.router.lock().unwrap().solver.current_transpose, 0);
+    assert_eq!(
+        keys(&spy),
+        [
+            "KEY_UP+", "KEY_UP-", "KEY_UP+", "KEY_UP-",
+            "KEY_LEFTSHIFT+", "KEY_E+", "KEY_E-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-",
+            "KEY_DOWN+", "KEY_DOWN-", "KEY_DOWN+", "KEY_DOWN-",
+        ]
+    );
+}
+
+#[test]
+fn velocity_gate_drops_ghost_notes_and_their_note_offs() {
+    let settings = Settings { min_velocity: 6, ..base() };
+    let ghost = [0x90, 60, 3];
+    // Neither the ghost nor its note-off reach the keys; a note right at the gate plays
+    assert_eq!(
+        run(settings, &[ghost, [0x90, 62, 80], off(60), off(62), [0x90, 60, 6], off(60)]),
+        ["KEY_Y+", "KEY_Y-", "KEY_T+", "KEY_T-"]
+    );
+}
+
+#[test]
+fn channel_routes_pick_what_plays_shows_or_goes_nowhere() {
+    use miditoroblox_rs::config::ChannelRoute;
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    let mut channel_routes = ChannelRoute::defaults();
+    channel_routes[0] = ChannelRoute::Ignore;
+    channel_routes[1] = ChannelRoute::Visualize;
+    channel_routes[9] = ChannelRoute::Play;
+    engine.apply_settings(&Settings { channel_routes, ..base() });
+    for message in [[0x90, 60, 100], [0x91, 62, 100], [0x99, 64, 100], [0x89, 64, 0]] {
+        let _ = engine.handle_message(&message);
+    }
+    // Channel 10 plays on the piano, channel 2 is only shown, channel 1 isn't even shown
+    assert_eq!(keys(&spy), ["KEY_U+", "KEY_U-"]);
+    let shown = engine.active_notes.lock().unwrap().clone();
+    assert!(shown.contains(&62) && !shown.contains(&60));
+}
+
+#[test]
+fn midi_thru_sends_the_notes_the_game_sounds() {
+    use miditoroblox_rs::thru::ThruBackend;
+    use std::sync::{Arc, Mutex};
+
+    struct Thru(Arc<Mutex<Vec<Vec<u8>>>>);
+    impl ThruBackend for Thru {
+        fn send(&mut self, message: &[u8]) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(message.to_vec());
+            Ok(())
+        }
+    }
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    engine.apply_settings(&Settings { auto_transpose_enabled: true, ..base() });
+    engine.router.lock().unwrap().thru = Some(Box::new(Thru(sent.clone())));
+    // The low A is out of range and folds up to the A♭ the game plays; channels and velocities stay
+    for message in [on(60), [0x91, 20, 90], off(60), [0x81, 20, 0]] {
+        let _ = engine.handle_message(&message);
+    }
+    assert_eq!(*sent.lock().unwrap(), [[0x90, 60, 100], [0x91, 44, 90], [0x80, 60, 0], [0x81, 44, 0]]);
+}
+
+#[test]
+fn panic_trigger_releases_everything_and_undoes_the_transpose() {
+    use miditoroblox_rs::config::Trigger;
+
+    let settings = Settings { panic_trigger: Trigger::Cc(119), solver_mode_efficiency: false, ..solver() };
+    // C# is played as D with the game transposed down; the note-offs after the panic do nothing
+    let keys = run(settings, &[on(60), on(61), [0xB0, 119, 127], off(60), off(61)]);
+    assert_eq!(
+        keys,
+        [
+            "KEY_T+", "KEY_DOWN+", "KEY_DOWN-", "KEY_Y+",
+            "KEY_T-", "KEY_Y-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-", "KEY_UP+", "KEY_UP-",
+        ]
+    );
+}
+
+#[test]
+fn exiting_releases_keys_even_under_the_sustain_pedal() {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&Settings { sustain_hold_notes: true, ..base() });
+    for message in [on(60), pedal(true), off(60), on(62)] {
+        let _ = engine.handle_message(&message);
+    }
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_Y+"]);
+    engine.release_on_exit();
+    assert_eq!(keys(&spy), ["KEY_T-", "KEY_Y-", "KEY_LEFTSHIFT-", "KEY_LEFTCTRL-"]);
+}
+
+#[test]
+fn the_worker_plays_queued_messages_in_order() {
+    use miditoroblox_rs::worker;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    let spy = SpyOutput::new();
+    let engine = Arc::new(Engine::new(KeyOutput::Spy(spy.clone())));
+    engine.apply_settings(&base());
+    let queue = worker::spawn(engine.clone());
+    for message in [on(60), on(62), off(60), off(62)] {
+        queue.push(&message);
+    }
+    drop(queue);
+    // The worker goes away with the last queue, once everything is played
+    let started = Instant::now();
+    while Arc::strong_count(&engine) > 1 && started.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(keys(&spy), ["KEY_T+", "KEY_Y+", "KEY_T-", "KEY_Y-"]);
+}
+
+#[test]
+fn solver_can_drop_instead_of_stealing() {
+    // With no room to transpose, a repeated C4 can only come from KEY_T, which is still held
+    let settings = Settings { solver_mode_efficiency: false, transpose_range: 0, ..solver() };
+    assert_eq!(run(settings.clone(), &[on(60), on(60)]), ["KEY_T+", "KEY_T-", "KEY_T+"]);
+    assert_eq!(run(Settings { solver_drop_steals: true, ..settings }, &[on(60), on(60)]), ["KEY_T+"]);
+}
+
+#[test]
+fn solver_keeps_the_transpose_under_held_keys() {
+    // C#4 without Shift needs a transpose, which would retune the held C4
+    let settings = Settings { shift_role: ModifierRole::Sustain, solver_hold_transpose: true, ..solver() };
+    assert_eq!(run(settings.clone(), &[on(60), on(61)]), ["KEY_T+"]);
+    assert_eq!(run(settings, &[on(60), off(60), on(61)]), ["KEY_T+", "KEY_T-", "KEY_LEFTCTRL-", "KEY_UP+", "KEY_UP-", "KEY_T+"]);
+}
+
+#[test]
+fn solver_jumps_less_in_fast_runs() {
+    use miditoroblox_rs::clock::Clock;
+
+    // C#4 without Shift is a semitone away. Right after C4 the tap would be late, which
+    // costs more than the one-semitone jump allows; half a second later it fits.
+    let settings = Settings { shift_role: ModifierRole::Sustain, solver_max_jump: 3, ..solver() };
+    let play = |gap_us: u64| {
+        let spy = SpyOutput::new();
+        let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), Clock::new_virtual());
+        engine.apply_settings(&settings);
+        let _ = engine.inject(0, &on(60));
+        let _ = engine.inject(gap_us / 2, &off(60));
+        let _ = engine.inject(gap_us, &on(61));
+        keys(&spy)
+    };
+    assert_eq!(play(2_000), ["KEY_T+", "KEY_T-", "KEY_LEFTCTRL-"]);
+    assert_eq!(play(500_000), ["KEY_T+", "KEY_T-", "KEY_LEFTCTRL-", "KEY_UP+", "KEY_UP-", "KEY_T+"]);
+}
+
+#[test]
+fn solver_log_explains_each_note() {
+    use miditoroblox_rs::solver::Rejection;
+
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&Settings { shift_role: ModifierRole::Sustain, ..solver() });
+    engine.solver_log_enabled.store(true, std::sync::atomic::Ordering::Relaxed);
+    let _ = engine.handle_message(&on(60));
+    let _ = engine.handle_message(&on(61));
+    let log = engine.solver_log.lock().unwrap();
+    let decisions: Vec<String> = log.entries.iter().map(|d| d.describe()).collect();
+    assert_eq!(decisions, ["C4 (at +0) -> KEY_T at +0", "C#4 (at +0) -> KEY_Y at -1"]);
+    // KEY_T is held, and stealing it a semitone up costs more than the max jump
+    let on_t = log.entries[1].candidates.iter().find(|c| c.transpose == 1).unwrap();
+    assert_eq!((on_t.key, on_t.outcome), (evdev::KeyCode::KEY_T, Err(Rejection::TooFar)));
+    assert!(log.entries[1].candidates.iter().any(|c| c.outcome == Err(Rejection::OutOfRange)));
+}
+
+#[test]
+fn folding_wraps_each_end_on_its_own() {
+    // C1 folds up to C2 and C8 down to C7; only the end that's switched on folds
+    let low = Settings { fold_low_notes: true, ..base() };
+    assert_eq!(run(low.clone(), &[on(24), off(24), on(108), off(108)]), ["KEY_1+", "KEY_1-"]);
+    let high = Settings { fold_high_notes: true, ..base() };
+    assert_eq!(run(high, &[on(24), off(24), on(108), off(108)]), ["KEY_M+", "KEY_M-"]);
+    // In range notes are untouched
+    assert_eq!(run(low, &[on(36), off(36)]), ["KEY_1+", "KEY_1-"]);
+}
+
+#[test]
+fn snapping_moves_wrong_notes_onto_the_scale() {
+    use miditoroblox_rs::config::ScaleMode;
+    let c_major = Settings { snap_key: Some(0), ..base() };
+    // C# is between C and D, so it takes the lower; its note-off lets go of the same key
+    assert_eq!(run(c_major.clone(), &[on(61), off(61)]), run(base(), &[on(60), off(60)]));
+    // E is in the scale and stays
+    assert_eq!(run(c_major, &[on(64), off(64)]), run(base(), &[on(64), off(64)]));
+    // In A minor pentatonic, B is a semitone from C and a whole tone from A
+    let a_minor_pentatonic = Settings { snap_key: Some(9), snap_scale: ScaleMode::MinorPentatonic, ..base() };
+    assert_eq!(run(a_minor_pentatonic, &[on(59), off(59)]), run(base(), &[on(60), off(60)]));
+}
+
+#[test]
+fn black_keys_fold_onto_white_neighbours() {
+    use miditoroblox_rs::config::BlackKeys;
+    // C# plays C's key or D's key, with no Shift
+    let down = Settings { black_keys: BlackKeys::FoldDown, ..base() };
+    assert_eq!(run(down, &[on(61), off(61)]), run(base(), &[on(60), off(60)]));
+    let up = Settings { black_keys: BlackKeys::FoldUp, ..base() };
+    assert_eq!(run(up.clone(), &[on(61), off(61)]), run(base(), &[on(62), off(62)]));
+    // White keys are left alone
+    assert_eq!(run(up, &[on(64), off(64)]), run(base(), &[on(64), off(64)]));
+}
+
+#[test]
+fn solver_plays_folded_black_keys_without_shift() {
+    use miditoroblox_rs::config::BlackKeys;
+    let settings = Settings { black_keys: BlackKeys::FoldDown, ..solver() };
+    assert_eq!(run(settings, &[on(61), off(61)]), run(solver(), &[on(60), off(60)]));
+}
+
+#[test]
+fn channel_transpose_moves_one_part() {
+    let mut settings = base();
+    settings.channel_transpose[1] = -12;
+    // C5 on channel 2 plays as C4, and its note-off lets go of the same key; channel 1 is untouched
+    let keys = run(settings, &[[0x91, 72, 100], [0x81, 72, 0], [0x90, 72, 100], [0x80, 72, 0]]);
+    let expected = [run(base(), &[on(60), off(60)]), run(base(), &[on(72), off(72)])].concat();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn transpose_nudges_tap_and_count_as_the_current_transpose() {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&solver());
+    engine.nudge_transpose(2).unwrap();
+    engine.nudge_transpose(-1).unwrap();
+    assert_eq!(keys(&spy), ["KEY_UP+", "KEY_UP-", "KEY_UP+", "KEY_UP-", "KEY_DOWN+", "KEY_DOWN-"]);
+    assert_eq!(engine.game_transpose(), 1);
+    assert_eq!(engine.router.lock().unwrap().solver.current_transpose, 1);
+    assert_eq!(engine.router.lock().unwrap().current_transpose_offset, 1);
+}
+
+#[test]
+fn transpose_triggers_nudge_on_press_only() {
+    use miditoroblox_rs::config::Trigger;
+    let settings = Settings { transpose_octave_down_trigger: Trigger::Cc(20), transpose_up_trigger: Trigger::Note(21), ..base() };
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&settings);
+    for message in [[0xB0, 20, 127], [0xB0, 20, 0], [0x90, 21, 100], [0x80, 21, 0]] {
+        engine.handle_message(&message).unwrap();
+    }
+    // The note is a button, not something to play
+    let taps = keys(&spy);
+    assert_eq!(taps.iter().filter(|k| *k == "KEY_DOWN+").count(), 12);
+    assert_eq!(taps.iter().filter(|k| *k == "KEY_UP+").count(), 1);
+    assert_eq!(taps.len(), 26);
+    assert_eq!(engine.game_transpose(), -11);
+}
+
+#[test]
+fn transpose_taps_go_to_the_configured_keys() {
+    let settings = Settings {
+        transpose_up_key: "KEY_RIGHTBRACE".to_string(),
+        transpose_down_key: "KEY_LEFTBRACE".to_string(),
+        ..solver()
+    };
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&settings);
+    engine.nudge_transpose(1).unwrap();
+    engine.nudge_transpose(-1).unwrap();
+    assert_eq!(keys(&spy), ["KEY_RIGHTBRACE+", "KEY_RIGHTBRACE-", "KEY_LEFTBRACE+", "KEY_LEFTBRACE-"]);
+    // The panic taps back with them too
+    engine.nudge_transpose(2).unwrap();
+    spy.take();
+    engine.panic().unwrap();
+    assert_eq!(keys(&spy).iter().filter(|k| *k == "KEY_LEFTBRACE+").count(), 2);
+}
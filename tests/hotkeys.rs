@@ -0,0 +1,41 @@
+// Hotkeys: which action a key runs, which keyboards they're read from, and checking the file.
+
+use evdev::KeyCode;
+use miditoroblox_rs::check;
+use miditoroblox_rs::config::Hotkeys;
+use miditoroblox_rs::hotkeys::{self, HotkeyAction};
+
+#[test]
+fn keys_pick_their_action() {
+    let bound = Hotkeys { transpose_up: "KEY_F9".to_string(), toggle_solver: "KEY_F10".to_string(), ..Hotkeys::default() };
+    assert_eq!(hotkeys::action_for(&bound, KeyCode::KEY_KPPLUS), Some(HotkeyAction::OctaveUp));
+    assert_eq!(hotkeys::action_for(&bound, KeyCode::KEY_F9), Some(HotkeyAction::TransposeUp));
+    assert_eq!(hotkeys::action_for(&bound, KeyCode::KEY_F10), Some(HotkeyAction::ToggleSolver));
+    assert_eq!(hotkeys::action_for(&bound, KeyCode::KEY_F11), None, "unbound actions are empty and match nothing");
+    let names: Vec<_> = HotkeyAction::ALL.iter().map(|a| a.name()).collect();
+    assert_eq!(names, ["octave_up", "octave_down", "transpose_up", "transpose_down", "transpose_octave_up", "transpose_octave_down", "toggle_solver", "pause_playback", "panic"]);
+}
+
+#[test]
+fn a_picked_keyboard_is_the_only_one_read() {
+    assert!(hotkeys::listens_on(&Hotkeys::default(), "AT Translated Set 2 keyboard"));
+    let numpad = Hotkeys { keyboard: "numpad".to_string(), ..Hotkeys::default() };
+    assert!(hotkeys::listens_on(&numpad, "USB NumPad"));
+    assert!(!hotkeys::listens_on(&numpad, "AT Translated Set 2 keyboard"));
+}
+
+#[test]
+fn check_allows_unbound_and_flags_unknown_keys() {
+    assert!(check::check_hotkeys(r#"{"transpose_up": "", "keyboard": "numpad"}"#).is_empty());
+    let problems = check::check_hotkeys(r#"{"pause_playback": "KEY_NOPE"}"#);
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].message.contains("pause_playback"));
+}
+
+#[test]
+fn grab_is_off_unless_asked_for() {
+    assert!(!Hotkeys::default().grab);
+    let hotkeys: Hotkeys = serde_json::from_str(r#"{"keyboard": "numpad", "grab": true}"#).unwrap();
+    assert!(hotkeys.grab);
+    assert_eq!(hotkeys.panic, "KEY_PAUSE", "the rest keeps its defaults");
+}
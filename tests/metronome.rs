@@ -0,0 +1,41 @@
+// Beat timing of the metronome, driven with explicit clock times.
+
+use miditoroblox_rs::metronome::Metronome;
+use miditoroblox_rs::midifile::MidiFile;
+
+// Times (ms) in 0..until at which `poll` reported a beat, polled every millisecond
+fn beats(metronome: &mut Metronome, quantize_ms: u64, until_ms: u64) -> Vec<(u64, bool)> {
+    (0..until_ms)
+        .filter_map(|ms| metronome.poll(ms * 1000, quantize_ms).map(|downbeat| (ms, downbeat)))
+        .collect()
+}
+
+#[test]
+fn clicks_on_the_quantize_grid() {
+    let mut metronome = Metronome::new();
+    metronome.enabled = true;
+    metronome.grid_steps = 2;
+    metronome.beats_per_bar = 3;
+    // 100 ms grid, a beat every second line; bar lines every third beat
+    assert_eq!(
+        beats(&mut metronome, 100, 1000),
+        [(200, false), (400, false), (600, true), (800, false)]
+    );
+}
+
+#[test]
+fn follows_the_file_tempo_map() {
+    let file = MidiFile {
+        name: "tempo".to_string(),
+        events: Vec::new(),
+        beats_us: vec![0, 500_000, 800_000, 1_100_000],
+        beats_per_bar: 2,
+    };
+    let mut metronome = Metronome::new();
+    metronome.enabled = true;
+    metronome.start_file(&file, 100_000);
+    assert_eq!(
+        beats(&mut metronome, 100, 2000),
+        [(100, true), (600, false), (900, true), (1200, false)]
+    );
+}
@@ -0,0 +1,32 @@
+// MPRIS: what media players see of playback, and play/pause on the transport.
+
+use std::sync::atomic::Ordering;
+
+use miditoroblox_rs::{mpris, player};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::Engine;
+
+#[test]
+fn status_and_title_follow_playback() {
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    *engine.playback_titles.lock().unwrap() = vec!["first".to_string(), "second".to_string()];
+    assert_eq!(mpris::status(&engine), "Stopped");
+    assert_eq!(mpris::title(&engine), None);
+
+    engine.playback_running.store(true, Ordering::Relaxed);
+    engine.playback_song.store(1, Ordering::Relaxed);
+    assert_eq!(mpris::status(&engine), "Playing");
+    assert_eq!(mpris::title(&engine).as_deref(), Some("second"));
+
+    assert!(player::toggle_pause(&engine));
+    assert_eq!(mpris::status(&engine), "Paused");
+    assert!(!player::toggle_pause(&engine));
+    assert_eq!(mpris::status(&engine), "Playing");
+}
+
+#[test]
+fn play_pause_does_nothing_when_stopped() {
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    assert!(!player::toggle_pause(&engine));
+    assert!(!engine.playback_paused.load(Ordering::Relaxed), "stopped playback shouldn't be left paused");
+}
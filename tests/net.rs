@@ -0,0 +1,28 @@
+// The network key emitter's handshake: a receiver that greets with something other than a
+// nonce is refused, not trusted or panicked on.
+
+use std::io::{ErrorKind, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use miditoroblox_rs::net::RemoteEmitter;
+
+fn connect_to_greeting(greeting: &'static [u8]) -> std::io::ErrorKind {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(greeting);
+    });
+    let error = RemoteEmitter::connect(&addr, "secret").err().unwrap();
+    server.join().unwrap();
+    error.kind()
+}
+
+#[test]
+fn odd_greetings_are_refused() {
+    // Even length, but a byte offset lands inside the "é"
+    assert_eq!(connect_to_greeting("MTRB1 aé0\n".as_bytes()), ErrorKind::InvalidData);
+    assert_eq!(connect_to_greeting(b"MTRB1 0g\n"), ErrorKind::InvalidData);
+    assert_eq!(connect_to_greeting(&[b'0'; 4096]), ErrorKind::InvalidData);
+}
@@ -0,0 +1,21 @@
+// Note and chord names as the viewer window shows them.
+
+use miditoroblox_rs::midifile::{chord_name, note_name, parse_note};
+
+#[test]
+fn note_names_round_trip() {
+    for note in 0..128 {
+        assert_eq!(parse_note(&note_name(note)), Some(note));
+    }
+}
+
+#[test]
+fn chords_are_named_from_the_bass_up() {
+    assert_eq!(chord_name(&[60, 64, 67]).as_deref(), Some("C"));
+    assert_eq!(chord_name(&[57, 60, 64, 72]).as_deref(), Some("Am"));
+    assert_eq!(chord_name(&[55, 59, 62, 65]).as_deref(), Some("G7"));
+    // First inversion keeps the root and names the bass
+    assert_eq!(chord_name(&[64, 67, 72]).as_deref(), Some("C/E"));
+    assert_eq!(chord_name(&[60]), None);
+    assert_eq!(chord_name(&[60, 61, 62]), None);
+}
@@ -0,0 +1,97 @@
+// OSC packets in, MIDI out: decoding, the address mapping, and UDP into an engine.
+
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use miditoroblox_rs::config::Settings;
+use miditoroblox_rs::osc::{self, OscArg, OscMessage};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::{worker, Engine};
+
+fn padded(text: &str) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+    bytes
+}
+
+fn packet(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let tags: String = args
+        .iter()
+        .map(|a| match a {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+        })
+        .collect();
+    let mut bytes = padded(address);
+    bytes.extend(padded(&format!(",{}", tags)));
+    for arg in args {
+        match arg {
+            OscArg::Int(i) => bytes.extend(i.to_be_bytes()),
+            OscArg::Float(f) => bytes.extend(f.to_be_bytes()),
+            OscArg::Str(s) => bytes.extend(padded(s)),
+        }
+    }
+    bytes
+}
+
+fn bundle(packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = b"#bundle\0".to_vec();
+    bytes.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+    for packet in packets {
+        bytes.extend((packet.len() as u32).to_be_bytes());
+        bytes.extend(packet);
+    }
+    bytes
+}
+
+fn message(address: &str, args: &[OscArg]) -> OscMessage {
+    OscMessage { address: address.to_string(), args: args.to_vec() }
+}
+
+#[test]
+fn packets_decode() {
+    let args = [OscArg::Int(60), OscArg::Float(0.5), OscArg::Str("hi".to_string())];
+    assert_eq!(osc::decode(&packet("/note/on", &args)).unwrap(), [message("/note/on", &args)]);
+    let nested = bundle(&[packet("/cc", &[OscArg::Int(1)]), bundle(&[packet("/note/off", &[OscArg::Int(60)])])]);
+    let addresses: Vec<_> = osc::decode(&nested).unwrap().into_iter().map(|m| m.address).collect();
+    assert_eq!(addresses, ["/cc", "/note/off"]);
+    assert!(osc::decode(b"/note").is_err(), "unterminated address");
+    assert!(osc::decode(&packet("/note/on", &[OscArg::Int(60)])[..18]).is_err(), "argument cut off");
+}
+
+#[test]
+fn addresses_become_midi() {
+    use OscArg::{Float, Int};
+    assert_eq!(osc::to_midi(&message("/note/on", &[Int(60)])), Some([0x90, 60, 100]));
+    assert_eq!(osc::to_midi(&message("/note/on", &[Int(60), Int(80), Int(10)])), Some([0x99, 60, 80]));
+    // TouchOSC's 0-1 floats
+    assert_eq!(osc::to_midi(&message("/note/on", &[Float(60.0), Float(1.0)])), Some([0x90, 60, 127]));
+    assert_eq!(osc::to_midi(&message("/note/on", &[Int(60), Float(0.0)])), Some([0x90, 60, 0]));
+    assert_eq!(osc::to_midi(&message("/note/off", &[Int(60), Int(2)])), Some([0x81, 60, 0]));
+    assert_eq!(osc::to_midi(&message("/cc", &[Int(64), Float(0.5)])), Some([0xB0, 64, 64]));
+    assert_eq!(osc::to_midi(&message("/note/on", &[Int(128)])), None);
+    assert_eq!(osc::to_midi(&message("/note/on", &[Int(60), Int(100), Int(17)])), None);
+    assert_eq!(osc::to_midi(&message("/fader1", &[Float(0.5)])), None);
+}
+
+#[test]
+fn udp_plays_into_the_engine() {
+    let spy = SpyOutput::new();
+    let engine = Arc::new(Engine::new(KeyOutput::Spy(spy.clone())));
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    let addr = osc::spawn("0", worker::spawn(engine.clone())).unwrap();
+    assert!(addr.ip().is_loopback());
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(&bundle(&[packet("/note/on", &[OscArg::Int(60)]), packet("/note/off", &[OscArg::Int(60)])]), addr).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut keys = Vec::new();
+    while keys.len() < 2 && Instant::now() < deadline {
+        keys.extend(spy.take().into_iter().map(|(key, value)| format!("{:?}{}", key, if value == 0 { "-" } else { "+" })));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(keys, ["KEY_T+", "KEY_T-"]);
+}
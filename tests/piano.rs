@@ -0,0 +1,36 @@
+// The on-screen keyboard's geometry: where keys are and which one the pointer is on.
+
+use miditoroblox_rs::config::GameMode;
+use miditoroblox_rs::piano::{self, PianoLayout};
+
+#[test]
+fn keys_share_the_width() {
+    // C4 to B4: seven white keys
+    let layout = PianoLayout::new(60..=71);
+    assert_eq!(layout.key_span(60), Some((0.0, 1.0 / 7.0)));
+    let (left, right) = layout.key_span(61).unwrap();
+    assert!((left + right) / 2.0 - 1.0 / 7.0 < 1e-6, "C# sits on the line between C and D");
+    assert!(right - left < 1.0 / 7.0);
+    assert_eq!(layout.key_span(72), None);
+    assert!(piano::is_black(70) && !piano::is_black(71));
+}
+
+#[test]
+fn pointer_finds_the_key_on_top() {
+    let layout = PianoLayout::new(60..=71);
+    let c_sharp_line = 1.0 / 7.0;
+    assert_eq!(layout.note_at(c_sharp_line, 0.1), Some(61), "black keys win up top");
+    assert_eq!(layout.note_at(c_sharp_line - 0.01, 0.9), Some(60), "below the black keys it's the white one");
+    assert_eq!(layout.note_at(0.99, 0.5), Some(71));
+    assert_eq!(layout.note_at(0.5, 1.5), None);
+    assert_eq!(layout.note_at(-0.1, 0.5), None);
+}
+
+#[test]
+fn transpose_moves_what_the_game_can_play() {
+    assert_eq!(GameMode::Keys61.reachable_range(0), Some(36..=96));
+    assert_eq!(GameMode::Keys61.reachable_range(-3), Some(33..=93));
+    // Past the top of MIDI, what's left of the keyboard
+    assert_eq!(GameMode::Full88.reachable_range(24), Some(45..=127));
+    assert_eq!(GameMode::Full88.reachable_range(-120), None);
+}
@@ -0,0 +1,78 @@
+// Whole-song transpose planning: the plan can't do worse than the note-by-note solver, and
+// file playback presses exactly the taps the plan counted.
+
+use std::time::Duration;
+
+use evdev::KeyCode;
+use miditoroblox_rs::clock::Clock;
+use miditoroblox_rs::config::{ModifierRole, Settings};
+use miditoroblox_rs::mappings::MappingSet;
+use miditoroblox_rs::midifile::{FileEvent, MidiFile};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::{planner, playability, player, Engine};
+
+// Shift does something else in the game, so black keys need the transpose
+fn settings() -> Settings {
+    Settings { solver_enabled: true, shift_role: ModifierRole::Sustain, ..Settings::default() }
+}
+
+// Each note held for half of its 100 ms slot
+fn song(notes: &[u8]) -> MidiFile {
+    let mut events = Vec::new();
+    for (i, &note) in notes.iter().enumerate() {
+        let at_us = i as u64 * 100_000;
+        events.push(FileEvent { at_us, message: [0x90, note, 100] });
+        events.push(FileEvent { at_us: at_us + 50_000, message: [0x80, note, 0] });
+    }
+    MidiFile { name: "test".to_string(), events, beats_us: Vec::new(), beats_per_bar: 4 }
+}
+
+#[test]
+fn plan_taps_less_than_the_greedy_solver() {
+    // C#4 and B3 alternate: one semitone up plays both (on D4 and C4), where the solver picks
+    // whichever side of C#4 comes first and then has to move for B3
+    let file = song(&[61, 59, 61, 59]);
+    let greedy = playability::analyze(&file, &settings());
+    let plan = planner::plan(&file.events, &settings(), &MappingSet::builtin(), 0);
+    assert_eq!((greedy.transpose_taps, greedy.unplayable.len()), (2, 0));
+    assert_eq!((plan.transpose_taps, plan.dropped), (1, 0));
+    assert_eq!(plan.at(0), plan.at(2));
+    assert_eq!(plan.at(1), None, "note-offs aren't planned");
+}
+
+#[test]
+fn playback_follows_the_plan() {
+    let file = song(&[61, 59, 61, 59]);
+    let plan = planner::plan(&file.events, &settings(), &MappingSet::builtin(), 0);
+
+    let taps = |plan_files: bool| {
+        let clock = Clock::new_virtual();
+        let spy = SpyOutput::with_clock(clock.clone());
+        let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock);
+        engine.apply_settings(&Settings { solver_plan_files: plan_files, ..settings() });
+        player::play(&engine, &file.events, Duration::ZERO);
+        spy.take()
+            .into_iter()
+            .filter(|&(key, value)| matches!(key, KeyCode::KEY_UP | KeyCode::KEY_DOWN) && value == 1)
+            .count() as u64
+    };
+    assert_eq!(taps(true), plan.transpose_taps);
+    assert!(taps(false) > plan.transpose_taps);
+}
+
+#[test]
+fn analysis_sees_snapped_notes() {
+    // Snapped to C major, C#4 plays as C4: nothing needs the transpose and nothing is dropped
+    let file = song(&[61, 59, 61, 59]);
+    let snapped = Settings { snap_key: Some(0), ..settings() };
+    let report = playability::analyze(&file, &snapped);
+    let plan = planner::plan(&file.events, &snapped, &MappingSet::builtin(), 0);
+    assert_eq!((report.transpose_taps, report.unplayable.len()), (0, 0));
+    assert_eq!((plan.transpose_taps, plan.dropped), (0, 0));
+
+    // Without a solver a black key needs Shift, which this game uses for something else
+    let legacy = Settings { solver_enabled: false, base_mapping_enabled: true, ..snapped };
+    assert!(playability::analyze(&file, &legacy).unplayable.is_empty());
+    let unsnapped = Settings { snap_key: None, ..legacy };
+    assert_eq!(playability::analyze(&file, &unsnapped).unplayable.len(), 2);
+}
@@ -0,0 +1,61 @@
+// The polyphony limiter: which note goes past the limit under each priority, and a held
+// note let go early to make room.
+
+use miditoroblox_rs::config::{NotePriority, Settings};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::polyphony::{Admit, Polyphony};
+use miditoroblox_rs::Engine;
+
+// Holds 60, 67 and 64 (in that order) with room for three, then plays `note`
+fn fourth(priority: NotePriority, note: u8) -> Admit {
+    let mut polyphony = Polyphony::default();
+    for held in [60, 67, 64] {
+        assert_eq!(polyphony.on(0, held, 3, priority), Admit::Play);
+    }
+    polyphony.on(0, note, 3, priority)
+}
+
+#[test]
+fn priorities_pick_what_goes() {
+    assert_eq!(fourth(NotePriority::Highest, 72), Admit::Steal { channel: 0, note: 60 });
+    assert_eq!(fourth(NotePriority::Highest, 55), Admit::Drop);
+    assert_eq!(fourth(NotePriority::Lowest, 55), Admit::Steal { channel: 0, note: 67 });
+    assert_eq!(fourth(NotePriority::Lowest, 72), Admit::Drop);
+    assert_eq!(fourth(NotePriority::MostRecent, 55), Admit::Steal { channel: 0, note: 60 });
+    // The melody (67) stays; the oldest of the rest goes
+    assert_eq!(fourth(NotePriority::MelodyOnTop, 62), Admit::Steal { channel: 0, note: 60 });
+    assert_eq!(fourth(NotePriority::MelodyOnTop, 72), Admit::Steal { channel: 0, note: 60 });
+
+    let mut polyphony = Polyphony::default();
+    polyphony.on(0, 67, 1, NotePriority::MelodyOnTop);
+    assert_eq!(polyphony.on(0, 60, 1, NotePriority::MelodyOnTop), Admit::Drop, "nothing but the melody to let go");
+    assert!(polyphony.off(0, 67));
+    assert_eq!(polyphony.on(0, 60, 0, NotePriority::Highest), Admit::Play, "0 is no limit");
+}
+
+#[test]
+fn stolen_notes_stay_up_when_released() {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&Settings {
+        base_mapping_enabled: true,
+        max_polyphony: 2,
+        polyphony_priority: NotePriority::MostRecent,
+        ..Settings::default()
+    });
+
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    let [(c, 1)] = spy.take()[..] else { panic!("C down") };
+    engine.handle_message(&[0x90, 62, 100]).unwrap();
+    spy.take();
+    engine.handle_message(&[0x90, 64, 100]).unwrap();
+    let played = spy.take();
+    assert_eq!(played[0], (c, 0), "C let go first");
+    let (e, _) = played[1];
+    assert_eq!(played[1..], [(e, 1)]);
+
+    engine.handle_message(&[0x80, 60, 0]).unwrap();
+    assert!(spy.take().is_empty(), "C is already up");
+    engine.handle_message(&[0x80, 64, 0]).unwrap();
+    assert_eq!(spy.take(), [(e, 0)]);
+}
@@ -0,0 +1,59 @@
+// Profiles: the settings that travel with a profile and what the engine makes of them. Saving
+// and loading them by name is in config_dir.rs.
+
+use miditoroblox_rs::config::{self, BlackKeys, GameMode, ModifierRole, OverlayBackground, Settings};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::Engine;
+use miditoroblox_rs::pipeline::Routing;
+
+#[test]
+fn overlay_background_goes_with_the_profile() {
+    let stream = Settings { overlay_background: OverlayBackground::Transparent, ..Settings::default() };
+    let json = serde_json::to_string(&stream).unwrap();
+    assert!(json.contains(r#""overlay_background":"transparent""#));
+    assert_eq!(serde_json::from_str::<Settings>(&json).unwrap(), stream);
+
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    engine.apply_settings(&stream);
+    assert_eq!(engine.settings().overlay_background, OverlayBackground::Transparent);
+}
+
+#[test]
+fn notes_are_routed_by_the_profile_in_use() {
+    let mut channel_transpose = [0; 16];
+    channel_transpose[9] = -12;
+    let profile = Settings {
+        game_mode: GameMode::Keys61,
+        fold_high_notes: true,
+        snap_key: Some(2),
+        black_keys: BlackKeys::FoldUp,
+        shift_role: ModifierRole::Accent,
+        channel_transpose,
+        ..Settings::default()
+    };
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    engine.apply_settings(&profile);
+    assert_eq!(engine.routing(), Routing::from(&profile));
+}
+
+#[test]
+fn theme_colors_are_hex() {
+    assert_eq!(config::parse_color("#00B4ff"), Some([0, 180, 255]));
+    assert_eq!(config::format_color([0, 180, 255]), "#00b4ff");
+    for bad in ["00b4ff", "#00b4f", "#00b4ffa", "#00g4ff", "#00é4f"] {
+        assert_eq!(config::parse_color(bad), None, "{}", bad);
+    }
+    let defaults = Settings::default();
+    for color in [&defaults.accent_color, &defaults.input_key_color, &defaults.output_key_color, &defaults.white_key_color, &defaults.black_key_color] {
+        assert_eq!(config::parse_color(color).map(config::format_color).as_ref(), Some(color));
+    }
+}
+
+#[test]
+fn ui_scale_stays_usable() {
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    for (asked, kept) in [(150, 150), (1000, config::MAX_UI_SCALE), (0, config::MIN_UI_SCALE)] {
+        engine.apply_settings(&Settings { ui_scale_percent: asked, ..Settings::default() });
+        assert_eq!(engine.settings().ui_scale_percent, kept);
+    }
+}
@@ -0,0 +1,203 @@
+// The tempo quantizer: its grid, tap tempo, MIDI clock, and live notes waiting for the next line.
+
+use evdev::KeyCode;
+use miditoroblox_rs::clock::Clock;
+use miditoroblox_rs::config::{QuantizeMode, Settings, VolumeSource};
+use miditoroblox_rs::midiclock::MidiClock;
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::quantize::{self, Grid, TapTempo};
+use miditoroblox_rs::Engine;
+
+#[test]
+fn tempo_grid_lines_count_from_the_anchor() {
+    // 16ths at 120 BPM: every 125 ms
+    let grid = Grid::tempo(120, 4, 1_010_000);
+    assert_eq!(grid.step_us, 125_000);
+    assert_eq!(grid.wait_us(1_010_000), 0);
+    assert_eq!(grid.wait_us(1_020_000), 115_000);
+    assert_eq!(grid.wait_us(1_135_000), 0);
+    assert_eq!(grid.wait_us(1_000_000), 10_000, "before the anchor: wait for it");
+    // Triplet 8ths at 100 BPM
+    assert_eq!(Grid::tempo(100, 3, 0).step_us, 200_000);
+    assert_eq!(Grid::tempo(1_000, 0, 0), Grid::tempo(quantize::MAX_BPM, 1, 0), "out of range plays at the limits");
+}
+
+#[test]
+fn swing_delays_the_off_beats() {
+    // 8ths at 120 BPM (250 ms) with a triplet-ish 66% swing: off-beats 80 ms late
+    let grid = Grid::tempo(120, 2, 0).swung(66);
+    assert_eq!(grid.swing_us, 80_000);
+    assert_eq!(grid.wait_us(100_000), 230_000, "early in the pair: the late off-beat");
+    assert_eq!(grid.wait_us(330_000), 0);
+    assert_eq!(grid.wait_us(400_000), 100_000, "after the off-beat: the next beat");
+    assert_eq!(Grid::tempo(120, 2, 0).swung(50), Grid::tempo(120, 2, 0), "50% is straight");
+    assert_eq!(Grid::tempo(120, 2, 0).swung(100), Grid::tempo(120, 2, 0).swung(quantize::MAX_SWING));
+}
+
+#[test]
+fn tapping_sets_the_tempo() {
+    let mut taps = TapTempo::default();
+    assert_eq!(taps.tap(1_000_000), None, "one tap is no tempo");
+    assert_eq!(taps.tap(1_500_000), Some(120));
+    assert_eq!(taps.tap(2_000_000), Some(120));
+    assert_eq!(taps.tap(2_600_000), Some(112), "the average of the gaps");
+    // A long pause starts over
+    assert_eq!(taps.tap(10_000_000), None);
+    assert_eq!(taps.tap(10_750_000), Some(80));
+}
+
+#[test]
+fn notes_wait_for_the_tempo_grid() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(&Settings {
+        base_mapping_enabled: true,
+        quantize_enabled: true,
+        quantize_mode: QuantizeMode::Tempo,
+        quantize_bpm: 120,
+        quantize_subdivision: 2,
+        ..Settings::default()
+    });
+
+    // The first note starts the grid (8ths at 120 BPM, every 250 ms) and plays right away
+    clock.advance_to(1_003_000);
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    engine.handle_message(&[0x80, 60, 0]).unwrap();
+    // The next one lands on the line after it
+    clock.advance_to(1_100_000);
+    engine.handle_message(&[0x90, 62, 100]).unwrap();
+    // The scheduler plays it once its line comes up
+    engine.advance_to(1_300_000).unwrap();
+    let downs: Vec<u64> = spy.take_timed().into_iter().filter(|(_, _, value)| *value == 1).map(|(at_us, _, _)| at_us).collect();
+    assert_eq!(downs, [1_003_000, 1_253_000]);
+
+    // Tapping moves the grid onto the taps
+    clock.advance_to(2_000_000);
+    engine.tap_tempo();
+    clock.advance_to(2_400_000);
+    assert_eq!(engine.tap_tempo(), Some(150));
+    assert_eq!(engine.quantize_grid(), Some(Grid::tempo(150, 2, 2_400_000)));
+}
+
+#[test]
+fn notes_wait_for_the_swung_off_beat() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(&Settings {
+        base_mapping_enabled: true,
+        quantize_enabled: true,
+        quantize_mode: QuantizeMode::Tempo,
+        quantize_bpm: 120,
+        quantize_subdivision: 2,
+        quantize_swing: 60,
+        ..Settings::default()
+    });
+
+    clock.advance_to(1_003_000);
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    engine.handle_message(&[0x80, 60, 0]).unwrap();
+    // The off-beat after the first note is swung 50 ms late
+    clock.advance_to(1_100_000);
+    engine.handle_message(&[0x90, 62, 100]).unwrap();
+    // The scheduler plays it once its line comes up
+    engine.advance_to(1_400_000).unwrap();
+    let downs: Vec<u64> = spy.take_timed().into_iter().filter(|(_, _, value)| *value == 1).map(|(at_us, _, _)| at_us).collect();
+    assert_eq!(downs, [1_003_000, 1_303_000]);
+    assert_eq!(engine.quantize_grid(), Some(Grid::tempo(120, 2, 1_003_000).swung(60)));
+}
+
+#[test]
+fn a_waiting_note_holds_up_nothing_behind_it() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(&Settings {
+        base_mapping_enabled: true,
+        quantize_enabled: true,
+        quantize_mode: QuantizeMode::Milliseconds,
+        quantize_ms: 100,
+        volume_source: VolumeSource::Cc(7),
+        volume_steps: 3,
+        ..Settings::default()
+    });
+
+    // The note waits for the line at 1.1 s; its note-off waits behind it, the volume doesn't
+    clock.advance_to(1_050_000);
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    engine.handle_message(&[0x80, 60, 0]).unwrap();
+    engine.handle_message(&[0xB0, 7, 0]).unwrap();
+    assert_eq!(clock.now_us(), 1_050_000, "nothing was waited on");
+    engine.advance_to(1_200_000).unwrap();
+    let events = spy.take_timed();
+    let at = |key: KeyCode| events.iter().filter(|(_, k, _)| *k == key).map(|(at_us, _, value)| (*at_us, *value)).collect::<Vec<_>>();
+    assert!(!at(KeyCode::KEY_LEFT).is_empty() && at(KeyCode::KEY_LEFT).iter().all(|(at_us, _)| *at_us == 1_050_000));
+    assert_eq!(at(KeyCode::KEY_T), [(1_100_000, 1), (1_100_000, 0)]);
+}
+
+#[test]
+fn midi_clock_follows_the_sequencer() {
+    let mut clock = MidiClock::default();
+    assert!(!clock.message(&[0x90, 60, 100], 0), "notes aren't clock");
+    // Ticks every 20 ms are 125 BPM, but there's no grid until the sequencer starts
+    for tick in 0..24 {
+        assert!(clock.message(&[0xF8], tick * 20_000));
+    }
+    assert_eq!(clock.bpm(), Some(125.0));
+    assert_eq!(clock.grid(2, 460_000), None);
+
+    // Start: the next tick is the first beat
+    clock.message(&[0xFA], 470_000);
+    for tick in 0..30 {
+        clock.message(&[0xF8], 480_000 + tick * 20_000);
+    }
+    assert_eq!(clock.beat(1_060_000), Some(1));
+    assert_eq!(clock.grid(2, 1_060_000), Some(Grid { step_us: 240_000, anchor_us: 960_000, swing_us: 0 }));
+
+    // Stop, and the clock going quiet, both leave notes alone
+    clock.message(&[0xFC], 1_070_000);
+    assert_eq!(clock.grid(2, 1_070_000), None);
+    clock.message(&[0xFB], 1_080_000);
+    assert!(clock.is_running(1_080_000));
+    assert!(!clock.is_running(2_000_000));
+
+    // Continuing from bar 2 (16th 16) counts beats from there
+    clock.message(&[0xFC], 2_000_000);
+    clock.message(&[0xF2, 16, 0], 2_000_000);
+    clock.message(&[0xFB], 2_000_000);
+    clock.message(&[0xF8], 2_010_000);
+    assert_eq!(clock.beat(2_010_000), Some(4));
+}
+
+#[test]
+fn notes_wait_for_the_midi_clock_grid() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(&Settings {
+        base_mapping_enabled: true,
+        quantize_enabled: true,
+        quantize_mode: QuantizeMode::MidiClock,
+        quantize_subdivision: 2,
+        ..Settings::default()
+    });
+
+    // Stopped: the note plays as it comes
+    clock.advance_to(1_000_000);
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    engine.handle_message(&[0x80, 60, 0]).unwrap();
+
+    // Playing at 125 BPM from 2 s: 8ths are every 240 ms from there
+    clock.advance_to(2_000_000);
+    engine.handle_message(&[0xFA]).unwrap();
+    for tick in 0..6 {
+        clock.advance_to(2_000_000 + tick * 20_000);
+        engine.handle_message(&[0xF8]).unwrap();
+    }
+    engine.handle_message(&[0x90, 62, 100]).unwrap();
+    // The scheduler plays it once its line comes up
+    engine.advance_to(2_300_000).unwrap();
+    let downs: Vec<u64> = spy.take_timed().into_iter().filter(|(_, _, value)| *value == 1).map(|(at_us, _, _)| at_us).collect();
+    assert_eq!(downs, [1_000_000, 2_240_000]);
+}
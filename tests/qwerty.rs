@@ -0,0 +1,55 @@
+// The visualizer's computer keyboard: a layout with every key the game can get, and taps
+// that stay lit long enough to see.
+
+use std::collections::HashMap;
+
+use evdev::KeyCode;
+use miditoroblox_rs::mappings::KEY_NAMES;
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::qwerty::{self, KeyLights};
+use miditoroblox_rs::Engine;
+
+#[test]
+fn layout_has_every_key_once_without_overlaps() {
+    let layout = qwerty::layout();
+    let modifiers = [KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_LEFTCTRL, KeyCode::KEY_UP, KeyCode::KEY_DOWN];
+    for key in KEY_NAMES.iter().map(|(_, key)| *key).chain(modifiers) {
+        assert_eq!(layout.iter().filter(|k| k.key == key).count(), 1, "{:?}", key);
+    }
+    for a in &layout {
+        assert!(a.x >= 0.0 && a.x + a.width <= qwerty::WIDTH && a.row < qwerty::ROWS, "{:?}", a.key);
+        for b in layout.iter().filter(|b| b.row == a.row && b.key != a.key) {
+            assert!(a.x + a.width <= b.x || b.x + b.width <= a.x, "{:?} overlaps {:?}", a.key, b.key);
+        }
+    }
+}
+
+#[test]
+fn taps_flash_and_go_dark() {
+    let mut lights = KeyLights::default();
+    let mut key_downs = HashMap::from([(KeyCode::KEY_A, 1)]);
+    lights.update(&key_downs, 1_000_000);
+    assert!(lights.is_lit(KeyCode::KEY_A, 1_000_000));
+    assert!(!lights.is_lit(KeyCode::KEY_A, 1_000_000 + qwerty::FLASH_US));
+    assert!(!lights.any_lit(1_000_000 + qwerty::FLASH_US));
+
+    // Nothing new: stays dark; pressed again: lit again
+    lights.update(&key_downs, 2_000_000);
+    assert!(!lights.is_lit(KeyCode::KEY_A, 2_000_000));
+    key_downs.insert(KeyCode::KEY_A, 2);
+    lights.update(&key_downs, 3_000_000);
+    assert!(lights.any_lit(3_000_000));
+    assert!(!lights.is_lit(KeyCode::KEY_B, 3_000_000));
+}
+
+#[test]
+fn router_counts_key_downs() {
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    let mut router = engine.router.lock().unwrap();
+    router.tap(KeyCode::KEY_Q).unwrap();
+    router.tap(KeyCode::KEY_Q).unwrap();
+    router.key_down(KeyCode::KEY_LEFTSHIFT).unwrap();
+    assert_eq!(router.key_downs[&KeyCode::KEY_Q], 2);
+    assert_eq!(router.key_downs[&KeyCode::KEY_LEFTSHIFT], 1);
+    assert!(router.held.contains(&KeyCode::KEY_LEFTSHIFT) && !router.held.contains(&KeyCode::KEY_Q));
+}
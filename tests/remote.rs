@@ -0,0 +1,172 @@
+// Remote control: JSON commands in, engine changes and events out.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use miditoroblox_rs::config::Settings;
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::remote::{self, Event, RemoteRequest, Watcher};
+use miditoroblox_rs::{http, Engine};
+
+fn engine() -> (Arc<Engine>, SpyOutput) {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    (Arc::new(engine), spy)
+}
+
+fn reply(engine: &Arc<Engine>, command: &str) -> serde_json::Value {
+    serde_json::from_str(&remote::handle(engine, command)).unwrap()
+}
+
+#[test]
+fn commands_change_the_engine() {
+    let (engine, spy) = engine();
+    assert_eq!(reply(&engine, r#"{"cmd": "solver"}"#), serde_json::json!({ "ok": true, "solver": true }));
+    assert_eq!(reply(&engine, r#"{"cmd": "solver", "enabled": false}"#)["solver"], false);
+    assert!(!engine.solver_enabled.load(Ordering::Relaxed));
+
+    assert_eq!(reply(&engine, r#"{"cmd": "transpose", "semitones": 2}"#)["ok"], true);
+    let keys: Vec<_> = spy.take().into_iter().filter(|(_, value)| *value == 1).map(|(key, _)| format!("{:?}", key)).collect();
+    assert_eq!(keys, ["KEY_UP", "KEY_UP"]);
+    assert_eq!(reply(&engine, r#"{"cmd": "state"}"#)["transpose"], 2);
+
+    reply(&engine, r#"{"cmd": "connect", "port": "arturia"}"#);
+    reply(&engine, r#"{"cmd": "disconnect"}"#);
+    assert_eq!(*engine.remote_requests.lock().unwrap(), [RemoteRequest::Connect(Some("arturia".to_string())), RemoteRequest::Disconnect]);
+
+    assert_eq!(reply(&engine, r#"{"cmd": "jump"}"#)["ok"], false);
+    assert_eq!(reply(&engine, r#"{"cmd": "play_file", "path": "/nonexistent.mid"}"#)["ok"], false);
+}
+
+#[test]
+fn watcher_reports_changes_only() {
+    let (engine, _spy) = engine();
+    let mut watcher = Watcher::default();
+    assert_eq!(watcher.poll(&engine).len(), 4, "the first poll is the whole state");
+    assert_eq!(watcher.poll(&engine), []);
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    assert_eq!(watcher.poll(&engine), [Event::Notes { active_notes: vec![60] }]);
+    engine.solver_enabled.store(true, Ordering::Relaxed);
+    assert_eq!(watcher.poll(&engine), [Event::Solver { enabled: true }]);
+}
+
+// (status, headers, JSON body) of one request with extra `headers`
+fn send(addr: SocketAddr, method: &str, target: &str, headers: &str, body: &str) -> (u16, String, serde_json::Value) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}", method, target, headers, body.len(), body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, head.to_string(), serde_json::from_str(body).unwrap_or_default())
+}
+
+// (status, JSON body) of one request as a script would send it, with the token "secret"
+fn request(addr: SocketAddr, method: &str, target: &str, body: &str) -> (u16, serde_json::Value) {
+    let (status, head, body) = send(addr, method, target, "Authorization: Bearer secret\r\nContent-Type: application/json\r\n", body);
+    let public = method == "GET" && matches!(target, "/status" | "/notes");
+    assert_eq!(head.contains("Access-Control-Allow-Origin: *"), public, "any page may read the status and notes, and nothing else");
+    (status, body)
+}
+
+#[test]
+fn http_reports_and_changes_settings() {
+    let (engine, _spy) = engine();
+    let addr = http::spawn(engine.clone(), "0", Some("secret".to_string())).unwrap();
+    assert!(addr.ip().is_loopback(), "a bare port stays on localhost");
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+
+    let (status, notes) = request(addr, "GET", "/notes", "");
+    assert_eq!(status, 200);
+    assert_eq!(notes["active_notes"], serde_json::json!([60]));
+    assert_eq!(notes["held_keys"], serde_json::json!(["KEY_T"]));
+    assert_eq!(request(addr, "GET", "/status", "").1["solver"], false);
+    assert_eq!(request(addr, "GET", "/settings", "").1["quantize_ms"], Settings::default().quantize_ms);
+
+    assert_eq!(request(addr, "POST", "/toggle/solver_enabled", "").1["solver_enabled"], true);
+    assert!(engine.solver_enabled.load(Ordering::Relaxed));
+    let (status, reply) = request(addr, "POST", "/settings", r#"{"quantize_enabled": true, "quantize_ms": 40}"#);
+    assert_eq!(status, 200);
+    assert_eq!(reply["settings"]["quantize_ms"], 40);
+    assert!(engine.quantize_enabled.load(Ordering::Relaxed));
+    assert_eq!(request(addr, "POST", "/command", r#"{"cmd": "solver", "enabled": false}"#).1["solver"], false);
+
+    assert_eq!(request(addr, "POST", "/settings", r#"{"no_such_setting": 1}"#).0, 400);
+    assert_eq!(request(addr, "POST", "/toggle/quantize_ms", "").0, 400);
+    assert_eq!(request(addr, "GET", "/nowhere", "").0, 404);
+    assert_eq!(request(addr, "DELETE", "/status", "").0, 405);
+}
+
+#[test]
+fn http_wants_the_token_when_set() {
+    let (engine, _spy) = engine();
+    let addr = http::spawn(engine, "127.0.0.1:0", Some("secret".to_string())).unwrap();
+    assert_eq!(send(addr, "GET", "/status", "", "").0, 401);
+    assert_eq!(send(addr, "GET", "/status?token=wrong", "", "").0, 401);
+    assert_eq!(send(addr, "GET", "/status?token=secret", "", "").0, 200);
+}
+
+#[test]
+fn http_control_stays_off_limits_to_other_sites() {
+    let (engine, _spy) = engine();
+    let json = "Content-Type: application/json\r\n";
+    let open = http::spawn(engine.clone(), "0", None).unwrap();
+    assert_eq!(send(open, "GET", "/status", "Origin: https://example.com\r\n", "").0, 200, "the status is open");
+    assert_eq!(send(open, "GET", "/settings", "Origin: https://example.com\r\n", "").0, 403, "the settings name local files");
+    assert_eq!(send(open, "GET", "/settings", "Origin: http://127.0.0.1:8080\r\n", "").0, 200);
+    assert_eq!(send(open, "POST", "/toggle/solver_enabled", json, "").0, 403, "no token, no control");
+    let padding = format!("X-Padding: {}\r\n", "a".repeat(20_000));
+    assert_eq!(send(open, "GET", "/status", &padding, "").0, 431);
+
+    let addr = http::spawn(engine.clone(), "0", Some("secret".to_string())).unwrap();
+    let bearer = "Authorization: Bearer secret\r\n";
+    assert_eq!(send(addr, "POST", "/toggle/solver_enabled", bearer, "").0, 415, "a form post isn't JSON");
+    let cross_site = format!("{}{}Origin: https://example.com\r\n", bearer, json);
+    assert_eq!(send(addr, "POST", "/toggle/solver_enabled", &cross_site, "").0, 403);
+    assert_eq!(send(addr, "OPTIONS", "/toggle/solver_enabled", "Origin: https://example.com\r\n", "").0, 403);
+    assert!(!engine.solver_enabled.load(Ordering::Relaxed));
+
+    // A page served from this machine may ask first and then post
+    let local = "Origin: http://localhost:8080\r\n";
+    let (status, head, _) = send(addr, "OPTIONS", "/toggle/solver_enabled", local, "");
+    assert_eq!(status, 204);
+    assert!(head.contains("Access-Control-Allow-Origin: http://localhost:8080"));
+    assert_eq!(send(addr, "POST", "/toggle/solver_enabled", &format!("{}{}{}", bearer, json, local), "").0, 200);
+    assert!(engine.solver_enabled.load(Ordering::Relaxed));
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn websocket_answers_and_streams() {
+    use tungstenite::Message;
+
+    use tungstenite::client::IntoClientRequest;
+
+    let (engine, _spy) = engine();
+    let addr = miditoroblox_rs::websocket::spawn(engine.clone(), "0", Some("secret".to_string())).unwrap();
+    assert!(addr.ip().is_loopback(), "a bare port stays on localhost");
+    assert!(tungstenite::connect(format!("ws://{}/?token=wrong", addr)).is_err());
+    // A page on some other site doesn't get in, token or not
+    let mut cross_site = format!("ws://{}/?token=secret", addr).into_client_request().unwrap();
+    cross_site.headers_mut().insert("Origin", "https://example.com".parse().unwrap());
+    assert!(tungstenite::connect(cross_site).is_err());
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{}/?token=secret", addr)).unwrap();
+    let mut read = || -> serde_json::Value {
+        let Message::Text(text) = socket.read().unwrap() else { panic!("expected text") };
+        serde_json::from_str(&text).unwrap()
+    };
+    let first: Vec<_> = (0..4).map(|_| read()["event"].as_str().unwrap().to_string()).collect();
+    assert_eq!(first, ["notes", "transpose", "solver", "playing"]);
+
+    socket.send(Message::text(r#"{"cmd": "solver", "enabled": true}"#)).unwrap();
+    let mut read = || -> serde_json::Value {
+        let Message::Text(text) = socket.read().unwrap() else { panic!("expected text") };
+        serde_json::from_str(&text).unwrap()
+    };
+    assert_eq!(read(), serde_json::json!({ "ok": true, "solver": true }));
+    assert_eq!(read(), serde_json::json!({ "event": "solver", "enabled": true }));
+}
@@ -0,0 +1,69 @@
+// The piano roll's note history, and the upcoming notes it reads from a song.
+
+use miditoroblox_rs::midifile::FileEvent;
+use miditoroblox_rs::roll::{self, NoteHistory, NoteSpan};
+
+fn event(at_us: u64, message: [u8; 3]) -> FileEvent {
+    FileEvent { at_us, message }
+}
+
+#[test]
+fn history_spans_notes_from_on_to_off() {
+    let mut history = NoteHistory::default();
+    history.note_on(1_000, 0, 60);
+    history.note_on(2_000, 9, 36);
+    history.note_off(3_000, 0, 60);
+    // The other channel's note-off for the same key isn't this note's
+    history.note_off(3_500, 1, 36);
+    assert_eq!(
+        history.since(0),
+        [
+            NoteSpan { note: 60, channel: 0, start_us: 1_000, end_us: Some(3_000) },
+            NoteSpan { note: 36, channel: 9, start_us: 2_000, end_us: None },
+        ]
+    );
+
+    // Struck again without a note-off: the first one ends there
+    history.note_on(4_000, 9, 36);
+    let spans = history.since(0);
+    assert_eq!(spans[1].end_us, Some(4_000));
+    assert_eq!(spans[2], NoteSpan { note: 36, channel: 9, start_us: 4_000, end_us: None });
+
+    history.clear();
+    assert!(history.since(0).is_empty());
+}
+
+#[test]
+fn old_notes_fall_out_of_the_history() {
+    let mut history = NoteHistory::default();
+    history.note_on(0, 0, 60);
+    history.note_off(500_000, 0, 60);
+    history.note_on(1_000_000, 0, 62);
+    assert_eq!(history.since(600_000).len(), 1, "ended before the window");
+
+    // Long after, the old note is gone for good; the held one stays
+    history.note_on(60_000_000, 0, 64);
+    let notes: Vec<u8> = history.since(0).iter().map(|s| s.note).collect();
+    assert_eq!(notes, [62, 64]);
+}
+
+#[test]
+fn upcoming_notes_end_at_their_note_offs() {
+    let events = [
+        event(0, [0x90, 60, 100]),
+        event(1_000, [0x91, 64, 100]),
+        event(2_000, [0x80, 60, 0]),
+        // A velocity-0 note-on ends a note too
+        event(3_000, [0x91, 64, 0]),
+        event(4_000, [0x90, 67, 100]),
+        event(9_000, [0x90, 72, 100]),
+    ];
+    assert_eq!(
+        roll::upcoming(&events, 500, 5_000),
+        [
+            NoteSpan { note: 64, channel: 1, start_us: 1_000, end_us: Some(3_000) },
+            NoteSpan { note: 67, channel: 0, start_us: 4_000, end_us: None },
+        ]
+    );
+    assert_eq!(roll::upcoming(&events, 0, 0)[0].end_us, Some(2_000));
+}
@@ -0,0 +1,115 @@
+// The output scheduler: notes held back by the humanizer's random delay or spread out by the
+// chord roll, and note-offs never overtaking their note-ons, even while those are being played.
+
+use std::time::Instant;
+
+use miditoroblox_rs::clock::Clock;
+use miditoroblox_rs::config::{RollDirection, Settings};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::processor::NoteEvent;
+use miditoroblox_rs::scheduler::{Scheduler, Spread, CHORD_WINDOW_US};
+use miditoroblox_rs::Engine;
+
+#[test]
+fn notes_are_held_up_to_the_most_delay() {
+    let mut humanizer = Scheduler::default();
+    let now = Instant::now();
+    let spread = Spread { humanize_us: 20_000, ..Spread::default() };
+    for note in [60, 64, 67] {
+        humanizer.hold(NoteEvent::on(0, note, 100), 1_000_000, now, spread);
+    }
+    // Let go before its note-on is out: it waits for it
+    humanizer.hold(NoteEvent::off(0, 64), 1_000_000, now, spread);
+    assert!(humanizer.take_due(999_999).is_empty());
+    assert!(humanizer.next_due().is_some_and(|due| (1_000_000..=1_020_000).contains(&due)));
+
+    let notes: Vec<NoteEvent> = humanizer.take_due(1_020_000).into_iter().map(|(event, _)| event).collect();
+    assert_eq!(notes.len(), 4);
+    let on = notes.iter().position(|event| *event == NoteEvent::on(0, 64, 100)).unwrap();
+    let off = notes.iter().position(|event| *event == NoteEvent::off(0, 64)).unwrap();
+    assert!(on < off);
+    assert_eq!(humanizer.next_due(), None);
+}
+
+#[test]
+fn a_note_off_waits_while_its_note_on_is_played() {
+    let mut scheduler = Scheduler::default();
+    let now = Instant::now();
+    let spread = Spread { humanize_us: 1, ..Spread::default() };
+    scheduler.hold(NoteEvent::on(0, 60, 100), 1_000_000, now, spread);
+    let taken = scheduler.take_due(1_000_001);
+    assert_eq!(taken.len(), 1);
+
+    // Let go while another thread is still playing the note-on
+    let off = NoteEvent::off(0, 60);
+    assert!(scheduler.holds_note(off));
+    scheduler.hold(off, 1_000_001, now, Spread::default());
+    assert!(scheduler.take_due(1_000_002).is_empty());
+
+    scheduler.played(taken[0].0);
+    assert!(!scheduler.holds_note(off));
+    let notes: Vec<NoteEvent> = scheduler.take_due(1_000_002).into_iter().map(|(event, _)| event).collect();
+    assert_eq!(notes, [off]);
+}
+
+#[test]
+fn chords_roll_in_pitch_order() {
+    let mut scheduler = Scheduler::default();
+    let now = Instant::now();
+    let spread = Spread { roll_us: 40_000, ..Spread::default() };
+    // Struck within the window, out of order; the middle one let go right away
+    scheduler.hold(NoteEvent::on(0, 64, 100), 1_000_000, now, spread);
+    scheduler.hold(NoteEvent::on(0, 67, 100), 1_005_000, now, spread);
+    scheduler.hold(NoteEvent::on(0, 60, 100), 1_010_000, now, spread);
+    scheduler.hold(NoteEvent::off(0, 64), 1_012_000, now, spread);
+    assert_eq!(scheduler.next_due(), Some(1_000_000 + CHORD_WINDOW_US));
+
+    let mut played = Vec::new();
+    while let Some(due_us) = scheduler.next_due() {
+        played.extend(scheduler.take_due(due_us).into_iter().map(|(event, _)| (due_us, event)));
+    }
+    assert_eq!(
+        played,
+        [
+            (1_020_000, NoteEvent::on(0, 60, 100)),
+            (1_040_000, NoteEvent::on(0, 64, 100)),
+            (1_040_000, NoteEvent::off(0, 64)),
+            (1_060_000, NoteEvent::on(0, 67, 100)),
+        ]
+    );
+
+    // Down from the top; a note after the window starts a chord of its own
+    let spread = Spread { roll_us: 30_000, roll: RollDirection::Down, ..Spread::default() };
+    scheduler.hold(NoteEvent::on(0, 48, 100), 2_000_000, now, spread);
+    scheduler.hold(NoteEvent::on(0, 52, 100), 2_000_000, now, spread);
+    scheduler.hold(NoteEvent::on(0, 55, 100), 2_030_000, now, spread);
+    let mut played = Vec::new();
+    while let Some(due_us) = scheduler.next_due() {
+        played.extend(scheduler.take_due(due_us).into_iter().map(|(event, _)| (due_us, event.note)));
+    }
+    assert_eq!(played, [(2_020_000, 52), (2_050_000, 48), (2_050_000, 55)]);
+}
+
+#[test]
+fn humanized_chords_spread_out() {
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock.clone());
+    engine.apply_settings(&Settings { base_mapping_enabled: true, humanize_ms: 30, ..Settings::default() });
+
+    clock.advance_to(1_000_000);
+    for note in [60, 62, 64, 65, 67] {
+        engine.handle_message(&[0x90, note, 100]).unwrap();
+    }
+    assert!(spy.take_timed().is_empty(), "held back");
+    engine.advance_to(1_100_000).unwrap();
+    let downs: Vec<u64> = spy.take_timed().into_iter().filter(|(_, _, value)| *value == 1).map(|(at_us, _, _)| at_us).collect();
+    assert_eq!(downs.len(), 5);
+    assert!(downs.iter().all(|at_us| (1_000_000..=1_030_000).contains(at_us)));
+    assert!(downs.iter().any(|at_us| *at_us != downs[0]), "not all at once");
+
+    // Off again: straight through
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    assert_eq!(spy.take_timed().first().map(|(at_us, _, _)| *at_us), Some(1_100_000));
+}
@@ -0,0 +1,109 @@
+// Scripts in front of the engine: what on_midi returns is what gets played.
+#![cfg(feature = "scripting")]
+
+use miditoroblox_rs::config::Settings;
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::script::{Script, ScriptAction, ScriptContext};
+use miditoroblox_rs::Engine;
+
+fn keys(spy: &SpyOutput) -> Vec<String> {
+    spy.take()
+        .into_iter()
+        .map(|(key, value)| format!("{:?}{}", key, if value == 0 { "-" } else { "+" }))
+        .collect()
+}
+
+// A base-mapping engine playing through the script `source`
+fn scripted(source: &str) -> (Engine, SpyOutput) {
+    let dir = std::env::temp_dir().join(format!("miditoroblox-script-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{:x}.rhai", source.len() * 31 + source.bytes().map(usize::from).sum::<usize>()));
+    std::fs::write(&path, source).unwrap();
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    let settings = Settings { base_mapping_enabled: true, script_file: path.to_string_lossy().into_owned(), ..Settings::default() };
+    engine.apply_settings(&settings);
+    assert_eq!(*engine.script_file.lock().unwrap(), settings.script_file, "script didn't load");
+    (engine, spy)
+}
+
+#[test]
+fn returns_decide_what_plays() {
+    let mut script = Script::compile(
+        r#"
+        fn on_midi(event) {
+            if event.kind == "cc" { return false; }
+            if event.kind == "note_on" && event.note == 60 { return [event, note_on(event.channel, 64, 90), tap("KEY_F")]; }
+            if event.channel == 1 { event.channel = 0; event.note += 12; return event; }
+        }
+        "#,
+    )
+    .unwrap();
+    let context = ScriptContext::default();
+    assert_eq!(script.run(&[0xB0, 64, 127], &context), []);
+    assert_eq!(
+        script.run(&[0x90, 60, 100], &context),
+        [ScriptAction::Midi(vec![0x90, 60, 100]), ScriptAction::Midi(vec![0x90, 64, 90]), ScriptAction::Tap(evdev::KeyCode::KEY_F)]
+    );
+    assert_eq!(script.run(&[0x81, 50, 0], &context), [ScriptAction::Midi(vec![0x80, 62, 0])]);
+    assert_eq!(script.run(&[0x90, 62, 100], &context), [ScriptAction::Midi(vec![0x90, 62, 100])]);
+}
+
+#[test]
+fn context_and_state_reach_the_script() {
+    // Every other note is dropped, counted in `this`; the context is in the velocity and note
+    let mut script = Script::compile(
+        r#"
+        fn on_midi(event, ctx) {
+            if event.kind != "note_on" { return; }
+            this.count = if this.count == () { 1 } else { this.count + 1 };
+            if this.count % 2 == 0 { return false; }
+            let played = note_on(0, ctx.active_notes.len() + ctx.transpose, ctx.active_notes[0]);
+            // A copy: the next call still sees the real context
+            ctx.transpose = 99;
+            ctx.active_notes.clear();
+            played
+        }
+        "#,
+    )
+    .unwrap();
+    let context = ScriptContext { at_us: 0, active_notes: vec![40, 52], transpose: 3 };
+    assert_eq!(script.run(&[0x90, 60, 100], &context), [ScriptAction::Midi(vec![0x90, 5, 40])]);
+    assert_eq!(script.run(&[0x90, 60, 100], &context), []);
+    assert_eq!(script.run(&[0x90, 60, 100], &context), [ScriptAction::Midi(vec![0x90, 5, 40])]);
+}
+
+#[test]
+fn errors_play_the_message_unchanged() {
+    assert!(Script::compile("fn other(event) {}").is_err(), "on_midi is required");
+    assert!(Script::compile("fn on_midi(event) {").is_err());
+    let mut script = Script::compile(r#"fn on_midi(event) { note_on(0, 200, 100) }"#).unwrap();
+    assert_eq!(script.run(&[0x90, 60, 100], &ScriptContext::default()), [ScriptAction::Midi(vec![0x90, 60, 100])]);
+    assert!(script.last_error.as_deref().is_some_and(|e| e.contains("note")));
+    // Runaway loops are stopped too
+    let mut script = Script::compile("fn on_midi(event) { loop {} }").unwrap();
+    assert_eq!(script.run(&[0x90, 60, 100], &ScriptContext::default()), [ScriptAction::Midi(vec![0x90, 60, 100])]);
+    assert!(script.last_error.is_some());
+}
+
+#[test]
+fn engine_plays_what_the_script_returns() {
+    let (engine, spy) = scripted(
+        r#"
+        fn on_midi(event) {
+            if "note" in event && event.note == 61 { return false; }
+            if event.kind == "note_on" && event.note == 62 { return tap("KEY_F"); }
+            if "note" in event { event.note -= 2; return event; }
+        }
+        "#,
+    );
+    for message in [[0x90, 61, 100], [0x90, 62, 100], [0x90, 62, 100], [0x80, 62, 0]] {
+        engine.handle_message(&message).unwrap();
+    }
+    // 61 dropped, 62 tapped F instead of playing, and the note-off arrives as 60's
+    assert_eq!(keys(&spy), ["KEY_F+", "KEY_F-", "KEY_F+", "KEY_F-", "KEY_T-"]);
+
+    engine.use_script_file("").unwrap();
+    engine.handle_message(&[0x90, 60, 100]).unwrap();
+    assert_eq!(keys(&spy), ["KEY_T+"]);
+}
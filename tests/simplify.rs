@@ -0,0 +1,57 @@
+// Chord simplification: dense chords in a file lose octave doublings, then inner voices,
+// keeping their top and bottom notes.
+
+use miditoroblox_rs::config::Settings;
+use miditoroblox_rs::midifile::FileEvent;
+use miditoroblox_rs::simplify::simplify;
+
+fn on(at_us: u64, channel: u8, note: u8) -> FileEvent {
+    FileEvent { at_us, message: [0x90 | channel, note, 100] }
+}
+
+fn off(at_us: u64, channel: u8, note: u8) -> FileEvent {
+    FileEvent { at_us, message: [0x80 | channel, note, 0] }
+}
+
+// A C major chord over four octaves, spread across channels and a few milliseconds the way
+// a score's sections land, plus a drum hit; all let go at 1 s
+fn orchestra() -> Vec<FileEvent> {
+    let notes = [36, 48, 52, 55, 60, 64, 67, 72];
+    let mut events: Vec<FileEvent> = notes.iter().enumerate().map(|(i, &note)| on(i as u64 * 1_000, (i % 3) as u8, note)).collect();
+    events.push(on(8_000, 9, 38));
+    events.extend(notes.iter().enumerate().map(|(i, &note)| off(1_000_000, (i % 3) as u8, note)));
+    events
+}
+
+fn notes_on(events: &[FileEvent]) -> Vec<u8> {
+    let mut notes: Vec<u8> = events.iter().filter(|event| event.is_note_on()).map(|event| event.note()).collect();
+    notes.sort();
+    notes
+}
+
+#[test]
+fn dense_chords_keep_top_bottom_and_the_voices_near_the_melody() {
+    let events = orchestra();
+    let settings = |max_chord_notes| Settings { max_chord_notes, ..Settings::default() };
+
+    // Doublings go first: C, E and G each once more between the bass and the top C
+    let simplified = simplify(&events, &settings(4));
+    assert_eq!(notes_on(&simplified), [36, 38, 64, 67, 72], "the drum hit isn't part of the chord");
+    // And each dropped note's note-off with it
+    assert_eq!(simplified.iter().filter(|event| !event.is_note_on()).count(), 4);
+
+    // Then inner voices from the bottom up
+    assert_eq!(notes_on(&simplify(&events, &settings(3))), [36, 38, 67, 72]);
+    assert_eq!(notes_on(&simplify(&events, &settings(1))), [36, 38, 72], "never below top and bottom");
+
+    // Small enough chords, and the setting off, leave the file alone
+    assert_eq!(simplify(&events, &settings(12)).len(), events.len());
+    assert_eq!(simplify(&events, &settings(0)).len(), events.len());
+}
+
+#[test]
+fn chords_are_counted_separately() {
+    let settings = Settings { max_chord_notes: 2, ..Settings::default() };
+    let events = [on(0, 0, 60), on(0, 0, 64), on(0, 0, 67), on(500_000, 0, 48), on(500_000, 0, 72)];
+    assert_eq!(notes_on(&simplify(&events, &settings)), [48, 60, 67, 72]);
+}
@@ -0,0 +1,227 @@
+// Snapshot tests for the deterministic offline simulation: a small MIDI file goes through the
+// whole pipeline and the canonical log has to match exactly. The letter-sheet export is
+// checked the same way.
+
+use miditoroblox_rs::config::{GameMode, Settings};
+use miditoroblox_rs::midifile::MidiFile;
+use miditoroblox_rs::sheet;
+use miditoroblox_rs::simulate::{format_log, simulate};
+
+// Single-track SMF at 96 ticks per beat (120 BPM by default, so 96 ticks = 500 ms).
+// `events` are (delta ticks, status, data1, data2).
+fn midi(events: &[(u8, u8, u8, u8)]) -> MidiFile {
+    let mut track = Vec::new();
+    for &(delta, status, data1, data2) in events {
+        track.extend_from_slice(&[delta, status, data1, data2]);
+    }
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut bytes = b"MThd".to_vec();
+    bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&track);
+    MidiFile::parse(&bytes, "test".to_string()).unwrap()
+}
+
+fn melody() -> MidiFile {
+    midi(&[
+        (0, 0x90, 60, 100),
+        (48, 0x80, 60, 0),
+        (0, 0x90, 61, 100),
+        (48, 0x80, 61, 0),
+        (0, 0x90, 20, 100),
+        (48, 0x80, 20, 0),
+        (0, 0x99, 38, 100),
+        (0, 0x90, 62, 100),
+        (96, 0x80, 62, 0),
+    ])
+}
+
+#[test]
+fn legacy_mappings() {
+    let settings = Settings { base_mapping_enabled: true, ..Settings::default() };
+    assert_eq!(
+        format_log(&simulate(&melody(), &settings)),
+        "\
+0 key KEY_T down
+250000 key KEY_T up
+250000 key KEY_LEFTSHIFT down
+250000 key KEY_T down
+250000 key KEY_T up
+250000 key KEY_LEFTSHIFT up
+500000 drop G#0 OutOfRange
+750000 drop D2 DrumChannel
+750000 key KEY_Y down
+1250000 key KEY_Y up
+"
+    );
+}
+
+#[test]
+fn solver_with_transpose_delays() {
+    // Transpose taps cost 5 ms each on the virtual clock instead of real sleeps
+    let settings = Settings { solver_enabled: true, solver_mode_efficiency: false, ..Settings::default() };
+    let file = midi(&[
+        (0, 0x90, 60, 100),
+        (0, 0x90, 61, 100),
+        (96, 0x80, 61, 0),
+        (0, 0x80, 60, 0),
+    ]);
+    assert_eq!(
+        format_log(&simulate(&file, &settings)),
+        "\
+0 key KEY_T down
+0 key KEY_DOWN down
+0 key KEY_DOWN up
+5000 key KEY_Y down
+500000 key KEY_Y up
+500000 key KEY_LEFTSHIFT up
+500000 key KEY_LEFTCTRL up
+500000 key KEY_T up
+500000 key KEY_LEFTSHIFT up
+500000 key KEY_LEFTCTRL up
+"
+    );
+}
+
+#[test]
+fn quantize_uses_the_virtual_clock() {
+    let settings = Settings { base_mapping_enabled: true, quantize_enabled: true, quantize_ms: 100, ..Settings::default() };
+    let file = midi(&[(10, 0x90, 60, 100), (10, 0x80, 60, 0)]);
+    // 10 ticks = 52.083 ms, snapped forward (in whole milliseconds) onto the 100 ms grid
+    assert_eq!(
+        format_log(&simulate(&file, &settings)),
+        "\
+100083 key KEY_T down
+104166 key KEY_T up
+"
+    );
+}
+
+#[test]
+fn runs_are_identical() {
+    let settings = Settings { solver_enabled: true, ..Settings::default() };
+    assert_eq!(simulate(&melody(), &settings), simulate(&melody(), &settings));
+}
+
+#[test]
+fn guitar_strums_each_chord_once() {
+    let settings = Settings { game_mode: GameMode::Guitar, ..Settings::default() };
+    // C major arpeggiated over ~10 ms, so one chord within the 30 ms strum window
+    let file = midi(&[
+        (0, 0x90, 48, 100),
+        (1, 0x90, 52, 100),
+        (1, 0x90, 55, 100),
+        (96, 0x80, 48, 0),
+        (0, 0x80, 52, 0),
+        (0, 0x80, 55, 0),
+    ]);
+    assert_eq!(
+        format_log(&simulate(&file, &settings)),
+        "\
+0 key KEY_1 down
+5208 key KEY_5 down
+10416 key KEY_8 down
+30000 key KEY_SPACE down
+30000 key KEY_SPACE up
+510416 key KEY_1 up
+510416 key KEY_5 up
+510416 key KEY_8 up
+"
+    );
+}
+
+#[test]
+fn letter_sheet_spells_sharps_and_chords() {
+    let settings = Settings { base_mapping_enabled: true, ..Settings::default() };
+    let file = midi(&[
+        (0, 0x90, 60, 100),
+        (48, 0x80, 60, 0),
+        (0, 0x90, 61, 100),
+        (48, 0x80, 61, 0),
+        (0, 0x90, 60, 100),
+        (0, 0x90, 64, 100),
+        (0, 0x90, 67, 100),
+        (96, 0x80, 60, 0),
+        (0, 0x80, 64, 0),
+        (0, 0x80, 67, 0),
+        (96, 0x90, 48, 100),
+        (96, 0x80, 48, 0),
+        (96, 0xB0, 1, 0),
+        (96, 0xB0, 1, 0),
+        (96, 0x90, 72, 100),
+        (48, 0x80, 72, 0),
+    ]);
+    // 250 ms apart is a space, 1 s a bar, 2 s or more a new line
+    assert_eq!(sheet::export(&file, &settings), "t T [tuo] | 8\ns\n");
+}
+
+#[test]
+fn playback_keeps_time_and_releases_what_is_left() {
+    use miditoroblox_rs::clock::Clock;
+    use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+    use miditoroblox_rs::{player, Engine};
+    use std::time::Duration;
+
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock);
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    // The file ends with D4 still sounding
+    let file = midi(&[(0, 0x90, 60, 100), (48, 0x80, 60, 0), (48, 0x90, 62, 100)]);
+    player::play(&engine, &file.events, Duration::ZERO);
+    let keys: Vec<String> = spy
+        .take_timed()
+        .into_iter()
+        .map(|(at_us, key, value)| format!("{} {:?}{}", at_us, key, if value == 0 { "-" } else { "+" }))
+        .collect();
+    assert_eq!(keys, ["0 KEY_T+", "250000 KEY_T-", "500000 KEY_Y+", "500000 KEY_Y-"]);
+}
+
+#[test]
+fn playback_seeks_and_changes_speed() {
+    use miditoroblox_rs::clock::Clock;
+    use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+    use miditoroblox_rs::{player, Engine};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock);
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    let file = midi(&[(0, 0x90, 60, 100), (48, 0x80, 60, 0), (48, 0x90, 62, 100), (48, 0x80, 62, 0)]);
+    // Start 400 ms in at double speed: C4 is skipped, D4 comes 50 ms later and lasts 125 ms
+    *engine.playback_seek_us.lock().unwrap() = Some(400_000);
+    engine.playback_rate_percent.store(200, Ordering::Relaxed);
+    player::play(&engine, &file.events, Duration::ZERO);
+    let keys: Vec<String> = spy
+        .take_timed()
+        .into_iter()
+        .map(|(at_us, key, value)| format!("{} {:?}{}", at_us, key, if value == 0 { "-" } else { "+" }))
+        .collect();
+    assert_eq!(keys, ["50000 KEY_Y+", "175000 KEY_Y-"]);
+}
+
+#[test]
+fn playlist_waits_the_gap_between_songs() {
+    use miditoroblox_rs::clock::Clock;
+    use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+    use miditoroblox_rs::{player, Engine};
+    use std::time::Duration;
+
+    let clock = Clock::new_virtual();
+    let spy = SpyOutput::with_clock(clock.clone());
+    let engine = Engine::with_clock(KeyOutput::Spy(spy.clone()), clock);
+    engine.apply_settings(&Settings { base_mapping_enabled: true, ..Settings::default() });
+    let first = midi(&[(0, 0x90, 60, 100), (48, 0x80, 60, 0)]);
+    let second = midi(&[(0, 0x90, 62, 100), (48, 0x80, 62, 0)]);
+    player::play_all(&engine, &[first.events, second.events], Duration::ZERO, Duration::from_secs(2));
+    let keys: Vec<String> = spy
+        .take_timed()
+        .into_iter()
+        .map(|(at_us, key, value)| format!("{} {:?}{}", at_us, key, if value == 0 { "-" } else { "+" }))
+        .collect();
+    assert_eq!(keys, ["0 KEY_T+", "250000 KEY_T-", "2250000 KEY_Y+", "2500000 KEY_Y-"]);
+}
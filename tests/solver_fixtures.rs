@@ -0,0 +1,51 @@
+// Replays every event stream in tests/fixtures/solver through the engine with the settings
+// in the .json next to it and compares the simulation log with the .log beside them. When a
+// change in output is intended, `UPDATE_FIXTURES=1 cargo test --test solver_fixtures`
+// rewrites the logs; review the diff before committing it.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use miditoroblox_rs::config::Settings;
+use miditoroblox_rs::simulate::{format_log, parse_events, simulate_events};
+
+#[test]
+fn fixtures_match_their_logs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/solver");
+    let update = env::var_os("UPDATE_FIXTURES").is_some();
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "events"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut mismatched = Vec::new();
+    for path in paths {
+        let events = parse_events(&fs::read_to_string(&path).unwrap()).unwrap();
+        let settings: Settings = serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+        let log = format_log(&simulate_events(&events, &settings));
+        let log_path = path.with_extension("log");
+        if update {
+            fs::write(&log_path, &log).unwrap();
+        } else if fs::read_to_string(&log_path).ok().as_deref() != Some(log.as_str()) {
+            eprintln!("{}:\n{}", path.display(), log);
+            mismatched.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    assert!(mismatched.is_empty(), "output changed for {:?}", mismatched);
+}
+
+#[test]
+fn event_streams_parse() {
+    let events = parse_events("# comment\n0 on C#4\n\n500 off 61\n750 cc 64 127 ch 2\n1000 on D4 40 ch 10").unwrap();
+    let messages: Vec<_> = events.iter().map(|e| (e.at_us, e.message)).collect();
+    assert_eq!(
+        messages,
+        [(0, [0x90, 61, 100]), (500, [0x80, 61, 0]), (750, [0xB1, 64, 127]), (1000, [0x99, 62, 40])]
+    );
+    assert!(parse_events("10 on C4\n5 off C4").is_err(), "time can't go backwards");
+    assert!(parse_events("0 up C4").is_err());
+}
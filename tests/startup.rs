@@ -0,0 +1,37 @@
+// Startup flags: overrides on top of the defaults, and picking the MIDI input by name.
+
+use miditoroblox_rs::startup::Startup;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_string).collect()
+}
+
+#[test]
+fn flags_override_the_settings() {
+    let startup = Startup::from_args(&args("miditoroblox --solver --quantize 120 --port arturia --connect"), None).unwrap();
+    assert!(startup.settings.solver_enabled);
+    assert!(startup.settings.quantize_enabled);
+    assert_eq!(startup.settings.quantize_ms, 120);
+    assert_eq!(startup.port.as_deref(), Some("arturia"));
+    assert!(startup.connect);
+    assert_eq!(startup.profile, None);
+    assert_eq!(startup.ble, None);
+    assert_eq!(Startup::from_args(&args("miditoroblox --ble microkey"), None).unwrap().ble.as_deref(), Some("microkey"));
+
+    let plain = Startup::from_args(&args("miditoroblox --quantize 0"), None).unwrap();
+    assert!(!plain.settings.solver_enabled && !plain.settings.quantize_enabled && !plain.connect);
+
+    assert!(Startup::from_args(&args("miditoroblox --quantize fast"), None).is_err());
+    assert!(Startup::from_args(&args("miditoroblox --port --connect"), None).is_err());
+}
+
+#[test]
+fn port_is_picked_by_any_part_of_its_name() {
+    let ports = ["Midi Through:Midi Through Port-0 14:0", "Arturia KeyStep 37:Arturia KeyStep 37 MIDI 1 24:0"].map(String::from);
+    let startup = Startup::from_args(&args("miditoroblox --port keystep"), None).unwrap();
+    assert_eq!(startup.find_port(&ports), Some(&ports[1]));
+    let startup = Startup::from_args(&args("miditoroblox --port roland"), None).unwrap();
+    assert_eq!(startup.find_port(&ports), None);
+    // Without --port the first one is picked, as the GUI does
+    assert_eq!(Startup::default().find_port(&ports), Some(&ports[0]));
+}
@@ -0,0 +1,71 @@
+// Typing notes: the layout, and note-offs that match their note-ons.
+
+use std::sync::atomic::Ordering;
+
+use evdev::KeyCode;
+use miditoroblox_rs::config::{Settings, TypingSource};
+use miditoroblox_rs::output::{KeyOutput, SpyOutput};
+use miditoroblox_rs::typing::{self, TypedNotes};
+use miditoroblox_rs::Engine;
+
+fn typing_engine(source: TypingSource) -> Engine {
+    let engine = Engine::new(KeyOutput::Spy(SpyOutput::new()));
+    engine.apply_settings(&Settings { typing_source: source, ..Settings::default() });
+    engine
+}
+
+#[test]
+fn layout_is_two_rows_of_piano() {
+    assert_eq!(typing::offset('z'), Some(0));
+    assert_eq!(typing::offset('S'), Some(1), "any case");
+    assert_eq!(typing::offset('q'), Some(12));
+    assert_eq!(typing::offset(']'), Some(31));
+    assert_eq!(typing::offset('a'), None);
+    assert_eq!(typing::char_for_key(KeyCode::KEY_LEFTBRACE), Some('['));
+    assert_eq!(typing::char_for_key(KeyCode::KEY_ENTER), None);
+}
+
+#[test]
+fn keys_play_from_their_source_only() {
+    let engine = typing_engine(TypingSource::Window);
+    let mut typed = TypedNotes::default();
+    assert_eq!(typed.key(&engine, TypingSource::Keyboard, 'z', true), None);
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'z', true), Some([0x90, 48, 100]));
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'z', true), None, "held already");
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'z', false), Some([0x80, 48, 0]));
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'z', false), None, "not held");
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'a', true), None);
+
+    let off = typing_engine(TypingSource::Off);
+    assert_eq!(TypedNotes::default().key(&off, TypingSource::Window, 'z', true), None);
+}
+
+#[test]
+fn note_offs_match_their_note_ons() {
+    let engine = typing_engine(TypingSource::Window);
+    let mut typed = TypedNotes::default();
+    typed.key(&engine, TypingSource::Window, 'q', true);
+    engine.typing_base_note.store(60, Ordering::Relaxed);
+    *engine.typing_source.lock().unwrap() = TypingSource::Off;
+    assert_eq!(typed.key(&engine, TypingSource::Window, 'q', false), Some([0x80, 60, 0]), "base and source changed while held");
+
+    *engine.typing_source.lock().unwrap() = TypingSource::Window;
+    typed.key(&engine, TypingSource::Window, 'z', true);
+    typed.key(&engine, TypingSource::Window, 'x', true);
+    assert_eq!(typed.release_all(), [[0x80, 60, 0], [0x80, 62, 0]]);
+    assert!(typed.is_empty());
+}
+
+#[test]
+fn typed_notes_play_through_the_pipeline() {
+    let spy = SpyOutput::new();
+    let engine = Engine::new(KeyOutput::Spy(spy.clone()));
+    engine.apply_settings(&Settings { typing_source: TypingSource::Window, typing_base_note: 60, base_mapping_enabled: true, ..Settings::default() });
+    let mut typed = TypedNotes::default();
+    let on = typed.key(&engine, TypingSource::Window, 'z', true).unwrap();
+    engine.handle_message(&on).unwrap();
+    let off = typed.key(&engine, TypingSource::Window, 'z', false).unwrap();
+    engine.handle_message(&off).unwrap();
+    let keys: Vec<_> = spy.take().into_iter().map(|(key, value)| (format!("{:?}", key), value)).collect();
+    assert_eq!(keys, [("KEY_T".to_string(), 1), ("KEY_T".to_string(), 0)]);
+}